@@ -0,0 +1,388 @@
+use std::{collections::HashMap, error::Error, fmt};
+
+//Two-pass assembler for the mnemonics the disassembler prints (Cowgod's).
+//One statement per line, a label may start any line:
+//
+//      ; comments run to the end of the line
+//      start:  LD V0, 0
+//      loop:   DRW V0, V1, 5
+//              ADD V0, 8
+//              SE V0, 64
+//              JP loop
+//      glyph:  DB 0xF0, 0x90, 0xF0
+//              DW 0x1234
+//      SPEED   EQU 3
+//
+//Mnemonics and registers are case-insensitive, numbers are decimal or 0x
+//hex and any address or value can be a label defined anywhere in the
+//file. EQU names a value, a number or a name defined above it, and is
+//used like a label. SHR and SHL also take a single register. LD I, LONG
+//addr is XO-CHIP's four byte F000 NNNN. Output is laid out from 0x200,
+//where the ROM gets loaded.
+
+const LOAD_ADDRESS: usize = 0x200;
+const MEMORY_SIZE: usize = 4096;
+
+//Operand names that can't be used as labels
+const RESERVED: [&str; 9] = ["I", "DT", "ST", "K", "F", "HF", "B", "R", "[I]"];
+
+const MNEMONICS: [&str; 30] = [
+    "CLS", "RET", "SCD", "SCU", "SCR", "SCL", "EXIT", "LOW", "HIGH", "PLANE", "SYS", "JP", "CALL", "SE", "SNE", "SAVE", "LOAD", "LD", "ADD",
+    "OR", "AND", "XOR", "SUB", "SHR", "SUBN", "SHL", "RND", "DRW", "SKP", "SKNP"
+];
+
+//line is 1-based, token is the part of it that was rejected
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AsmError {
+    pub line: usize,
+    pub token: String,
+    pub message: String
+}
+
+enum Operand<'a> {
+    Register(usize),
+    Index,
+    IndexMemory,
+    Delay,
+    Sound,
+    Key,
+    Font,
+    BigFont,
+    Bcd,
+    Flags,
+    //A number or a label
+    Value(&'a str)
+}
+
+struct Statement<'a> {
+    line: usize,
+    mnemonic: &'a str,
+    operands: Vec<&'a str>
+}
+
+struct Labels<'a> {
+    //Address, or value for EQU, and the line it was defined on
+    addresses: HashMap<&'a str, (usize, usize)>
+}
+
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    //First pass: lay out statements and note where each label lands
+    let mut labels = Labels { addresses: HashMap::new() };
+    let mut statements: Vec<Statement> = Vec::new();
+    let mut address = LOAD_ADDRESS;
+
+    for (i, line) in source.lines().enumerate() {
+        let line_number = i + 1;
+        let mut text = line.split(';').next().unwrap_or("").trim();
+
+        if let Some((label, rest)) = text.split_once(':') {
+            let label = label.trim();
+            labels.define(label, address, line_number)?;
+            text = rest.trim();
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        //NAME EQU VALUE takes no space in the output
+        if let [name, equ, value] = text.split_whitespace().collect::<Vec<&str>>()[..] {
+            if equ.eq_ignore_ascii_case("EQU") {
+                let value = labels.value(line_number, value, 0xFFFF)?;
+                labels.define(name, value as usize, line_number)?;
+                continue;
+            }
+        }
+        if text.split_whitespace().any(|word| word.eq_ignore_ascii_case("EQU")) {
+            return Err(AsmError::create(line_number, text, "expected NAME EQU VALUE"));
+        }
+
+        let (mnemonic, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        let operands: Vec<&str> = match rest.trim() {
+            "" => Vec::new(),
+            rest => rest.split(',').map(str::trim).collect()
+        };
+        if operands.iter().any(|operand| operand.is_empty()) {
+            return Err(AsmError::create(line_number, text, "missing operand"));
+        }
+
+        address += match mnemonic.to_ascii_uppercase().as_str() {
+            "DB" | "DW" if operands.is_empty() => {
+                return Err(AsmError::create(line_number, mnemonic, "needs at least one value"));
+            },
+            "DB" => operands.len(),
+            "DW" => operands.len() * 2,
+            _ if long_index(mnemonic, &operands).is_some() => 4,
+            _ => 2
+        };
+        if address > MEMORY_SIZE {
+            return Err(AsmError::create(line_number, mnemonic, "program doesn't fit in memory"));
+        }
+
+        statements.push(Statement { line: line_number, mnemonic, operands });
+    }
+
+    //Second pass: every label is known now, so forward references work
+    let mut output = Vec::with_capacity(address - LOAD_ADDRESS);
+    for statement in &statements {
+        match statement.mnemonic.to_ascii_uppercase().as_str() {
+            "DB" => for operand in &statement.operands {
+                output.push(labels.value(statement.line, operand, 0xFF)? as u8);
+            },
+            "DW" => for operand in &statement.operands {
+                output.extend_from_slice(&labels.value(statement.line, operand, 0xFFFF)?.to_be_bytes());
+            },
+            mnemonic => match long_index(mnemonic, &statement.operands) {
+                Some(address) => {
+                    output.extend_from_slice(&0xF000_u16.to_be_bytes());
+                    output.extend_from_slice(&labels.value(statement.line, address, 0xFFFF)?.to_be_bytes());
+                },
+                None => output.extend_from_slice(&encode(mnemonic, statement, &labels)?.to_be_bytes())
+            }
+        }
+    }
+
+    Ok(output)
+}
+
+//The address of an LD I, LONG addr
+fn long_index<'a>(mnemonic: &str, operands: &[&'a str]) -> Option<&'a str> {
+    match operands {
+        [index, long] if mnemonic.eq_ignore_ascii_case("LD") && index.eq_ignore_ascii_case("I") => {
+            let (keyword, address) = long.split_once(char::is_whitespace)?;
+            keyword.eq_ignore_ascii_case("LONG").then(|| address.trim())
+        },
+        _ => None
+    }
+}
+
+fn encode(mnemonic: &str, statement: &Statement, labels: &Labels) -> Result<u16, AsmError> {
+    let operands: Vec<Operand> = statement.operands.iter().map(|token| Operand::parse(token)).collect();
+    let value = |token: &str, max: u16| labels.value(statement.line, token, max);
+    let x = |x: usize| (x as u16) << 8;
+    let xy = |x: usize, y: usize| ((x as u16) << 8) | ((y as u16) << 4);
+
+    let opcode = match (mnemonic, &operands[..]) {
+        ("CLS", []) => 0x00E0,
+        ("RET", []) => 0x00EE,
+        ("LOW", []) => 0x00FE,
+        ("HIGH", []) => 0x00FF,
+        ("SCD", [Operand::Value(n)]) => 0x00C0 | value(n, 0xF)?,
+        ("SCU", [Operand::Value(n)]) => 0x00D0 | value(n, 0xF)?,
+        ("SCR", []) => 0x00FB,
+        ("SCL", []) => 0x00FC,
+        ("EXIT", []) => 0x00FD,
+        ("SYS", [Operand::Value(address)]) => value(address, 0xFFF)?,
+        ("JP", [Operand::Value(address)]) => 0x1000 | value(address, 0xFFF)?,
+        ("JP", [Operand::Register(0), Operand::Value(address)]) => 0xB000 | value(address, 0xFFF)?,
+        ("CALL", [Operand::Value(address)]) => 0x2000 | value(address, 0xFFF)?,
+        ("SE", [Operand::Register(vx), Operand::Value(byte)]) => 0x3000 | x(*vx) | value(byte, 0xFF)?,
+        ("SNE", [Operand::Register(vx), Operand::Value(byte)]) => 0x4000 | x(*vx) | value(byte, 0xFF)?,
+        ("SE", [Operand::Register(vx), Operand::Register(vy)]) => 0x5000 | xy(*vx, *vy),
+        ("SAVE", [Operand::Register(vx), Operand::Register(vy)]) => 0x5002 | xy(*vx, *vy),
+        ("LOAD", [Operand::Register(vx), Operand::Register(vy)]) => 0x5003 | xy(*vx, *vy),
+        ("LD", [Operand::Register(vx), Operand::Value(byte)]) => 0x6000 | x(*vx) | value(byte, 0xFF)?,
+        ("ADD", [Operand::Register(vx), Operand::Value(byte)]) => 0x7000 | x(*vx) | value(byte, 0xFF)?,
+        ("LD", [Operand::Register(vx), Operand::Register(vy)]) => 0x8000 | xy(*vx, *vy),
+        ("OR", [Operand::Register(vx), Operand::Register(vy)]) => 0x8001 | xy(*vx, *vy),
+        ("AND", [Operand::Register(vx), Operand::Register(vy)]) => 0x8002 | xy(*vx, *vy),
+        ("XOR", [Operand::Register(vx), Operand::Register(vy)]) => 0x8003 | xy(*vx, *vy),
+        ("ADD", [Operand::Register(vx), Operand::Register(vy)]) => 0x8004 | xy(*vx, *vy),
+        ("SUB", [Operand::Register(vx), Operand::Register(vy)]) => 0x8005 | xy(*vx, *vy),
+        ("SHR", [Operand::Register(vx)]) => 0x8006 | x(*vx),
+        ("SHR", [Operand::Register(vx), Operand::Register(vy)]) => 0x8006 | xy(*vx, *vy),
+        ("SUBN", [Operand::Register(vx), Operand::Register(vy)]) => 0x8007 | xy(*vx, *vy),
+        ("SHL", [Operand::Register(vx)]) => 0x800E | x(*vx),
+        ("SHL", [Operand::Register(vx), Operand::Register(vy)]) => 0x800E | xy(*vx, *vy),
+        ("SNE", [Operand::Register(vx), Operand::Register(vy)]) => 0x9000 | xy(*vx, *vy),
+        ("LD", [Operand::Index, Operand::Value(address)]) => 0xA000 | value(address, 0xFFF)?,
+        ("RND", [Operand::Register(vx), Operand::Value(byte)]) => 0xC000 | x(*vx) | value(byte, 0xFF)?,
+        ("DRW", [Operand::Register(vx), Operand::Register(vy), Operand::Value(height)]) => {
+            0xD000 | xy(*vx, *vy) | value(height, 0xF)?
+        },
+        ("SKP", [Operand::Register(vx)]) => 0xE09E | x(*vx),
+        ("SKNP", [Operand::Register(vx)]) => 0xE0A1 | x(*vx),
+        ("LD", [Operand::Register(vx), Operand::Delay]) => 0xF007 | x(*vx),
+        ("LD", [Operand::Register(vx), Operand::Key]) => 0xF00A | x(*vx),
+        ("LD", [Operand::Delay, Operand::Register(vx)]) => 0xF015 | x(*vx),
+        ("LD", [Operand::Sound, Operand::Register(vx)]) => 0xF018 | x(*vx),
+        ("ADD", [Operand::Index, Operand::Register(vx)]) => 0xF01E | x(*vx),
+        ("LD", [Operand::Font, Operand::Register(vx)]) => 0xF029 | x(*vx),
+        ("LD", [Operand::BigFont, Operand::Register(vx)]) => 0xF030 | x(*vx),
+        ("LD", [Operand::Bcd, Operand::Register(vx)]) => 0xF033 | x(*vx),
+        ("LD", [Operand::IndexMemory, Operand::Register(vx)]) => 0xF055 | x(*vx),
+        ("LD", [Operand::Register(vx), Operand::IndexMemory]) => 0xF065 | x(*vx),
+        //There are only 8 flags
+        ("LD", [Operand::Flags, Operand::Register(vx)]) if *vx <= 7 => 0xF075 | x(*vx),
+        ("LD", [Operand::Register(vx), Operand::Flags]) if *vx <= 7 => 0xF085 | x(*vx),
+        ("PLANE", [Operand::Value(n)]) => 0xF001 | value(n, 0x3)? << 8,
+        (mnemonic, _) if MNEMONICS.contains(&mnemonic) => {
+            let token = statement.operands.join(", ");
+            return Err(AsmError::create(statement.line, &token, &format!("invalid operands for {}", mnemonic)));
+        },
+        _ => return Err(AsmError::create(statement.line, statement.mnemonic, "unknown instruction"))
+    };
+
+    Ok(opcode)
+}
+
+impl<'a> Operand<'a> {
+
+    fn parse(token: &'a str) -> Operand<'a> {
+        match token.to_ascii_uppercase().as_str() {
+            "I" => Operand::Index,
+            "[I]" => Operand::IndexMemory,
+            "DT" => Operand::Delay,
+            "ST" => Operand::Sound,
+            "K" => Operand::Key,
+            "F" => Operand::Font,
+            "HF" => Operand::BigFont,
+            "B" => Operand::Bcd,
+            "R" => Operand::Flags,
+            register => match register_index(register) {
+                Some(x) => Operand::Register(x),
+                None => Operand::Value(token)
+            }
+        }
+    }
+
+}
+
+//V0-VF
+fn register_index(token: &str) -> Option<usize> {
+    let digit = token.strip_prefix(['V', 'v'])?;
+    if digit.len() != 1 {
+        return None;
+    }
+
+    usize::from_str_radix(digit, 16).ok()
+}
+
+impl<'a> Labels<'a> {
+
+    fn define(&mut self, name: &'a str, address: usize, line: usize) -> Result<(), AsmError> {
+        let valid = !name.is_empty()
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+            && !name.starts_with(|c: char| c.is_ascii_digit());
+        if !valid || RESERVED.contains(&name.to_ascii_uppercase().as_str()) || register_index(name).is_some() {
+            return Err(AsmError::create(line, name, "invalid label name"));
+        }
+        if let Some((_, defined)) = self.addresses.get(name) {
+            return Err(AsmError::create(line, name, &format!("already defined on line {}", defined)));
+        }
+
+        self.addresses.insert(name, (address, line));
+        Ok(())
+    }
+
+    //A decimal or 0x hex number, or a label's address or EQU value, no
+    //bigger than max
+    fn value(&self, line: usize, token: &str, max: u16) -> Result<u16, AsmError> {
+        let value = if let Some(hex) = token.strip_prefix("0x").or_else(|| token.strip_prefix("0X")) {
+            usize::from_str_radix(hex, 16).map_err(|_| AsmError::create(line, token, "invalid number"))?
+        } else if token.starts_with(|c: char| c.is_ascii_digit()) {
+            token.parse::<usize>().map_err(|_| AsmError::create(line, token, "invalid number"))?
+        } else {
+            match self.addresses.get(token) {
+                Some((address, _)) => *address,
+                None => return Err(AsmError::create(line, token, "undefined name"))
+            }
+        };
+
+        if value > max as usize {
+            return Err(AsmError::create(line, token, &format!("out of range, at most {:#X}", max)));
+        }
+
+        Ok(value as u16)
+    }
+
+}
+
+impl AsmError {
+
+    fn create(line: usize, token: &str, message: &str) -> AsmError {
+        AsmError {
+            line,
+            token: token.to_string(),
+            message: message.to_string()
+        }
+    }
+
+}
+
+impl fmt::Display for AsmError {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}: {}", self.line, self.message, self.token)
+    }
+
+}
+
+impl Error for AsmError {}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{chip8::Chip8, diagnostic::ascii_framebuffer, disasm, headless};
+
+    const DIGITS: &str = "
+        ; the font's 0-7 across the top of the screen
+                LD V0, 0
+                LD V1, 0
+                LD V2, 0
+        loop:   LD F, V2
+                DRW V0, V1, 5
+                ADD V0, GAP
+                ADD V2, 1
+                SE V2, 8
+                JP loop
+        done:   JP done
+        GAP     EQU 8
+    ";
+
+    #[test]
+    fn assembled_program_runs() {
+        let rom = assemble(DIGITS).unwrap();
+        assert_eq!(rom[..8], [0x60, 0x00, 0x61, 0x00, 0x62, 0x00, 0xF2, 0x29]);
+        assert_eq!(rom[rom.len() - 4..], [0x12, 0x06, 0x12, 0x12]);
+
+        let mut chip8 = Chip8::create();
+        chip8.load_bytes(&rom).unwrap();
+        let report = headless::run(&mut chip8, 200, 10, &[]);
+        assert!(report.halted.is_none());
+
+        let screen = ascii_framebuffer(&chip8.video, chip8.video_width());
+        let top: Vec<&str> = screen.lines().take(6).collect();
+        assert_eq!(top, [
+            "####......#.....####....####....#..#....####....####....####....",
+            "#..#.....##........#.......#....#..#....#.......#..........#....",
+            "#..#......#.....####....####....####....####....####......#.....",
+            "#..#......#.....#..........#.......#.......#....#..#.....#......",
+            "####.....###....####....####.......#....####....####.....#......",
+            "................................................................"
+        ]);
+    }
+
+    #[test]
+    fn disassembly_assembles_back() {
+        let rom = assemble(DIGITS).unwrap();
+        let listing = disasm::listing(&rom);
+        assert!(listing.contains("label_0x206:\n"));
+        assert!(listing.contains("JP label_0x212"));
+
+        assert_eq!(assemble(&listing).unwrap(), rom);
+    }
+
+    #[test]
+    fn errors() {
+        let error = |source: &str| assemble(source).unwrap_err().to_string();
+
+        assert_eq!(error("CLS\nFROB V0"), "line 2: unknown instruction: FROB");
+        assert_eq!(error("JP nowhere"), "line 1: undefined name: nowhere");
+        assert_eq!(error("LD V0, 256"), "line 1: out of range, at most 0xFF: 256");
+        assert_eq!(error("a: CLS\na: CLS"), "line 2: already defined on line 1: a");
+        assert_eq!(error("ADD V0,"), "line 1: missing operand: ADD V0,");
+        assert_eq!(error("DB"), "line 1: needs at least one value: DB");
+    }
+
+}
@@ -0,0 +1,101 @@
+use std::{
+    path::Path,
+    time::{Duration, Instant}
+};
+
+use crate::headless::{self, ScriptEvent};
+
+//chip8 attract DIR [--seconds N]: kiosk mode. Every ROM in DIR runs in
+//turn for N seconds, looping forever, until a key press hands the current
+//game to the player. A ROM with a demo next to it, pong.c8r for pong.ch8,
+//plays itself from it: lines like run --headless --input takes, except
+//that @N counts 60Hz frames from the ROM's start, not instructions.
+pub const DEFAULT_SECONDS: u64 = 60;
+
+pub struct AttractMode {
+    duration: Duration,
+    started: Instant,
+    demo: Vec<ScriptEvent>,
+    next_event: usize,
+    //Frames the current ROM has run
+    frames: u64,
+    //Set when the current ROM failed to load or halted
+    skip: bool,
+    //ROMs in a row that failed, see all_failed
+    failures: usize
+}
+
+impl AttractMode {
+
+    pub fn create(seconds: u64, rom_path: &str) -> AttractMode {
+        let mut mode = AttractMode {
+            duration: Duration::from_secs(seconds),
+            started: Instant::now(),
+            demo: Vec::new(),
+            next_event: 0,
+            frames: 0,
+            skip: false,
+            failures: 0
+        };
+        mode.start(rom_path);
+        mode
+    }
+
+    //Restarts the clock for the ROM just loaded and reads its demo, if any
+    pub fn start(&mut self, rom_path: &str) {
+        self.started = Instant::now();
+        self.frames = 0;
+        self.next_event = 0;
+        self.skip = false;
+
+        let demo_path = Path::new(rom_path).with_extension("c8r");
+        self.demo = match demo_path.exists() {
+            true => headless::read_script(&demo_path.to_string_lossy()).unwrap_or_else(|e| {
+                log::warn!("{}", e);
+                Vec::new()
+            }),
+            false => Vec::new()
+        };
+    }
+
+    //The current ROM didn't load, halted or exited, it is skipped
+    pub fn fail(&mut self) {
+        self.skip = true;
+        self.failures += 1;
+    }
+
+    pub fn is_over(&mut self) -> bool {
+        if self.skip {
+            return true;
+        }
+        let over = self.started.elapsed() >= self.duration;
+        if over {
+            self.failures = 0;
+        }
+        over
+    }
+
+    //Whether the last rom_count ROMs all failed, so going round again
+    //would only fail again
+    pub fn all_failed(&self, rom_count: usize) -> bool {
+        self.failures >= rom_count
+    }
+
+    //Called before each frame: presses and releases the demo's keys due by now
+    pub fn play_demo(&mut self, keypad: &mut [bool; 16]) {
+        while let Some(event) = self.demo.get(self.next_event).filter(|event| event.cycle <= self.frames) {
+            keypad[event.key as usize] = event.pressed;
+            self.next_event += 1;
+        }
+        self.frames += 1;
+    }
+
+}
+
+//chip8 attract DIR [--seconds N] [options] as the window's own options,
+//program --rom-dir=DIR --attract=N [options]
+pub fn command_line(program: &str, dir: &str, seconds: u64, options: &[String]) -> Vec<String> {
+    [program.to_string(), format!("--rom-dir={}", dir), format!("--attract={}", seconds)].into_iter()
+        .chain(options.iter().cloned())
+        .collect()
+}
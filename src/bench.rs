@@ -0,0 +1,184 @@
+use std::{
+    cell::RefCell,
+    fmt,
+    rc::Rc,
+    time::{Duration, Instant}
+};
+
+use serde_json::{json, Value};
+
+use crate::chip8::{Chip8, Chip8Error, ExecHook};
+
+//Every SAMPLE_INTERVAL-th instruction is timed, from its on_exec to the
+//next one, to split the run time between DXYN and everything else
+//without reading the clock for every instruction
+const SAMPLE_INTERVAL: u64 = 1024;
+
+pub struct BenchReport {
+    pub instructions: u64,
+    pub draws: u64,
+    pub wall_time: Duration,
+    //Share of the sampled time spent in DXYN, 0 to 1
+    pub draw_share: f64,
+    //Why the run ended before the requested number of instructions
+    pub halted: Option<Chip8Error>
+}
+
+//Exec hook doing the sampling
+struct DrawTimer {
+    executed: u64,
+    draws: u64,
+    //Start of the instruction being sampled and whether it is a DXYN
+    sample: Option<(Instant, bool)>,
+    draw_time: Duration,
+    other_time: Duration
+}
+
+//Runs up to cycles instructions as fast as possible, ticking the timers
+//after each one like the default pacing does
+pub fn bench(chip8: &mut Chip8, cycles: u64) -> BenchReport {
+    let timer = Rc::new(RefCell::new(DrawTimer {
+        executed: 0,
+        draws: 0,
+        sample: None,
+        draw_time: Duration::ZERO,
+        other_time: Duration::ZERO
+    }));
+    chip8.add_exec_hook(timer.clone());
+
+    let start = Instant::now();
+    let mut executed = 0;
+    let mut halted = None;
+    while executed < cycles {
+        if let Err(e) = chip8.run() {
+            halted = Some(e);
+            break;
+        }
+        executed += 1;
+        //Nothing runs after EXIT, there is nothing left to time
+        if chip8.exited {
+            break;
+        }
+    }
+    let wall_time = start.elapsed();
+
+    let mut timer = timer.borrow_mut();
+    timer.finish_sample();
+    let sampled = timer.draw_time + timer.other_time;
+
+    BenchReport {
+        instructions: executed,
+        draws: timer.draws,
+        wall_time,
+        draw_share: if sampled.is_zero() { 0.0 } else { timer.draw_time.as_secs_f64() / sampled.as_secs_f64() },
+        halted
+    }
+}
+
+impl DrawTimer {
+
+    fn finish_sample(&mut self) {
+        if let Some((start, draw)) = self.sample.take() {
+            let elapsed = start.elapsed();
+            if draw { self.draw_time += elapsed } else { self.other_time += elapsed }
+        }
+    }
+
+}
+
+impl ExecHook for DrawTimer {
+
+    fn on_exec(&mut self, _address: u16, opcode: u16) {
+        self.finish_sample();
+
+        let draw = opcode & 0xF000 == 0xD000;
+        self.executed += 1;
+        if draw {
+            self.draws += 1;
+        }
+        if self.executed.is_multiple_of(SAMPLE_INTERVAL) {
+            self.sample = Some((Instant::now(), draw));
+        }
+    }
+
+}
+
+impl BenchReport {
+
+    pub fn instructions_per_second(&self) -> f64 {
+        let seconds = self.wall_time.as_secs_f64();
+        if seconds > 0.0 { self.instructions as f64 / seconds } else { 0.0 }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "instructions": self.instructions,
+            "wall_time_seconds": self.wall_time.as_secs_f64(),
+            "instructions_per_second": self.instructions_per_second(),
+            "dxyn": {
+                "instructions": self.draws,
+                "time_share": self.draw_share
+            },
+            "halted": self.halted.map(|e| e.to_string())
+        })
+    }
+
+}
+
+impl fmt::Display for BenchReport {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(e) = &self.halted {
+            writeln!(f, "Halted early: {}", e)?;
+        }
+        writeln!(f, "Instructions: {}", self.instructions)?;
+        writeln!(f, "Wall time:    {:.3}s", self.wall_time.as_secs_f64())?;
+        writeln!(f, "Speed:        {:.0} instructions/s", self.instructions_per_second())?;
+        writeln!(f, "DXYN:         {} instruction(s), ~{:.1}% of the time", self.draws, self.draw_share * 100.0)?;
+        writeln!(f, "Other:        {} instruction(s), ~{:.1}% of the time", self.instructions.saturating_sub(self.draws), (1.0 - self.draw_share) * 100.0)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn machine(rom: &[u8]) -> Chip8 {
+        let mut chip8 = Chip8::create();
+        chip8.load_bytes(rom).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn runs_exactly_the_cycles_asked_for() {
+        //DRW V0, V0, 1; ADD V0, 1; JP 0x200
+        let mut chip8 = machine(&[0xD0, 0x01, 0x70, 0x01, 0x12, 0x00]);
+        let report = bench(&mut chip8, 30_000);
+
+        assert_eq!((report.instructions, report.draws), (30_000, 10_000));
+        assert!(report.halted.is_none());
+        assert!((0.0..=1.0).contains(&report.draw_share));
+        assert_eq!(report.to_json()["instructions"], 30_000);
+        assert_eq!(report.to_json()["dxyn"]["instructions"], 10_000);
+        assert!(report.to_string().starts_with("Instructions: 30000\n"));
+    }
+
+    #[test]
+    fn stops_when_the_rom_does() {
+        //An invalid opcode after two instructions
+        let mut chip8 = machine(&[0x60, 0x01, 0x70, 0x01, 0xE0, 0x00]);
+        let report = bench(&mut chip8, 1000);
+        assert_eq!(report.instructions, 2);
+        assert_eq!(report.halted, Some(Chip8Error::InvalidOpcode { address: 0x204, opcode: 0xE000 }));
+        assert!(report.to_string().starts_with("Halted early: "));
+        assert!(report.to_json()["halted"].is_string());
+
+        //EXIT counts, then nothing more runs
+        let mut chip8 = machine(&[0x60, 0x01, 0x00, 0xFD]);
+        let report = bench(&mut chip8, 1000);
+        assert_eq!((report.instructions, report.halted), (2, None));
+    }
+
+}
@@ -1,11 +1,83 @@
 use std::{
-    fs::{metadata, File},
-    io::{BufReader, Read}
+    cell::RefCell,
+    collections::BTreeSet,
+    error::Error,
+    fmt,
+    fs,
+    rc::Rc
 };
 
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    instruction::{decode, register_range, Instruction},
+    octocart::{is_octocart, Octocart},
+    quirks::Quirks,
+    rng::{RandomSource, RngLog},
+    rom_info::{schip_pattern, xo_chip_pattern},
+    strict::StrictChecker
+};
+#[cfg(feature = "http")]
+use crate::download;
+#[cfg(feature = "octo")]
+use crate::octo;
+#[cfg(feature = "zip")]
+use crate::zip;
+
+//Called with the address and opcode of every instruction right before it
+//executes, and optionally with every memory access the instruction makes
+pub trait ExecHook {
+    fn on_exec(&mut self, address: u16, opcode: u16);
+
+    //XO-CHIP's four byte F000 NNNN comes here instead, with the NNNN
+    fn on_exec_long(&mut self, address: u16, opcode: u16, _operand: u16) {
+        self.on_exec(address, opcode);
+    }
+
+    fn on_read(&mut self, _target: usize) {}
+
+    fn on_write(&mut self, _target: usize) {}
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Chip8Error {
+    InvalidOpcode { address: u16, opcode: u16 },
+    StackOverflow { address: u16 },
+    StackUnderflow { address: u16 },
+    //target is the memory address the instruction at address tried to use
+    MemoryOutOfBounds { address: u16, target: usize },
+    //A write below START_ADDRESS while LowMemoryProtection::Error is on
+    ProtectedWrite { address: u16, target: usize },
+    //A --verify-invariants check failed after the instruction at address
+    InvariantViolated { address: u16, opcode: u16, invariant: &'static str }
+}
+
+//What happens when a ROM writes into the interpreter/font area below 0x200
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum LowMemoryProtection {
+    #[default]
+    Off,
+    //Print a warning once per offending instruction and let the write happen
+    Warn,
+    //Stop with Chip8Error::ProtectedWrite
+    Error
+}
+
+//A CALL as seen by the call-stack tracker
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CallFrame {
+    pub call_site: u16,
+    pub target: u16
+}
+
 pub struct Chip8 {
     pub registers: [u8; 16],
-    pub memory: [u8; 4096],
+    //MEMORY_SIZE bytes, or XO_CHIP_MEMORY_SIZE for a machine made with
+    //create_with_memory. Fixed once made, save states record it.
+    pub memory: Box<[u8]>,
+    //Can point past the end of memory, F000 and FX1E take it anywhere in
+    //16 bits and arithmetic on it wraps there. Each access through it is
+    //checked against memory.len() instead, see read_memory.
     pub index_register: u16,
     pub program_counter: u16,
     pub stack: [u16; 16],
@@ -13,14 +85,83 @@ pub struct Chip8 {
     pub delay_timer: u8,
     pub sound_timer: u8,
     pub keypad: [bool; 16],
-    pub video: [u8; 64*32],
-    pub opcode: u16
+    //video_width() x video_height(), row by row
+    pub video: Vec<u8>,
+    //XO-CHIP's second drawing plane, laid out like video, which is the
+    //first. Stays blank until a ROM selects it with FN01.
+    pub plane2: Vec<u8>,
+    //FN01's mask of the planes DXYN, 00E0 and the scrolls act on, bit 0
+    //for video and bit 1 for plane2
+    pub plane_mask: u8,
+    //Set whenever video changes, frontends clear it once they've shown it
+    pub video_dirty: bool,
+    //SUPER-CHIP 128x64 mode, switched by 00FF/00FE
+    pub hires: bool,
+    //Set by SUPER-CHIP's EXIT (00FD). Nothing runs after it until a reset.
+    pub exited: bool,
+    pub opcode: u16,
+    pub quirks: Quirks,
+    pub low_memory_protection: LowMemoryProtection,
+    //Instructions already warned about under LowMemoryProtection::Warn
+    protection_warnings: BTreeSet<u16>,
+    //Checks the machine after every instruction, see invariants.rs
+    pub verify_invariants: bool,
+    //Skip SUPER-CHIP/XO-CHIP opcodes this interpreter doesn't implement
+    //instead of stopping on them, warning once per address
+    pub lenient: bool,
+    lenient_warnings: BTreeSet<u16>,
+    //Warn once per DXYN that draws a sprite from past the end of the ROM
+    //or wraps around the end of memory, likely a ROM bug
+    pub warn_sprite_overrun: bool,
+    sprite_warnings: BTreeSet<u16>,
+    //Just past the ROM last loaded, START_ADDRESS without one
+    rom_end: usize,
+    //Reports quirk-dependent behavior, see strict.rs
+    pub strict: Option<StrictChecker>,
+    //Print every CALL and RET with the stack depth they leave behind
+    pub log_stack: bool,
+    //CXNN's numbers come from rng, seeded with seed. rng_draws counts
+    //the numbers taken so a saved state can pick up the same sequence.
+    pub seed: u64,
+    pub rng_draws: u64,
+    rng: StdRng,
+    //--rng-replay's numbers, taken before the seeded ones, and --rng-log,
+    //told every number CXKK gets. See rng.rs.
+    random_source: Option<Box<dyn RandomSource>>,
+    rng_log: Option<RngLog>,
+    //False when the font area was left blank for ROMs that bring their own
+    fontset: bool,
+    //SHA-1 of the ROM last loaded, kept in save states so a state isn't
+    //restored over a different game
+    pub rom_hash: Option<[u8; 20]>,
+    //SUPER-CHIP's RPL user flags for FX75/FX85. Like the HP 48's they
+    //outlast a reset, rpl.rs keeps them on disk per ROM.
+    pub rpl_flags: [u8; 8],
+    //Mirrors the hardware stack with where each CALL came from.
+    //Goes unreliable once a RET has nothing to return to.
+    call_frames: Vec<CallFrame>,
+    call_frames_reliable: bool,
+    //Draws that turned a lit pixel off since the last reset, not kept
+    //in save states
+    collision_count: u64,
+    exec_hooks: Vec<Rc<RefCell<dyn ExecHook>>>
 }
 
 const START_ADDRESS: u16 = 0x200;
-const FONTSET_START_ADDRESS: u16 = 0x50;
-
-const FONTSET_SIZE: u16 = 80;
+pub const MEMORY_SIZE: usize = 4096;
+//XO-CHIP's, all that F000 NNNN's 16-bit I can reach
+pub const XO_CHIP_MEMORY_SIZE: usize = 65536;
+pub const MAX_ROM_SIZE: usize = MEMORY_SIZE - START_ADDRESS as usize;
+//The most any machine takes, reading a ROM checks against this and
+//loading it against the machine's own memory
+pub const MAX_XO_CHIP_ROM_SIZE: usize = XO_CHIP_MEMORY_SIZE - START_ADDRESS as usize;
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+pub const FONTSET_START_ADDRESS: u16 = 0x50;
+
+pub const FONTSET_SIZE: u16 = 80;
 
 const FONT_DATA: [u8; FONTSET_SIZE as usize] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -41,13 +182,61 @@ const FONT_DATA: [u8; FONTSET_SIZE as usize] = [
 	  0xF0, 0x80, 0xF0, 0x80, 0x80  // F
 ];
 
+//SUPER-CHIP's 8x10 digits for FX30, right after the small font so both
+//stay clear of the ROM at 0x200: 0x50-0x9F small, 0xA0-0x103 big
+pub const BIG_FONTSET_START_ADDRESS: u16 = FONTSET_START_ADDRESS + FONTSET_SIZE;
+
+pub const BIG_FONTSET_SIZE: u16 = 100;
+
+const BIG_FONT_DATA: [u8; BIG_FONTSET_SIZE as usize] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C  // 9
+];
+
+//Each sprite byte spread over 8 video bytes, 0xFF where a bit is set,
+//for DXYN to XOR a whole row in one go
+const SPRITE_ROW_MASKS: [u64; 256] = sprite_row_masks();
+
+const fn sprite_row_masks() -> [u64; 256] {
+    let mut masks = [0u64; 256];
+    let mut byte = 0;
+    while byte < 256 {
+        let mut bit = 0;
+        while bit < 8 {
+            if byte & (0x80 >> bit) != 0 {
+                masks[byte] |= 0xFF << (8 * (7 - bit));
+            }
+            bit += 1;
+        }
+        byte += 1;
+    }
+
+    masks
+}
+
 #[allow(dead_code)]
 impl Chip8 {
 
+    //Seeded randomly, see set_seed for reproducible runs
     pub fn create() -> Chip8 {
+        Chip8::create_with_memory(MEMORY_SIZE)
+    }
+
+    //With memory_size bytes of memory, XO_CHIP_MEMORY_SIZE for XO-CHIP
+    //ROMs past MAX_ROM_SIZE, see Quirks::memory_size
+    pub fn create_with_memory(memory_size: usize) -> Chip8 {
+        let seed = rand::random();
         let mut chip = Chip8 {
             registers: [0; 16],
-            memory: [0; 4096],
+            memory: vec![0; memory_size].into_boxed_slice(),
             index_register: 0,
             program_counter: START_ADDRESS,
             stack: [0; 16],
@@ -55,229 +244,761 @@ impl Chip8 {
             delay_timer: 0, 
             sound_timer: 0, 
             keypad: [false; 16],
-            video: [0; 64*32],
-            opcode: 0
+            video: vec![0; LORES_WIDTH * LORES_HEIGHT],
+            plane2: vec![0; LORES_WIDTH * LORES_HEIGHT],
+            plane_mask: 1,
+            video_dirty: true,
+            hires: false,
+            exited: false,
+            opcode: 0,
+            quirks: Quirks::default(),
+            low_memory_protection: LowMemoryProtection::Off,
+            protection_warnings: BTreeSet::new(),
+            verify_invariants: false,
+            lenient: false,
+            lenient_warnings: BTreeSet::new(),
+            warn_sprite_overrun: false,
+            sprite_warnings: BTreeSet::new(),
+            rom_end: START_ADDRESS as usize,
+            strict: None,
+            log_stack: false,
+            seed,
+            rng_draws: 0,
+            rng: StdRng::seed_from_u64(seed),
+            random_source: None,
+            rng_log: None,
+            fontset: true,
+            rom_hash: None,
+            rpl_flags: [0; 8],
+            call_frames: Vec::new(),
+            call_frames_reliable: true,
+            collision_count: 0,
+            exec_hooks: Vec::new()
         };
 
-        for i in 0..FONTSET_SIZE {
-            chip.memory[( FONTSET_START_ADDRESS + i ) as usize] = FONT_DATA[i as usize];
-        }
+        chip.set_fontset(true);
 
         chip
     }
 
+    //Back to power-on state, keeping any installed hooks, the RPL flags,
+    //the RNG replay and log and the quirk, protection, invariant, lenient,
+    //sprite overrun, strict, stack logging and fontset settings. The random numbers start
+    //over from the same seed.
+    pub fn reset(&mut self) {
+        self.reset_with_memory(self.memory.len());
+    }
+
+    //reset, with memory_size bytes of memory from now on
+    pub fn reset_with_memory(&mut self, memory_size: usize) {
+        let exec_hooks = std::mem::take(&mut self.exec_hooks);
+        let random_source = self.random_source.take();
+        let rng_log = self.rng_log.take();
+        let quirks = self.quirks;
+        let low_memory_protection = self.low_memory_protection;
+        let verify_invariants = self.verify_invariants;
+        let lenient = self.lenient;
+        let warn_sprite_overrun = self.warn_sprite_overrun;
+        let strict = self.strict.take();
+        let log_stack = self.log_stack;
+        let seed = self.seed;
+        let fontset = self.fontset;
+        let rpl_flags = self.rpl_flags;
+
+        *self = Chip8::create_with_memory(memory_size);
+        self.exec_hooks = exec_hooks;
+        self.random_source = random_source;
+        self.rng_log = rng_log;
+        self.quirks = quirks;
+        self.low_memory_protection = low_memory_protection;
+        self.verify_invariants = verify_invariants;
+        self.lenient = lenient;
+        self.warn_sprite_overrun = warn_sprite_overrun;
+        self.strict = strict;
+        self.log_stack = log_stack;
+        self.set_seed(seed);
+        self.set_fontset(fontset);
+        self.rpl_flags = rpl_flags;
+    }
+
+    //Loads the built-in fonts at 0x50 and 0xA0, or zeroes that area for
+    //ROMs that install their own. Without them FX29 and FX30 point at
+    //blank glyphs.
+    pub fn set_fontset(&mut self, loaded: bool) {
+        self.fontset = loaded;
+
+        let font = &mut self.memory[FONTSET_START_ADDRESS as usize..(BIG_FONTSET_START_ADDRESS + BIG_FONTSET_SIZE) as usize];
+        if loaded {
+            let (small, big) = font.split_at_mut(FONTSET_SIZE as usize);
+            small.copy_from_slice(&FONT_DATA);
+            big.copy_from_slice(&BIG_FONT_DATA);
+        } else {
+            font.fill(0);
+        }
+    }
+
+    pub fn has_fontset(&self) -> bool {
+        self.fontset
+    }
+
+    //Restarts the random numbers from seed, the same seed and input give
+    //the same run
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = StdRng::seed_from_u64(seed);
+        self.rng_draws = 0;
+    }
+
+    //Seeds and then skips draws numbers, to continue a saved sequence
+    pub fn restore_rng(&mut self, seed: u64, draws: u64) {
+        self.set_seed(seed);
+        for _ in 0..draws {
+            self.random_byte();
+        }
+    }
+
+    fn random_byte(&mut self) -> u8 {
+        self.rng_draws += 1;
+        self.rng.gen()
+    }
+
+    //Replaces the seeded numbers for CXKK until source runs out
+    pub fn set_random_source(&mut self, source: Box<dyn RandomSource>) {
+        self.random_source = Some(source);
+    }
+
+    pub fn set_rng_log(&mut self, log: RngLog) {
+        self.rng_log = Some(log);
+    }
+
+    //CXKK's number, from the random source while it lasts. Only the
+    //seeded ones count in rng_draws.
+    fn next_random(&mut self) -> u8 {
+        let byte = match self.random_source.as_mut().and_then(|source| source.next_byte()) {
+            Some(byte) => byte,
+            None => self.random_byte()
+        };
+        if let Some(log) = self.rng_log.as_mut() {
+            log.record(byte);
+        }
+
+        byte
+    }
+
+    pub fn add_exec_hook(&mut self, hook: Rc<RefCell<dyn ExecHook>>) {
+        self.exec_hooks.push(hook);
+    }
+
+    pub fn video_width(&self) -> usize {
+        if self.hires { HIRES_WIDTH } else { LORES_WIDTH }
+    }
+
+    pub fn video_height(&self) -> usize {
+        if self.hires { HIRES_HEIGHT } else { LORES_HEIGHT }
+    }
+
+    //The display, if it changed since the last call, for frontends that
+    //only redraw when something happened. Clears video_dirty.
+    pub fn take_frame_if_dirty(&mut self) -> Option<&[u8]> {
+        if !std::mem::take(&mut self.video_dirty) {
+            return None;
+        }
+
+        Some(&self.video)
+    }
+
+    //Blanks the selected planes at whatever resolution they are in
+    pub fn clear_display(&mut self) {
+        for plane in self.selected_planes() {
+            plane.fill(0);
+        }
+        self.video_dirty = true;
+    }
+
+    //The buffers of the planes in plane_mask, video first
+    fn selected_planes(&mut self) -> Vec<&mut Vec<u8>> {
+        let mask = self.plane_mask;
+        [&mut self.video, &mut self.plane2].into_iter()
+            .enumerate()
+            .filter(|(plane, _)| mask & (1 << plane) != 0)
+            .map(|(_, buffer)| buffer)
+            .collect()
+    }
+
+    //Whether anything was ever drawn into the second plane, which only
+    //XO-CHIP ROMs do
+    pub fn uses_plane2(&self) -> bool {
+        self.plane2.iter().any(|pixel| *pixel != 0)
+    }
+
+    //Return addresses on the hardware stack, outermost first
+    pub fn call_stack(&self) -> Vec<u16> {
+        self.stack[..(self.stack_pointer as usize).min(self.stack.len())].to_vec()
+    }
+
+    //Active calls, outermost first
+    pub fn backtrace(&self) -> &[CallFrame] {
+        &self.call_frames
+    }
+
+    //False once the ROM returned from a subroutine it never called
+    pub fn backtrace_reliable(&self) -> bool {
+        self.call_frames_reliable
+    }
+
+    pub fn call_depth(&self) -> usize {
+        self.call_frames.len()
+    }
+
+    //DXYNs that set VF, once per draw however many pixels it erased
+    pub fn collision_count(&self) -> u64 {
+        self.collision_count
+    }
+
+    //Whether the buzzer sounds right now, for frontends to drive their
+    //audio with instead of reading sound_timer themselves
+    pub fn is_beeping(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    //Recovers the call frames from the hardware stack after the stack
+    //was replaced wholesale (e.g. by loading a save state). Each return
+    //address sits just past its CALL, which holds the target.
+    pub fn rebuild_call_frames(&mut self) {
+        self.call_frames = self.stack[..self.stack_pointer as usize].iter()
+            .map(|return_address| {
+                let call_site = return_address.wrapping_sub(2);
+                let target = match (self.memory.get(call_site as usize), self.memory.get(call_site as usize + 1)) {
+                    (Some(high), Some(low)) => u16::from_be_bytes([*high, *low]) & 0x0FFF,
+                    _ => 0
+                };
+
+                CallFrame { call_site, target }
+            })
+            .collect();
+        self.call_frames_reliable = true;
+    }
+
+    //Address of the instruction currently executing, PC has already moved past it
+    fn instruction_address(&self) -> u16 {
+        self.program_counter.wrapping_sub(2)
+    }
+
+    pub fn read_memory(&self, target: usize) -> Result<u8, Chip8Error> {
+        match self.memory.get(target) {
+            Some(value) => {
+                for hook in &self.exec_hooks {
+                    hook.borrow_mut().on_read(target);
+                }
+                Ok(*value)
+            },
+            None => Err(Chip8Error::MemoryOutOfBounds { address: self.instruction_address(), target })
+        }
+    }
+
+    //Patches memory from outside the program (--poke, the debugger). Unlike
+    //write_memory it ignores low memory protection, which only guards
+    //against the ROM itself.
+    pub fn poke(&mut self, address: u16, bytes: &[u8]) -> Result<(), String> {
+        let start = address as usize;
+        if start + bytes.len() > self.memory.len() {
+            return Err(format!("{} byte(s) at {:#05x} run past the end of memory", bytes.len(), address));
+        }
+
+        self.memory[start..start + bytes.len()].copy_from_slice(bytes);
+        for target in start..start + bytes.len() {
+            for hook in &self.exec_hooks {
+                hook.borrow_mut().on_write(target);
+            }
+        }
+
+        Ok(())
+    }
+
+    //Sets a register from outside the program (the debugger). name is
+    //V0-VF, I, PC, DT or ST in any case. Values that don't fit the
+    //register are refused, as are an I outside memory and a PC with no
+    //room to fetch an instruction.
+    pub fn set_register(&mut self, name: &str, value: u16) -> Result<(), String> {
+        let name = name.to_ascii_uppercase();
+        let byte = || u8::try_from(value).map_err(|_| format!("{} holds a byte, {:#x} doesn't fit", name, value));
+
+        match name.as_str() {
+            "I" if (value as usize) < self.memory.len() => self.index_register = value,
+            "I" => return Err(format!("{:#x} is outside memory", value)),
+            "PC" if (value as usize) + 1 < self.memory.len() => self.program_counter = value,
+            "PC" => return Err(format!("{:#x} leaves no room for an instruction before the end of memory", value)),
+            "DT" => self.delay_timer = byte()?,
+            "ST" => self.sound_timer = byte()?,
+            _ => match name.strip_prefix('V').filter(|digit| digit.len() == 1).and_then(|digit| usize::from_str_radix(digit, 16).ok()) {
+                Some(index) => self.registers[index] = byte()?,
+                None => return Err(format!("Unknown register: {}", name))
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn write_memory(&mut self, target: usize, value: u8) -> Result<(), Chip8Error> {
+        let address = self.instruction_address();
+
+        if target < START_ADDRESS as usize {
+            match self.low_memory_protection {
+                LowMemoryProtection::Off => (),
+                LowMemoryProtection::Warn => {
+                    if self.protection_warnings.insert(address) {
+                        log::warn!("instruction at {:#05x} wrote to reserved memory at {:#05x}", address, target);
+                    }
+                },
+                LowMemoryProtection::Error => return Err(Chip8Error::ProtectedWrite { address, target })
+            }
+        }
+        match self.memory.get_mut(target) {
+            Some(byte) => {
+                *byte = value;
+                for hook in &self.exec_hooks {
+                    hook.borrow_mut().on_write(target);
+                }
+                Ok(())
+            },
+            None => Err(Chip8Error::MemoryOutOfBounds { address, target })
+        }
+    }
+
     //Clear Display
     fn op_00e0(&mut self) {
-        self.video = [0; 64*32];
+        self.clear_display();
+    }
+
+    //LOW (00FE) / HIGH (00FF), SUPER-CHIP
+    //Resizes the display, clearing it unless the scale-on-resize quirk
+    //asks for the old picture to be scaled into the new resolution. Both
+    //planes change, whichever are selected.
+    fn op_00fe_00ff(&mut self, hires: bool) {
+        let (old_width, old_height) = (self.video_width(), self.video_height());
+        self.hires = hires;
+        let (width, height) = (self.video_width(), self.video_height());
+
+        for plane in [&mut self.video, &mut self.plane2] {
+            let mut resized = vec![0; width * height];
+            if self.quirks.scale_on_resize {
+                for y in 0..height {
+                    for x in 0..width {
+                        resized[x + y * width] = plane[x * old_width / width + (y * old_height / height) * old_width];
+                    }
+                }
+            }
+            *plane = resized;
+        }
+        self.video_dirty = true;
+    }
+
+    //SCD n (00CN), SUPER-CHIP, and SCU n (00DN), XO-CHIP. In lores the
+    //half-scroll quirk halves n, an odd n losing its half pixel.
+    fn op_00cn_00dn(&mut self, n: u8, down: bool) {
+        let rows = if self.quirks.half_scroll && !self.hires { n / 2 } else { n } as isize;
+        self.scroll_rows(if down { rows } else { -rows });
+    }
+
+    //SCR (00FB) / SCL (00FC), SUPER-CHIP: 4 pixels right or left, 2 in
+    //lores under the half-scroll quirk
+    fn op_00fb_00fc(&mut self, right: bool) {
+        let columns = if self.quirks.half_scroll && !self.hires { 2 } else { 4 };
+        self.scroll_columns(if right { columns } else { -columns });
+    }
+
+    //Moves the selected planes right columns pixels, left when negative,
+    //a row at a time. Columns that come in are blank.
+    fn scroll_columns(&mut self, columns: isize) {
+        let width = self.video_width();
+        let shift = columns.unsigned_abs().min(width);
+
+        for plane in self.selected_planes() {
+            for row in plane.chunks_mut(width) {
+                if columns > 0 {
+                    row.copy_within(..width - shift, shift);
+                    row[..shift].fill(0);
+                } else {
+                    row.copy_within(shift.., 0);
+                    row[width - shift..].fill(0);
+                }
+            }
+        }
+        self.video_dirty = true;
+    }
+
+    //Moves the selected planes down rows pixels, up when negative. Rows
+    //that come in are blank and the ones pushed off the edge are lost.
+    fn scroll_rows(&mut self, rows: isize) {
+        let width = self.video_width();
+
+        for plane in self.selected_planes() {
+            let length = plane.len();
+            let shift = (rows.unsigned_abs() * width).min(length);
+            if rows > 0 {
+                plane.copy_within(..length - shift, shift);
+                plane[..shift].fill(0);
+            } else {
+                plane.copy_within(shift.., 0);
+                plane[length - shift..].fill(0);
+            }
+        }
+        self.video_dirty = true;
     }
 
     //RET: return from a subroutine
-    fn op_00ee(&mut self) {
+    fn op_00ee(&mut self) -> Result<(), Chip8Error> {
+        if self.call_frames.pop().is_none() {
+            self.call_frames_reliable = false;
+        }
+
+        if self.stack_pointer == 0 {
+            return Err(Chip8Error::StackUnderflow { address: self.instruction_address() });
+        }
+
+        if self.log_stack {
+            let return_address = self.stack[self.stack_pointer as usize - 1];
+            eprintln!("{:#05x}: {:width$}RET to {:#05x}, depth {}", self.instruction_address(), "",
+                return_address, self.stack_pointer - 1, width = (self.stack_pointer as usize - 1) * 2);
+        }
+
         self.stack_pointer -= 1;
         self.program_counter = self.stack[self.stack_pointer as usize];
+
+        Ok(())
     }
 
     //JP addr
-    fn op_1nnn(&mut self) {
-        let address: u16 = self.opcode & 0x0FFF;
+    fn op_1nnn(&mut self, address: u16) {
         self.program_counter = address;
     }
 
     //CALL addr
     //Will return eventually
-    fn op_2nnn(&mut self) {
-        let address: u16 = self.opcode & 0x0FFF;
+    fn op_2nnn(&mut self, address: u16) -> Result<(), Chip8Error> {
+        if self.stack_pointer as usize >= self.stack.len() {
+            return Err(Chip8Error::StackOverflow { address: self.instruction_address() });
+        }
 
         self.stack[self.stack_pointer as usize] = self.program_counter;
         self.stack_pointer += 1;
 
+        self.call_frames.push(CallFrame {
+            call_site: self.instruction_address(),
+            target: address
+        });
+
+        if self.log_stack {
+            eprintln!("{:#05x}: {:width$}CALL {:#05x}, returns to {:#05x}, depth {}", self.instruction_address(), "",
+                address, self.program_counter, self.stack_pointer, width = (self.stack_pointer as usize - 1) * 2);
+        }
+
         self.program_counter = address;
+
+        Ok(())
     }
 
     //SE Vx, byte
     //Skip next instruction if Vx == kk
-    fn op_3xnn(&mut self) {
-        let register_index: usize = ((self.opcode & 0x0F00) >> 8_u16) as usize;
-        let compare_to    : u8 = (self.opcode & 0x00FF) as u8;
-
+    fn op_3xnn(&mut self, register_index: usize, compare_to: u8) {
         if self.registers[register_index] == compare_to {
-            self.program_counter += 2;
+            self.skip_next();
         }
     }
 
     //SNE Vx, byte
     //Skip next instruction if Vx != kk
-    fn op_4xnn(&mut self) {
-        let register_index: usize = ((self.opcode & 0x0F00) >> 8_u16) as usize;
-        let compare_to    : u8    = (self.opcode & 0x00FF) as u8;
-
+    fn op_4xnn(&mut self, register_index: usize, compare_to: u8) {
         if self.registers[register_index] != compare_to {
-            self.program_counter += 2;
+            self.skip_next();
         }
     }
 
     //SE Vx, Vy
     //Skip if equal
-    fn op_5xy0(&mut self) {
-        let register_0: usize = ((self.opcode & 0x0F00) >> 8_u16) as usize;
-        let register_1: usize = ((self.opcode & 0x00F0) >> 4_u16) as usize;
-
+    fn op_5xy0(&mut self, register_0: usize, register_1: usize) {
         if self.registers[register_0] == self.registers[register_1] {
-            self.program_counter += 2;
+            self.skip_next();
         }
     }
 
-    //SNE Vx, Vy
-    //Skip not equal
-    fn op_6xnn(&mut self) {
-        let register_index: usize = ((self.opcode & 0x0F00) >> 8_u16) as usize;
-        let set_to        : u8   = (self.opcode & 0x00FF) as u8;
+    //SAVE Vx, Vy and LOAD Vx, Vy (XO-CHIP 5XY2/5XY3). Unlike FX55/FX65
+    //I stays put whatever the increment-i quirk says.
+    fn op_5xy2_5xy3(&mut self, register_0: usize, register_1: usize, save: bool) -> Result<(), Chip8Error> {
+        for (offset, register) in register_range(register_0, register_1).into_iter().enumerate() {
+            let address = self.index_register as usize + offset;
+            if save {
+                self.write_memory(address, self.registers[register])?;
+            } else {
+                self.registers[register] = self.read_memory(address)?;
+            }
+        }
 
-        self.registers[register_index] = set_to;
+        Ok(())
     }
 
-    fn op_7xnn(&mut self) {
-        let register_index: usize = ((self.opcode & 0x0F00) >> 8_u16) as usize;
-        let add           : u16   = self.opcode & 0x00FF;
+    //Steps over the next instruction, all four bytes of an F000 NNNN when
+    //XO-CHIP opcodes are on
+    fn skip_next(&mut self) {
+        let long = self.quirks.xo_chip_opcodes && matches!(self.fetch(), Ok(0xF000));
+        self.program_counter = self.program_counter.wrapping_add(if long { 4 } else { 2 });
+    }
 
-        let sum: u16 = self.registers[register_index] as u16 + add;
+    //LD Vx, byte
+    fn op_6xnn(&mut self, register_index: usize, set_to: u8) {
+        self.registers[register_index] = set_to;
+    }
+
+    //ADD Vx, byte
+    fn op_7xnn(&mut self, register_index: usize, add: u8) {
+        let sum: u16 = self.registers[register_index] as u16 + add as u16;
 
         self.registers[register_index] = (sum & 0xFF) as u8;
     }
 
-    fn op_8xyk(&mut self, k: u32) {
-        let a: usize = ((self.opcode & 0x0F00) >> 8u16) as usize;
-        let b: usize = ((self.opcode & 0x00F0) >> 4u16) as usize;
+    //LD Vx, Vy
+    fn op_8xy0(&mut self, a: usize, b: usize) {
+        self.registers[a] = self.registers[b];
+    }
 
-        match k {
-            0 => self.registers[a]  = self.registers[b],
-            1 => self.registers[a] |= self.registers[b],
-            2 => self.registers[a] &= self.registers[b],
-            3 => self.registers[a] ^= self.registers[b],
-            4 => {
-                let sum: u16 = self.registers[a] as u16 + self.registers[b] as u16;
-                self.registers[0xF] = if sum > 0xFF { 1 } else { 0 };
+    //OR Vx, Vy
+    fn op_8xy1(&mut self, a: usize, b: usize) {
+        self.registers[a] |= self.registers[b];
 
-                self.registers[a] = (sum & 0xFF) as u8;
-            },
-            5 => {
-                self.registers[0xF] = if self.registers[a] >= self.registers[b] { 1 } else { 0 };
-                self.registers[a] = self.registers[a].overflowing_sub(self.registers[b]).0;
-            },
-            6 => {
-                self.registers[0xF] = self.registers[a] & 0x1;
-                self.registers[a] >>= 1;
-            },
-            7 => {
-                self.registers[0xF] = if self.registers[b] > self.registers[a] { 1 } else { 0 };
-                self.registers[a] = self.registers[b].overflowing_sub(self.registers[a]).0;
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
+        }
+    }
 
-            },
-            0xE => {
-                self.registers[0xF] = (self.registers[a] & 0x80) >> 7;
-                self.registers[a] <<= 1;
-            },
-            _ => {
-                panic!("Invalid opcode: {:#04x}", self.opcode);
-            }
+    //AND Vx, Vy
+    fn op_8xy2(&mut self, a: usize, b: usize) {
+        self.registers[a] &= self.registers[b];
+
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
         }
     }
 
-    //SNE Vx, Vy
-    fn op_9xy0(&mut self) {
-        let a: usize = ((self.opcode & 0x0F00) >> 8u16) as usize;
-        let b: usize = ((self.opcode & 0x00F0) >> 4u16) as usize;
+    //XOR Vx, Vy
+    fn op_8xy3(&mut self, a: usize, b: usize) {
+        self.registers[a] ^= self.registers[b];
+
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
+        }
+    }
+
+    //ADD Vx, Vy
+    //The flag is written last in 8XY4-8XYE, so with VF as Vx it ends up
+    //holding the flag rather than the result, like the VIP interpreter
+    fn op_8xy4(&mut self, a: usize, b: usize) {
+        let sum: u16 = self.registers[a] as u16 + self.registers[b] as u16;
+
+        self.registers[a] = (sum & 0xFF) as u8;
+        self.registers[0xF] = if sum > 0xFF { 1 } else { 0 };
+    }
+
+    //SUB Vx, Vy
+    fn op_8xy5(&mut self, a: usize, b: usize) {
+        let flag = if self.registers[a] >= self.registers[b] { 1 } else { 0 };
+        self.registers[a] = self.registers[a].wrapping_sub(self.registers[b]);
+        self.registers[0xF] = flag;
+    }
 
+    //SHR Vx {, Vy}
+    fn op_8xy6(&mut self, a: usize, b: usize) {
+        if self.quirks.shift_vy {
+            self.registers[a] = self.registers[b];
+        }
+
+        let flag = self.registers[a] & 0x1;
+        self.registers[a] >>= 1;
+        self.registers[0xF] = flag;
+    }
+
+    //SUBN Vx, Vy
+    fn op_8xy7(&mut self, a: usize, b: usize) {
+        let flag = if self.registers[b] > self.registers[a] { 1 } else { 0 };
+        self.registers[a] = self.registers[b].wrapping_sub(self.registers[a]);
+        self.registers[0xF] = flag;
+    }
+
+    //SHL Vx {, Vy}
+    fn op_8xye(&mut self, a: usize, b: usize) {
+        if self.quirks.shift_vy {
+            self.registers[a] = self.registers[b];
+        }
+
+        let flag = (self.registers[a] & 0x80) >> 7;
+        self.registers[a] <<= 1;
+        self.registers[0xF] = flag;
+    }
+
+    //SNE Vx, Vy
+    fn op_9xy0(&mut self, a: usize, b: usize) {
         if self.registers[a] != self.registers[b] {
-            self.program_counter += 2;
+            self.skip_next();
         }
     }
 
     //LD I, addr
-    fn op_annn(&mut self) {
-        let address: u16 = self.opcode & 0x0FFF;
+    fn op_annn(&mut self, address: u16) {
         self.index_register = address;
     }
 
     //JP V0, addr
-    fn op_bnnn(&mut self) {
-        let address: u16 = self.opcode & 0x0FFF;
-        self.program_counter = self.registers[0] as u16 + address;
+    //or JP Vx, addr with the jump-vx quirk, x being the top nibble of addr
+    fn op_bnnn(&mut self, address: u16) {
+        let register_index: usize = if self.quirks.jump_vx { (address >> 8) as usize } else { 0 };
+        self.program_counter = self.registers[register_index] as u16 + address;
     }
 
     //RND Vx, byte
-    fn op_cxkk(&mut self) {
-        let register_index = ((self.opcode & 0x0F00) >> 8) as usize;
-        let byte: u8 = (self.opcode & 0x00FF) as u8;
-
-        self.registers[register_index] = rand::random::<u8>() & byte;
+    fn op_cxkk(&mut self, register_index: usize, byte: u8) {
+        self.registers[register_index] = self.next_random() & byte;
     }
 
-    fn op_dxyn(&mut self) {
-        let a: usize = ((self.opcode & 0x0F00) >> 8) as usize;
-        let b: usize = ((self.opcode & 0x00F0) >> 4) as usize;
-        let height: u8 = (self.opcode & 0x000F) as u8;
-
-        const VIDEO_WIDTH: u16 = 64;
-        const VIDEO_HEIGHT: u16 = 32;
+    //DRW Vx, Vy, n. DXY0 is SUPER-CHIP's 16x16 sprite, see sprite_size.
+    //With both XO-CHIP planes selected the sprite is drawn into each, the
+    //second plane's rows following the first's in memory.
+    fn op_dxyn(&mut self, a: usize, b: usize, height: u8) -> Result<(), Chip8Error> {
+        let video_width = self.video_width() as u16;
+        let video_height = self.video_height() as u16;
 
-        let x: u8 = self.registers[a] % 64;
-        let y: u8 = self.registers[b] % 32; 
+        let x: u16 = self.registers[a] as u16 % video_width;
+        let y: u16 = self.registers[b] as u16 % video_height;
+        let (rows, bytes_per_row) = self.sprite_size(height);
 
         self.registers[0xF] = 0;
 
-        for row in 0..height {
-            let i: usize = ( self.index_register + (row as u16) ) as usize;
-            let sprite: u8 = self.memory[i];
+        let mask = self.plane_mask;
+        let mut collided = false;
+        let mut start = self.index_register as usize % self.memory.len();
+        if self.warn_sprite_overrun {
+            let planes = mask.count_ones() as usize;
+            self.check_sprite_overrun(start, start + planes * rows * bytes_per_row);
+        }
 
-            for col in 0..8_u8 {
-                let pixel: u8 = sprite & (0x80 >> col);
-                let ypos: u16 = ((y as u16) + (row as u16)) % VIDEO_HEIGHT;
-                let xpos: u16 = ((x as u16) + (col as u16)) % VIDEO_WIDTH;
+        //Sprites running off the end of memory wrap around to 0, like
+        //I's arithmetic does, rather than stopping the machine
+        for plane in (0..2).filter(|plane| mask & (1 << plane) != 0) {
+            for row in 0..rows as u16 {
+                let i: usize = start + row as usize * bytes_per_row;
+                let mut sprite = [0u8; 2];
+                for (byte, address) in sprite.iter_mut().zip(i..i + bytes_per_row) {
+                    *byte = self.read_memory(address % self.memory.len())?;
+                }
 
-                let screen_pixel: &mut u8 = &mut self.video[( xpos + ypos * 64 ) as usize];
+                if self.quirks.clip_y && y + row >= video_height {
+                    break;
+                }
+                log::trace!("sprite row {:02x?} from {:#05x} at ({}, {})", &sprite[..bytes_per_row], i, x, y + row);
 
-                //println!("sprite_byte: {:#04x}, pixel: {}", sprite, pixel);
-                if pixel != 0x0 {
-                    if *screen_pixel == 0xFF {
+                let ypos: u16 = (y + row) % video_height;
+                let line_start = (ypos * video_width) as usize;
+                for (byte, sprite) in sprite[..bytes_per_row].iter().enumerate() {
+                    if self.blit_byte(plane, line_start, x + 8 * byte as u16, *sprite) {
                         self.registers[0xF] = 1;
+                        collided = true;
                     }
+                }
+            }
+            start += rows * bytes_per_row;
+        }
+        if collided {
+            self.collision_count += 1;
+        }
 
-                    *screen_pixel ^= 0xFF;
+        Ok(())
+    }
 
-                }
+    //For --warn-sprite-overrun, a draw from the bytes start..end
+    fn check_sprite_overrun(&mut self, start: usize, end: usize) {
+        let address = self.instruction_address();
+        if end <= self.rom_end || !self.sprite_warnings.insert(address) {
+            return;
+        }
+
+        let wraps = if end > self.memory.len() { ", wrapping around the end of memory" } else { "" };
+        log::warn!("{:04X} at {:#05x} draws a sprite from {:#05x}-{:#05x}, past the end of the ROM at {:#05x}{}",
+            self.opcode, address, start, (end - 1) % self.memory.len(), self.rom_end, wraps);
+    }
+
+    //Rows and bytes per row a DXYN sprite takes. DXY0 is 16 rows of two
+    //bytes, 16x16, or of one byte in lores under the dxy0-8x16 quirk.
+    pub fn sprite_size(&self, height: u8) -> (usize, usize) {
+        match height {
+            0 if self.quirks.dxy0_8x16 && !self.hires => (16, 1),
+            0 => (16, 2),
+            height => (height as usize, 1)
+        }
+    }
+
+    //XORs the 8 pixels of sprite into the row of plane (0 for video, 1
+    //for plane2) starting at line_start, from column x on, which may be
+    //past the right edge. Returns whether a lit pixel was turned off.
+    fn blit_byte(&mut self, plane: usize, line_start: usize, x: u16, sprite: u8) -> bool {
+        let video_width = self.video_width() as u16;
+        let buffer = if plane == 0 { &mut self.video } else { &mut self.plane2 };
+        let line = &mut buffer[line_start..line_start + video_width as usize];
+        let mut collided = false;
+
+        //Bytes that land on screen whole are XORed as one word, a pixel
+        //per byte, with a single collision test
+        if x + 8 <= video_width {
+            let pixels = &mut line[x as usize..x as usize + 8];
+            let mask = SPRITE_ROW_MASKS[sprite as usize];
+            let screen = u64::from_be_bytes(pixels.try_into().unwrap_or_default());
+
+            pixels.copy_from_slice(&(screen ^ mask).to_be_bytes());
+            self.video_dirty |= sprite != 0;
+            return screen & mask != 0;
+        }
+
+        //Bytes crossing the right edge clip or wrap pixel by pixel
+        for col in 0..8_u16 {
+            if self.quirks.clip_x && x + col >= video_width {
+                break;
+            }
+
+            if sprite & (0x80 >> col) != 0 {
+                let screen_pixel: &mut u8 = &mut line[((x + col) % video_width) as usize];
+                collided |= *screen_pixel == 0xFF;
+                *screen_pixel ^= 0xFF;
+                self.video_dirty = true;
             }
         }
 
-        self.registers[0xF] = 0;
-        
+        collided
     }
 
-    fn op_ex9e(&mut self) {
-        let register_index: usize = ((self.opcode & 0x0F00) >> 8) as usize;
-        let key: usize = self.registers[register_index] as usize;
+    fn op_ex9e(&mut self, register_index: usize) {
+        if self.key_down(register_index) {
+            self.skip_next();
+        }
+    }
 
-        if self.keypad[key] {
-            self.program_counter += 2;
+    fn op_exa1(&mut self, register_index: usize) {
+        if !self.key_down(register_index) {
+            self.skip_next();
         }
     }
 
-    fn op_exa1(&mut self) {
-        let register_index: usize = ((self.opcode & 0x0F00) >> 8) as usize;
-        let key: usize = self.registers[register_index] as usize;
+    //Vx past 0xF is a key that is never down, or wraps under key-wrap
+    fn key_down(&self, register_index: usize) -> bool {
+        let key = self.registers[register_index] as usize;
 
-        if !self.keypad[key] {
-            self.program_counter += 2;
+        match self.quirks.key_wrap {
+            true => self.keypad[key & 0x0F],
+            false => self.keypad.get(key).copied().unwrap_or(false)
         }
     }
 
-    fn op_fx07(&mut self) {
-        let register_index: usize = ((self.opcode & 0x0F00) >> 8) as usize;
+    fn op_fx07(&mut self, register_index: usize) {
         self.registers[register_index] = self.delay_timer;
     }
 
-    fn op_fx0a(&mut self) {
-        let register_index: usize = ((self.opcode & 0x0F00) >> 8) as usize;
+    fn op_fx0a(&mut self, register_index: usize) {
         let mut flag: bool = false;
 
         for (i, key) in self.keypad.iter().enumerate() {
@@ -288,155 +1009,1408 @@ impl Chip8 {
         }
 
         if !flag {
-            self.program_counter -= 2;
+            self.program_counter = self.program_counter.wrapping_sub(2);
         }
     }
 
-    fn op_fx15(&mut self) {
-        let register_index: usize = ((self.opcode & 0x0F00) >> 8) as usize;
+    fn op_fx15(&mut self, register_index: usize) {
         self.delay_timer = self.registers[register_index];
     }
 
-    fn op_fx18(&mut self) {
-        let register_index: usize = ((self.opcode & 0x0F00) >> 8) as usize;
+    fn op_fx18(&mut self, register_index: usize) {
         self.sound_timer = self.registers[register_index];
     }
 
-    fn op_fx1e(&mut self) {
-        let register_index: usize = ((self.opcode & 0x0F00) >> 8) as usize;
-        self.index_register += self.registers[register_index] as u16;
+    fn op_fx1e(&mut self, register_index: usize) {
+        self.index_register = self.index_register.wrapping_add(self.registers[register_index] as u16);
     }
 
-    fn op_fx29(&mut self) {
-        let register_index: usize = ((self.opcode & 0x0F00) >> 8) as usize;
-        let digit = self.registers[register_index];
+    //LD F, Vx. Only a glyph when the fontset is loaded or the ROM put
+    //its own font at 0x50. Like the VIP only the low nibble counts.
+    fn op_fx29(&mut self, register_index: usize) {
+        let digit = self.registers[register_index] & 0x0F;
 
-        self.index_register = FONTSET_START_ADDRESS + (5 * digit) as u16;
+        self.index_register = FONTSET_START_ADDRESS + 5 * digit as u16;
     }
 
-    fn op_fx33(&mut self) {
-        let register_index: usize = ((self.opcode & 0x0F00) >> 8) as usize;
+    //LD HF, Vx. There are only the digits 0-9, A-F land on the blank
+    //memory past them.
+    fn op_fx30(&mut self, register_index: usize) {
+        let digit = self.registers[register_index] & 0x0F;
+
+        self.index_register = BIG_FONTSET_START_ADDRESS + (10 * digit) as u16;
+    }
+
+    fn op_fx33(&mut self, register_index: usize) -> Result<(), Chip8Error> {
         let value: f64 = self.registers[register_index] as f64;
 
         let hundreds: u8 = (value / 100.0).floor() as u8;
         let tens    : u8 = ((value / 10.0) % 10.0).floor() as u8;
         let ones    : u8 = (value % 10.0) as u8;
 
-        self.memory[self.index_register as usize] = hundreds;
-        self.memory[( self.index_register+1 ) as usize] = tens;
-        self.memory[( self.index_register+2 ) as usize] = ones;
-
+        let index = self.index_register as usize;
+        self.write_memory(index, hundreds)?;
+        self.write_memory(index + 1, tens)?;
+        self.write_memory(index + 2, ones)
     }
 
-    fn op_fx55(&mut self) {
-        let register_index: usize = ((self.opcode & 0x0F00) >> 8) as usize;
-        
+    fn op_fx55(&mut self, register_index: usize) -> Result<(), Chip8Error> {
         for i in 0..=register_index {
-            self.memory[( self.index_register + i as u16) as usize] = self.registers[i];
+            self.write_memory(self.index_register as usize + i, self.registers[i])?;
         }
+
+        self.advance_index(register_index);
+        Ok(())
     }
 
-    fn op_fx65(&mut self) {
-        let register_index: usize = ((self.opcode & 0x0F00) >> 8) as usize;
-        
+    fn op_fx65(&mut self, register_index: usize) -> Result<(), Chip8Error> {
         for i in 0..=register_index {
-            self.registers[i] = self.memory[(self.index_register + i as u16) as usize];
+            self.registers[i] = self.read_memory(self.index_register as usize + i)?;
+        }
+
+        self.advance_index(register_index);
+        Ok(())
+    }
+
+    //Where FX55/FX65 leave I: past the last register with increment-i, on
+    //it with increment-i-x (CHIP-48), where it was otherwise
+    fn advance_index(&mut self, register_index: usize) {
+        if self.quirks.increment_i_x {
+            self.index_register = self.index_register.wrapping_add(register_index as u16);
+        } else if self.quirks.increment_i {
+            self.index_register = self.index_register.wrapping_add(register_index as u16 + 1);
+        }
+    }
+
+    //LD R, Vx. Decoding keeps x to 7.
+    fn op_fx75(&mut self, register_index: usize) {
+        self.rpl_flags[..=register_index].copy_from_slice(&self.registers[..=register_index]);
+    }
+
+    //LD Vx, R
+    fn op_fx85(&mut self, register_index: usize) {
+        self.registers[..=register_index].copy_from_slice(&self.rpl_flags[..=register_index]);
+    }
+
+    //LD I, LONG addr (XO-CHIP F000 NNNN)
+    fn op_f000(&mut self, address: u16) {
+        self.index_register = address;
+    }
+
+    //PLANE n (XO-CHIP FN01)
+    fn op_fn01(&mut self, mask: u8) {
+        self.plane_mask = mask;
+    }
+
+    //An opcode execute() has nothing for. Extension opcodes are skipped
+    //with a warning under --lenient, anything else stops the machine.
+    fn op_unimplemented(&mut self, opcode: u16) -> Result<(), Chip8Error> {
+        let address = self.instruction_address();
+        let extension = match (schip_pattern(opcode), xo_chip_pattern(opcode)) {
+            (Some(pattern), _) => Some(("SUPER-CHIP", pattern)),
+            (_, Some(pattern)) => Some(("XO-CHIP", pattern)),
+            _ => None
+        };
+
+        match extension {
+            Some((platform, pattern)) if self.lenient => {
+                if self.lenient_warnings.insert(address) {
+                    log::warn!("skipping {} instruction {:04X} ({}) at {:#05x}, this ROM looks like it needs a {} interpreter",
+                        platform, opcode, pattern, address, platform);
+                }
+                //F000's address isn't an instruction either
+                if opcode == 0xF000 {
+                    self.program_counter = self.program_counter.wrapping_add(2);
+                }
+                Ok(())
+            },
+            _ => Err(Chip8Error::InvalidOpcode { address, opcode })
         }
     }
-    
-    pub fn run(&mut self) {
+
+    pub fn fetch(&self) -> Result<u16, Chip8Error> {
         let program_counter = self.program_counter as usize;
-        let first_part : u16 = ( self.memory[program_counter] as u16 ) << 8_u16;
-        let second_part: u16 = ( self.memory[program_counter+1] ) as u16;
-        self.opcode = first_part | second_part;
 
-        self.program_counter += 2;
-        //println!("Decoding opcode: {:#04x} at {:#04x}", self.opcode, self.program_counter);
+        match (self.memory.get(program_counter), self.memory.get(program_counter+1)) {
+            (Some(first_part), Some(second_part)) => {
+                Ok(( (*first_part as u16) << 8_u16 ) | *second_part as u16)
+            },
+            (first_part, _) => Err(Chip8Error::MemoryOutOfBounds {
+                address: self.program_counter,
+                target: if first_part.is_none() { program_counter } else { program_counter+1 }
+            })
+        }
+    }
+
+    pub fn execute(&mut self, instruction: Instruction) -> Result<(), Chip8Error> {
+        match instruction {
+            Instruction::Cls => self.op_00e0(),
+            Instruction::Ret => return self.op_00ee(),
+            Instruction::Lores => self.op_00fe_00ff(false),
+            Instruction::Hires => self.op_00fe_00ff(true),
+            Instruction::ScrollDown { n } => self.op_00cn_00dn(n, true),
+            Instruction::ScrollUp { n } if self.quirks.xo_chip_opcodes => self.op_00cn_00dn(n, false),
+            Instruction::ScrollUp { n } => return self.op_unimplemented(0x00D0 | n as u16),
+            Instruction::ScrollRight => self.op_00fb_00fc(true),
+            Instruction::ScrollLeft => self.op_00fb_00fc(false),
+            Instruction::Exit => self.exited = true,
+            Instruction::Jump { address } => self.op_1nnn(address),
+            Instruction::Call { address } => return self.op_2nnn(address),
+            Instruction::SkipEqByte { x, byte } => self.op_3xnn(x, byte),
+            Instruction::SkipNeByte { x, byte } => self.op_4xnn(x, byte),
+            Instruction::SkipEqReg { x, y } => self.op_5xy0(x, y),
+            Instruction::SaveRange { x, y } if self.quirks.xo_chip_opcodes => return self.op_5xy2_5xy3(x, y, true),
+            Instruction::SaveRange { x, y } => return self.op_unimplemented(0x5002 | (x as u16) << 8 | (y as u16) << 4),
+            Instruction::LoadRange { x, y } if self.quirks.xo_chip_opcodes => return self.op_5xy2_5xy3(x, y, false),
+            Instruction::LoadRange { x, y } => return self.op_unimplemented(0x5003 | (x as u16) << 8 | (y as u16) << 4),
+            Instruction::LoadByte { x, byte } => self.op_6xnn(x, byte),
+            Instruction::AddByte { x, byte } => self.op_7xnn(x, byte),
+            Instruction::LoadReg { x, y } => self.op_8xy0(x, y),
+            Instruction::Or { x, y } => self.op_8xy1(x, y),
+            Instruction::And { x, y } => self.op_8xy2(x, y),
+            Instruction::Xor { x, y } => self.op_8xy3(x, y),
+            Instruction::AddReg { x, y } => self.op_8xy4(x, y),
+            Instruction::Sub { x, y } => self.op_8xy5(x, y),
+            Instruction::ShiftRight { x, y } => self.op_8xy6(x, y),
+            Instruction::SubN { x, y } => self.op_8xy7(x, y),
+            Instruction::ShiftLeft { x, y } => self.op_8xye(x, y),
+            Instruction::SkipNeReg { x, y } => self.op_9xy0(x, y),
+            Instruction::LoadIndex { address } => self.op_annn(address),
+            Instruction::JumpV0 { address } => self.op_bnnn(address),
+            Instruction::Random { x, byte } => self.op_cxkk(x, byte),
+            Instruction::Draw { x, y, height } => return self.op_dxyn(x, y, height),
+            Instruction::SkipKeyPressed { x } => self.op_ex9e(x),
+            Instruction::SkipKeyNotPressed { x } => self.op_exa1(x),
+            Instruction::LoadDelay { x } => self.op_fx07(x),
+            Instruction::WaitKey { x } => self.op_fx0a(x),
+            Instruction::SetDelay { x } => self.op_fx15(x),
+            Instruction::SetSound { x } => self.op_fx18(x),
+            Instruction::AddIndex { x } => self.op_fx1e(x),
+            Instruction::LoadFont { x } => self.op_fx29(x),
+            Instruction::LoadBigFont { x } => self.op_fx30(x),
+            Instruction::StoreBcd { x } => return self.op_fx33(x),
+            Instruction::StoreRegisters { x } => return self.op_fx55(x),
+            Instruction::LoadRegisters { x } => return self.op_fx65(x),
+            Instruction::StoreFlags { x } => self.op_fx75(x),
+            Instruction::LoadFlags { x } => self.op_fx85(x),
+            Instruction::LoadLongIndex { address } => self.op_f000(address),
+            Instruction::Plane { n } if self.quirks.xo_chip_opcodes => self.op_fn01(n),
+            Instruction::Plane { n } => return self.op_unimplemented(0xF001 | (n as u16) << 8),
+            Instruction::Sys { address: opcode } | Instruction::Invalid { opcode } => return self.op_unimplemented(opcode)
+        }
+
+        Ok(())
+    }
+
+    //One instruction followed by a timer tick
+    pub fn run(&mut self) -> Result<(), Chip8Error> {
+        self.step()?;
+        self.tick_timers();
+
+        Ok(())
+    }
+
+    //Executes one instruction without touching the timers and returns
+    //what was executed. After EXIT it does nothing and returns Exit again.
+    pub fn step(&mut self) -> Result<Instruction, Chip8Error> {
+        if self.exited {
+            return Ok(Instruction::Exit);
+        }
+
+        //Fetch. XO-CHIP's F000 is the one four byte instruction, its
+        //address is the word after it.
+        self.opcode = self.fetch()?;
+        let address = self.program_counter;
+        self.program_counter = self.program_counter.wrapping_add(2);
+        let long_operand = (self.opcode == 0xF000 && self.quirks.xo_chip_opcodes).then(|| self.fetch());
+
+        for hook in &self.exec_hooks {
+            match long_operand {
+                Some(Ok(operand)) => hook.borrow_mut().on_exec_long(address, self.opcode, operand),
+                _ => hook.borrow_mut().on_exec(address, self.opcode)
+            }
+        }
 
         //Decode
-        let cmd: u8 = ((self.opcode & 0xF000) >> 12) as u8;
-        //println!("CMD: {:#02x}", cmd);
+        let instruction = match long_operand {
+            Some(operand) => {
+                let address = operand?;
+                self.program_counter = self.program_counter.wrapping_add(2);
+                Instruction::LoadLongIndex { address }
+            },
+            None => decode(self.opcode)
+        };
+        log::trace!("{:#05x}: {:04X} {:?}", address, self.opcode, instruction);
+
+        if let Some(mut strict) = self.strict.take() {
+            strict.check(self, address, instruction);
+            self.strict = Some(strict);
+        }
 
         //Execute
-        match cmd {
-            0x0 => {
-                let operand: u16 = self.opcode & 0x0FFF;
-                match operand {
-                    0x0E0 => self.op_00e0(),
-                    0x0EE => self.op_00ee(),
-                    _ => panic!("Invalid opcode: {:#04x}", self.opcode)
-                }
+        let video_before = (self.verify_invariants && matches!(instruction, Instruction::Draw { .. })).then(|| [self.video.as_slice(), &self.plane2].concat());
+        self.execute(instruction)?;
+
+        if self.verify_invariants {
+            self.check_invariants(address, instruction, video_before.as_ref())?;
+        }
+
+        Ok(instruction)
+    }
+
+    pub fn tick_timers(&mut self) {
+        if self.delay_timer > 0 { self.delay_timer -= 1; }
+        if self.sound_timer > 0 { self.sound_timer -= 1; }
+    }
+
+    //Returns the ROM's size in bytes, see read_rom for the paths taken
+    pub fn load_rom(&mut self, path: &str) -> Result<usize, String> {
+        let rom = read_rom(path)?;
+        log::debug!("Read {} byte(s) from {}", rom.len(), path);
+        self.load_bytes(&rom).map_err(|e| format!("{} is {}", path, e))?;
+
+        Ok(rom.len())
+    }
+
+    //Bytes from 0x200 to the end of memory
+    pub fn max_rom_size(&self) -> usize {
+        self.memory.len() - START_ADDRESS as usize
+    }
+
+    //Copies a ROM already in memory to 0x200. Fails, leaving memory alone,
+    //when it doesn't fit in max_rom_size.
+    pub fn load_bytes(&mut self, rom: &[u8]) -> Result<(), String> {
+        if rom.len() > self.max_rom_size() {
+            let hint = if rom.len() <= MAX_XO_CHIP_ROM_SIZE { ", an XO-CHIP ROM this big needs --quirks xo-chip" } else { "" };
+            return Err(format!("{} bytes, more than the {} that fit in memory{}", rom.len(), self.max_rom_size(), hint));
+        }
+
+        let start = START_ADDRESS as usize;
+        self.memory[start..start + rom.len()].copy_from_slice(rom);
+        self.rom_end = start + rom.len();
+        self.rom_hash = Some(sha1_smol::Sha1::from(rom).digest().bytes());
+
+        Ok(())
+    }
+
+}
+
+//A ROM file, or with the zip feature a ROM inside an archive: the only
+//.ch8/.c8 in collection.zip, or the one named by collection.zip#GAME.ch8.
+//With the octo feature, .8o source is assembled first, see octo.rs.
+//A .gif is taken for an Octocart and its program loaded, see octocart.rs.
+//With the http feature, an http(s):// URL is downloaded, see download.rs.
+//Fails when the ROM doesn't fit in even XO-CHIP's memory.
+pub fn read_rom(path: &str) -> Result<Vec<u8>, String> {
+    if is_url(path) {
+        return fetch_rom(path);
+    }
+    if is_octo_source(path) || is_octocart(path) {
+        let rom = if is_octocart(path) { Octocart::load(path)?.rom(path)? } else { assemble_octo(path)? };
+        if rom.len() > MAX_XO_CHIP_ROM_SIZE {
+            return Err(format!("{} assembles to {} bytes, more than the {} that fit in memory", path, rom.len(), MAX_XO_CHIP_ROM_SIZE));
+        }
+        return Ok(rom);
+    }
+
+    let (file, entry) = match path.split_once('#') {
+        Some((archive, entry)) if is_zip(archive) => (archive, Some(entry)),
+        _ => (path, None)
+    };
+    let bytes = fs::read(file).map_err(|e| format!("Couldn't read {}: {}", file, e))?;
+
+    let rom = if is_zip(file) {
+        extract_rom(file, &bytes, entry)?
+    } else {
+        bytes
+    };
+    if rom.len() > MAX_XO_CHIP_ROM_SIZE {
+        return Err(format!("{} is {} bytes, more than the {} that fit in memory", path, rom.len(), MAX_XO_CHIP_ROM_SIZE));
+    }
+
+    Ok(rom)
+}
+
+fn is_zip(path: &str) -> bool {
+    path.to_ascii_lowercase().ends_with(".zip")
+}
+
+fn is_url(path: &str) -> bool {
+    let path = path.to_ascii_lowercase();
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+#[cfg(feature = "http")]
+fn fetch_rom(url: &str) -> Result<Vec<u8>, String> {
+    download::fetch_rom(url)
+}
+
+#[cfg(not(feature = "http"))]
+fn fetch_rom(url: &str) -> Result<Vec<u8>, String> {
+    Err(format!("{}: this build can't download ROMs, rebuild with --features http", url))
+}
+
+fn is_octo_source(path: &str) -> bool {
+    path.to_ascii_lowercase().ends_with(".8o")
+}
+
+#[cfg(feature = "octo")]
+fn assemble_octo(path: &str) -> Result<Vec<u8>, String> {
+    octo::assemble(path)
+}
+
+#[cfg(not(feature = "octo"))]
+fn assemble_octo(path: &str) -> Result<Vec<u8>, String> {
+    Err(format!("{}: this build can't assemble Octo source, rebuild with --features octo", path))
+}
+
+#[cfg(feature = "zip")]
+fn extract_rom(file: &str, archive: &[u8], entry: Option<&str>) -> Result<Vec<u8>, String> {
+    zip::extract_rom(archive, entry).map_err(|e| format!("{}: {}", file, e))
+}
+
+#[cfg(not(feature = "zip"))]
+fn extract_rom(file: &str, _archive: &[u8], _entry: Option<&str>) -> Result<Vec<u8>, String> {
+    Err(format!("{}: this build can't read zip archives, rebuild with --features zip", file))
+}
+
+impl fmt::Display for Chip8Error {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Chip8Error::InvalidOpcode { address, opcode } => {
+                write!(f, "Invalid opcode {:#06x} at {:#05x}", opcode, address)
             },
-            0x1 => self.op_1nnn(),
-            0x2 => self.op_2nnn(),
-            0x3 => self.op_3xnn(),
-            0x4 => self.op_4xnn(),
-            0x5 => self.op_5xy0(),
-            0x6 => self.op_6xnn(),
-            0x7 => self.op_7xnn(),
-            0x8 => {
-                let k = self.opcode & 0x000F;
-                self.op_8xyk(k.into());
+            Chip8Error::StackOverflow { address } => {
+                write!(f, "Stack overflow: CALL with a full stack at {:#05x}", address)
             },
-            0x9 => self.op_9xy0(),
-            0xA => self.op_annn(),
-            0xB => self.op_bnnn(),
-            0xC => self.op_cxkk(),
-            0xD => self.op_dxyn(),
-            0xE => {
-                let identity: u16 = self.opcode & 0x00FF;
-                match identity {
-                    0x9E => self.op_ex9e(),
-                    0xA1 => self.op_exa1(),
-                    _ => panic!("Invalid opcode: {:#04x}", self.opcode)
-                }
+            Chip8Error::StackUnderflow { address } => {
+                write!(f, "Stack underflow: RET with an empty stack at {:#05x}", address)
             },
-            0xF => {
-                let identity: u16 = self.opcode & 0x00FF;
-                match identity {
-                    0x07 => self.op_fx07(),
-                    0x0A => self.op_fx0a(),
-                    0x15 => self.op_fx15(),
-                    0x18 => self.op_fx18(),
-                    0x1E => self.op_fx1e(),
-                    0x29 => self.op_fx29(),
-                    0x33 => self.op_fx33(),
-                    0x55 => self.op_fx55(),
-                    0x65 => self.op_fx65(),
-                    _ => panic!("Invalid opcode {:#04x}", self.opcode)
-                }
+            Chip8Error::MemoryOutOfBounds { address, target } => {
+                write!(f, "Memory access out of bounds: {:#06x} by the instruction at {:#05x}", target, address)
             },
-            _ => panic!("Invalid opcode: {:#04x}", self.opcode)
+            Chip8Error::ProtectedWrite { address, target } => {
+                write!(f, "Write to reserved memory at {:#05x} by the instruction at {:#05x}", target, address)
+            },
+            Chip8Error::InvariantViolated { address, opcode, invariant } => {
+                write!(f, "Invariant violated by {:#06x} at {:#05x}: {}", opcode, address, invariant)
+            }
         }
+    }
 
-        if self.delay_timer > 0 { self.delay_timer -= 1; }
-        if self.sound_timer > 0 { self.sound_timer -= 1; }
+}
+
+impl Error for Chip8Error {}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::quirks::Quirks;
+
+    fn machine(rom: &[u8]) -> Chip8 {
+        let mut chip8 = Chip8::create();
+        chip8.load_bytes(rom).unwrap();
+        chip8
+    }
+
+    fn steps(chip8: &mut Chip8, count: usize) {
+        for _ in 0..count {
+            chip8.step().unwrap();
+        }
+    }
+
+    #[test]
+    fn step_is_fetch_decode_execute() {
+        //Draws the font's 0-9 across the screen, a call and a BCD on the way
+        let rom = [
+            0x60, 0x00, 0x61, 0x00, 0x62, 0x00, 0xF2, 0x29, 0xD0, 0x15, 0x70, 0x05, 0x72, 0x01, 0x22, 0x16,
+            0x32, 0x0A, 0x12, 0x06, 0x12, 0x14, 0xA3, 0x00, 0xF0, 0x33, 0x00, 0xEE
+        ];
+        let mut stepped = machine(&rom);
+        let mut by_hand = machine(&rom);
+
+        for _ in 0..120 {
+            stepped.step().unwrap();
+
+            let opcode = by_hand.fetch().unwrap();
+            by_hand.program_counter += 2;
+            by_hand.execute(decode(opcode)).unwrap();
+
+            assert_eq!(stepped.program_counter, by_hand.program_counter);
+            assert_eq!(stepped.registers, by_hand.registers);
+            assert_eq!(stepped.index_register, by_hand.index_register);
+        }
+        assert_eq!(stepped.video, by_hand.video);
+        assert_eq!(stepped.memory, by_hand.memory);
+        assert_eq!(stepped.program_counter, 0x214);
+        assert_eq!(stepped.registers[0..3], [50, 0, 10]);
+    }
+
+    #[test]
+    fn run_is_a_step_and_a_timer_tick() {
+        //LD V0, 30; LD DT, V0; LD ST, V0; then loops on JP
+        let rom = [0x60, 0x1E, 0xF0, 0x15, 0xF0, 0x18, 0x12, 0x06];
+        let mut ran = machine(&rom);
+        let mut stepped = machine(&rom);
+
+        for _ in 0..10 {
+            ran.run().unwrap();
+            stepped.step().unwrap();
+            stepped.tick_timers();
+
+            assert_eq!(ran.program_counter, stepped.program_counter);
+            assert_eq!((ran.delay_timer, ran.sound_timer), (stepped.delay_timer, stepped.sound_timer));
+        }
+        assert_eq!((ran.program_counter, ran.delay_timer, ran.sound_timer), (0x206, 21, 22));
+    }
+
+    #[test]
+    fn arithmetic_flags() {
+        let mut chip8 = Chip8::create();
+        let mut run = |opcode: u16, x: u8, y: u8| {
+            chip8.registers[0] = x;
+            chip8.registers[1] = y;
+            chip8.execute(decode(opcode)).unwrap();
+            (chip8.registers[0], chip8.registers[0xF])
+        };
+
+        assert_eq!(run(0x8014, 0xF0, 0x20), (0x10, 1));
+        assert_eq!(run(0x8014, 0x10, 0x20), (0x30, 0));
+        assert_eq!(run(0x8015, 0x10, 0x20), (0xF0, 0));
+        assert_eq!(run(0x8015, 0x20, 0x20), (0x00, 1));
+        assert_eq!(run(0x8017, 0x10, 0x20), (0x10, 1));
+        assert_eq!(run(0x8016, 0x03, 0x00), (0x01, 1));
+        assert_eq!(run(0x801E, 0x81, 0x00), (0x02, 1));
+    }
+
+    #[test]
+    fn flag_written_last_with_vf_as_vx() {
+        let mut chip8 = Chip8::create();
+        chip8.registers[0xF] = 0xFF;
+        chip8.registers[1] = 0x02;
+        chip8.execute(decode(0x8F14)).unwrap();
+
+        assert_eq!(chip8.registers[0xF], 1);
+    }
+
+    #[test]
+    fn shifts_leave_the_bit_in_vf_as_vx() {
+        //The shifted values differ from the bits shifted out, so VF shows
+        //which was written last
+        for shift_vy in [false, true] {
+            let mut chip8 = Chip8::create();
+            chip8.quirks.shift_vy = shift_vy;
+            let mut run = |opcode: u16, vf: u8| {
+                chip8.registers[0xF] = vf;
+                chip8.execute(decode(opcode)).unwrap();
+                chip8.registers[0xF]
+            };
+
+            assert_eq!(run(0x8FF6, 0x02), 0, "8FF6, shift_vy {}", shift_vy);
+            assert_eq!(run(0x8FF6, 0x81), 1, "8FF6, shift_vy {}", shift_vy);
+            assert_eq!(run(0x8FFE, 0x40), 0, "8FFE, shift_vy {}", shift_vy);
+            assert_eq!(run(0x8FFE, 0x80), 1, "8FFE, shift_vy {}", shift_vy);
+        }
+    }
+
+    #[test]
+    fn shift_quirk() {
+        let mut chip8 = Chip8::create();
+        chip8.quirks.shift_vy = true;
+        chip8.registers[0] = 0x00;
+        chip8.registers[1] = 0x81;
+        chip8.execute(decode(0x8016)).unwrap();
+
+        assert_eq!((chip8.registers[0], chip8.registers[0xF]), (0x40, 1));
+    }
+
+    #[test]
+    fn store_and_load_registers() {
+        let mut chip8 = machine(&[0xA3, 0x00, 0xF2, 0x55, 0xA3, 0x00, 0xF5, 0x65]);
+        chip8.registers[..3].copy_from_slice(&[1, 2, 3]);
+        steps(&mut chip8, 4);
+
+        assert_eq!(chip8.memory[0x300..0x303], [1, 2, 3]);
+        assert_eq!(chip8.registers[..6], [1, 2, 3, 0, 0, 0]);
+        assert_eq!(chip8.index_register, 0x300);
+
+        chip8.reset();
+        chip8.load_bytes(&[0xA3, 0x00, 0xF2, 0x55]).unwrap();
+        chip8.quirks.increment_i = true;
+        steps(&mut chip8, 2);
+        assert_eq!(chip8.index_register, 0x303);
+    }
+
+    #[test]
+    fn stores_bcd() {
+        let mut chip8 = machine(&[0x60, 0xFE, 0xA3, 0x00, 0xF0, 0x33]);
+        steps(&mut chip8, 3);
+
+        assert_eq!(chip8.memory[0x300..0x303], [2, 5, 4]);
+    }
+
+    #[test]
+    fn invalid_opcode_stops() {
+        let mut chip8 = machine(&[0x60, 0x01, 0xFF, 0xFF]);
+        steps(&mut chip8, 1);
+
+        assert_eq!(chip8.step(), Err(Chip8Error::InvalidOpcode { address: 0x202, opcode: 0xFFFF }));
+    }
+
+
+    #[test]
+    fn fontset_is_loaded() {
+        let chip8 = Chip8::create();
+        let start = FONTSET_START_ADDRESS as usize;
+
+        assert_eq!(start, 0x50);
+        assert_eq!(chip8.memory[start..start + FONTSET_SIZE as usize], FONT_DATA);
+    }
+
+    #[test]
+    fn draws_a_font_digit() {
+        //I = the 7, drawn at (8, 4)
+        let mut chip8 = machine(&[0x62, 0x07, 0xF2, 0x29, 0x60, 0x08, 0x61, 0x04, 0xD0, 0x15]);
+        steps(&mut chip8, 2);
+        assert_eq!(chip8.index_register, 0x50 + 5 * 7);
+
+        steps(&mut chip8, 3);
+        //F0 10 20 40 40: the top row across, then a stroke down and left
+        let lit = |x: usize, y: usize| chip8.video[x + y * LORES_WIDTH] != 0;
+        assert!((8..12).all(|x| lit(x, 4)));
+        assert!(!lit(12, 4));
+        assert!(lit(11, 5) && !lit(8, 5));
+        assert!(lit(10, 6));
+        assert!(lit(9, 7) && lit(9, 8));
+        assert!(!lit(9, 9));
+        assert_eq!(chip8.registers[0xF], 0);
+    }
+
+    #[test]
+    fn font_digit_uses_the_low_nibble() {
+        let mut chip8 = machine(&[0x62, 0xFA, 0xF2, 0x29]);
+        steps(&mut chip8, 2);
+
+        assert_eq!(chip8.index_register, 0x50 + 5 * 0xA);
+    }
+
+
+    #[test]
+    fn drawing_twice_collides() {
+        //The 0 at (0, 0), twice
+        let mut chip8 = machine(&[0xA0, 0x50, 0xD0, 0x05, 0xD0, 0x05]);
+        chip8.verify_invariants = true;
+        steps(&mut chip8, 2);
+        assert_eq!(chip8.registers[0xF], 0);
+        assert!(chip8.video.iter().any(|pixel| *pixel != 0));
+
+        steps(&mut chip8, 1);
+        assert_eq!(chip8.registers[0xF], 1);
+        assert!(chip8.video.iter().all(|pixel| *pixel == 0));
+        assert_eq!(chip8.collision_count(), 1);
+    }
+
+
+    //PC after the instruction at address, V0 = 5 and V1 = 5 or 6
+    fn skip_from(address: u16, opcode: u16, v1: u8) -> Result<u16, Chip8Error> {
+        let mut chip8 = Chip8::create();
+        chip8.registers[0] = 5;
+        chip8.registers[1] = v1;
+        chip8.program_counter = address;
+        chip8.memory[address as usize..address as usize + 2].copy_from_slice(&opcode.to_be_bytes());
+        chip8.step()?;
+        Ok(chip8.program_counter)
+    }
+
+    #[test]
+    fn skips() {
+        //4 past the instruction when taken, 2 when not
+        for (opcode, v1, to) in [
+            (0x3005, 5, 0x304), (0x3006, 5, 0x302),
+            (0x4005, 5, 0x302), (0x4006, 5, 0x304),
+            (0x5010, 5, 0x304), (0x5010, 6, 0x302),
+            (0x9010, 5, 0x302), (0x9010, 6, 0x304)
+        ] {
+            assert_eq!(skip_from(0x300, opcode, v1), Ok(to), "{:04X} with V1 = {}", opcode, v1);
+        }
+    }
+
+    #[test]
+    fn skips_at_the_end_of_memory() {
+        //Onto the last instruction, which still fetches
+        let mut chip8 = Chip8::create();
+        chip8.memory[0xFFA..].copy_from_slice(&[0x30, 0x00, 0xFF, 0xFF, 0x60, 0x2A]);
+        chip8.program_counter = 0xFFA;
+        steps(&mut chip8, 2);
+        assert_eq!((chip8.program_counter, chip8.registers[0]), (0x1000, 0x2A));
+
+        //Over the last instruction, past the end: the next fetch is an
+        //error rather than a read outside memory
+        assert_eq!(skip_from(0xFFC, 0x3005, 5), Ok(0x1000));
+        let mut chip8 = Chip8::create();
+        chip8.memory[0xFFC..].copy_from_slice(&[0x30, 0x00, 0x60, 0x2A]);
+        chip8.program_counter = 0xFFC;
+        steps(&mut chip8, 1);
+        assert_eq!(chip8.step(), Err(Chip8Error::MemoryOutOfBounds { address: 0x1000, target: 0x1000 }));
+        assert_eq!(chip8.registers[0], 0);
+
+        //From the last instruction, with XO-CHIP opcodes looking past it
+        //for an F000
+        assert_eq!(skip_from(0xFFE, 0x3006, 5), Ok(0x1000));
+        let mut chip8 = Chip8::create();
+        chip8.quirks.xo_chip_opcodes = true;
+        chip8.memory[0xFFE..].copy_from_slice(&[0x30, 0x00]);
+        chip8.program_counter = 0xFFE;
+        steps(&mut chip8, 1);
+        assert_eq!(chip8.program_counter, 0x1002);
+    }
+
+    #[test]
+    fn skips_a_long_instruction() {
+        //F000 NNNN is four bytes under XO-CHIP opcodes
+        let mut chip8 = machine(&[0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x60, 0x01]);
+        chip8.quirks.xo_chip_opcodes = true;
+        steps(&mut chip8, 2);
+
+        assert_eq!((chip8.program_counter, chip8.registers[0], chip8.index_register), (0x208, 1, 0));
+    }
+
+
+    #[test]
+    fn key_past_f() {
+        //VA = 0xFF, SKP VA, SKNP VA, with every key down
+        let rom = [0x6A, 0xFF, 0xEA, 0x9E, 0x00, 0x00, 0xEA, 0xA1];
+        let mut chip8 = machine(&rom);
+        chip8.keypad = [true; 16];
+        steps(&mut chip8, 2);
+        //Never down, so SKP doesn't skip and SKNP does
+        assert_eq!(chip8.program_counter, 0x204);
+        chip8.program_counter = 0x206;
+        steps(&mut chip8, 1);
+        assert_eq!(chip8.program_counter, 0x20A);
+
+        //Key F under key-wrap
+        let mut chip8 = machine(&rom);
+        chip8.quirks.key_wrap = true;
+        chip8.keypad[0xF] = true;
+        steps(&mut chip8, 2);
+        assert_eq!(chip8.program_counter, 0x206);
     }
 
-    pub fn temp(&mut self) {
-        for _ in 0..246 {
-            let program_counter = self.program_counter as usize;
-            let first_part : u16 = ( self.memory[program_counter] as u16 ) << 8_u16;
-            let second_part: u16 = ( self.memory[program_counter+1] ) as u16;
-            println!("{:#04x}", first_part | second_part);
 
-            self.program_counter += 2;
+    #[test]
+    fn call_and_return() {
+        //0x200 calls 0x300, which calls 0x400, both return
+        let mut chip8 = Chip8::create();
+        chip8.memory[0x200..0x202].copy_from_slice(&[0x23, 0x00]);
+        chip8.memory[0x300..0x304].copy_from_slice(&[0x24, 0x00, 0x00, 0xEE]);
+        chip8.memory[0x400..0x402].copy_from_slice(&[0x00, 0xEE]);
+
+        steps(&mut chip8, 1);
+        assert_eq!((chip8.program_counter, chip8.stack_pointer), (0x300, 1));
+        assert_eq!(chip8.stack[0], 0x202);
+
+        steps(&mut chip8, 1);
+        assert_eq!((chip8.program_counter, chip8.stack_pointer), (0x400, 2));
+        assert_eq!(chip8.stack[..2], [0x202, 0x302]);
+        assert_eq!(chip8.call_stack(), [0x202, 0x302]);
+
+        steps(&mut chip8, 1);
+        assert_eq!((chip8.program_counter, chip8.stack_pointer), (0x302, 1));
+        steps(&mut chip8, 1);
+        assert_eq!((chip8.program_counter, chip8.stack_pointer), (0x202, 0));
+        assert!(chip8.backtrace().is_empty() && chip8.backtrace_reliable());
+    }
+
+    #[test]
+    fn stack_faults() {
+        let mut chip8 = machine(&[0x00, 0xEE]);
+        assert_eq!(chip8.step(), Err(Chip8Error::StackUnderflow { address: 0x200 }));
+
+        //Calls itself until the stack is full
+        let mut chip8 = machine(&[0x22, 0x00]);
+        let depth = chip8.stack.len();
+        steps(&mut chip8, depth);
+        assert_eq!(chip8.step(), Err(Chip8Error::StackOverflow { address: 0x200 }));
+    }
+
+
+    //20 KB: a jump to 0xFFE, then code running on past 0x1000 into data
+    //near the end of the ROM
+    fn big_rom() -> Vec<u8> {
+        let mut rom = vec![0; 20 * 1024];
+        rom[..2].copy_from_slice(&[0x1F, 0xFE]);
+        let code = 0xFFE - START_ADDRESS as usize;
+        rom[code..code + 10].copy_from_slice(&[0x60, 0x01, 0x61, 0x02, 0xF0, 0x00, 0x51, 0x00, 0xF1, 0x65]);
+        rom[0x5100 - START_ADDRESS as usize..][..2].copy_from_slice(&[0xAA, 0xBB]);
+        rom
+    }
+
+    #[test]
+    fn big_rom_needs_xo_chip_memory() {
+        let rom = big_rom();
+        let mut chip8 = Chip8::create_with_memory(Quirks::memory_size("chip8"));
+        assert_eq!(chip8.load_bytes(&rom), Err("20480 bytes, more than the 3584 that fit in memory, an XO-CHIP ROM this big needs --quirks xo-chip".to_string()));
+        assert!(chip8.memory[START_ADDRESS as usize..].iter().all(|byte| *byte == 0));
+
+        let mut chip8 = Chip8::create_with_memory(Quirks::memory_size("xo-chip"));
+        chip8.quirks = Quirks::profile("xo-chip").unwrap();
+        chip8.load_bytes(&rom).unwrap();
+        //Past 0x1000: V0 = 1, V1 = 2, I = 0x5100, V0-V1 = [I]
+        steps(&mut chip8, 5);
+        assert_eq!((chip8.program_counter, chip8.index_register), (0x1008, 0x5102));
+        assert_eq!(chip8.registers[..2], [0xAA, 0xBB]);
+
+        chip8.poke(0x1234, &[0x42]).unwrap();
+        chip8.poke(0xFFFF, &[0x43]).unwrap();
+        assert_eq!((chip8.memory[0x1234], chip8.memory[0xFFFF]), (0x42, 0x43));
+        assert!(chip8.poke(0xFFFF, &[1, 2]).is_err());
+    }
+
+    //The lit pixels of the first width columns of a row
+    fn row(chip8: &Chip8, y: usize, width: usize) -> String {
+        let start = y * chip8.video_width();
+        chip8.video[start..start + width].iter().map(|pixel| if *pixel != 0 { '#' } else { '.' }).collect()
+    }
+
+    #[test]
+    fn tall_sprite_wraps_around_memory() {
+        //I = 0xFFF, DF0F: 15 rows from 0xFFF, then 0x000 on
+        let mut chip8 = machine(&[0xAF, 0xFF, 0xD0, 0x0F]);
+        chip8.warn_sprite_overrun = true;
+        chip8.poke(0xFFF, &[0xFF]).unwrap();
+        chip8.poke(0x000, &[0x81, 0x3C]).unwrap();
+
+        steps(&mut chip8, 2);
+        assert_eq!(row(&chip8, 0, 8), "########");
+        assert_eq!(row(&chip8, 1, 8), "#......#");
+        assert_eq!(row(&chip8, 2, 8), "..####..");
+        assert!((3..15).all(|y| row(&chip8, y, 8) == "........"));
+        assert_eq!(chip8.program_counter, 0x204);
+        assert_eq!(chip8.sprite_warnings.iter().copied().collect::<Vec<u16>>(), [0x202]);
+    }
+
+    #[test]
+    fn big_sprite_wraps_around_xo_chip_memory() {
+        //I = 0xFFFF, D000: 16 rows of two bytes from 0xFFFF, then 0x0000 on
+        let mut chip8 = Chip8::create_with_memory(Quirks::memory_size("xo-chip"));
+        chip8.quirks = Quirks::profile("xo-chip").unwrap();
+        chip8.load_bytes(&[0xF0, 0x00, 0xFF, 0xFF, 0xD0, 0x00]).unwrap();
+        chip8.poke(0xFFFF, &[0xAA]).unwrap();
+        chip8.poke(0x0000, &[0x55, 0xF0, 0x0F]).unwrap();
+
+        steps(&mut chip8, 2);
+        assert_eq!(row(&chip8, 0, 16), "#.#.#.#..#.#.#.#");
+        assert_eq!(row(&chip8, 1, 16), "####........####");
+        assert!((2..16).all(|y| row(&chip8, y, 16) == "................"));
+        assert_eq!(chip8.program_counter, 0x206);
+    }
+
+    #[test]
+    fn sprite_inside_the_rom_doesnt_warn() {
+        //I = 0x206, D001 draws the ROM's last byte
+        let mut chip8 = machine(&[0xA2, 0x06, 0xD0, 0x01, 0x12, 0x04, 0x80]);
+        chip8.warn_sprite_overrun = true;
+
+        steps(&mut chip8, 2);
+        assert_eq!(row(&chip8, 0, 8), "#.......");
+        assert!(chip8.sprite_warnings.is_empty());
+
+        //One byte more runs past it
+        chip8.load_bytes(&[0xA2, 0x06, 0xD0, 0x02, 0x12, 0x04, 0x80]).unwrap();
+        chip8.program_counter = 0x200;
+        steps(&mut chip8, 2);
+        assert_eq!(chip8.sprite_warnings.len(), 1);
+    }
+
+    //The lit pixels of a plane as (x, y)
+    fn lit(chip8: &Chip8, plane: &[u8]) -> Vec<(usize, usize)> {
+        let width = chip8.video_width();
+        plane.iter().enumerate().filter(|(_, pixel)| **pixel != 0).map(|(i, _)| (i % width, i / width)).collect()
+    }
+
+    #[test]
+    fn resolution_switch_clears() {
+        let mut chip8 = Chip8::create();
+        chip8.video[3 + 2 * LORES_WIDTH] = 0xFF;
+        chip8.plane2[5] = 0xFF;
+
+        chip8.execute(decode(0x00FF)).unwrap();
+        assert!(chip8.hires);
+        assert_eq!((chip8.video.len(), chip8.plane2.len()), (HIRES_WIDTH * HIRES_HEIGHT, HIRES_WIDTH * HIRES_HEIGHT));
+        assert!(lit(&chip8, &chip8.video).is_empty() && lit(&chip8, &chip8.plane2).is_empty());
+
+        chip8.video[7] = 0xFF;
+        chip8.execute(decode(0x00FE)).unwrap();
+        assert!(!chip8.hires);
+        assert_eq!((chip8.video.len(), chip8.plane2.len()), (LORES_WIDTH * LORES_HEIGHT, LORES_WIDTH * LORES_HEIGHT));
+        assert!(lit(&chip8, &chip8.video).is_empty());
+    }
+
+    #[test]
+    fn resolution_switch_scales_under_the_quirk() {
+        let mut chip8 = Chip8::create();
+        chip8.quirks.scale_on_resize = true;
+        chip8.video[3 + 2 * LORES_WIDTH] = 0xFF;
+        chip8.plane2[63 + 31 * LORES_WIDTH] = 0xFF;
+
+        chip8.execute(decode(0x00FF)).unwrap();
+        assert_eq!(chip8.video.len(), HIRES_WIDTH * HIRES_HEIGHT);
+        assert_eq!(lit(&chip8, &chip8.video), [(6, 4), (7, 4), (6, 5), (7, 5)]);
+        assert_eq!(lit(&chip8, &chip8.plane2), [(126, 62), (127, 62), (126, 63), (127, 63)]);
+
+        //Back down every other pixel is kept
+        chip8.video[9 + 4 * HIRES_WIDTH] = 0xFF;
+        chip8.execute(decode(0x00FE)).unwrap();
+        assert_eq!(chip8.video.len(), LORES_WIDTH * LORES_HEIGHT);
+        assert_eq!(lit(&chip8, &chip8.video), [(3, 2)]);
+        assert_eq!(lit(&chip8, &chip8.plane2), [(63, 31)]);
+        assert!(chip8.video_dirty);
+    }
+
+    #[test]
+    fn clear_display_blanks_every_pixel() {
+        for hires in [false, true] {
+            let mut chip8 = Chip8::create();
+            chip8.execute(decode(if hires { 0x00FF } else { 0x00FE })).unwrap();
+            chip8.video.fill(0xFF);
+            chip8.take_frame_if_dirty();
+
+            chip8.clear_display();
+            assert!(chip8.video.iter().all(|pixel| *pixel == 0), "hires {}", hires);
+            assert_eq!(chip8.video.len(), chip8.video_width() * chip8.video_height());
+            assert!(chip8.take_frame_if_dirty().is_some());
+        }
+    }
+
+    #[test]
+    fn cls_only_clears_the_selected_planes() {
+        let mut chip8 = Chip8::create();
+        chip8.video.fill(0xFF);
+        chip8.plane2.fill(0xFF);
+        chip8.plane_mask = 2;
+
+        chip8.execute(decode(0x00E0)).unwrap();
+        assert!(chip8.video.iter().all(|pixel| *pixel == 0xFF));
+        assert!(chip8.plane2.iter().all(|pixel| *pixel == 0));
+        assert!(chip8.video_dirty);
+    }
+
+    #[test]
+    fn scrolls_only_move_the_selected_planes() {
+        let mut chip8 = Chip8::create();
+        chip8.video[0] = 0xFF;
+        chip8.plane2[0] = 0xFF;
+
+        chip8.plane_mask = 1;
+        chip8.execute(decode(0x00FB)).unwrap();
+        chip8.plane_mask = 2;
+        chip8.execute(decode(0x00C2)).unwrap();
+        assert_eq!((lit(&chip8, &chip8.video), lit(&chip8, &chip8.plane2)), (vec![(4, 0)], vec![(0, 2)]));
+
+        //With neither selected nothing moves
+        chip8.plane_mask = 0;
+        chip8.execute(decode(0x00FC)).unwrap();
+        assert_eq!((lit(&chip8, &chip8.video), lit(&chip8, &chip8.plane2)), (vec![(4, 0)], vec![(0, 2)]));
+    }
+
+    //Draws a 2x8 block of lit pixels, I at two 0xFF bytes, from (x, y)
+    fn draw_block(quirks: Quirks, x: u8, y: u8) -> Chip8 {
+        let mut chip8 = machine(&[0x60, x, 0x61, y, 0xA2, 0x08, 0xD0, 0x12, 0xFF, 0xFF]);
+        chip8.quirks = quirks;
+        steps(&mut chip8, 4);
+        chip8
+    }
+
+    #[test]
+    fn clip_x_alone() {
+        let chip8 = draw_block(Quirks { clip_x: true, ..Quirks::default() }, 60, 31);
+        assert_eq!(lit(&chip8, &chip8.video), [(60, 0), (61, 0), (62, 0), (63, 0), (60, 31), (61, 31), (62, 31), (63, 31)]);
+
+        let chip8 = draw_block(Quirks::default(), 60, 31);
+        assert_eq!(lit(&chip8, &chip8.video), [
+            (0, 0), (1, 0), (2, 0), (3, 0), (60, 0), (61, 0), (62, 0), (63, 0),
+            (0, 31), (1, 31), (2, 31), (3, 31), (60, 31), (61, 31), (62, 31), (63, 31)
+        ]);
+    }
+
+    #[test]
+    fn clip_y_alone() {
+        let chip8 = draw_block(Quirks { clip_y: true, ..Quirks::default() }, 60, 31);
+        assert_eq!(lit(&chip8, &chip8.video), [(0, 31), (1, 31), (2, 31), (3, 31), (60, 31), (61, 31), (62, 31), (63, 31)]);
+
+        let chip8 = draw_block(Quirks { clip_x: true, clip_y: true, ..Quirks::default() }, 60, 31);
+        assert_eq!(lit(&chip8, &chip8.video), [(60, 31), (61, 31), (62, 31), (63, 31)]);
+    }
+
+    #[test]
+    fn whole_bytes_collide_on_any_shared_pixel() {
+        //V0 = 3, D011 of 0x81: an on-screen byte, XORed as one word
+        let mut chip8 = machine(&[0x60, 0x03, 0xA2, 0x08, 0xD0, 0x11, 0x12, 0x06, 0x81]);
+        chip8.video[4] = 0xFF;
+        steps(&mut chip8, 3);
+        assert_eq!(row(&chip8, 0, 16), "...##.....#.....");
+        assert_eq!(chip8.registers[0xF], 0);
+
+        //Only the pixel at 10 is shared the second time
+        chip8.video[3] = 0;
+        chip8.execute(decode(0xD011)).unwrap();
+        assert_eq!(row(&chip8, 0, 16), "...##...........");
+        assert_eq!(chip8.registers[0xF], 1);
+        assert_eq!(chip8.collision_count(), 1);
+    }
+
+    #[test]
+    fn bytes_across_the_edge_collide_where_they_wrap() {
+        //V0 = 60, D011 of 0xFF over a lit pixel at (2, 0)
+        let rom = [0x60, 0x3C, 0xA2, 0x08, 0xD0, 0x11, 0x12, 0x06, 0xFF];
+        let mut chip8 = machine(&rom);
+        chip8.video[2] = 0xFF;
+        steps(&mut chip8, 3);
+        assert_eq!(row(&chip8, 0, 64), format!("##.#{}####", ".".repeat(56)));
+        assert_eq!(chip8.registers[0xF], 1);
+
+        //Clipped, the part that would wrap can't collide
+        let mut chip8 = machine(&rom);
+        chip8.quirks.clip_x = true;
+        chip8.video[2] = 0xFF;
+        steps(&mut chip8, 3);
+        assert_eq!(row(&chip8, 0, 64), format!("..#.{}####", ".".repeat(56)));
+        assert_eq!(chip8.registers[0xF], 0);
+    }
+
+    #[test]
+    fn index_near_the_end_of_memory() {
+        //FX1E wraps I at 16 bits
+        let mut chip8 = Chip8::create_with_memory(XO_CHIP_MEMORY_SIZE);
+        chip8.index_register = 0xFFFF;
+        chip8.registers[0] = 2;
+        chip8.execute(decode(0xF01E)).unwrap();
+        assert_eq!(chip8.index_register, 0x0001);
+
+        //The last byte is in reach, and increment-i wraps I past it
+        chip8.quirks.increment_i = true;
+        chip8.index_register = 0xFFFF;
+        chip8.registers[0] = 0xAB;
+        chip8.execute(decode(0xF055)).unwrap();
+        assert_eq!((chip8.memory[0xFFFF], chip8.index_register), (0xAB, 0x0000));
+
+        //With 4 KB, FX55 stops at the first byte past memory
+        let mut chip8 = Chip8::create();
+        chip8.program_counter = 0x202;
+        chip8.index_register = 0xFFE;
+        chip8.registers[..3].copy_from_slice(&[1, 2, 3]);
+        assert_eq!(chip8.execute(decode(0xF255)), Err(Chip8Error::MemoryOutOfBounds { address: 0x200, target: 0x1000 }));
+        assert_eq!(chip8.memory[0xFFE..], [1, 2]);
+
+        chip8.index_register = 0xFFF;
+        assert_eq!(chip8.execute(decode(0xF165)), Err(Chip8Error::MemoryOutOfBounds { address: 0x200, target: 0x1000 }));
+        chip8.execute(decode(0xF065)).unwrap();
+        assert_eq!(chip8.registers[0], 2);
+        assert_eq!(chip8.execute(decode(0xF033)), Err(Chip8Error::MemoryOutOfBounds { address: 0x200, target: 0x1000 }));
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn reads_roms_from_zips() {
+        let fixture = |name: &str| format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+
+        assert_eq!(read_rom(&fixture("one_rom.zip")).unwrap().len(), 32);
+        assert_eq!(read_rom(&format!("{}#games/TETRIS.c8", fixture("several_roms.zip"))).unwrap(), [0x61, 0x07, 0x12, 0x00]);
+        assert!(read_rom(&fixture("several_roms.zip")).unwrap_err().contains("several ROMs in the archive"));
+        assert!(read_rom(&fixture("no_rom.zip")).unwrap_err().contains("no .ch8 or .c8 file"));
+    }
+
+    //Three marker pixels down a diagonal in rows 0-2
+    fn markers(hires: bool, half_scroll: bool) -> Chip8 {
+        let mut chip8 = Chip8::create();
+        chip8.quirks.half_scroll = half_scroll;
+        if hires {
+            chip8.execute(decode(0x00FF)).unwrap();
+        }
+        let width = chip8.video_width();
+        for i in 0..3 {
+            chip8.video[i + i * width] = 0xFF;
+        }
+        chip8.video_dirty = false;
+        chip8
+    }
+
+    #[test]
+    fn scroll_down_by_three() {
+        for (hires, half_scroll, moved) in [(true, false, 3), (true, true, 3), (false, false, 3), (false, true, 1)] {
+            let mut chip8 = markers(hires, half_scroll);
+            chip8.execute(decode(0x00C3)).unwrap();
+            assert_eq!(lit(&chip8, &chip8.video), [(0, moved), (1, 1 + moved), (2, 2 + moved)], "hires {}, half-scroll {}", hires, half_scroll);
+            assert!(chip8.video_dirty);
+        }
+    }
+
+    #[test]
+    fn scrolled_off_rows_are_gone() {
+        let mut chip8 = markers(false, false);
+        chip8.execute(decode(0x00C0)).unwrap();
+        assert_eq!(lit(&chip8, &chip8.video), [(0, 0), (1, 1), (2, 2)]);
+
+        for _ in 0..3 {
+            chip8.execute(decode(0x00CF)).unwrap();
+        }
+        assert_eq!(lit(&chip8, &chip8.video), []);
+    }
+
+    #[test]
+    fn scroll_sideways() {
+        for (hires, half_scroll, columns) in [(true, false, 4), (true, true, 4), (false, false, 4), (false, true, 2)] {
+            let mut chip8 = Chip8::create();
+            chip8.quirks.half_scroll = half_scroll;
+            if hires {
+                chip8.execute(decode(0x00FF)).unwrap();
+            }
+            let width = chip8.video_width();
+            for x in [0, 1, 3, width - 1] {
+                chip8.video[x + width] = 0xFF;
+            }
+            chip8.video_dirty = false;
+
+            //Right: the last column drops off the edge
+            chip8.execute(decode(0x00FB)).unwrap();
+            assert_eq!(lit(&chip8, &chip8.video), [(columns, 1), (columns + 1, 1), (columns + 3, 1)], "hires {}, half-scroll {}", hires, half_scroll);
+            assert!(chip8.video_dirty);
+
+            chip8.execute(decode(0x00FC)).unwrap();
+            assert_eq!(lit(&chip8, &chip8.video), [(0, 1), (1, 1), (3, 1)]);
+
+            //Left: only the pixel at 3 survives, and only a 2 pixel scroll
+            chip8.execute(decode(0x00FC)).unwrap();
+            let expected: &[(usize, usize)] = if columns == 2 { &[(1, 1)] } else { &[] };
+            assert_eq!(lit(&chip8, &chip8.video), expected);
+        }
+    }
+
+    #[test]
+    fn blit_byte_reports_collisions() {
+        let mut chip8 = Chip8::create();
+        chip8.video[5] = 0xFF;
+        assert!(!chip8.blit_byte(0, 0, 8, 0xFF));
+        assert!(chip8.blit_byte(0, 0, 0, 0x04));
+        assert_eq!(row(&chip8, 0, 16), "........########");
+        assert!(!chip8.blit_byte(0, 0, 0, 0x04));
+
+        //Across the right edge only the wrapped pixel at 1 is shared
+        chip8.video[1] = 0xFF;
+        assert!(chip8.blit_byte(0, 0, 60, 0x84));
+        assert_eq!(row(&chip8, 0, 64), format!(".....#..########{}#...", ".".repeat(44)));
+
+        //Clipped, the pixel past the edge is dropped and can't collide
+        chip8.video[1] = 0xFF;
+        chip8.quirks.clip_x = true;
+        assert!(!chip8.blit_byte(0, 0, 60, 0x04));
+        assert_eq!(chip8.video[1], 0xFF);
+    }
+
+    //DXY0 with V0 = x, V1 = 0, from a sprite of 16 rows of 0x80 0x01
+    fn draw_16x16(hires: bool, quirks: Quirks, x: u8) -> Chip8 {
+        let mut chip8 = Chip8::create();
+        chip8.quirks = quirks;
+        if hires {
+            chip8.execute(decode(0x00FF)).unwrap();
+        }
+        chip8.index_register = 0x300;
+        chip8.poke(0x300, &[0x80, 0x01].repeat(16)).unwrap();
+        chip8.registers[0] = x;
+        chip8.execute(decode(0xD010)).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn dxy0_draws_16x16() {
+        let mut chip8 = draw_16x16(true, Quirks::default(), 0);
+        assert_eq!(lit(&chip8, &chip8.video), (0..16).flat_map(|y| [(0, y), (15, y)]).collect::<Vec<_>>());
+        assert_eq!(chip8.registers[0xF], 0);
+
+        //The same sprite again erases itself and collides
+        chip8.execute(decode(0xD010)).unwrap();
+        assert_eq!(lit(&chip8, &chip8.video), []);
+        assert_eq!(chip8.registers[0xF], 1);
+
+        //lores: 16x16 by default, 16 rows of one byte under dxy0-8x16
+        let chip8 = draw_16x16(false, Quirks::default(), 0);
+        assert_eq!(lit(&chip8, &chip8.video), (0..16).flat_map(|y| [(0, y), (15, y)]).collect::<Vec<_>>());
+        let chip8 = draw_16x16(false, Quirks { dxy0_8x16: true, ..Quirks::default() }, 0);
+        assert_eq!(lit(&chip8, &chip8.video), (0..16).map(|y| (if y % 2 == 0 { 0 } else { 7 }, y)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn dxy0_at_the_right_edge() {
+        //The second byte of each row starts at 128 and wraps to column 0
+        let chip8 = draw_16x16(true, Quirks::default(), 120);
+        assert_eq!(lit(&chip8, &chip8.video), (0..16).flat_map(|y| [(7, y), (120, y)]).collect::<Vec<_>>());
+
+        let chip8 = draw_16x16(true, Quirks { clip_x: true, ..Quirks::default() }, 120);
+        assert_eq!(lit(&chip8, &chip8.video), (0..16).map(|y| (120, y)).collect::<Vec<_>>());
+
+        //Halfway into the second byte only its low pixels are cut
+        let chip8 = draw_16x16(true, Quirks { clip_x: true, ..Quirks::default() }, 113);
+        assert_eq!(lit(&chip8, &chip8.video), (0..16).map(|y| (113, y)).collect::<Vec<_>>());
+        let chip8 = draw_16x16(true, Quirks::default(), 113);
+        assert_eq!(lit(&chip8, &chip8.video), (0..16).flat_map(|y| [(0, y), (113, y)]).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn big_font_digits_draw_their_glyphs() {
+        for hires in [false, true] {
+            for digit in 0..10 {
+                //LD HF, V0 for V0 = digit + 0x10, then DRW V1, V1, 10 at (0, 0)
+                let mut chip8 = Chip8::create();
+                if hires {
+                    chip8.execute(decode(0x00FF)).unwrap();
+                }
+                chip8.registers[0] = digit + 0x10;
+                chip8.execute(decode(0xF030)).unwrap();
+                assert_eq!(chip8.index_register, 0xA0 + 10 * digit as u16);
+                chip8.execute(decode(0xD11A)).unwrap();
+
+                let glyph: Vec<String> = (0..10).map(|y| row(&chip8, y, 8)).collect();
+                let expected: Vec<String> = BIG_FONT_DATA[digit as usize * 10..][..10].iter()
+                    .map(|byte| (0..8).map(|col| if byte & (0x80 >> col) != 0 { '#' } else { '.' }).collect())
+                    .collect();
+                assert_eq!(glyph, expected, "digit {}, hires {}", digit, hires);
+                assert_eq!(lit(&chip8, &chip8.video).iter().filter(|(x, y)| *x >= 8 || *y >= 10).count(), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn big_font_zero() {
+        let mut chip8 = Chip8::create();
+        chip8.execute(decode(0xF030)).unwrap();
+        chip8.execute(decode(0xD11A)).unwrap();
+        let glyph: Vec<String> = (0..10).map(|y| row(&chip8, y, 8)).collect();
+        assert_eq!(glyph, ["..####..", ".######.", "###..###", "##....##", "##....##", "##....##", "##....##", "###..###", ".######.", "..####.."]);
+    }
+
+    #[test]
+    fn rpl_flags_store_and_load() {
+        let mut chip8 = Chip8::create();
+        chip8.registers[..8].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        chip8.execute(decode(0xF275)).unwrap();
+        assert_eq!(chip8.rpl_flags, [1, 2, 3, 0, 0, 0, 0, 0]);
+        chip8.execute(decode(0xF775)).unwrap();
+        assert_eq!(chip8.rpl_flags, [1, 2, 3, 4, 5, 6, 7, 8]);
+
+        chip8.registers = [0; 16];
+        chip8.execute(decode(0xF185)).unwrap();
+        assert_eq!(chip8.registers[..3], [1, 2, 0]);
+
+        //There are only 8 flags
+        assert!(!matches!(decode(0xF875), Instruction::StoreFlags { .. }));
+        assert!(!matches!(decode(0xF885), Instruction::LoadFlags { .. }));
+    }
+
+    #[test]
+    fn exit_stops_the_machine() {
+        let mut chip8 = machine(&[0x00, 0xFD, 0x60, 0x01]);
+        assert_eq!(chip8.step(), Ok(Instruction::Exit));
+        assert!(chip8.exited);
+        assert_eq!(chip8.program_counter, 0x202);
+
+        //Nothing more runs, not even the next instruction
+        for _ in 0..3 {
+            assert_eq!(chip8.step(), Ok(Instruction::Exit));
+        }
+        assert_eq!((chip8.program_counter, chip8.registers[0]), (0x202, 0));
+    }
+
+    #[test]
+    fn long_index_load() {
+        let mut chip8 = machine(&[0xF0, 0x00, 0xAB, 0xCD, 0x60, 0x01]);
+        chip8.quirks.xo_chip_opcodes = true;
+        assert_eq!(chip8.step(), Ok(Instruction::LoadLongIndex { address: 0xABCD }));
+        assert_eq!((chip8.index_register, chip8.program_counter), (0xABCD, 0x204));
+
+        //Only an XO-CHIP opcode
+        let mut chip8 = machine(&[0xF0, 0x00, 0xAB, 0xCD]);
+        assert!(chip8.step().is_err());
+    }
+
+    #[test]
+    fn every_skip_steps_over_a_long_load() {
+        //V0 = 1, V1 = 1, V2 = 2, key 1 down; each skip is taken
+        for skip in [[0x30, 0x01], [0x40, 0x02], [0x50, 0x10], [0x90, 0x20], [0xE0, 0x9E], [0xE2, 0xA1]] {
+            for xo_chip_opcodes in [true, false] {
+                let mut chip8 = machine(&[skip[0], skip[1], 0xF0, 0x00, 0x12, 0x34]);
+                chip8.quirks.xo_chip_opcodes = xo_chip_opcodes;
+                chip8.registers[..3].copy_from_slice(&[1, 1, 2]);
+                chip8.keypad[1] = true;
+                steps(&mut chip8, 1);
+                let expected = if xo_chip_opcodes { 0x206 } else { 0x204 };
+                assert_eq!(chip8.program_counter, expected, "{:02X}{:02X}, xo-chip opcodes {}", skip[0], skip[1], xo_chip_opcodes);
+            }
+        }
+
+        //Not taken, the F000 runs whole
+        let mut chip8 = machine(&[0x30, 0x02, 0xF0, 0x00, 0x12, 0x34]);
+        chip8.quirks.xo_chip_opcodes = true;
+        chip8.registers[0] = 1;
+        steps(&mut chip8, 2);
+        assert_eq!((chip8.program_counter, chip8.index_register), (0x206, 0x1234));
+    }
+
+    //One lit pixel per row, at x = y, down the whole display
+    fn diagonal(hires: bool) -> Chip8 {
+        let mut chip8 = Chip8::create();
+        chip8.quirks.xo_chip_opcodes = true;
+        if hires {
+            chip8.execute(decode(0x00FF)).unwrap();
+        }
+        let width = chip8.video_width();
+        for y in 0..chip8.video_height() {
+            chip8.video[y + y * width] = 0xFF;
+        }
+        chip8.video_dirty = false;
+        chip8
+    }
+
+    #[test]
+    fn scroll_up() {
+        for hires in [false, true] {
+            for n in [1, 4] {
+                let mut chip8 = diagonal(hires);
+                let height = chip8.video_height();
+                chip8.execute(decode(0x00D0 | n as u16)).unwrap();
+
+                //Row y now holds what was in row y + n, the bottom n are blank
+                let expected: Vec<(usize, usize)> = (n..height).map(|y| (y, y - n)).collect();
+                assert_eq!(lit(&chip8, &chip8.video), expected, "hires {}, n {}", hires, n);
+                assert!(chip8.video_dirty);
+            }
         }
     }
 
-    pub fn load_rom(&mut self, path: &str) {
-        let file = File::open(path).unwrap();
-        let mut buffer_reader = BufReader::new(file);
+    #[test]
+    fn scroll_up_halves_in_lores_under_half_scroll() {
+        let mut chip8 = diagonal(false);
+        chip8.quirks.half_scroll = true;
+        chip8.execute(decode(0x00D4)).unwrap();
+        assert_eq!(lit(&chip8, &chip8.video), (2..32).map(|y| (y, y - 2)).collect::<Vec<_>>());
+
+        //Only an XO-CHIP opcode
+        let mut chip8 = diagonal(false);
+        chip8.quirks.xo_chip_opcodes = false;
+        assert!(chip8.execute(decode(0x00D4)).is_err());
+        assert_eq!(lit(&chip8, &chip8.video).len(), 32);
+    }
 
-        let size = metadata(path).unwrap().len() as usize;
-        let mut buffer = vec![0u8; size];
-        buffer_reader.read_exact(&mut buffer).unwrap();
+    //V0-VF = 0x10-0x1F, I = 0x300, XO-CHIP opcodes on
+    fn range_machine() -> Chip8 {
+        let mut chip8 = Chip8::create();
+        chip8.quirks.xo_chip_opcodes = true;
+        chip8.index_register = 0x300;
+        for (i, register) in chip8.registers.iter_mut().enumerate() {
+            *register = 0x10 + i as u8;
+        }
+        chip8
+    }
 
-        println!("size: {}", size);
-        for (i, buf) in buffer.iter().enumerate() {
-            self.memory[(START_ADDRESS as usize) + i] = *buf;
+    #[test]
+    fn save_register_ranges() {
+        for (opcode, saved) in [(0x5242, vec![0x12, 0x13, 0x14]), (0x5422, vec![0x14, 0x13, 0x12]), (0x5772, vec![0x17])] {
+            for increment_i in [false, true] {
+                let mut chip8 = range_machine();
+                chip8.quirks.increment_i = increment_i;
+                chip8.execute(decode(opcode)).unwrap();
+                assert_eq!(chip8.memory[0x300..0x304], [saved.as_slice(), &[0; 4][saved.len()..]].concat(), "{:04X}", opcode);
+                assert_eq!(chip8.index_register, 0x300, "{:04X}", opcode);
+            }
         }
+    }
 
+    #[test]
+    fn load_register_ranges() {
+        for (opcode, loaded) in [(0x5243, [0xA0, 0xA1, 0xA2]), (0x5423, [0xA2, 0xA1, 0xA0])] {
+            let mut chip8 = range_machine();
+            chip8.quirks.increment_i = true;
+            chip8.poke(0x300, &[0xA0, 0xA1, 0xA2]).unwrap();
+            chip8.execute(decode(opcode)).unwrap();
+            assert_eq!(chip8.registers[1..6], [0x11, loaded[0], loaded[1], loaded[2], 0x15], "{:04X}", opcode);
+            assert_eq!(chip8.index_register, 0x300);
+        }
+
+        let mut chip8 = range_machine();
+        chip8.poke(0x300, &[0xA0]).unwrap();
+        chip8.execute(decode(0x5EE3)).unwrap();
+        assert_eq!(chip8.registers[0xD..], [0x1D, 0xA0, 0x1F]);
+    }
+
+    #[test]
+    fn register_ranges_need_xo_chip_opcodes() {
+        let mut chip8 = range_machine();
+        chip8.quirks.xo_chip_opcodes = false;
+        assert!(chip8.execute(decode(0x5242)).is_err());
+        assert!(chip8.execute(decode(0x5243)).is_err());
+        assert_eq!(chip8.memory[0x300], 0);
+
+        //The rest of the 5 row is still invalid
+        assert_eq!(decode(0x5241), Instruction::Invalid { opcode: 0x5241 });
+        assert!(matches!(decode(0x5240), Instruction::SkipEqReg { .. }));
+    }
+
+    fn chip48() -> Chip8 {
+        let mut chip8 = Chip8::create();
+        chip8.quirks = Quirks::profile("chip48").unwrap();
+        chip8
+    }
+
+    #[test]
+    fn chip48_shifts_vx_in_place() {
+        let mut chip8 = chip48();
+        chip8.registers[1] = 0x81;
+        chip8.registers[2] = 0x02;
+        chip8.execute(decode(0x8126)).unwrap();
+        assert_eq!((chip8.registers[1], chip8.registers[0xF]), (0x40, 1));
+        chip8.execute(decode(0x812E)).unwrap();
+        assert_eq!((chip8.registers[1], chip8.registers[0xF]), (0x80, 0));
+    }
+
+    #[test]
+    fn chip48_jumps_by_vx() {
+        let mut chip8 = chip48();
+        chip8.registers[0] = 0x01;
+        chip8.registers[2] = 0x10;
+        chip8.execute(decode(0xB234)).unwrap();
+        assert_eq!(chip8.program_counter, 0x244);
+    }
+
+    #[test]
+    fn chip48_leaves_i_at_i_plus_x() {
+        let mut chip8 = chip48();
+        chip8.index_register = 0x300;
+        chip8.registers[..3].copy_from_slice(&[1, 2, 3]);
+        chip8.execute(decode(0xF255)).unwrap();
+        assert_eq!((&chip8.memory[0x300..0x303], chip8.index_register), (&[1, 2, 3][..], 0x302));
+
+        chip8.index_register = 0x300;
+        chip8.registers = [0; 16];
+        chip8.execute(decode(0xF265)).unwrap();
+        assert_eq!((&chip8.registers[..3], chip8.index_register), (&[1, 2, 3][..], 0x302));
+
+        //Over increment-i, which would leave it at I + X + 1
+        chip8.quirks.increment_i = true;
+        chip8.index_register = 0x300;
+        chip8.execute(decode(0xF255)).unwrap();
+        assert_eq!(chip8.index_register, 0x302);
+    }
+
+    #[test]
+    fn chip48_logic_leaves_vf() {
+        let mut chip8 = chip48();
+        chip8.registers[0xF] = 5;
+        for opcode in [0x8121, 0x8122, 0x8123] {
+            chip8.execute(decode(opcode)).unwrap();
+            assert_eq!(chip8.registers[0xF], 5, "{:04X}", opcode);
+        }
     }
 
 }
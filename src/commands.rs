@@ -0,0 +1,729 @@
+use std::{fs, io, path::Path, time::{SystemTime, UNIX_EPOCH}};
+
+use clap::{Args, Subcommand};
+
+use crate::{
+    asm,
+    attract,
+    bench,
+    disasm,
+    chip8::{self, Chip8, MEMORY_SIZE},
+    config,
+    headless,
+    history,
+    lint::{self, Severity},
+    lockstep::{run_compare, run_lockstep},
+    octocart::{is_octocart, Octocart},
+    quirks::{Quirks, PROFILE_NAMES, QUIRK_NAMES},
+    repl,
+    rng::{RngLog, RngReplay},
+    rom_info::RomInfo,
+    settings,
+    state::{self, StateDiff},
+    timing::DEFAULT_IPF
+};
+
+//Subcommands run instead of the emulator window. Each returns the
+//process exit code, which is 2 for usage errors like clap's own.
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    #[command(about = "Assemble a program")]
+    Asm {
+        #[arg(value_name = "INPUT.s8")]
+        input: String,
+
+        #[arg(short, value_name = "OUTPUT.ch8", help = "Where the ROM goes [default: next to the input, with a .ch8 extension]")]
+        output: Option<String>
+    },
+
+    #[command(about = "Run every ROM in a directory in turn until a key is pressed")]
+    Attract {
+        dir: String,
+
+        #[arg(long, value_name = "N", default_value_t = attract::DEFAULT_SECONDS, value_parser = clap::value_parser!(u64).range(1..),
+            help = "Seconds each ROM runs")]
+        seconds: u64,
+
+        #[arg(value_name = "OPTIONS", trailing_var_arg = true, allow_hyphen_values = true,
+            help = "Options for the window, after --seconds, see chip8 --help")]
+        options: Vec<String>
+    },
+
+    #[command(about = "Run a ROM without a window or pacing and report the speed")]
+    Bench {
+        rom: String,
+
+        #[arg(long, value_name = "N", default_value = "10_000_000", value_parser = parse_count, help = "Instructions to run, may use _ separators")]
+        cycles: u64,
+
+        #[arg(long, help = "Report as JSON")]
+        json: bool
+    },
+
+    #[command(about = "Run a ROM once per quirk profile and report where they differ")]
+    Compare {
+        rom: String,
+
+        #[arg(long, value_name = "A,B,...", value_delimiter = ',', value_parser = PROFILE_NAMES,
+            help = "Profiles to run, the first is compared with the others [default: all of them]")]
+        profiles: Option<Vec<String>>,
+
+        #[arg(long, value_name = "N", default_value = "100_000", value_parser = parse_count, help = "Instructions to run, may use _ separators")]
+        cycles: u64,
+
+        #[arg(long, value_name = "FILE", help = "Press and release keys as the script says, as for run --headless")]
+        input: Option<String>,
+
+        #[arg(long, value_name = "SEED", default_value = "0", value_parser = parse_count, help = "Seed every machine's random numbers")]
+        seed: u64
+    },
+
+    #[command(about = "Print a commented config file to start from", after_help = config_paths())]
+    Config {
+        #[arg(long, required = true, help = "Write every key, commented out, e.g. chip8 config --print-default > ~/.config/chip8/config.toml")]
+        print_default: bool
+    },
+
+    #[command(about = "Explain opcodes, with the quirks that change them")]
+    Decode {
+        #[arg(value_name = "OPCODE", required_unless_present = "bytes", value_parser = parse_opcode)]
+        opcodes: Vec<u16>,
+
+        #[arg(long, value_name = "BYTES", value_delimiter = ' ', value_parser = parse_byte, conflicts_with = "opcodes",
+            help = "Hex bytes like \"1A 22 60 05\", paired up big-endian into opcodes")]
+        bytes: Vec<u8>
+    },
+
+    #[command(about = "Compare two save states, exits 1 when they differ")]
+    DiffState {
+        #[arg(value_name = "A.state")]
+        a: String,
+
+        #[arg(value_name = "B.state")]
+        b: String
+    },
+
+    #[command(about = "Print a ROM as labelled assembly")]
+    Disasm {
+        rom: String
+    },
+
+    #[command(about = "Describe a ROM, like --info")]
+    Info {
+        rom: String,
+
+        #[arg(long, value_name = "FILE", help = "Match the per-ROM sections of this config file instead of the default one")]
+        config: Option<String>
+    },
+
+    #[command(about = "Check a ROM without running it, exits 1 on errors")]
+    Lint {
+        rom: String
+    },
+
+    #[command(about = "Run a ROM on two machines and report where they first differ")]
+    Lockstep {
+        rom: String,
+
+        #[arg(long, value_name = "N", default_value = "100_000", value_parser = parse_count, help = "Instructions to run, may use _ separators")]
+        cycles: u64,
+
+        #[arg(long, value_name = "QUIRK,...", value_parser = parse_quirks, help = "Quirks turned on for the first machine")]
+        a: Option<Quirks>,
+
+        #[arg(long, value_name = "QUIRK,...", value_parser = parse_quirks, help = "Quirks turned on for the second machine")]
+        b: Option<Quirks>
+    },
+
+    #[command(about = "List the quirks and the presets that turn them on")]
+    Quirks {
+        #[arg(long, required = true, help = "Print the table")]
+        list: bool
+    },
+
+    #[command(about = "List the ROMs played last")]
+    Recent {
+        #[arg(long, help = "Forget them")]
+        clear: bool
+    },
+
+    #[command(about = "Execute instructions typed at a prompt")]
+    Repl,
+
+    #[command(about = "Run a ROM without a window and report a hash of the display")]
+    Run(HeadlessArgs)
+}
+
+#[derive(Args, Debug)]
+pub struct HeadlessArgs {
+    #[arg(long, required = true, help = "Run without a window, the only way chip8 run runs")]
+    pub headless: bool,
+
+    pub rom: String,
+
+    #[arg(long, value_name = "N", default_value = "1_000_000", value_parser = parse_count, help = "Instructions to run, may use _ separators")]
+    pub cycles: u64,
+
+    #[arg(long, value_name = "FILE", help = "Press and release keys as the script says, lines like @1200 press 5")]
+    pub input: Option<String>,
+
+    //Seeded with 0 unless told otherwise so runs are reproducible
+    #[arg(long, value_name = "SEED", default_value = "0", value_parser = parse_count, help = "Seed the random numbers")]
+    pub seed: u64,
+
+    #[arg(long, value_name = "FILE", help = "Write every random number RND draws to FILE, one per line")]
+    pub rng_log: Option<String>,
+
+    #[arg(long, value_name = "FILE", help = "Give RND the numbers an --rng-log wrote, then seeded ones")]
+    pub rng_replay: Option<String>,
+
+    #[arg(long, value_name = "PRESET", value_parser = PROFILE_NAMES, help = "Run with the quirks of an interpreter [default: an Octocart's own, else default]")]
+    pub quirks: Option<String>,
+
+    #[arg(long, value_name = "FILE", help = "Carry on from a save state")]
+    pub load_state: Option<String>,
+
+    #[arg(long, value_name = "FILE", help = "Write a save state at the end")]
+    pub save_state_on_exit: Option<String>,
+
+    //Instructions per 60Hz frame, the timers tick at the end of each
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_IPF as u64, value_parser = clap::value_parser!(u64).range(1..),
+        help = "Instructions per 60Hz frame")]
+    pub ipf: u64,
+
+    #[cfg(feature = "http")]
+    #[arg(long, help = "Download a ROM given as a URL even if it's cached, and don't cache it")]
+    pub no_cache: bool,
+
+    #[arg(long, help = "Write the framebuffer hash alone to stdout")]
+    pub print_hash: bool
+}
+
+//Runs command. rom_sections are the config file's per-ROM sections that
+//apply to chip8 info's ROM. Attract starts the window, main.rs runs it.
+pub fn run(command: Command, rom_sections: &[String]) -> i32 {
+    match command {
+        Command::Asm { input, output } => assemble(&input, output.as_deref()),
+        Command::Attract { .. } => 2,
+        Command::Bench { rom, cycles, json } => bench(&rom, cycles, json),
+        Command::Compare { rom, profiles, cycles, input, seed } => compare(&rom, profiles.as_deref(), cycles, input.as_deref(), seed),
+        Command::Config { .. } => config(),
+        Command::Decode { opcodes, bytes } => decode(&opcodes, &bytes),
+        Command::DiffState { a, b } => diff_state(&a, &b),
+        Command::Disasm { rom } => disassemble(&rom),
+        Command::Info { rom, .. } => info(&rom, rom_sections),
+        Command::Lint { rom } => lint(&rom),
+        Command::Lockstep { rom, cycles, a, b } => lockstep(&rom, cycles, [a.unwrap_or_default(), b.unwrap_or_default()]),
+        Command::Quirks { .. } => quirks(),
+        Command::Recent { clear } => recent(clear),
+        Command::Repl => repl::run(io::stdin().lock(), &mut io::stdout()),
+        Command::Run(args) => run_headless(&args)
+    }
+}
+
+//chip8 diff-state a.state b.state
+//Exits 0 when the states match, 1 when they differ and 2 on errors, like diff
+pub fn diff_state(a_path: &str, b_path: &str) -> i32 {
+    let load = |path: &str| -> Result<Chip8, String> {
+        let bytes = fs::read(path).map_err(|e| format!("Couldn't read {}: {}", path, e))?;
+        Chip8::from_state(&bytes).map_err(|e| format!("Couldn't load {}: {}", path, e))
+    };
+
+    let (a, b) = match (load(a_path), load(b_path)) {
+        (Ok(a), Ok(b)) => (a, b),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+
+    let diff = StateDiff::compare(&a, &b);
+    print!("{}", diff);
+
+    if diff.is_empty() { 0 } else { 1 }
+}
+
+//chip8 asm input.s8 [-o out.ch8]
+//Without -o the output goes next to the input with a .ch8 extension
+pub fn assemble(input: &str, output: Option<&str>) -> i32 {
+    let output = output.map_or_else(|| Path::new(input).with_extension("ch8").to_string_lossy().into_owned(), str::to_string);
+
+    let source = match fs::read_to_string(input) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("Couldn't read {}: {}", input, e);
+            return 2;
+        }
+    };
+
+    let bytes = match asm::assemble(&source) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("{}: {}", input, e);
+            return 1;
+        }
+    };
+
+    match fs::write(&output, &bytes) {
+        Ok(()) => {
+            println!("Wrote {} byte(s) to {}", bytes.len(), output);
+            0
+        },
+        Err(e) => {
+            eprintln!("Couldn't write {}: {}", output, e);
+            2
+        }
+    }
+}
+
+//chip8 disasm rom.ch8
+//The ROM as labelled assembly, see disasm::listing
+pub fn disassemble(path: &str) -> i32 {
+    let Some(rom) = read_rom(path) else {
+        return 2;
+    };
+
+    print!("{}", disasm::listing(&rom));
+    0
+}
+
+//chip8 lint rom.ch8
+//Exits 1 when there are error findings so it can gate a ROM build, 2 when
+//the ROM can't be read
+pub fn lint(path: &str) -> i32 {
+    let Some(rom) = read_rom(path) else {
+        return 2;
+    };
+
+    let findings = lint::lint(&rom);
+    for finding in &findings {
+        println!("{}", finding);
+    }
+
+    let errors = findings.iter().filter(|finding| finding.severity == Severity::Error).count();
+    println!("{} error(s), {} warning(s)", errors, findings.len() - errors);
+
+    if errors > 0 { 1 } else { 0 }
+}
+
+//chip8 lockstep rom.ch8 [--cycles N] [--a quirk,...] [--b quirk,...]
+//Runs the ROM on two machines with the listed quirks turned on and reports
+//the first instruction after which they differ. Exits 0 when they never
+//do, 1 when they diverge and 2 on errors.
+pub fn lockstep(path: &str, cycles: u64, quirks: [Quirks; 2]) -> i32 {
+    let Some(rom) = read_rom(path) else {
+        return 2;
+    };
+    let [mut a, mut b] = quirks.map(|quirks| {
+        let mut chip8 = Chip8::create();
+        chip8.quirks = quirks;
+        chip8
+    });
+    if let Err(e) = a.load_bytes(&rom).and_then(|_| b.load_bytes(&rom)) {
+        eprintln!("{} is {}", path, e);
+        return 2;
+    }
+
+    match run_lockstep(&mut a, &mut b, cycles) {
+        Ok(ran) => {
+            println!("No divergence in {} instruction(s)", ran);
+            0
+        },
+        Err(divergence) => {
+            print!("{}", divergence);
+            1
+        }
+    }
+}
+
+//chip8 bench rom.ch8 [--cycles N] [--json]
+//Runs the ROM without a window or pacing and reports the speed. N may use
+//_ separators, e.g. 10_000_000.
+pub fn bench(path: &str, cycles: u64, json: bool) -> i32 {
+    let Some(rom) = read_rom(path) else {
+        return 2;
+    };
+    let mut chip8 = Chip8::create();
+    if let Err(e) = chip8.load_bytes(&rom) {
+        eprintln!("{} is {}", path, e);
+        return 2;
+    }
+
+    let report = bench::bench(&mut chip8, cycles);
+    if json {
+        println!("{}", report.to_json());
+    } else {
+        print!("{}", report);
+    }
+
+    if report.halted.is_some() { 1 } else { 0 }
+}
+
+//chip8 compare rom.ch8 [--profiles chip8,schip,...] [--cycles N]
+//                      [--input script.txt] [--seed N]
+//Runs the ROM once per quirk profile, side by side with the same input
+//and random numbers, and reports where each profile first differs from
+//the first one listed and what each run ends up showing. --input and
+//--seed work as for run --headless. Exits 0 when all profiles agree, 1
+//when any differ and 2 on errors.
+pub fn compare(path: &str, profiles: Option<&[String]>, cycles: u64, input: Option<&str>, seed: u64) -> i32 {
+    let script = match input.map_or(Ok(Vec::new()), headless::read_script) {
+        Ok(script) => script,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+
+    let names: Vec<&str> = match profiles {
+        Some(names) => names.iter().map(String::as_str).collect(),
+        None => PROFILE_NAMES.to_vec()
+    };
+    if names.len() < 2 {
+        eprintln!("--profiles needs at least two profiles");
+        return 2;
+    }
+    let profiles: Vec<Quirks> = names.iter().map(|name| Quirks::profile(name).unwrap_or_default()).collect();
+
+    let Some(rom) = read_rom(path) else {
+        return 2;
+    };
+    //All the same size, so memory compares byte for byte
+    let memory_size = names.iter().map(|name| Quirks::memory_size(name)).max().unwrap_or(MEMORY_SIZE);
+    let mut machines: Vec<Chip8> = profiles.iter().map(|quirks| {
+        let mut chip8 = Chip8::create_with_memory(memory_size);
+        chip8.quirks = *quirks;
+        chip8.set_seed(seed);
+        chip8
+    }).collect();
+    if let Err(e) = machines.iter_mut().try_for_each(|chip8| chip8.load_bytes(&rom)) {
+        eprintln!("{} is {}", path, e);
+        return 2;
+    }
+
+    let divergences = run_compare(&mut machines, cycles, &script);
+    let hashes: Vec<String> = machines.iter().map(|chip8| {
+        let digest = headless::framebuffer_hash(chip8);
+        digest[..12].to_string()
+    }).collect();
+
+    let width = names.iter().map(|name| name.len()).max().unwrap_or(0);
+    println!("{:width$}  {:12}  Compared with {}", "Profile", "Final video", names[0], width = width);
+    for (i, name) in names.iter().enumerate() {
+        let status = match &divergences[i] {
+            _ if i == 0 => "reference".to_string(),
+            None => "agrees".to_string(),
+            Some(divergence) => format!("diverges after instruction {} at {:#05x}", divergence.cycle, divergence.address)
+        };
+        println!("{:width$}  {}  {}", name, hashes[i], status, width = width);
+    }
+
+    for (name, divergence) in names.iter().zip(divergences.iter()) {
+        if let Some(divergence) = divergence {
+            println!();
+            println!("{} vs {}:", name, names[0]);
+            print!("{}", divergence);
+        }
+    }
+
+    if divergences.iter().all(Option::is_none) { 0 } else { 1 }
+}
+
+//chip8 run --headless rom.ch8 [--cycles N] [--input script.txt] [--seed N]
+//          [--rng-log FILE] [--rng-replay FILE] [--quirks profile]
+//          [--load-state FILE] [--save-state-on-exit FILE] [--ipf N]
+//          [--no-cache] [--print-hash]
+//Runs the ROM without a window, see headless.rs. The status goes to
+//stderr and --print-hash writes the framebuffer hash alone to stdout, so
+//a test can capture it. A loaded state carries on where it was saved,
+//with the script's cycles counted from there. Exits 0 when the ROM ran
+//all N instructions or ended itself with EXIT, 1 when it halted and 2 on
+//errors.
+pub fn run_headless(args: &HeadlessArgs) -> i32 {
+    let path = args.rom.as_str();
+    let script = match args.input.as_deref().map_or(Ok(Vec::new()), headless::read_script) {
+        Ok(script) => script,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+
+    #[cfg(feature = "http")]
+    crate::download::set_use_cache(!args.no_cache);
+    let Some(rom) = read_rom(path) else {
+        return 2;
+    };
+    //Without --quirks an Octocart runs with its own options, other ROMs
+    //with the default ones so runs don't depend on detect.rs
+    let cart = (args.quirks.is_none() && is_octocart(path)).then(|| Octocart::load(path).ok()).flatten();
+    let profile = args.quirks.as_deref().or(cart.as_ref().map(|_| "xo-chip"));
+    let mut chip8 = Chip8::create_with_memory(profile.map_or(MEMORY_SIZE, Quirks::memory_size));
+    chip8.quirks = match cart {
+        Some(cart) => cart.quirks(),
+        None => profile.and_then(Quirks::profile).unwrap_or_default()
+    };
+    chip8.set_seed(args.seed);
+    if let Err(e) = chip8.load_bytes(&rom) {
+        eprintln!("{} is {}", path, e);
+        return 2;
+    }
+    if let Some(path) = &args.rng_replay {
+        match RngReplay::load(path) {
+            Ok(replay) => chip8.set_random_source(Box::new(replay)),
+            Err(e) => {
+                eprintln!("{}", e);
+                return 2;
+            }
+        }
+    }
+    if let Some(path) = &args.rng_log {
+        match fs::File::create(path) {
+            Ok(file) => chip8.set_rng_log(RngLog::create(Box::new(file))),
+            Err(e) => {
+                eprintln!("Can't create {}: {}", path, e);
+                return 2;
+            }
+        }
+    }
+    if let Some(path) = &args.load_state {
+        if let Err(e) = state::load_state_file(&mut chip8, path) {
+            eprintln!("{}", e);
+            return 2;
+        }
+    }
+
+    let report = headless::run(&mut chip8, args.cycles, args.ipf, &script);
+    eprint!("{}", report);
+    if let Some(path) = &args.save_state_on_exit {
+        if let Err(e) = state::save_state_file(&chip8, path) {
+            eprintln!("{}", e);
+            return 2;
+        }
+    }
+    if args.print_hash {
+        println!("{}", report.hash);
+    }
+
+    if report.halted.is_some() { 1 } else { 0 }
+}
+
+//A count that may use _ separators, e.g. 10_000_000
+pub fn parse_count(text: &str) -> Result<u64, String> {
+    text.replace('_', "").parse::<u64>().map_err(|_| format!("Expected a number, got \"{}\"", text))
+}
+
+//"shift-vy,vf-reset" -> those quirks on, the rest off
+pub fn parse_quirks(names: &str) -> Result<Quirks, String> {
+    let mut quirks = Quirks::default();
+    for name in names.split(',').filter(|name| !name.is_empty()) {
+        *quirks.flag_mut(name).ok_or_else(|| format!("unknown quirk {}", name))? = true;
+    }
+
+    Ok(quirks)
+}
+
+//Prints why when the file can't be read or doesn't fit in memory. Takes
+//collection.zip#GAME.ch8 like the window does.
+fn read_rom(path: &str) -> Option<Vec<u8>> {
+    chip8::read_rom(path).map_err(|e| eprintln!("{}", e)).ok()
+}
+
+//chip8 config --print-default
+//Writes a commented config file to start from, e.g.
+//chip8 config --print-default > ~/.config/chip8/config.toml
+pub fn config() -> i32 {
+    print!("{}", config::default_template());
+    0
+}
+
+//Where chip8 config --help says the files are
+fn config_paths() -> String {
+    let mut paths = Vec::new();
+    if let Some(path) = config::default_path() {
+        paths.push(format!("The config file is read from {} or --config <file>", path.display()));
+    }
+    if let Some(path) = settings::default_path() {
+        paths.push(format!("The window size is remembered in {}", path.display()));
+    }
+
+    paths.join("\n")
+}
+
+//chip8 recent [--clear]
+//The ROMs loaded last, newest first. --clear forgets them.
+pub fn recent(clear: bool) -> i32 {
+    let Some(file) = history::default_path() else {
+        eprintln!("Neither XDG_DATA_HOME nor HOME is set, there is no ROM history");
+        return 2;
+    };
+
+    if clear {
+        return match fs::remove_file(&file) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                eprintln!("Couldn't remove {}: {}", file.display(), e);
+                1
+            },
+            _ => 0
+        };
+    }
+
+    let recent = history::load(&file);
+    if recent.is_empty() {
+        println!("No ROMs played yet");
+        return 0;
+    }
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+    for (number, rom) in recent.iter().enumerate() {
+        let missing = if rom.exists() { "" } else { "  (missing)" };
+        println!("{:2}) {:24} {:>12}  {}{}", number + 1, rom.title, history::ago(now, rom.played), rom.path, missing);
+    }
+
+    0
+}
+
+//chip8 quirks --list
+//Every quirk, what it does and whether each --quirks preset turns it on
+pub fn quirks() -> i32 {
+    let profiles: Vec<Quirks> = PROFILE_NAMES.iter().filter_map(|name| Quirks::profile(name)).collect();
+    print!("{:17}", "QUIRK");
+    for name in PROFILE_NAMES {
+        print!("{:9}", name);
+    }
+    println!("MEANING");
+
+    for name in QUIRK_NAMES {
+        print!("{:17}", name);
+        for profile in &profiles {
+            print!("{:9}", if profile.get(name) == Some(true) { "on" } else { "off" });
+        }
+        println!("{}", Quirks::meaning(name).unwrap_or_default());
+    }
+
+    0
+}
+
+//chip8 decode 8A15 00E0 ...
+//chip8 decode --bytes "1A 22 60 05"
+//bytes are paired up big-endian into opcodes
+pub fn decode(opcodes: &[u16], bytes: &[u8]) -> i32 {
+    if !bytes.len().is_multiple_of(2) {
+        eprintln!("{} byte(s) given, opcodes take two each", bytes.len());
+        return 2;
+    }
+
+    let pairs = bytes.chunks(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]]));
+    for opcode in opcodes.iter().copied().chain(pairs) {
+        print!("{}", disasm::explain(opcode));
+    }
+    0
+}
+
+pub fn parse_opcode(text: &str) -> Result<u16, String> {
+    parse_hex(text, 4).map(|opcode| opcode as u16)
+}
+
+pub fn parse_byte(text: &str) -> Result<u8, String> {
+    parse_hex(text, 2).map(|byte| byte as u8)
+}
+
+//Up to digits hex digits, with or without 0x
+fn parse_hex(text: &str, digits: usize) -> Result<u32, String> {
+    let hex = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+    if hex.is_empty() || hex.len() > digits {
+        return Err(format!("Not a {}-digit hex value: {}", digits, text));
+    }
+
+    u32::from_str_radix(hex, 16).map_err(|_| format!("Not a {}-digit hex value: {}", digits, text))
+}
+
+//chip8 --info=rom.ch8, or chip8 info rom.ch8
+//rom_sections are the config file's per-ROM sections that apply to it
+pub fn info(path: &str, rom_sections: &[String]) -> i32 {
+    //The ROM as it gets loaded, so a zip entry or assembled .8o source
+    //shows the same SHA-1 that per-ROM sections and save states use
+    let Some(rom) = read_rom(path) else {
+        return 2;
+    };
+
+    println!("File:      {}", path);
+    print!("{}", RomInfo::scan(&rom));
+    match rom_sections {
+        [] => println!("Config:    no per-ROM section"),
+        sections => println!("Config:    {}", sections.join(", "))
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+
+    use clap::Parser;
+
+    use super::*;
+    use crate::config::Config;
+
+    fn command(args: &[&str]) -> Result<Command, String> {
+        Config::try_parse_from(["chip8"].iter().chain(args))
+            .map(|config| config.command.unwrap())
+            .map_err(|e| e.to_string().lines().next().unwrap_or_default().trim_start_matches("error: ").to_string())
+    }
+
+    #[test]
+    fn parses_subcommands() {
+        let Ok(Command::Run(args)) = command(&["run", "pong.ch8", "--headless", "--cycles=10_000", "--quirks", "schip", "--print-hash"]) else {
+            panic!("not run");
+        };
+        assert_eq!((args.rom.as_str(), args.cycles, args.seed, args.ipf), ("pong.ch8", 10_000, 0, DEFAULT_IPF as u64));
+        assert_eq!(args.quirks.as_deref(), Some("schip"));
+        assert!(args.print_hash);
+
+        let Ok(Command::Lockstep { rom, cycles, a, b }) = command(&["lockstep", "pong.ch8", "--a", "shift-vy,vf-reset"]) else {
+            panic!("not lockstep");
+        };
+        assert_eq!((rom.as_str(), cycles, b), ("pong.ch8", 100_000, None));
+        assert!(a.is_some_and(|a| a.shift_vy && a.vf_reset && !a.jump_vx));
+
+        let Ok(Command::Compare { profiles, seed, .. }) = command(&["compare", "pong.ch8", "--profiles", "chip8,schip", "--seed", "7"]) else {
+            panic!("not compare");
+        };
+        assert_eq!((profiles, seed), (Some(vec!["chip8".to_string(), "schip".to_string()]), 7));
+
+        let Ok(Command::Decode { opcodes, bytes }) = command(&["decode", "--bytes", "1A 22 60 05"]) else {
+            panic!("not decode");
+        };
+        assert_eq!((opcodes, bytes), (vec![], vec![0x1A, 0x22, 0x60, 0x05]));
+
+        let Ok(Command::Attract { dir, seconds, options }) = command(&["attract", "roms", "--seconds", "5", "--scale", "4"]) else {
+            panic!("not attract");
+        };
+        assert_eq!((dir.as_str(), seconds, options), ("roms", 5, vec!["--scale".to_string(), "4".to_string()]));
+
+        assert!(matches!(command(&["info", "pong.ch8", "--config", "c.toml"]), Ok(Command::Info { config: Some(_), .. })));
+        assert!(matches!(command(&["bench", "pong.ch8", "--json"]), Ok(Command::Bench { cycles: 10_000_000, json: true, .. })));
+    }
+
+    #[test]
+    fn subcommand_errors() {
+        for (args, expected) in [
+            (&["run", "pong.ch8"][..], "the following required arguments were not provided:"),
+            (&["run", "--headless", "pong.ch8", "--cycles", "lots"], "invalid value 'lots' for '--cycles <N>': Expected a number, got \"lots\""),
+            (&["run", "--headless", "pong.ch8", "--ipf", "0"], "invalid value '0' for '--ipf <N>': 0 is not in 1.."),
+            (&["bench"], "the following required arguments were not provided:"),
+            (&["bench", "pong.ch8", "--frob"], "unexpected argument '--frob' found"),
+            (&["lockstep", "pong.ch8", "--a", "shift-vy,frob"], "invalid value 'shift-vy,frob' for '--a <QUIRK,...>': unknown quirk frob"),
+            (&["compare", "pong.ch8", "--profiles", "chip8,nope"], "invalid value 'nope' for '--profiles <A,B,...>'"),
+            (&["decode"], "the following required arguments were not provided:"),
+            (&["decode", "12345"], "invalid value '12345' for '[OPCODE]...': Not a 4-digit hex value: 12345"),
+            (&["decode", "00E0", "--bytes", "00 E0"], "the argument '[OPCODE]...' cannot be used with '--bytes <BYTES>'"),
+            (&["quirks"], "the following required arguments were not provided:"),
+            (&["attract", "roms", "--seconds", "0"], "invalid value '0' for '--seconds <N>': 0 is not in 1.."),
+            (&["lint", "pong.ch8", "--scale", "4"], "unexpected argument '--scale' found")
+        ] {
+            let message = command(args).err().unwrap_or_default();
+            assert!(message.starts_with(expected), "{:?}: {}", args, message);
+        }
+    }
+
+}
@@ -0,0 +1,701 @@
+use std::{env, fs, path::{Path, PathBuf}};
+
+use clap::{CommandFactory, Parser};
+use log::LevelFilter;
+use piston::Key;
+use toml_edit::{Document, Item, Value};
+
+use crate::{
+    chip8::{self, LowMemoryProtection},
+    commands::Command,
+    detect::Detection,
+    device::{DisplayMode, Palette, DEFAULT_PALETTE},
+    input,
+    poke::{self, Poke},
+    logging,
+    quirks::{self, Quirks, PROFILE_NAMES},
+    timing::ExecOrder
+};
+
+//Everything the emulator window is started with. Options that take an
+//optional value (--ipf, --profile, ...) need it attached with =, so a
+//following ROM path isn't mistaken for it.
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about = "A CHIP-8 emulator",
+    args_override_self = true,
+    mut_args = switch_values,
+    args_conflicts_with_subcommands = true
+)]
+pub struct Config {
+    #[arg(long, value_name = "FILE", help = "Read options from this TOML file instead of the default one, see chip8 config")]
+    pub config: Option<String>,
+
+    //Without one the user picks from a menu, see main.rs
+    #[arg(help = "ROM to run")]
+    pub rom: Option<String>,
+
+    #[arg(long, value_name = "ROM", help = "Describe a ROM and exit")]
+    pub info: Option<String>,
+
+    #[arg(long, value_name = "DIR", help = "Browse every ROM in a directory with Page Up/Down")]
+    pub rom_dir: Option<String>,
+
+    #[cfg(feature = "http")]
+    #[arg(long, help = "Download a ROM given as a URL even if it's cached, and don't cache it")]
+    pub no_cache: bool,
+
+    #[cfg(feature = "octo")]
+    #[arg(long, value_name = "PATH", help = "Octo's command line tool, used to assemble .8o ROMs [default: $CHIP8_OCTO, else octo]")]
+    pub octo: Option<String>,
+
+    #[arg(long, value_name = "SECONDS", requires = "rom_dir", conflicts_with = "rom", value_parser = clap::value_parser!(u64).range(1..),
+        help = "Run every ROM in the --rom-dir for SECONDS each until a key is pressed, see chip8 attract")]
+    pub attract: Option<u64>,
+
+    #[arg(long, help = "Without a ROM, exit with usage instead of offering a menu of ROMs")]
+    pub no_picker: bool,
+
+    #[arg(long, conflicts_with_all = ["rom", "attract"],
+        help = "Start without a ROM, paused at 0x200 over blank memory, for entering a program with the debugger")]
+    pub blank: bool,
+
+    #[arg(long, help = "Don't read or update the list of recently played ROMs")]
+    pub no_history: bool,
+
+    #[arg(long, help = "Don't keep SUPER-CHIP's FX75 flags (high scores, settings) between runs")]
+    pub no_flag_persistence: bool,
+
+    #[arg(long, value_name = "DIR", help = "Keep SUPER-CHIP's FX75 flags (high scores, settings) in DIR [default: rpl/ in the data directory]")]
+    pub save_dir: Option<String>,
+
+    //None leaves it to the saved settings, see settings.rs
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..),
+        help = "Window pixels per CHIP-8 pixel [default: the size the window was last left at, else 16]")]
+    pub scale: Option<u32>,
+
+    #[arg(long, value_name = "BOOL", default_value_t = true, num_args = 0..=1, require_equals = true, default_missing_value = "true",
+        action = clap::ArgAction::Set, hide_possible_values = true, help = "Keep the display at whole-pixel scales, =false lets it stretch")]
+    pub integer_scale: bool,
+
+    #[arg(long, value_name = "MODE", default_value = "raw", value_parser = DisplayMode::parse,
+        help = "Show video as it is (raw, flickers like the real thing), with lit pixels fading out (fade) or averaging the last two frames (blend). F11 cycles them")]
+    pub display_mode: DisplayMode,
+
+    #[arg(long, value_name = "COLORS", value_parser = Palette::parse,
+        help = "Four RRGGBB colors for no plane lit, XO-CHIP's first plane, its second and both [default: 000000,ffffff,aaaaaa,555555, or an Octocart's own]")]
+    pub palette: Option<Palette>,
+
+    #[arg(long, value_name = "MS", default_value_t = 1, help = "Milliseconds between emulation updates")]
+    pub cycle_delay: u128,
+
+    //Some(None) for a bare --ipf, which means DEFAULT_IPF
+    #[arg(long, value_name = "N", num_args = 0..=1, require_equals = true,
+        conflicts_with = "cycle_accurate", help = "Run N instructions per 60Hz frame (default 10)")]
+    pub ipf: Option<Option<u32>>,
+
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..=1000),
+        help = "Run exactly N frames a second, reporting dropped frames. Timers tick once a frame, so 60 is full speed")]
+    pub fps: Option<u32>,
+
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..=1000), conflicts_with = "fps",
+        help = "Draw at most N frames a second, e.g. to lighten recording. The emulation keeps its own pace")]
+    pub render_fps: Option<u32>,
+
+    #[arg(long, help = "Pace instructions by COSMAC VIP timing")]
+    pub cycle_accurate: bool,
+
+    #[arg(long, value_name = "ORDER", default_value = "pre", value_parser = ExecOrder::parse,
+        help = "Run each frame's instructions before the render that shows them (pre), or after it (post), which shows every frame one render later")]
+    pub exec_order: ExecOrder,
+
+    #[arg(long, help = "Skip the repeats of a keypad polling loop while no key is down, to save host CPU. Needs --ipf, --cycle-accurate or --fps")]
+    pub idle_throttle: bool,
+
+    #[arg(long, value_name = "PRESET", value_parser = PROFILE_NAMES,
+        help = "Start from the quirks of an interpreter, see chip8 quirks --list. Without it the ROM's own is detected")]
+    pub quirks: Option<String>,
+
+    #[arg(long, help = "Start from the default quirks when --quirks isn't given, instead of detecting the ROM's")]
+    pub no_autodetect: bool,
+
+    #[arg(long, value_name = "NAME=on|off", value_parser = quirks::parse_override, action = clap::ArgAction::Append,
+        help = "Turn one quirk on or off on top of --quirks, can be repeated")]
+    pub quirk: Vec<(String, bool)>,
+
+    #[arg(long, value_name = "ADDR=VALUE", value_parser = poke::parse_poke, action = clap::ArgAction::Append,
+        help = "Write a byte into memory after the ROM loads, can be repeated")]
+    pub poke: Vec<Poke>,
+
+    #[arg(long, value_name = "FILE", help = "Apply one ADDR=VALUE poke per line, before any --poke")]
+    pub poke_file: Option<String>,
+
+    #[arg(long, value_name = "SEED", help = "Seed the random numbers, the same seed and input replay the same run")]
+    pub seed: Option<u64>,
+
+    #[arg(long, value_name = "FILE", help = "Write every random number RND draws to FILE, one per line")]
+    pub rng_log: Option<String>,
+
+    #[arg(long, value_name = "FILE", help = "Give RND the numbers an --rng-log wrote, whatever the seed, then seeded ones")]
+    pub rng_replay: Option<String>,
+
+    #[arg(long, value_name = "N", num_args = 0..=1, require_equals = true, default_missing_value = "10",
+        help = "Print the N hottest addresses on exit (default 10)")]
+    pub profile: Option<usize>,
+
+    #[arg(long, value_name = "MODE", num_args = 0..=1, require_equals = true, default_missing_value = "error",
+        default_value = "off", value_parser = parse_protection, help = "Writes below 0x200: off, warn or error (the default without a value)")]
+    pub protect_low_mem: LowMemoryProtection,
+
+    #[arg(long, help = "Halt as soon as an instruction leaves the machine inconsistent")]
+    pub verify_invariants: bool,
+
+    #[arg(long, help = "Skip SUPER-CHIP/XO-CHIP instructions instead of halting on them")]
+    pub lenient: bool,
+
+    #[arg(long, help = "Report behavior that depends on a quirk, with a summary on exit")]
+    pub strict: bool,
+
+    #[arg(long, help = "Leave the font area zeroed for ROMs that install their own, FX29 digits are blank otherwise")]
+    pub no_fontset: bool,
+
+    #[arg(long, help = "Print every CALL and RET with the resulting stack depth")]
+    pub log_stack: bool,
+
+    #[arg(long, help = "Warn when FX65/DXYN read memory nothing ever wrote")]
+    pub warn_uninit: bool,
+
+    #[arg(long, help = "Warn when DXYN draws a sprite from past the end of the ROM or wraps it around the end of memory")]
+    pub warn_sprite_overrun: bool,
+
+    #[arg(long, value_name = "LEVEL", value_parser = logging::parse_level,
+        help = "Messages to show: off, error, warn, info, debug or trace (default info, or RUST_LOG)")]
+    pub log_level: Option<LevelFilter>,
+
+    #[arg(long, value_name = "FILE", help = "Label addresses in the debugger and --trace output")]
+    pub symbols: Option<String>,
+
+    #[arg(long, help = "Print every instruction as it executes")]
+    pub trace: bool,
+
+    #[arg(long, value_name = "FILE", requires = "rom", help = "Restore a save state right after loading the ROM it was saved from")]
+    pub load_state: Option<String>,
+
+    #[arg(long, value_name = "FILE", help = "Write a save state when the window is closed")]
+    pub save_state_on_exit: Option<String>,
+
+    #[arg(long, value_name = "DIR", help = "On exit or a crash, leave the state, a diagnostic report, the screen and any input log in DIR")]
+    pub dump_state_on_exit: Option<String>,
+
+    #[arg(long, help = "Load the ROM but wait before its first instruction, P or the debugger's continue starts it")]
+    pub start_paused: bool,
+
+    #[arg(long, help = "Read debugger commands from stdin")]
+    pub debug: bool,
+
+    #[arg(long, value_name = "ADDR:PORT", help = "Serve the debugger over TCP")]
+    pub debug_listen: Option<String>,
+
+    #[arg(long, value_name = "PNG", help = "Write a map of executed/read/written memory on exit")]
+    pub coverage: Option<String>,
+
+    #[arg(long, value_name = "GIF", help = "Record every rendered frame, F9 pauses and resumes")]
+    pub record_gif: Option<String>,
+
+    #[arg(long, value_name = "FILE", num_args = 0..=1, require_equals = true,
+        help = "Log keypad presses and releases to stderr, or to FILE")]
+    pub log_input: Option<Option<String>>,
+
+    #[arg(long, value_name = "LAYOUT", default_value = "qwerty", value_parser = input::LAYOUT_NAMES,
+        help = "The keyboard's layout, so the keypad is on the same keys: qwerty, azerty, qwertz, dvorak or colemak")]
+    pub layout: String,
+
+    #[arg(long, value_name = "KEY=PAD", value_parser = input::parse_keymap_entry, action = clap::ArgAction::Append,
+        help = "Map a keyboard key to a keypad key on top of the --layout, e.g. Space=5, can be repeated")]
+    pub keymap: Vec<(Key, u8)>,
+
+    #[arg(long, help = "Log every set of keypad keys held at once, to check the keyboard doesn't drop any")]
+    pub log_chords: bool,
+
+    //Run instead of the window, see commands.rs
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    //The config file's [rom."..."] sections that applied, with the file
+    #[arg(skip)]
+    pub rom_sections: Vec<String>
+}
+
+//Every switch also takes =true or =false, so the command line can turn
+//off one the config file turned on
+fn switch_values(arg: clap::Arg) -> clap::Arg {
+    if !matches!(arg.get_action(), clap::ArgAction::SetTrue) {
+        return arg;
+    }
+
+    arg.action(clap::ArgAction::Set)
+        .num_args(0..=1)
+        .require_equals(true)
+        .default_missing_value("true")
+        .default_value("false")
+        .value_name("BOOL")
+        .hide_default_value(true)
+        .hide_possible_values(true)
+}
+
+//--protect-low-mem's values. true/false are kept from the old parser.
+fn parse_protection(value: &str) -> Result<LowMemoryProtection, String> {
+    match value {
+        "off" | "false" => Ok(LowMemoryProtection::Off),
+        "warn" => Ok(LowMemoryProtection::Warn),
+        "error" | "true" => Ok(LowMemoryProtection::Error),
+        _ => Err("expected off, warn or error".to_string())
+    }
+}
+
+//The config file is TOML with a table per section. Each key stands for
+//the long option of the same name and is passed as that option ahead of
+//the real command line, so parsing alone gives the precedence: command
+//line, then the ROM's [rom."..."] sections, then the rest of the file,
+//then the defaults above. Switches are passed as --switch=true or
+//--switch=false, so --switch=false turns off one the file turned on.
+struct FileKey {
+    section: &'static str,
+    key: &'static str,
+    //Shown commented out in the template
+    example: &'static str
+}
+
+//[rom."..."] holds per-ROM overrides, see file_args
+const ROM_SECTION: &str = "rom";
+
+const FILE_KEYS: &[FileKey] = &[
+    FileKey { section: "display", key: "scale", example: "16" },
+    FileKey { section: "display", key: "integer_scale", example: "true" },
+    FileKey { section: "display", key: "display_mode", example: "\"fade\"" },
+    FileKey { section: "display", key: "palette", example: "\"000000,ffffff,aaaaaa,555555\"" },
+    FileKey { section: "display", key: "fps", example: "60" },
+    FileKey { section: "display", key: "render_fps", example: "30" },
+    FileKey { section: "emulation", key: "ipf", example: "10" },
+    FileKey { section: "emulation", key: "cycle_accurate", example: "false" },
+    FileKey { section: "emulation", key: "exec_order", example: "\"pre\"" },
+    FileKey { section: "emulation", key: "cycle_delay", example: "1" },
+    FileKey { section: "emulation", key: "idle_throttle", example: "false" },
+    FileKey { section: "emulation", key: "quirks", example: "\"chip8\"" },
+    FileKey { section: "emulation", key: "no_autodetect", example: "false" },
+    FileKey { section: "emulation", key: "quirk", example: "[\"shift-vy=on\", \"increment-i=off\"]" },
+    FileKey { section: "emulation", key: "seed", example: "1234" },
+    FileKey { section: "emulation", key: "no_fontset", example: "false" },
+    FileKey { section: "emulation", key: "poke", example: "[\"0x3A2=0xFF\"]" },
+    FileKey { section: "emulation", key: "protect_low_mem", example: "\"off\"" },
+    FileKey { section: "emulation", key: "verify_invariants", example: "false" },
+    FileKey { section: "emulation", key: "lenient", example: "false" },
+    FileKey { section: "emulation", key: "strict", example: "false" },
+    FileKey { section: "input", key: "layout", example: "\"qwerty\"" },
+    FileKey { section: "input", key: "keymap", example: "[\"Space=5\", \"Up=2\"]" },
+    FileKey { section: "input", key: "log_input", example: "\"input.log\"" },
+    FileKey { section: "input", key: "log_chords", example: "false" },
+    FileKey { section: "paths", key: "rom_dir", example: "\"roms\"" },
+    FileKey { section: "paths", key: "symbols", example: "\"game.sym\"" },
+    FileKey { section: "paths", key: "poke_file", example: "\"patches.txt\"" },
+    FileKey { section: "paths", key: "no_history", example: "false" },
+    FileKey { section: "paths", key: "save_dir", example: "\"saves\"" },
+    FileKey { section: "paths", key: "no_flag_persistence", example: "false" }
+];
+
+impl Config {
+
+    //Parses the command line, args with the program first, on top of the
+    //file given with --config, or the default one if there is one. Exits
+    //on errors like parse() does. Subcommands don't read the file, except
+    //chip8 info for the ROM's per-ROM sections.
+    pub fn load(args: Vec<String>) -> Config {
+        let mut command_line = Config::parse_from(&args);
+        let (config, info) = match &command_line.command {
+            None => (command_line.config.clone(), command_line.info.clone()),
+            Some(Command::Info { rom, config }) => (config.clone(), Some(rom.clone())),
+            Some(_) => return command_line
+        };
+
+        let path = match config {
+            Some(path) => PathBuf::from(path),
+            None => match default_path().filter(|path| path.exists()) {
+                Some(path) => path,
+                None => return command_line
+            }
+        };
+
+        //Per-ROM sections need the ROM, the one to run or to --info. A URL
+        //is downloaded for that, so --no-cache has to apply already.
+        #[cfg(feature = "http")]
+        crate::download::set_use_cache(!command_line.no_cache);
+        let rom = command_line.rom.as_ref().or(info.as_ref()).and_then(|path| rom_key(path));
+        let file_args = fs::read_to_string(&path)
+            .map_err(|e| format!("Couldn't read {}: {}", path.display(), e))
+            .and_then(|source| file_args(&source, rom.as_ref()).map_err(|e| format!("{}: {}", path.display(), e)))
+            .unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(2);
+            });
+        let rom_sections = file_args.rom_sections.iter().map(|section| format!("{} in {}", section, path.display())).collect();
+        if command_line.command.is_some() {
+            command_line.rom_sections = rom_sections;
+            return command_line;
+        }
+
+        let mut config = Config::parse_from(args.iter().take(1).chain(&file_args.args).chain(args.iter().skip(1)));
+        config.rom_sections = rom_sections;
+        config
+    }
+
+    //--palette, else an Octocart's colors, else DEFAULT_PALETTE
+    pub fn palette(&self, detected: Option<&Detection>) -> Palette {
+        self.palette
+            .or(detected.and_then(|detected| detected.palette))
+            .or_else(|| Palette::parse(DEFAULT_PALETTE).ok())
+            .unwrap_or(Palette([[0; 3]; 4]))
+    }
+
+    //Whether the ROM picks the profile, see detect.rs
+    pub fn autodetect(&self) -> bool {
+        self.quirks.is_none() && !self.no_autodetect
+    }
+
+    //The --quirks preset, else the one detected for the ROM, else default
+    pub fn profile(&self, detected: Option<&Detection>) -> &str {
+        match (&self.quirks, detected) {
+            (Some(profile), _) => profile,
+            (None, Some(detected)) if !self.no_autodetect => detected.profile,
+            _ => "default"
+        }
+    }
+
+    //The --quirks preset, else the detected quirks, else the default ones,
+    //with every --quirk applied in order
+    pub fn quirks(&self, detected: Option<&Detection>) -> Quirks {
+        let mut quirks = match (&self.quirks, detected) {
+            (Some(profile), _) => Quirks::profile(profile).unwrap_or_default(),
+            (None, Some(detected)) if !self.no_autodetect => detected.quirks,
+            _ => Quirks::default()
+        };
+        for (name, value) in &self.quirk {
+            if let Some(flag) = quirks.flag_mut(name) {
+                *flag = *value;
+            }
+        }
+
+        quirks
+    }
+
+}
+
+//None when the ROM can't be read, loading it reports that later
+fn rom_key(path: &str) -> Option<RomKey> {
+    let rom = chip8::read_rom(path).ok()?;
+    let name = Path::new(path).file_name()?.to_string_lossy().into_owned();
+    Some(RomKey { name, sha1: sha1_smol::Sha1::from(&rom).digest().to_string() })
+}
+
+//config.toml in the config directory
+pub fn default_path() -> Option<PathBuf> {
+    Some(config_dir()?.join("config.toml"))
+}
+
+//$XDG_CONFIG_HOME/chip8, else ~/.config/chip8
+pub fn config_dir() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+    Some(config_home.join("chip8"))
+}
+
+//Where the emulator keeps what it writes itself, like the ROM history:
+//$XDG_DATA_HOME/chip8, else ~/.local/share/chip8
+pub fn data_dir() -> Option<PathBuf> {
+    let data_home = env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share")))?;
+
+    Some(data_home.join("chip8"))
+}
+
+//The ROM being started, as per-ROM sections name it: [rom."pong.ch8"]
+//by file name or [rom."sha1:0f1e..."] by hash, which may be cut short to
+//8 or more digits
+pub struct RomKey {
+    pub name: String,
+    pub sha1: String
+}
+
+//Options a config file stands for, and the per-ROM sections that matched
+pub struct FileArgs {
+    pub args: Vec<String>,
+    pub rom_sections: Vec<String>
+}
+
+//Turns a config file into the options it stands for. Matching per-ROM
+//sections come after the global keys, in file order, so they win over
+//them; every section is checked whether it matches or not.
+pub fn file_args(source: &str, rom: Option<&RomKey>) -> Result<FileArgs, String> {
+    let document: Document = source.parse().map_err(|e: toml_edit::TomlError| e.to_string())?;
+    let command = Config::command();
+    let mut args = Vec::new();
+    let mut rom_args = Vec::new();
+    let mut rom_sections = Vec::new();
+
+    for (section, item) in document.iter() {
+        let Some(table) = item.as_table() else {
+            return Err(at_line(source, "", section, format!("{} isn't in a section, expected one of {}", section, section_names())));
+        };
+
+        if section == ROM_SECTION {
+            for (name, item) in table.iter() {
+                let header = format!("{}.\"{}\"", ROM_SECTION, name);
+                let Some(table) = item.as_table() else {
+                    return Err(at_line(source, ROM_SECTION, name, format!("{}: expected a section like [{}]", name, header)));
+                };
+
+                let mut section_args = Vec::new();
+                for (key, item) in table.iter() {
+                    let error = |message: String| at_line(source, &header, key, format!("{}.{}: {}", header, key, message));
+                    if !FILE_KEYS.iter().any(|file_key| file_key.section != "paths" && file_key.key == key) {
+                        let known: Vec<&str> = FILE_KEYS.iter().filter(|file_key| file_key.section != "paths").map(|file_key| file_key.key).collect();
+                        return Err(error(format!("unknown key, per-ROM sections take {}", known.join(", "))));
+                    }
+                    section_args.extend(key_args(&command, key, item).map_err(error)?);
+                }
+
+                if rom.is_some_and(|rom| rom_matches(name, rom)) {
+                    rom_args.extend(section_args);
+                    rom_sections.push(format!("[{}]", header));
+                }
+            }
+            continue;
+        }
+
+        for (key, item) in table.iter() {
+            let error = |message: String| at_line(source, section, key, format!("{}.{}: {}", section, key, message));
+
+            if !FILE_KEYS.iter().any(|file_key| file_key.section == section && file_key.key == key) {
+                let known: Vec<&str> = FILE_KEYS.iter().filter(|file_key| file_key.section == section).map(|file_key| file_key.key).collect();
+                return Err(if known.is_empty() {
+                    at_line(source, "", section, format!("unknown section [{}], expected one of {}", section, section_names()))
+                } else {
+                    error(format!("unknown key, [{}] takes {}", section, known.join(", ")))
+                });
+            }
+
+            args.extend(key_args(&command, key, item).map_err(error)?);
+        }
+    }
+
+    args.extend(rom_args);
+    Ok(FileArgs { args, rom_sections })
+}
+
+//One key's value as the options it stands for
+fn key_args(command: &clap::Command, key: &str, item: &Item) -> Result<Vec<String>, String> {
+    let option = format!("--{}", key.replace('_', "-"));
+    //Switches, see switch_values, and --integer-scale
+    let switch = command.get_arguments()
+        .find(|arg| arg.get_id() == key)
+        .and_then(|arg| arg.get_value_names())
+        .is_some_and(|names| names == ["BOOL"]);
+
+    let key_args: Vec<String> = match item {
+        Item::Value(Value::Boolean(value)) => vec![format!("{}={}", option, value.value())],
+        Item::Value(Value::Integer(value)) if !switch => vec![format!("{}={}", option, value.value())],
+        Item::Value(Value::String(value)) if !switch => vec![format!("{}={}", option, value.value())],
+        //Repeats the option, for ones like --quirk
+        Item::Value(Value::Array(values)) if !switch => match values.iter().map(|value| value.as_str()).collect::<Option<Vec<&str>>>() {
+            Some(values) => values.iter().map(|value| format!("{}={}", option, value)).collect(),
+            None => return Err("expected a list of strings".to_string())
+        },
+        _ if switch => return Err("expected true or false".to_string()),
+        _ => return Err("expected a number, a string or true/false".to_string())
+    };
+
+    for arg in &key_args {
+        //Checked alone so a bad value is blamed on its line
+        if let Err(e) = Config::try_parse_from(["chip8", arg.as_str()]) {
+            let message = e.to_string();
+            return Err(message.lines().next().unwrap_or_default().trim_start_matches("error: ").to_string());
+        }
+    }
+
+    Ok(key_args)
+}
+
+fn rom_matches(section: &str, rom: &RomKey) -> bool {
+    match section.strip_prefix("sha1:") {
+        Some(hash) => hash.len() >= 8 && rom.sha1.starts_with(&hash.to_ascii_lowercase()),
+        None => section == rom.name
+    }
+}
+
+//Prefixes message with the line the key (or [section] header, when
+//section is empty) is on
+fn at_line(source: &str, section: &str, key: &str, message: String) -> String {
+    let mut current = "";
+    for (number, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if let Some(header) = line.strip_prefix('[').and_then(|header| header.split(']').next()) {
+            current = header.trim();
+            if section.is_empty() && current == key {
+                return format!("line {}: {}", number + 1, message);
+            }
+        } else if current == section && line.strip_prefix(key).is_some_and(|rest| rest.trim_start().starts_with('=')) {
+            return format!("line {}: {}", number + 1, message);
+        }
+    }
+
+    message
+}
+
+fn section_names() -> String {
+    let mut sections: Vec<&str> = FILE_KEYS.iter().map(|file_key| file_key.section).collect();
+    sections.dedup();
+    let mut names: Vec<String> = sections.iter().map(|section| format!("[{}]", section)).collect();
+    names.push(format!("[{}.\"...\"]", ROM_SECTION));
+    names.join(", ")
+}
+
+//What chip8 config --print-default writes, every key commented out
+pub fn default_template() -> String {
+    let command = Config::command();
+    let mut template = String::from(
+        "# chip8 config file. Options given on the command line win over the\n\
+         # ones here. Uncomment a key to set it.\n");
+
+    let mut section = "";
+    for file_key in FILE_KEYS {
+        if file_key.section != section {
+            section = file_key.section;
+            template.push_str(&format!("\n[{}]\n", section));
+        }
+
+        let help = command.get_arguments()
+            .find(|arg| arg.get_id() == file_key.key)
+            .and_then(|arg| arg.get_help())
+            .map(|help| help.to_string())
+            .unwrap_or_default();
+        template.push_str(&format!("# {}\n# {} = {}\n", help, file_key.key, file_key.example));
+    }
+
+    template.push_str(
+        "\n# Overrides for one ROM, by file name or by SHA-1 (chip8 --info shows it,\n\
+         # 8 or more digits will do). They take the keys above except [paths],\n\
+         # without their section, and win over them.\n\
+         # [rom.\"pong.ch8\"]\n\
+         # ipf = 15\n\
+         # [rom.\"sha1:0123456789abcdef\"]\n\
+         # quirks = \"schip\"\n");
+
+    template
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    //The first line of clap's message, without "error: "
+    fn parse_error(args: &[&str]) -> String {
+        let e = Config::try_parse_from(["chip8"].iter().chain(args)).unwrap_err();
+        e.to_string().lines().next().unwrap_or_default().trim_start_matches("error: ").to_string()
+    }
+
+    #[test]
+    fn parses_options() {
+        let config = Config::try_parse_from(["chip8", "pong.ch8", "--scale", "4", "--quirks=schip", "--quirk", "shift-vy=on", "--ipf=15", "--poke=0x300=0xFF"]).unwrap();
+
+        assert_eq!(config.rom.as_deref(), Some("pong.ch8"));
+        assert_eq!(config.scale, Some(4));
+        assert_eq!(config.profile(None), "schip");
+        assert!(config.quirks(None).shift_vy);
+        assert_eq!(config.ipf, Some(Some(15)));
+        assert_eq!(config.poke.len(), 1);
+    }
+
+    #[test]
+    fn option_errors() {
+        for (args, expected) in [
+            (&["--frob"][..], "unexpected argument '--frob' found"),
+            (&["--scale"], "a value is required for '--scale <SCALE>' but none was supplied"),
+            (&["--scale", "0"], "invalid value '0' for '--scale <SCALE>': 0 is not in 1..=4294967295"),
+            (&["--scale", "big"], "invalid value 'big' for '--scale <SCALE>': invalid digit found in string"),
+            (&["--quirks", "nope"], "invalid value 'nope' for '--quirks <PRESET>'"),
+            (&["--quirk", "frob=on"], "invalid value 'frob=on' for '--quirk <NAME=on|off>'"),
+            (&["--protect-low-mem=maybe"], "invalid value 'maybe' for '--protect-low-mem[=<MODE>]': expected off, warn or error"),
+            (&["--fps", "30", "--render-fps", "30"], "the argument '--fps <N>' cannot be used with '--render-fps <N>'"),
+            (&["a.ch8", "b.ch8"], "the subcommand 'b.ch8' cannot be used with '[ROM]'")
+        ] {
+            let message = parse_error(args);
+            assert!(message.starts_with(expected), "{:?}: {}", args, message);
+        }
+    }
+
+    #[test]
+    fn file_errors() {
+        let error = |source: &str| file_args(source, None).err().unwrap_or_default();
+
+        assert!(error("scale = 4").starts_with("line 1: scale isn't in a section"));
+        assert!(error("[video]\nscale = 4").starts_with("line 1: unknown section [video]"));
+        assert!(error("[display]\nfps = 30\nzoom = 2").starts_with("line 3: display.zoom: unknown key, [display] takes scale, "));
+        assert_eq!(error("[display]\nscale = 0"), "line 2: display.scale: invalid value '0' for '--scale <SCALE>': 0 is not in 1..=4294967295");
+        assert_eq!(error("[emulation]\nstrict = \"yes\""), "line 2: emulation.strict: expected true or false");
+        assert!(error("[rom.\"pong.ch8\"]\nsymbols = \"a.sym\"").starts_with("line 2: rom.\"pong.ch8\".symbols: unknown key, per-ROM sections take "));
+        assert!(error("[display\nscale = 4").starts_with("TOML parse error"));
+    }
+
+    #[test]
+    fn file_options_come_first() {
+        let rom = RomKey { name: "pong.ch8".to_string(), sha1: "0123456789abcdef".to_string() };
+        let file = file_args("[emulation]\nquirks = \"chip8\"\nstrict = true\n[rom.\"sha1:01234567\"]\nquirks = \"schip\"", Some(&rom)).unwrap();
+        assert_eq!(file.args, ["--quirks=chip8", "--strict=true", "--quirks=schip"]);
+        assert_eq!(file.rom_sections, ["[rom.\"sha1:01234567\"]"]);
+
+        let args = ["chip8"].into_iter().chain(file.args.iter().map(String::as_str)).chain(["--quirks=xo-chip"]);
+        assert_eq!(Config::try_parse_from(args).unwrap().quirks.as_deref(), Some("xo-chip"));
+    }
+
+    #[test]
+    fn command_line_turns_off_file_switches() {
+        let file = file_args("[emulation]\nstrict = true\nlenient = true\n[display]\ninteger_scale = false", None).unwrap();
+        let parse = |args: &[&str]| Config::try_parse_from(["chip8"].into_iter().chain(file.args.iter().map(String::as_str)).chain(args.iter().copied())).unwrap();
+
+        let config = parse(&[]);
+        assert!(config.strict && config.lenient && !config.integer_scale);
+
+        let config = parse(&["--strict=false", "--integer-scale", "pong.ch8"]);
+        assert!(!config.strict && config.lenient && config.integer_scale);
+
+        let config = parse(&["--lenient=false", "--lenient"]);
+        assert!(config.lenient);
+
+        let config = Config::try_parse_from(["chip8", "--trace"]).unwrap();
+        assert!(config.trace && !config.strict);
+        assert!(parse_error(&["--trace=yes"]).starts_with("invalid value 'yes' for '--trace[=<BOOL>]'"));
+    }
+
+    #[test]
+    fn input_and_paths_keys() {
+        let file = file_args("[input]\nlayout = \"azerty\"\nkeymap = [\"Space=5\", \"Up=2\"]\n[paths]\nsave_dir = \"saves\"", None).unwrap();
+        assert_eq!(file.args, ["--layout=azerty", "--keymap=Space=5", "--keymap=Up=2", "--save-dir=saves"]);
+
+        let args = ["chip8"].into_iter().chain(file.args.iter().map(String::as_str)).chain(["--keymap=X=0"]);
+        let config = Config::try_parse_from(args).unwrap();
+        assert_eq!(config.layout, "azerty");
+        assert_eq!(config.keymap, [(Key::Space, 0x5), (Key::Up, 0x2), (Key::X, 0x0)]);
+        assert_eq!(config.save_dir.as_deref(), Some("saves"));
+
+        let error = |source: &str| file_args(source, None).err().unwrap_or_default();
+        assert!(error("[input]\nlayout = \"bepo\"").starts_with("line 2: input.layout: invalid value 'bepo' for '--layout <LAYOUT>'"));
+        assert_eq!(error("[input]\nkeymap = [\"Space=G\"]"), "line 2: input.keymap: invalid value 'Space=G' for '--keymap <KEY=PAD>': G isn't a keypad key, expected 0-F");
+    }
+
+}
@@ -0,0 +1,238 @@
+use std::{fmt, fs::File, io::{self, BufWriter}};
+
+use crate::chip8::ExecHook;
+
+const EXECUTED: u8 = 1;
+const READ: u8 = 2;
+const WRITTEN: u8 = 4;
+
+const MEMORY_SIZE: usize = 4096;
+//One pixel per byte, 64 bytes per row
+const IMAGE_WIDTH: u32 = 64;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Access {
+    Untouched,
+    Code,
+    //Only ever read as data, e.g. sprites and tables
+    Data,
+    //Written at some point, possibly read too
+    Written,
+    //Both executed and written
+    SelfModifying
+}
+
+impl Access {
+
+    fn classify(flags: u8) -> Access {
+        match (flags & EXECUTED != 0, flags & WRITTEN != 0, flags & READ != 0) {
+            (true, true, _) => Access::SelfModifying,
+            (true, false, _) => Access::Code,
+            (false, true, _) => Access::Written,
+            (false, false, true) => Access::Data,
+            (false, false, false) => Access::Untouched
+        }
+    }
+
+    fn color(self) -> [u8; 3] {
+        match self {
+            Access::Untouched => [0x00, 0x00, 0x00],
+            Access::Code => [0x30, 0xC0, 0x40],
+            Access::Data => [0x30, 0x60, 0xE0],
+            Access::Written => [0xF0, 0x90, 0x20],
+            Access::SelfModifying => [0xE0, 0x20, 0xC0]
+        }
+    }
+
+}
+
+//Which addresses have been fetched as instructions, read or written.
+//Install it as an exec hook.
+pub struct Coverage {
+    flags: [u8; MEMORY_SIZE]
+}
+
+impl Coverage {
+
+    pub fn create() -> Coverage {
+        Coverage {
+            flags: [0; MEMORY_SIZE]
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.flags = [0; MEMORY_SIZE];
+    }
+
+    pub fn access(&self, address: usize) -> Access {
+        Access::classify(self.flags.get(address).copied().unwrap_or(0))
+    }
+
+    //Runs of addresses with the same access type, untouched ones left out.
+    //end is exclusive.
+    pub fn ranges(&self) -> Vec<(usize, usize, Access)> {
+        let mut ranges: Vec<(usize, usize, Access)> = Vec::new();
+
+        for address in 0..MEMORY_SIZE {
+            let access = self.access(address);
+            match ranges.last_mut() {
+                Some((_, end, last)) if *end == address && *last == access => *end += 1,
+                _ if access == Access::Untouched => (),
+                _ => ranges.push((address, address + 1, access))
+            }
+        }
+
+        ranges
+    }
+
+    //64 pixels wide, one pixel per byte, colored by access type
+    pub fn write_png(&self, path: &str) -> io::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, IMAGE_WIDTH, MEMORY_SIZE as u32 / IMAGE_WIDTH);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let pixels: Vec<u8> = (0..MEMORY_SIZE).flat_map(|address| self.access(address).color()).collect();
+        encoder.write_header()
+            .and_then(|mut writer| writer.write_image_data(&pixels))
+            .map_err(io::Error::other)
+    }
+
+}
+
+impl ExecHook for Coverage {
+
+    fn on_exec(&mut self, address: u16, _opcode: u16) {
+        for byte in [address as usize, address as usize + 1] {
+            if let Some(flags) = self.flags.get_mut(byte) {
+                *flags |= EXECUTED;
+            }
+        }
+    }
+
+    //F000's address word is part of the instruction too
+    fn on_exec_long(&mut self, address: u16, opcode: u16, _operand: u16) {
+        self.on_exec(address, opcode);
+        self.on_exec(address.wrapping_add(2), opcode);
+    }
+
+    fn on_read(&mut self, target: usize) {
+        if let Some(flags) = self.flags.get_mut(target) {
+            *flags |= READ;
+        }
+    }
+
+    fn on_write(&mut self, target: usize) {
+        if let Some(flags) = self.flags.get_mut(target) {
+            *flags |= WRITTEN;
+        }
+    }
+
+}
+
+impl fmt::Display for Access {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Access::Untouched => "untouched",
+            Access::Code => "code",
+            Access::Data => "data",
+            Access::Written => "written",
+            Access::SelfModifying => "self-modifying"
+        };
+
+        write!(f, "{}", name)
+    }
+
+}
+
+impl fmt::Display for Coverage {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ranges = self.ranges();
+        if ranges.is_empty() {
+            return writeln!(f, "Nothing executed or accessed yet");
+        }
+
+        for (start, end, access) in ranges {
+            writeln!(f, "{:#05x}-{:#05x}  {:5} byte(s)  {}", start, end - 1, end - start, access)?;
+        }
+
+        Ok(())
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::chip8::Chip8;
+
+    //Runs rom with a Coverage hook installed
+    fn cover(rom: &[u8], steps: usize) -> Rc<RefCell<Coverage>> {
+        let coverage = Rc::new(RefCell::new(Coverage::create()));
+        let mut chip8 = Chip8::create();
+        chip8.add_exec_hook(coverage.clone());
+        chip8.load_bytes(rom).unwrap();
+        for _ in 0..steps {
+            chip8.step().unwrap();
+        }
+
+        coverage
+    }
+
+    #[test]
+    fn classifies_code_data_and_writes() {
+        //Loads two bytes from the table at 0x20E, writes V0's BCD to 0x300
+        //and the two bytes over 0x20C, where they run as JP 0x20C
+        let rom = [
+            0xA2, 0x0E, 0xF1, 0x65, 0xA3, 0x00, 0xF0, 0x33, 0xA2, 0x0C, 0xF1, 0x55,
+            0x00, 0x00,
+            0x12, 0x0C
+        ];
+        let coverage = cover(&rom, 10);
+
+        assert_eq!(coverage.borrow().ranges(), [
+            (0x200, 0x20C, Access::Code),
+            (0x20C, 0x20E, Access::SelfModifying),
+            (0x20E, 0x210, Access::Data),
+            (0x300, 0x303, Access::Written)
+        ]);
+        assert_eq!(coverage.borrow().to_string(), "\
+0x200-0x20b     12 byte(s)  code
+0x20c-0x20d      2 byte(s)  self-modifying
+0x20e-0x20f      2 byte(s)  data
+0x300-0x302      3 byte(s)  written
+");
+        assert_eq!(coverage.borrow().access(0x210), Access::Untouched);
+    }
+
+    #[test]
+    fn clear_forgets_everything() {
+        let coverage = cover(&[0x12, 0x00], 3);
+        assert_eq!(coverage.borrow().ranges(), [(0x200, 0x202, Access::Code)]);
+
+        coverage.borrow_mut().clear();
+        assert!(coverage.borrow().ranges().is_empty());
+        assert_eq!(coverage.borrow().to_string(), "Nothing executed or accessed yet\n");
+    }
+
+    #[test]
+    fn long_loads_cover_their_operand() {
+        let coverage = Rc::new(RefCell::new(Coverage::create()));
+        let mut chip8 = Chip8::create();
+        chip8.quirks.xo_chip_opcodes = true;
+        chip8.add_exec_hook(coverage.clone());
+        chip8.load_bytes(&[0xF0, 0x00, 0x03, 0x00, 0x12, 0x04]).unwrap();
+        for _ in 0..3 {
+            chip8.step().unwrap();
+        }
+
+        assert_eq!(chip8.index_register, 0x300);
+        assert_eq!(coverage.borrow().ranges(), [(0x200, 0x206, Access::Code)]);
+    }
+
+}
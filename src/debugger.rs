@@ -0,0 +1,558 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeSet, VecDeque},
+    fs,
+    io::{self, BufRead},
+    rc::Rc,
+    sync::mpsc::{self, Receiver},
+    thread
+};
+
+use crate::{
+    chip8::{Chip8, Chip8Error},
+    coverage::Coverage,
+    diagnostic, disasm,
+    sprite::{SpritePreview, DEFAULT_SPRITE_HEIGHT},
+    state::StateDiff,
+    symbols::SymbolTable,
+    timing::Scheduler
+};
+
+const HELP: &str = "\
+Commands:
+  help                 show this text
+  help <mnemonic>      describe an instruction, e.g. help SHR
+  regs                 show registers, timers, collisions and the next instruction
+  bt                   show the call stack
+  sprite [height]      show the sprite at I (default 15 rows)
+  coverage             list address ranges executed, read or written so far
+  step [n]             execute n instructions (default 1)
+  rstep [n]            undo the last n instructions (default 1)
+  pause                stop execution
+  continue             resume execution
+  break <addr|label>   stop before executing addr
+  delete <addr|label>  remove a breakpoint
+  set <reg> <value>    set V0-VF, I, PC, DT or ST while paused
+  poke <addr> <bytes>  write bytes to memory while paused
+  save <file>          write a save state
+  load <file>          restore a save state
+  diffstate <file>     compare a save state against the running machine
+
+Press P in the window to pause or resume, . to run one frame and pause.
+";
+
+pub struct Debugger {
+    pub paused: bool,
+    breakpoints: BTreeSet<u16>,
+    //Lets `continue` leave the breakpoint it is sitting on
+    resume_from: Option<u16>,
+    //Save state taken before each of the last HISTORY_DEPTH instructions
+    history: VecDeque<Vec<u8>>,
+    coverage: Option<Rc<RefCell<Coverage>>>,
+    symbols: Option<Rc<SymbolTable>>
+}
+
+const HISTORY_DEPTH: usize = 1000;
+
+impl Debugger {
+
+    pub fn create() -> Debugger {
+        Debugger {
+            paused: false,
+            breakpoints: BTreeSet::new(),
+            resume_from: None,
+            history: VecDeque::with_capacity(HISTORY_DEPTH),
+            coverage: None,
+            symbols: None
+        }
+    }
+
+    //Labels for disassembly and for break/delete
+    pub fn attach_symbols(&mut self, symbols: Rc<SymbolTable>) {
+        self.symbols = Some(symbols);
+    }
+
+    fn symbols(&self) -> Option<&SymbolTable> {
+        self.symbols.as_deref()
+    }
+
+    //A label if there is one by that name, otherwise a hex address
+    fn resolve(&self, text: &str) -> Option<u16> {
+        self.symbols().and_then(|symbols| symbols.address_of(text)).or_else(|| parse_address(text))
+    }
+
+    //The next instruction, labelled when symbols are loaded
+    pub fn location(&self, chip8: &Chip8) -> String {
+        current_instruction(chip8, self.symbols())
+    }
+
+    //Gives the coverage command something to report, the hook itself
+    //has to be installed on the machine too
+    pub fn attach_coverage(&mut self, coverage: Rc<RefCell<Coverage>>) {
+        self.coverage = Some(coverage);
+    }
+
+    //Runs one command line against the machine and returns what to print
+    pub fn execute(&mut self, chip8: &mut Chip8, line: &str) -> String {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((command, args)) = words.split_first() else {
+            return String::new();
+        };
+
+        match *command {
+            "help" | "h" => match args.first() {
+                Some(mnemonic) => disasm::mnemonic_help(mnemonic).unwrap_or_else(|| format!("Unknown instruction: {}\n", mnemonic)),
+                None => HELP.to_string()
+            },
+            "regs" | "r" => registers(chip8, self.symbols()),
+            "bt" => backtrace(chip8, self.symbols()),
+            "sprite" => match args.first().map(|height| height.parse::<usize>()) {
+                None => SpritePreview::create(&chip8.memory, chip8.index_register, DEFAULT_SPRITE_HEIGHT).to_string(),
+                Some(Ok(height)) => SpritePreview::create(&chip8.memory, chip8.index_register, height).to_string(),
+                Some(Err(_)) => format!("Invalid sprite height: {}\n", args[0])
+            },
+            "coverage" => match &self.coverage {
+                Some(coverage) => coverage.borrow().to_string(),
+                None => "Coverage isn't being tracked\n".to_string()
+            },
+            "step" | "s" => {
+                let count = match args.first().map(|count| count.parse::<usize>()) {
+                    None => 1,
+                    Some(Ok(count)) => count,
+                    Some(Err(_)) => return format!("Invalid step count: {}\n", args[0])
+                };
+
+                self.paused = true;
+                match self.step(chip8, count) {
+                    Ok(()) => self.location(chip8),
+                    Err(e) => format!("{}\n", e)
+                }
+            },
+            "rstep" => {
+                let count = match args.first().map(|count| count.parse::<usize>()) {
+                    None => 1,
+                    Some(Ok(count)) => count,
+                    Some(Err(_)) => return format!("Invalid step count: {}\n", args[0])
+                };
+
+                self.paused = true;
+                self.reverse_step(chip8, count)
+            },
+            "pause" | "p" => {
+                self.paused = true;
+                self.location(chip8)
+            },
+            "continue" | "c" => {
+                self.resume(chip8);
+                "Continuing\n".to_string()
+            },
+            "break" | "b" => match args.first().map(|address| self.resolve(address)) {
+                Some(Some(address)) => {
+                    self.add_breakpoint(address);
+                    format!("Breakpoint set at {:#05x}\n", address)
+                },
+                _ => "Usage: break <addr>\n".to_string()
+            },
+            "delete" | "d" => match args.first().map(|address| self.resolve(address)) {
+                Some(Some(address)) if self.remove_breakpoint(address) => {
+                    format!("Breakpoint at {:#05x} deleted\n", address)
+                },
+                Some(Some(address)) => format!("No breakpoint at {:#05x}\n", address),
+                _ => "Usage: delete <addr>\n".to_string()
+            },
+            "set" => match args {
+                [target, value] => match parse_address(value) {
+                    Some(value) => self.set(chip8, target, value).unwrap_or_else(|e| format!("{}\n", e)),
+                    None => format!("Invalid value: {}\n", value)
+                },
+                _ => "Usage: set <reg> <value>\n".to_string()
+            },
+            "poke" => match args.split_first() {
+                Some((address, bytes)) if !bytes.is_empty() => {
+                    let address = self.resolve(address);
+                    let bytes = bytes.iter().map(|byte| parse_address(byte).and_then(|byte| u8::try_from(byte).ok())).collect::<Option<Vec<u8>>>();
+                    match (address, bytes) {
+                        (Some(address), Some(bytes)) => self.poke(chip8, address, &bytes).unwrap_or_else(|e| format!("{}\n", e)),
+                        (None, _) => format!("Invalid address: {}\n", args[0]),
+                        (_, None) => "Bytes must be 00-FF\n".to_string()
+                    }
+                },
+                _ => "Usage: poke <addr> <byte>...\n".to_string()
+            },
+            "save" => match args.first() {
+                Some(path) => match fs::write(path, chip8.save_state()) {
+                    Ok(()) => format!("State saved to {}\n", path),
+                    Err(e) => format!("Couldn't write {}: {}\n", path, e)
+                },
+                None => "Usage: save <file>\n".to_string()
+            },
+            "load" => match args.first() {
+                Some(path) => match fs::read(path).map_err(|e| e.to_string())
+                    .and_then(|bytes| chip8.load_state(&bytes).map_err(|e| e.to_string())) {
+                    Ok(()) => self.location(chip8),
+                    Err(e) => format!("Couldn't load {}: {}\n", path, e)
+                },
+                None => "Usage: load <file>\n".to_string()
+            },
+            //The file is the left side, the live machine the right
+            "diffstate" => match args.first() {
+                Some(path) => match fs::read(path).map_err(|e| e.to_string())
+                    .and_then(|bytes| Chip8::from_state(&bytes).map_err(|e| e.to_string())) {
+                    Ok(saved) => StateDiff::compare(&saved, chip8).to_string(),
+                    Err(e) => format!("Couldn't load {}: {}\n", path, e)
+                },
+                None => "Usage: diffstate <file>\n".to_string()
+            },
+            _ => format!("Unknown command: {} (try help)\n", command)
+        }
+    }
+
+    //Called before every instruction while running, pauses on breakpoints
+    pub fn check_breakpoint(&mut self, chip8: &Chip8) -> bool {
+        let program_counter = chip8.program_counter;
+        if self.resume_from.take() == Some(program_counter) {
+            return false;
+        }
+
+        if self.breakpoints.contains(&program_counter) {
+            self.paused = true;
+            return true;
+        }
+
+        false
+    }
+
+    //Remembers the machine as it is right before an instruction runs,
+    //the frontend calls this for every instruction while running
+    pub fn record(&mut self, chip8: &Chip8) {
+        if self.history.len() == HISTORY_DEPTH {
+            self.history.pop_front();
+        }
+
+        self.history.push_back(chip8.save_state());
+    }
+
+    //Forgets the states rstep rewinds through, for when another ROM loads
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+    }
+
+    pub fn step(&mut self, chip8: &mut Chip8, count: usize) -> Result<(), Chip8Error> {
+        for _ in 0..count {
+            self.record(chip8);
+            chip8.run()?;
+        }
+
+        Ok(())
+    }
+
+    //Pauses after running one frame of the scheduler, or less if a
+    //breakpoint comes up first
+    pub fn frame_step(&mut self, chip8: &mut Chip8, scheduler: &mut Scheduler) -> Result<(), Chip8Error> {
+        self.paused = true;
+        self.resume_from = Some(chip8.program_counter);
+
+        scheduler.run_frame(chip8, |chip8| {
+            if self.check_breakpoint(chip8) {
+                return true;
+            }
+            self.record(chip8);
+            false
+        })?;
+
+        Ok(())
+    }
+
+    //Changes a register of the paused machine and echoes the new value.
+    //target is V0-VF, I, PC, DT or ST.
+    pub fn set(&mut self, chip8: &mut Chip8, target: &str, value: u16) -> Result<String, String> {
+        if !self.paused {
+            return Err("Pause before editing the machine".to_string());
+        }
+
+        chip8.set_register(target, value)?;
+        let name = target.to_ascii_uppercase();
+        //Execution picks up from the new PC, so a breakpoint there should
+        //still be hit when continuing
+        if name == "PC" {
+            self.resume_from = None;
+        }
+
+        Ok(format!("{} = {:#x}\n", name, value))
+    }
+
+    //Writes bytes into the paused machine's memory like --poke, then
+    //echoes what is there now
+    pub fn poke(&mut self, chip8: &mut Chip8, address: u16, bytes: &[u8]) -> Result<String, String> {
+        if !self.paused {
+            return Err("Pause before editing the machine".to_string());
+        }
+        chip8.poke(address, bytes)?;
+
+        let start = address as usize;
+        let written: Vec<String> = chip8.memory[start..start + bytes.len()].iter().map(|byte| format!("{:02x}", byte)).collect();
+        Ok(format!("{:#05x}: {}\n", address, written.join(" ")))
+    }
+
+    //Runs again, without stopping on a breakpoint at the current PC
+    pub fn resume(&mut self, chip8: &Chip8) {
+        self.paused = false;
+        self.resume_from = Some(chip8.program_counter);
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    //False when there was no breakpoint at address
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn is_breakpoint(&self, address: u16) -> bool {
+        self.breakpoints.contains(&address)
+    }
+
+    fn reverse_step(&mut self, chip8: &mut Chip8, count: usize) -> String {
+        if count == 0 {
+            return self.location(chip8);
+        }
+        if count > self.history.len() {
+            return format!("Only {} instruction(s) of history\n", self.history.len());
+        }
+
+        self.history.truncate(self.history.len() - count + 1);
+        let state = self.history.pop_back().unwrap();
+        if let Err(e) = chip8.load_state(&state) {
+            return format!("Couldn't restore state: {}\n", e);
+        }
+
+        self.location(chip8)
+    }
+
+}
+
+//Reads debugger commands from stdin on a background thread so the
+//window's event loop never blocks waiting for input
+pub fn spawn_stdin_reader() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    receiver
+}
+
+//Accepts 0x2A4, 2A4h or plain hex 2A4
+pub fn parse_address(text: &str) -> Option<u16> {
+    let digits = text.strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .or_else(|| text.strip_suffix('h'))
+        .unwrap_or(text);
+
+    u16::from_str_radix(digits, 16).ok()
+}
+
+pub fn current_instruction(chip8: &Chip8, symbols: Option<&SymbolTable>) -> String {
+    format!(
+        "{}  {}\n",
+        disasm::describe_address(chip8.program_counter, symbols),
+        disasm::disassemble_at(&chip8.memory, chip8.program_counter, symbols)
+    )
+}
+
+fn registers(chip8: &Chip8, symbols: Option<&SymbolTable>) -> String {
+    let mut out = diagnostic::register_summary(chip8);
+
+    let call_stack: Vec<String> = chip8.call_stack().iter().map(|address| format!("{:#05x}", address)).collect();
+    out.push_str(&format!("Stack {}\n", if call_stack.is_empty() { "(empty)".to_string() } else { call_stack.join(" ") }));
+    out.push_str(&format!("Collisions {}\n", chip8.collision_count()));
+    out.push_str(&current_instruction(chip8, symbols));
+    out
+}
+
+//Innermost frame first, each with its disassembled call site
+pub fn backtrace(chip8: &Chip8, symbols: Option<&SymbolTable>) -> String {
+    let mut out = format!("#0  {}", current_instruction(chip8, symbols));
+
+    for (depth, frame) in chip8.backtrace().iter().rev().enumerate() {
+        out.push_str(&format!(
+            "#{}  {}  {}\n",
+            depth + 1,
+            disasm::describe_address(frame.call_site, symbols),
+            disasm::disassemble_at(&chip8.memory, frame.call_site, symbols)
+        ));
+    }
+
+    if !chip8.backtrace_reliable() {
+        out.push_str("Backtrace unreliable: the ROM returned from a subroutine it never called\n");
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    //LD V0, K; LD V1, 0x22; JP 0x204
+    const WAITING_ROM: [u8; 6] = [0xF0, 0x0A, 0x61, 0x22, 0x12, 0x04];
+
+    fn paused_machine() -> (Debugger, Chip8) {
+        let mut chip8 = Chip8::create();
+        chip8.load_bytes(&WAITING_ROM).unwrap();
+        let mut debugger = Debugger::create();
+        debugger.paused = true;
+        (debugger, chip8)
+    }
+
+    #[test]
+    fn set_edits_registers() {
+        let (mut debugger, mut chip8) = paused_machine();
+
+        assert_eq!(debugger.execute(&mut chip8, "set V3 0x1F"), "V3 = 0x1f\n");
+        assert_eq!(debugger.execute(&mut chip8, "set i 300"), "I = 0x300\n");
+        assert_eq!(debugger.execute(&mut chip8, "set DT 3Ch"), "DT = 0x3c\n");
+        assert_eq!(chip8.registers[3], 0x1F);
+        assert_eq!((chip8.index_register, chip8.delay_timer), (0x300, 0x3C));
+
+        assert_eq!(debugger.execute(&mut chip8, "set VG 1"), "Unknown register: VG\n");
+        assert_eq!(debugger.execute(&mut chip8, "set V3 0x100"), "V3 holds a byte, 0x100 doesn't fit\n");
+        assert_eq!(debugger.execute(&mut chip8, "set I 0x1000"), "0x1000 is outside memory\n");
+        assert_eq!(debugger.execute(&mut chip8, "set PC 0xFFF"), "0xfff leaves no room for an instruction before the end of memory\n");
+        assert_eq!(debugger.execute(&mut chip8, "set V3 zz"), "Invalid value: zz\n");
+        assert_eq!(debugger.execute(&mut chip8, "set V3"), "Usage: set <reg> <value>\n");
+        assert_eq!((chip8.registers[3], chip8.index_register, chip8.program_counter), (0x1F, 0x300, 0x200));
+
+        debugger.paused = false;
+        assert_eq!(debugger.execute(&mut chip8, "set V3 0"), "Pause before editing the machine\n");
+        assert_eq!(chip8.registers[3], 0x1F);
+    }
+
+    #[test]
+    fn set_pc_leaves_a_key_wait() {
+        let (mut debugger, mut chip8) = paused_machine();
+        debugger.execute(&mut chip8, "step 3");
+        assert_eq!(chip8.program_counter, 0x200);
+
+        assert_eq!(debugger.execute(&mut chip8, "set pc 0x202"), "PC = 0x202\n");
+        debugger.execute(&mut chip8, "step");
+        assert_eq!((chip8.program_counter, chip8.registers[1]), (0x204, 0x22));
+    }
+
+    #[test]
+    fn set_pc_stops_on_a_breakpoint_there() {
+        //Continuing from a breakpoint skips it once, a PC set onto it
+        //doesn't
+        let (mut debugger, mut chip8) = paused_machine();
+        debugger.execute(&mut chip8, "break 0x200");
+        debugger.resume(&chip8);
+        debugger.paused = true;
+
+        debugger.execute(&mut chip8, "set PC 0x200");
+        debugger.paused = false;
+        assert!(debugger.check_breakpoint(&chip8));
+    }
+
+    #[test]
+    fn poke_writes_memory() {
+        let (mut debugger, mut chip8) = paused_machine();
+
+        assert_eq!(debugger.execute(&mut chip8, "poke 0x300 0xAA BB"), "0x300: aa bb\n");
+        assert_eq!(chip8.memory[0x300..0x302], [0xAA, 0xBB]);
+        assert_eq!(debugger.execute(&mut chip8, "poke 0xFFF 1 2"), "2 byte(s) at 0xfff run past the end of memory\n");
+        assert_eq!(debugger.execute(&mut chip8, "poke 0x300 0x100"), "Bytes must be 00-FF\n");
+        assert_eq!(debugger.execute(&mut chip8, "poke zz 1"), "Invalid address: zz\n");
+        assert_eq!(debugger.execute(&mut chip8, "poke 0x300"), "Usage: poke <addr> <byte>...\n");
+        assert_eq!(chip8.memory[0xFFF], 0);
+
+        //The ROM runs what was poked
+        debugger.execute(&mut chip8, "poke 0x200 0x65 0x07");
+        debugger.execute(&mut chip8, "step");
+        assert_eq!(chip8.registers[5], 0x07);
+    }
+
+    //0x200 calls 0x204, which calls 0x208, which calls 0x20C, each returns
+    const NESTED_ROM: [u8; 14] = [0x22, 0x04, 0x00, 0x00, 0x22, 0x08, 0x00, 0xEE, 0x22, 0x0C, 0x00, 0xEE, 0x00, 0xEE];
+
+    #[test]
+    fn bt_shows_nested_calls() {
+        let mut chip8 = Chip8::create();
+        chip8.load_bytes(&NESTED_ROM).unwrap();
+        let mut debugger = Debugger::create();
+
+        debugger.execute(&mut chip8, "step 3");
+        assert_eq!(chip8.call_depth(), 3);
+        assert_eq!(debugger.execute(&mut chip8, "bt"), "\
+#0  0x020c  RET
+#1  0x0208  CALL 0x20C
+#2  0x0204  CALL 0x208
+#3  0x0200  CALL 0x204
+");
+
+        debugger.execute(&mut chip8, "step 3");
+        assert_eq!(debugger.execute(&mut chip8, "bt"), "#0  0x0202  SYS 0x000\n");
+        assert!(chip8.backtrace_reliable());
+    }
+
+    #[test]
+    fn bt_after_an_unmatched_ret() {
+        //A stack set up without a CALL, as a ROM jumping into the middle
+        //of a subroutine leaves it
+        let mut chip8 = Chip8::create();
+        chip8.load_bytes(&NESTED_ROM).unwrap();
+        chip8.program_counter = 0x20C;
+        chip8.stack[0] = 0x20A;
+        chip8.stack_pointer = 1;
+        let mut debugger = Debugger::create();
+
+        debugger.execute(&mut chip8, "step");
+        assert_eq!(chip8.program_counter, 0x20A);
+        assert_eq!(debugger.execute(&mut chip8, "bt"), "\
+#0  0x020a  RET
+Backtrace unreliable: the ROM returned from a subroutine it never called
+");
+
+        //With nothing left on the stack RET stops the machine
+        assert_eq!(debugger.execute(&mut chip8, "step"), "Stack underflow: RET with an empty stack at 0x20a\n");
+    }
+
+    #[test]
+    fn rstep_restores_earlier_states() {
+        //LD V0, 0; ADD V0, 3; ADD V1, V0; JP 0x202
+        let mut chip8 = Chip8::create();
+        chip8.load_bytes(&[0x60, 0x00, 0x70, 0x03, 0x81, 0x04, 0x12, 0x02]).unwrap();
+        let mut debugger = Debugger::create();
+
+        let mut states = vec![chip8.save_state()];
+        for _ in 0..30 {
+            debugger.execute(&mut chip8, "step");
+            states.push(chip8.save_state());
+        }
+
+        assert_eq!(debugger.execute(&mut chip8, "rstep 10"), "0x0204  ADD V1, V0\n");
+        assert_eq!(chip8.save_state(), states[20]);
+        debugger.execute(&mut chip8, "rstep");
+        assert_eq!(chip8.save_state(), states[19]);
+        debugger.execute(&mut chip8, "rstep 0");
+        assert_eq!(chip8.save_state(), states[19]);
+
+        //Stepping again records from here
+        debugger.execute(&mut chip8, "step 2");
+        assert_eq!(chip8.save_state(), states[21]);
+        debugger.execute(&mut chip8, "rstep 21");
+        assert_eq!(chip8.save_state(), states[0]);
+
+        assert_eq!(debugger.execute(&mut chip8, "rstep"), "Only 0 instruction(s) of history\n");
+        assert_eq!(debugger.execute(&mut chip8, "rstep x"), "Invalid step count: x\n");
+        assert_eq!(chip8.save_state(), states[0]);
+    }
+
+}
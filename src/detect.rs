@@ -0,0 +1,169 @@
+use std::fmt;
+
+use crate::{
+    chip8::{self, MAX_ROM_SIZE},
+    device::Palette,
+    octocart::{is_octocart, Octocart},
+    quirks::Quirks,
+    rom_info::RomInfo
+};
+
+//Without --quirks the emulator picks a profile for the ROM itself:
+//  1. an Octocart runs with its own options, see octocart.rs
+//  2. a ROM in KNOWN_ROMS gets the profile listed for it
+//  3. else one using XO-CHIP or SUPER-CHIP opcodes gets xo-chip or schip
+//  4. else the default
+//--quirks, on the command line or in the config file, always wins and
+//--no-autodetect goes straight to the default. Every --quirk still
+//applies on top of whichever it is.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Detection {
+    pub profile: &'static str,
+    pub source: Source,
+    //The profile's quirks, or an Octocart's own
+    pub quirks: Quirks,
+    //An Octocart's colors, unless --palette is given
+    pub palette: Option<Palette>
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Source {
+    //The name in KNOWN_ROMS
+    Known(&'static str),
+    Opcodes,
+    Octocart
+}
+
+pub struct KnownRom {
+    //SHA-1 of the ROM file, lowercase hex
+    pub sha1: &'static str,
+    pub profile: &'static str,
+    pub name: &'static str
+}
+
+//Public domain ROMs whose platform the opcode scan gets wrong or can't
+//tell. Entries are hashed from the file as distributed, one per release.
+pub const KNOWN_ROMS: &[KnownRom] = &[];
+
+//Opcodes that don't turn up in a ROM for any other platform. Unlike
+//DXY0 or 00CN, they are unlikely byte pairs in sprite data too.
+const XO_CHIP_SIGNS: [&str; 3] = ["F000", "F002", "FN01"];
+const SCHIP_SIGNS: [&str; 4] = ["00FB", "00FC", "00FE", "00FF"];
+
+impl Detection {
+
+    pub fn known(rom: &[u8], known_roms: &[KnownRom]) -> Option<Detection> {
+        let sha1 = sha1_smol::Sha1::from(rom).digest().to_string();
+        known_roms.iter()
+            .find(|known| known.sha1 == sha1)
+            .map(|known| Detection::from_profile(known.profile, Source::Known(known.name)))
+    }
+
+    //Only XO-CHIP has room for ROMs that don't fit the classic 4K
+    pub fn scan(rom: &[u8]) -> Option<Detection> {
+        let info = RomInfo::scan(rom);
+        let uses = |found: &[(&'static str, Vec<u16>)], signs: &[&str]| found.iter().any(|(pattern, _)| signs.contains(pattern));
+
+        let profile = if rom.len() > MAX_ROM_SIZE || uses(&info.xo_chip, &XO_CHIP_SIGNS) {
+            "xo-chip"
+        } else if uses(&info.schip, &SCHIP_SIGNS) {
+            "schip"
+        } else {
+            return None;
+        };
+
+        Some(Detection::from_profile(profile, Source::Opcodes))
+    }
+
+    pub fn octocart(cart: &Octocart) -> Detection {
+        Detection { profile: "xo-chip", source: Source::Octocart, quirks: cart.quirks(), palette: cart.palette() }
+    }
+
+    pub fn detect(rom: &[u8]) -> Option<Detection> {
+        Detection::known(rom, KNOWN_ROMS).or_else(|| Detection::scan(rom))
+    }
+
+    //For the ROM at path, which is read for it. None when it can't be.
+    pub fn detect_path(path: &str) -> Option<Detection> {
+        if is_octocart(path) {
+            return Octocart::load(path).ok().map(|cart| Detection::octocart(&cart));
+        }
+        chip8::read_rom(path).ok().and_then(|rom| Detection::detect(&rom))
+    }
+
+    fn from_profile(profile: &'static str, source: Source) -> Detection {
+        Detection { profile, source, quirks: Quirks::profile(profile).unwrap_or_default(), palette: None }
+    }
+
+}
+
+//"Brix (CHIP-8), using --quirks chip8" for a known ROM
+impl fmt::Display for Detection {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let platform = platform_name(self.profile);
+        match self.source {
+            Source::Known(name) => write!(f, "{} ({}), using --quirks {}", name, platform, self.profile),
+            Source::Opcodes => write!(f, "{} opcodes, using --quirks {}", platform, self.profile),
+            Source::Octocart => write!(f, "an Octocart, using its options: {}", self.quirks)
+        }
+    }
+
+}
+
+pub fn platform_name(profile: &str) -> &str {
+    match profile {
+        "chip8" => "CHIP-8",
+        "chip48" => "CHIP-48",
+        "schip" => "SUPER-CHIP",
+        "xo-chip" => "XO-CHIP",
+        _ => profile
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    //00FF 1202: switches to hires, which the scan takes for SUPER-CHIP
+    const HIRES_ROM: [u8; 4] = [0x00, 0xFF, 0x12, 0x02];
+
+    const TABLE: &[KnownRom] = &[
+        KnownRom { sha1: "7b3f3d97549d49af8dc765be630b7a2e17a9af15", profile: "chip8", name: "Hires Test" }
+    ];
+
+    #[test]
+    fn known_rom_beats_the_scan() {
+        assert_eq!(Detection::scan(&HIRES_ROM).map(|detection| detection.profile), Some("schip"));
+
+        let detection = Detection::known(&HIRES_ROM, TABLE).unwrap();
+        assert_eq!(detection.profile, "chip8");
+        assert_eq!(detection.source, Source::Known("Hires Test"));
+        assert_eq!(detection.quirks, Quirks::profile("chip8").unwrap());
+        assert_eq!(detection.to_string(), "Hires Test (CHIP-8), using --quirks chip8");
+
+        assert!(Detection::known(&[0x00, 0xFF, 0x12, 0x04], TABLE).is_none());
+    }
+
+    #[test]
+    fn scans_opcodes() {
+        let platform = |rom: &[u8]| Detection::scan(rom).map(|detection| detection.profile);
+
+        assert_eq!(platform(&[0x60, 0x01, 0x12, 0x02]), None);
+        assert_eq!(platform(&[0x00, 0xFE, 0x12, 0x02]), Some("schip"));
+        assert_eq!(platform(&[0x00, 0xFF, 0xF0, 0x00, 0x12, 0x34, 0x12, 0x06]), Some("xo-chip"));
+        assert_eq!(platform(&[0xF2, 0x01, 0x12, 0x02]), Some("xo-chip"));
+        assert_eq!(platform(&vec![0; MAX_ROM_SIZE + 1]), Some("xo-chip"));
+        assert_eq!(Detection::scan(&HIRES_ROM).unwrap().to_string(), "SUPER-CHIP opcodes, using --quirks schip");
+    }
+
+    #[test]
+    fn known_roms_are_well_formed() {
+        for known in KNOWN_ROMS {
+            assert!(known.sha1.len() == 40 && known.sha1.chars().all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c)), "{}", known.name);
+            assert!(Quirks::profile(known.profile).is_some(), "{}", known.name);
+        }
+    }
+
+}
@@ -1,51 +1,398 @@
+use std::fmt;
+
 use glutin_window::OpenGL;
-use opengl_graphics::{Filter, GlGraphics, Texture, TextureSettings};
+use opengl_graphics::{CreateTexture, Filter, Format, GlGraphics, Texture, TextureSettings};
 use piston::{
-    window::WindowSettings, RenderArgs
+    window::{AdvancedWindow, Window as _, WindowSettings}, RenderArgs
 };
 
-use graphics::{clear, Transformed};
+use graphics::{clear, rectangle, Transformed};
+
+use crate::sprite::{rasterize, SpritePreview};
 
 use glutin_window::GlutinWindow as Window;
 
+//Window pixels per CHIP-8 pixel when neither --scale nor the saved
+//settings give one
+pub const DEFAULT_SCALE: u32 = 16;
+
+//How video memory gets to the screen, cycled with F11. Raw shows it as
+//it is, so sprites a game erases and redraws every frame flicker like
+//they did on the real thing. Fade lets pixels that go out die away over
+//a few frames, like a slow phosphor. Blend shows the average of the last
+//two frames, so a pixel lit every other frame is half bright.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum DisplayMode {
+    #[default]
+    Raw,
+    Fade,
+    Blend
+}
+
+pub const DISPLAY_MODE_NAMES: [&str; 3] = ["raw", "fade", "blend"];
+
+//Share of a faded pixel's brightness left after each render, out of 256
+const FADE_KEEP: u16 = 160;
+
+//What XO-CHIP's plane combinations show as: neither plane lit, the
+//first, the second and both. ROMs that never select the second plane
+//only use the first two, white on black by default.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Palette(pub [[u8; 3]; 4]);
+
+pub const DEFAULT_PALETTE: &str = "000000,ffffff,aaaaaa,555555";
+
 pub struct Device {
     gl: GlGraphics,
     pub window: Window,
-    scale: u32
+    //Only whole multiples of the CHIP-8 resolution, letterboxing the rest
+    integer_scale: bool,
+    rom_name: Option<String>,
+    status: Option<String>,
+    //There's no audio yet, the title shows the buzzer instead
+    beeping: bool,
+    pub display_mode: DisplayMode,
+    pub palette: Palette,
+    //What render_video last drew of each plane, and the video it drew
+    //it from
+    shown: [Vec<u8>; 2],
+    last_video: [Vec<u8>; 2]
 }
 
 impl Device {
 
-    pub fn start(scale: u32) -> Device {
+    pub fn start(scale: u32, integer_scale: bool, display_mode: DisplayMode, palette: Palette) -> Device {
         let opengl = OpenGL::V3_2;
 
         let window: Window = WindowSettings::new("CHIP-8", [64 * scale, 32 * scale])
             .graphics_api(opengl)
-            .resizable(false)
+            .resizable(true)
             .exit_on_esc(true)
             .build()
-            .unwrap();
+            .unwrap_or_else(|e| {
+                log::error!("Couldn't open a window: {}", e);
+                std::process::exit(1);
+            });
+        log::debug!("Opened a {}x{} window", 64 * scale, 32 * scale);
 
         Device {
             gl: GlGraphics::new(opengl),
             window,
-            scale
+            integer_scale,
+            rom_name: None,
+            status: None,
+            beeping: false,
+            display_mode,
+            palette,
+            shown: [Vec::new(), Vec::new()],
+            last_video: [Vec::new(), Vec::new()]
         }
     }
 
-    pub fn render(&mut self, args: &RenderArgs, video: [u8; 64*32]) {
+    //The scale the window is at now, which the user may have resized it to
+    pub fn scale(&self) -> u32 {
+        let size = self.window.size();
+        (size.width / 64.0).min(size.height / 32.0).round().max(1.0) as u32
+    }
+
+    pub fn set_rom_name(&mut self, rom_name: Option<&str>) {
+        self.rom_name = rom_name.map(str::to_string);
+        self.update_title();
+    }
+
+    //Shows a short status next to the window title, None clears it
+    pub fn set_status(&mut self, status: Option<&str>) {
+        self.status = status.map(str::to_string);
+        self.update_title();
+    }
+
+    //Only retitles the window when the buzzer starts or stops
+    pub fn set_beeping(&mut self, beeping: bool) {
+        if beeping != self.beeping {
+            self.beeping = beeping;
+            self.update_title();
+        }
+    }
+
+    fn update_title(&mut self) {
+        let mut title = String::from("CHIP-8");
+        for part in [&self.rom_name, &self.status].into_iter().flatten() {
+            title.push_str(" - ");
+            title.push_str(part);
+        }
+        if self.beeping {
+            title.push_str(" [BEEP]");
+        }
+
+        log::debug!("Window title: {}", title);
+        self.window.set_title(title);
+    }
+
+    //The emulator's video, both planes through the display mode and then
+    //the palette. render draws other screens, like the ROM browser, as
+    //they are.
+    pub fn render_video(&mut self, args: &RenderArgs, video: &[u8], plane2: &[u8], width: usize, sprite: Option<&SpritePreview>) {
+        for (plane, pixels) in [video, plane2].into_iter().enumerate() {
+            compose(self.display_mode, &mut self.shown[plane], &mut self.last_video[plane], pixels);
+        }
+        let colors = colorize(&self.palette, &self.shown[0], &self.shown[1]);
+
+        let mut setting = TextureSettings::new();
+        setting.set_filter(Filter::Nearest);
+        let height = video.len() / width;
+        let texture = Texture::create(&mut (), Format::Rgba8, &colors, [width as u32, height as u32], &setting).unwrap();
+        self.draw(args, &texture, width, height, sprite);
+    }
+
+    //video is width pixels wide (64 or 128 in hires), sprite draws a
+    //magnified preview of it in the top right corner
+    pub fn render(&mut self, args: &RenderArgs, video: &[u8], width: usize, sprite: Option<&SpritePreview>) {
+        let height = video.len() / width;
 
         //let image = Image::new().rect([0.0, 0.0, WIDTH as f64, HEIGHT as f64]);
         let mut setting = TextureSettings::new();
         setting.set_filter(Filter::Nearest);
-        let texture = Texture::from_memory_alpha(&video, 64, 32, &setting).unwrap();
+        let texture = Texture::from_memory_alpha(video, width as u32, height as u32, &setting).unwrap();
+        self.draw(args, &texture, width, height, sprite);
+    }
+
+    //texture is the width x height picture, scaled up and centered
+    fn draw(&mut self, args: &RenderArgs, texture: &Texture, width: usize, height: usize, sprite: Option<&SpritePreview>) {
+        //Scale and offsets are picked in physical pixels so whole multiples
+        //stay crisp on HiDPI screens, then converted to window coordinates
+        let draw_size = [args.draw_size[0] as f64, args.draw_size[1] as f64];
+        let pixels_per_point = if args.window_size[0] > 0.0 { draw_size[0] / args.window_size[0] } else { 1.0 };
+        let (width, height) = (width as f64, height as f64);
+        let fit = (draw_size[0] / width).min(draw_size[1] / height);
+        let scale = if self.integer_scale { fit.floor().max(1.0) } else { fit };
+        let offset_x = ((draw_size[0] - width * scale) / 2.0).floor() / pixels_per_point;
+        let offset_y = ((draw_size[1] - height * scale) / 2.0).floor() / pixels_per_point;
+        let scale = scale / pixels_per_point;
+        log::trace!("Drawing {}x{} video at {:.2}x into {}x{}", width, height, scale, draw_size[0], draw_size[1]);
 
         self.gl.draw(args.viewport(), |c, gl| {
             // Clear the screen.
             clear([0.0, 0.0, 0.0, 1.0], gl);
 
-            graphics::image(&texture, c.transform.scale(self.scale as f64, self.scale as f64), gl);
+            graphics::image(texture, c.transform.trans(offset_x, offset_y).scale(scale, scale), gl);
+
+            if let Some(sprite) = sprite {
+                draw_sprite_overlay(sprite, c.transform.trans(args.window_size[0] - OVERLAY_WIDTH, 0.0), gl);
+            }
         });
     }
 
 }
+
+//Works out shown from the new video and last_video, the one before it.
+//A change of resolution starts both over.
+pub fn compose(mode: DisplayMode, shown: &mut Vec<u8>, last_video: &mut Vec<u8>, video: &[u8]) {
+    if shown.len() != video.len() {
+        *shown = video.to_vec();
+        *last_video = video.to_vec();
+    }
+
+    match mode {
+        DisplayMode::Raw => shown.copy_from_slice(video),
+        DisplayMode::Fade => {
+            for (shown, pixel) in shown.iter_mut().zip(video) {
+                *shown = (*pixel).max((*shown as u16 * FADE_KEEP / 256) as u8);
+            }
+        },
+        DisplayMode::Blend => {
+            for ((shown, pixel), last) in shown.iter_mut().zip(video).zip(last_video.iter()) {
+                *shown = ((*pixel as u16 + *last as u16) / 2) as u8;
+            }
+        }
+    }
+    last_video.copy_from_slice(video);
+}
+
+//RGBA for the two planes' composed pixels. A half-lit pixel, as fade
+//and blend leave them, mixes the colors of the combinations around it.
+pub fn colorize(palette: &Palette, first: &[u8], second: &[u8]) -> Vec<u8> {
+    let mut colors = Vec::with_capacity(first.len() * 4);
+    for (a, b) in first.iter().zip(second) {
+        let (a, b) = (*a as u32, *b as u32);
+        let weights = [(255 - a) * (255 - b), a * (255 - b), (255 - a) * b, a * b];
+        for channel in 0..3 {
+            let mixed: u32 = palette.0.iter().zip(weights).map(|(color, weight)| color[channel] as u32 * weight).sum();
+            colors.push((mixed / (255 * 255)) as u8);
+        }
+        colors.push(0xFF);
+    }
+    colors
+}
+
+impl Palette {
+
+    //Four RRGGBB colors separated by commas, a # in front of each is fine
+    pub fn parse(value: &str) -> Result<Palette, String> {
+        let colors: Vec<&str> = value.split(',').map(|color| color.trim().trim_start_matches('#')).collect();
+        if colors.len() != 4 {
+            return Err("expected four colors, e.g. 000000,ffffff,aaaaaa,555555".to_string());
+        }
+
+        let mut palette = [[0; 3]; 4];
+        for (entry, color) in palette.iter_mut().zip(colors) {
+            let rgb = u32::from_str_radix(color, 16).ok().filter(|_| color.len() == 6)
+                .ok_or_else(|| format!("{} isn't an RRGGBB color", color))?;
+            *entry = [(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8];
+        }
+        Ok(Palette(palette))
+    }
+
+}
+
+impl DisplayMode {
+
+    pub fn parse(name: &str) -> Result<DisplayMode, String> {
+        match name {
+            "raw" => Ok(DisplayMode::Raw),
+            "fade" => Ok(DisplayMode::Fade),
+            "blend" => Ok(DisplayMode::Blend),
+            _ => Err(format!("expected one of {}", DISPLAY_MODE_NAMES.join(", ")))
+        }
+    }
+
+    //Raw, Fade, Blend and round again
+    pub fn next(self) -> DisplayMode {
+        match self {
+            DisplayMode::Raw => DisplayMode::Fade,
+            DisplayMode::Fade => DisplayMode::Blend,
+            DisplayMode::Blend => DisplayMode::Raw
+        }
+    }
+
+}
+
+impl fmt::Display for DisplayMode {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            DisplayMode::Raw => DISPLAY_MODE_NAMES[0],
+            DisplayMode::Fade => DISPLAY_MODE_NAMES[1],
+            DisplayMode::Blend => DISPLAY_MODE_NAMES[2]
+        };
+        write!(f, "{}", name)
+    }
+
+}
+
+const OVERLAY_CELL: f64 = 6.0;
+const OVERLAY_MARGIN: f64 = 4.0;
+const OVERLAY_WIDTH: f64 = 8.0 * OVERLAY_CELL + 2.0 * OVERLAY_MARGIN;
+
+//Font glyphs are tinted so it's obvious I points into the fontset, a red
+//bar under the rows means memory ran out before the requested height
+fn draw_sprite_overlay(sprite: &SpritePreview, transform: graphics::math::Matrix2d, gl: &mut GlGraphics) {
+    let rows = rasterize(&sprite.rows);
+    let height = rows.len() as f64 * OVERLAY_CELL + 2.0 * OVERLAY_MARGIN;
+    let lit = if sprite.font_glyph().or(sprite.big_font_glyph()).is_some() { [1.0, 0.8, 0.2, 1.0] } else { [1.0, 1.0, 1.0, 1.0] };
+
+    rectangle([0.15, 0.15, 0.15, 0.9], [0.0, 0.0, OVERLAY_WIDTH, height], transform, gl);
+    for (y, row) in rows.iter().enumerate() {
+        for (x, _) in row.iter().enumerate().filter(|(_, pixel)| **pixel) {
+            let cell = [
+                OVERLAY_MARGIN + x as f64 * OVERLAY_CELL,
+                OVERLAY_MARGIN + y as f64 * OVERLAY_CELL,
+                OVERLAY_CELL, OVERLAY_CELL
+            ];
+            rectangle(lit, cell, transform, gl);
+        }
+    }
+
+    if sprite.clamped {
+        rectangle([0.9, 0.2, 0.2, 1.0], [0.0, height, OVERLAY_WIDTH, 2.0], transform, gl);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{chip8::Chip8, instruction::decode, quirks::Quirks};
+
+    //A row of 8 pixels at x = 0 in plane 1, 8 in plane 2 and 16 in both
+    fn three_sprites() -> Chip8 {
+        let mut chip8 = Chip8::create();
+        chip8.quirks = Quirks { xo_chip_opcodes: true, ..Quirks::default() };
+        chip8.index_register = 0x300;
+        chip8.poke(0x300, &[0xFF, 0xFF]).unwrap();
+        for (x, plane) in [(0, 0xF101), (8, 0xF201), (16, 0xF301)] {
+            chip8.registers[0] = x;
+            chip8.execute(decode(plane)).unwrap();
+            chip8.execute(decode(0xD011)).unwrap();
+        }
+        chip8
+    }
+
+    fn color(colors: &[u8], x: usize) -> [u8; 4] {
+        colors[x * 4..x * 4 + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn planes_compose_into_palette_colors() {
+        let chip8 = three_sprites();
+        let colors = colorize(&Palette::parse(DEFAULT_PALETTE).unwrap(), &chip8.video, &chip8.plane2);
+        assert_eq!(colors.len(), chip8.video.len() * 4);
+
+        assert_eq!(color(&colors, 0), [0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(color(&colors, 7), [0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(color(&colors, 8), [0xAA, 0xAA, 0xAA, 0xFF]);
+        assert_eq!(color(&colors, 16), [0x55, 0x55, 0x55, 0xFF]);
+        assert_eq!(color(&colors, 24), [0x00, 0x00, 0x00, 0xFF]);
+        assert_eq!(color(&colors, 64), [0x00, 0x00, 0x00, 0xFF]);
+    }
+
+    #[test]
+    fn half_lit_pixels_mix_colors() {
+        let palette = Palette::parse("#000000, #ff0000, #00ff00, #0000ff").unwrap();
+        let colors = colorize(&palette, &[0xFF, 0x00, 0xFF], &[0x00, 0xFF, 0xFF]);
+        assert_eq!(colors, [0xFF, 0, 0, 0xFF, 0, 0xFF, 0, 0xFF, 0, 0, 0xFF, 0xFF]);
+
+        //Half of the first plane over nothing is half its color
+        assert_eq!(colorize(&palette, &[0x80], &[0x00]), [0x80, 0, 0, 0xFF]);
+    }
+
+    #[test]
+    fn one_plane_looks_as_it_did() {
+        let mut chip8 = Chip8::create();
+        chip8.index_register = 0x300;
+        chip8.poke(0x300, &[0xA5]).unwrap();
+        chip8.execute(decode(0xD011)).unwrap();
+
+        let colors = colorize(&Palette::parse(DEFAULT_PALETTE).unwrap(), &chip8.video, &chip8.plane2);
+        let row: Vec<u8> = (0..8).map(|x| color(&colors, x)[0]).collect();
+        assert_eq!(row, [0xFF, 0, 0xFF, 0, 0, 0xFF, 0, 0xFF]);
+
+        //PLANE needs the XO-CHIP opcodes
+        assert!(chip8.execute(decode(0xF201)).is_err());
+    }
+
+    #[test]
+    fn parses_palettes() {
+        assert_eq!(Palette::parse(DEFAULT_PALETTE), Ok(Palette([[0, 0, 0], [0xFF, 0xFF, 0xFF], [0xAA, 0xAA, 0xAA], [0x55, 0x55, 0x55]])));
+        assert_eq!(Palette::parse("000000,ffffff"), Err("expected four colors, e.g. 000000,ffffff,aaaaaa,555555".to_string()));
+        assert_eq!(Palette::parse("000000,ffffff,aaaaaa,fff"), Err("fff isn't an RRGGBB color".to_string()));
+        assert_eq!(Palette::parse("000000,ffffff,aaaaaa,gggggg"), Err("gggggg isn't an RRGGBB color".to_string()));
+    }
+
+    #[test]
+    fn display_modes() {
+        let (mut shown, mut last) = (Vec::new(), Vec::new());
+        compose(DisplayMode::Raw, &mut shown, &mut last, &[0xFF, 0x00]);
+        assert_eq!(shown, [0xFF, 0x00]);
+
+        //A pixel that goes out fades, one lit every other frame blends
+        compose(DisplayMode::Fade, &mut shown, &mut last, &[0x00, 0x00]);
+        assert_eq!(shown, [(255 * FADE_KEEP / 256) as u8, 0x00]);
+        compose(DisplayMode::Blend, &mut shown, &mut last, &[0xFF, 0x00]);
+        assert_eq!(shown, [0x7F, 0x00]);
+
+        //A new resolution starts over
+        compose(DisplayMode::Blend, &mut shown, &mut last, &[0xFF; 4]);
+        assert_eq!(shown, [0xFF; 4]);
+    }
+
+}
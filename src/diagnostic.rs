@@ -0,0 +1,154 @@
+use crate::{
+    chip8::{Chip8, Chip8Error},
+    debugger, disasm
+};
+
+//Instructions shown on each side of the faulting one
+const DISASSEMBLY_CONTEXT: u16 = 16;
+const HEXDUMP_SIZE: usize = 64;
+
+impl Chip8 {
+
+    //Post-mortem text report for an error returned by run()
+    pub fn diagnostic_report(&self, err: &Chip8Error) -> String {
+        self.report(&format!("Error: {}", err), err.address(), err.memory_target())
+    }
+
+    //The same report for a machine that stopped without an error, e.g.
+    //reason is "window closed", centered on the next instruction
+    pub fn exit_report(&self, reason: &str) -> String {
+        self.report(&format!("Exit: {}", reason), self.program_counter, None)
+    }
+
+    fn report(&self, heading: &str, fault_address: u16, memory_target: Option<usize>) -> String {
+        let mut out = String::from("CHIP-8 diagnostic report\n");
+        out.push_str(&format!("{}\n", heading));
+        out.push_str(&format!("Seed: {}\n\n", self.seed));
+
+        out.push_str("Registers:\n");
+        out.push_str(&register_summary(self));
+
+        out.push_str("\nStack:\n");
+        if self.stack_pointer == 0 {
+            out.push_str("  (empty)\n");
+        }
+        for (i, address) in self.call_stack().iter().enumerate() {
+            out.push_str(&format!("  [{:2}] {:#06x}\n", i, address));
+        }
+
+        out.push_str("\nBacktrace:\n");
+        out.push_str(&debugger::backtrace(self, None));
+
+        out.push_str("\nDisassembly:\n");
+        let first = fault_address.saturating_sub(DISASSEMBLY_CONTEXT * 2);
+        for address in (first..=fault_address.saturating_add(DISASSEMBLY_CONTEXT * 2)).step_by(2) {
+            let Some(opcode) = disasm::opcode_at(&self.memory, address) else { break };
+            out.push_str(&format!(
+                "{} {:#06x}  {:04X}  {}\n",
+                if address == fault_address { ">" } else { " " },
+                address, opcode, disasm::disassemble(opcode)
+            ));
+        }
+
+        if let Some(target) = memory_target {
+            out.push_str(&format!("\nMemory around {:#06x}:\n", target));
+            out.push_str(&hexdump(&self.memory, target));
+        }
+
+        out.push_str("\nDisplay:\n");
+        out.push_str(&ascii_framebuffer(&self.video, self.video_width()));
+
+        out
+    }
+
+}
+
+impl Chip8Error {
+
+    //Where the faulting instruction lives
+    pub fn address(&self) -> u16 {
+        match *self {
+            Chip8Error::InvalidOpcode { address, .. } |
+            Chip8Error::StackOverflow { address } |
+            Chip8Error::StackUnderflow { address } |
+            Chip8Error::MemoryOutOfBounds { address, .. } |
+            Chip8Error::ProtectedWrite { address, .. } |
+            Chip8Error::InvariantViolated { address, .. } => address
+        }
+    }
+
+    //The memory worth dumping: the bad opcode itself or the bad access
+    pub fn memory_target(&self) -> Option<usize> {
+        match *self {
+            Chip8Error::InvalidOpcode { address, .. } => Some(address as usize),
+            Chip8Error::MemoryOutOfBounds { target, .. } |
+            Chip8Error::ProtectedWrite { target, .. } => Some(target),
+            _ => None
+        }
+    }
+
+}
+
+pub fn register_summary(chip8: &Chip8) -> String {
+    let mut out = format!(
+        "PC {:#06x}  I {:#06x}  SP {}  DT {}  ST {}\n",
+        chip8.program_counter, chip8.index_register, chip8.stack_pointer,
+        chip8.delay_timer, chip8.sound_timer
+    );
+
+    for (i, value) in chip8.registers.iter().enumerate() {
+        out.push_str(&format!("V{:X} {:02X}{}", i, value, if i % 8 == 7 { "\n" } else { "  " }));
+    }
+
+    out
+}
+
+//16 bytes per line, the 64 byte window is kept inside memory even
+//when target itself is past the end
+pub fn hexdump(memory: &[u8], target: usize) -> String {
+    let start = (target.saturating_sub(HEXDUMP_SIZE / 2) & !0xF)
+        .min(memory.len().saturating_sub(HEXDUMP_SIZE));
+
+    let mut out = String::new();
+    for (line, bytes) in memory[start..(start + HEXDUMP_SIZE).min(memory.len())].chunks(16).enumerate() {
+        let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        out.push_str(&format!("  {:#06x}  {}\n", start + line * 16, hex.join(" ")));
+    }
+
+    out
+}
+
+pub fn ascii_framebuffer(video: &[u8], width: usize) -> String {
+    let mut out = String::new();
+    for row in video.chunks(width) {
+        out.extend(row.iter().map(|pixel| if *pixel != 0 { '#' } else { '.' }));
+        out.push('\n');
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    //Draws a 0 at (2, 1) from a subroutine, then runs into FFFF
+    const FAULTY_ROM: [u8; 16] = [0x22, 0x04, 0xFF, 0xFF, 0x60, 0x02, 0x61, 0x01, 0x62, 0x00, 0xF2, 0x29, 0xD0, 0x15, 0xFF, 0xFF];
+
+    #[test]
+    fn invalid_opcode_report() {
+        let mut chip8 = Chip8::create();
+        chip8.load_bytes(&FAULTY_ROM).unwrap();
+        chip8.set_seed(7);
+        let err = loop {
+            if let Err(err) = chip8.step() {
+                break err;
+            }
+        };
+
+        assert_eq!(err, Chip8Error::InvalidOpcode { address: 0x20E, opcode: 0xFFFF });
+        assert_eq!(chip8.diagnostic_report(&err), include_str!("../tests/snapshots/diagnostic_invalid_opcode.txt"));
+    }
+
+}
@@ -0,0 +1,259 @@
+use std::collections::BTreeSet;
+
+use crate::{
+    instruction::{decode, Instruction, OPCODES},
+    symbols::SymbolTable
+};
+
+const LOAD_ADDRESS: u16 = 0x200;
+
+pub fn disassemble(opcode: u16) -> String {
+    decode(opcode).to_string()
+}
+
+//Like disassemble, but address operands with a label are shown by name,
+//e.g. CALL draw_score instead of CALL 0x2A4
+pub fn disassemble_with(opcode: u16, symbols: Option<&SymbolTable>) -> String {
+    let instruction = decode(opcode);
+    let named = |mnemonic: &str, address: u16| {
+        symbols.and_then(|symbols| symbols.name_at(address)).map(|name| format!("{} {}", mnemonic, name))
+    };
+
+    let labelled = match instruction {
+        Instruction::Sys { address } => named("SYS", address),
+        Instruction::Jump { address } => named("JP", address),
+        Instruction::Call { address } => named("CALL", address),
+        Instruction::LoadIndex { address } => named("LD I,", address),
+        Instruction::JumpV0 { address } => named("JP V0,", address),
+        _ => None
+    };
+
+    labelled.unwrap_or_else(|| instruction.to_string())
+}
+
+//XO-CHIP's F000 NNNN, given the NNNN, with its label like disassemble_with
+pub fn disassemble_long(address: u16, symbols: Option<&SymbolTable>) -> String {
+    match symbols.and_then(|symbols| symbols.name_at(address)) {
+        Some(name) => format!("LD I, LONG {}", name),
+        None => Instruction::LoadLongIndex { address }.to_string()
+    }
+}
+
+//The big-endian word at address, None when it runs past the end of memory
+pub fn opcode_at(memory: &[u8], address: u16) -> Option<u16> {
+    let i = address as usize;
+    match (memory.get(i), memory.get(i + 1)) {
+        (Some(hi), Some(lo)) => Some(((*hi as u16) << 8) | *lo as u16),
+        _ => None
+    }
+}
+
+//F000 is shown with the word after it, as the one instruction it is
+//under XO-CHIP
+pub fn disassemble_at(memory: &[u8], address: u16, symbols: Option<&SymbolTable>) -> String {
+    match (opcode_at(memory, address), opcode_at(memory, address.wrapping_add(2))) {
+        (Some(0xF000), Some(operand)) => disassemble_long(operand, symbols),
+        (Some(opcode), _) => disassemble_with(opcode, symbols),
+        (None, _) => "<out of memory>".to_string()
+    }
+}
+
+//Address followed by its label position when symbols are loaded,
+//e.g. "0x02a8 <draw_score+0x4>"
+pub fn describe_address(address: u16, symbols: Option<&SymbolTable>) -> String {
+    match symbols.and_then(|symbols| symbols.describe(address)) {
+        Some(label) => format!("{:#06x} <{}>", address, label),
+        None => format!("{:#06x}", address)
+    }
+}
+
+//The whole ROM for chip8 disasm, every word as an instruction and
+//F000 NNNN as one. Targets of JP, CALL and JP V0 that land on an
+//instruction get a label_0x2A0: line and are referred to by it, so the
+//flow reads like source and chip8 asm takes it back:
+//  label_0x200:
+//          CALL label_0x20A            ; 0x200  220A
+//          JP label_0x200              ; 0x202  1200
+pub fn listing(rom: &[u8]) -> String {
+    let end = LOAD_ADDRESS as usize + rom.len();
+
+    //First pass: where the instructions start, and the branch targets
+    //that land on one
+    let mut instructions: Vec<(u16, u16, Option<u16>)> = Vec::new();
+    let mut i = 0;
+    while i + 1 < rom.len() {
+        let address = LOAD_ADDRESS + i as u16;
+        let opcode = u16::from_be_bytes([rom[i], rom[i + 1]]);
+        let operand = rom.get(i + 2..i + 4).filter(|_| opcode == 0xF000).map(|pair| u16::from_be_bytes([pair[0], pair[1]]));
+        instructions.push((address, opcode, operand));
+        i += if operand.is_some() { 4 } else { 2 };
+    }
+    let starts: BTreeSet<u16> = instructions.iter().map(|(address, _, _)| *address).collect();
+    let targets: BTreeSet<u16> = instructions.iter()
+        .filter_map(|(_, opcode, _)| match decode(*opcode) {
+            Instruction::Jump { address } |
+            Instruction::Call { address } |
+            Instruction::JumpV0 { address } => Some(address),
+            _ => None
+        })
+        .filter(|address| starts.contains(address))
+        .collect();
+    let label = |address: u16| targets.contains(&address).then(|| format!("label_0x{:03X}", address));
+
+    //Second pass: the listing, labels first
+    let mut out = String::new();
+    for (address, opcode, operand) in &instructions {
+        if let Some(name) = label(*address) {
+            out.push_str(&format!("{}:\n", name));
+        }
+        if let Some(operand) = operand {
+            let text = disassemble_long(*operand, None);
+            out.push_str(&format!("        {:28}; {:#05x}  {:04X} {:04X}\n", text, address, opcode, operand));
+            continue;
+        }
+
+        let instruction = decode(*opcode);
+        let text = match instruction {
+            Instruction::Jump { address } => label(address).map(|name| format!("JP {}", name)),
+            Instruction::Call { address } => label(address).map(|name| format!("CALL {}", name)),
+            Instruction::JumpV0 { address } => label(address).map(|name| format!("JP V0, {}", name)),
+            _ => None
+        };
+        let text = text.unwrap_or_else(|| instruction.to_string());
+        out.push_str(&format!("        {:28}; {:#05x}  {:04X}\n", text, address, opcode));
+    }
+
+    //An odd last byte can't be an instruction
+    if let [.., last] = rom {
+        if !rom.len().is_multiple_of(2) {
+            out.push_str(&format!("        {:28}; {:#05x}\n", format!("DB 0x{:02X}", last), end - 1));
+        }
+    }
+
+    out
+}
+
+//Mnemonic, operand fields and what the opcode does, for chip8 decode:
+//  8A15  SUB VA, V1
+//        x=A y=1
+//        Vx = Vx - Vy, VF = 1 when there is no borrow
+pub fn explain(opcode: u16) -> String {
+    let instruction = decode(opcode);
+    let mut out = format!("{:04X}  {}\n", opcode, instruction);
+
+    let Some(info) = instruction.info() else {
+        out.push_str("      not an instruction\n");
+        return out;
+    };
+
+    let family = info.family;
+    let mut fields = Vec::new();
+    if family.contains('X') {
+        fields.push(format!("x={:X}", (opcode >> 8) & 0xF));
+    }
+    if family.contains('Y') {
+        fields.push(format!("y={:X}", (opcode >> 4) & 0xF));
+    }
+    if family.ends_with("KK") {
+        fields.push(format!("kk=0x{:02X}", opcode & 0xFF));
+    } else if family.ends_with("NNN") {
+        fields.push(format!("nnn=0x{:03X}", opcode & 0xFFF));
+    } else if family.ends_with('N') {
+        fields.push(format!("n={}", opcode & 0xF));
+    }
+    if !fields.is_empty() {
+        out.push_str(&format!("      {}\n", fields.join(" ")));
+    }
+
+    out.push_str(&format!("      {}\n", info.description));
+    if !info.quirks.is_empty() {
+        out.push_str(&format!("      affected by quirks: {}\n", info.quirks.join(", ")));
+    }
+
+    out
+}
+
+//Reference lines for every form of a mnemonic, e.g. all the LDs
+pub fn mnemonic_help(mnemonic: &str) -> Option<String> {
+    let mnemonic = mnemonic.to_ascii_uppercase();
+    let lines: Vec<String> = OPCODES.iter()
+        .filter(|info| info.syntax.split_whitespace().next() == Some(mnemonic.as_str()))
+        .map(|info| {
+            let quirks = if info.quirks.is_empty() { String::new() } else { format!(" [quirks: {}]", info.quirks.join(", ")) };
+            format!("{}  {:20} {}{}\n", info.family, info.syntax, info.description, quirks)
+        })
+        .collect();
+
+    (!lines.is_empty()).then(|| lines.concat())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn explains_fields_and_meaning() {
+        assert_eq!(explain(0x8A15), "\
+8A15  SUB VA, V1
+      x=A y=1
+      Vx = Vx - Vy, VF = 1 when there is no borrow
+");
+        assert_eq!(explain(0x6A2F), "\
+6A2F  LD VA, 0x2F
+      x=A kk=0x2F
+      Vx = kk
+");
+        assert!(explain(0xD01F).starts_with("D01F  DRW V0, V1, 15\n      x=0 y=1 n=15\n"));
+        assert_eq!(explain(0xE000), "E000  DW 0xE000\n      not an instruction\n");
+    }
+
+    #[test]
+    fn names_the_quirks_that_change_an_opcode() {
+        assert_eq!(explain(0x8126), "\
+8126  SHR V1, V2
+      x=1 y=2
+      Vx = Vx >> 1, VF = the bit shifted out
+      affected by quirks: shift-vy
+");
+        assert_eq!(explain(0xF255), "\
+F255  LD [I], V2
+      x=2
+      Store V0 through Vx in memory starting at I
+      affected by quirks: increment-i, increment-i-x
+");
+        assert!(!explain(0x8A15).contains("quirks"));
+    }
+
+    #[test]
+    fn help_lists_every_form_of_a_mnemonic() {
+        let help = mnemonic_help("shr").unwrap();
+        assert!(help.starts_with("8XY6  SHR Vx {, Vy}"), "{}", help);
+        assert!(help.ends_with("[quirks: shift-vy]\n"), "{}", help);
+        assert_eq!(mnemonic_help("SE").map(|help| help.lines().count()), Some(2));
+        assert_eq!(mnemonic_help("NOPE"), None);
+    }
+
+    #[test]
+    fn long_loads_list_as_one_instruction() {
+        //A jump into F000's operand gets no label, there's no instruction there
+        let rom = [0x30, 0x00, 0xF0, 0x00, 0x03, 0x00, 0x12, 0x04, 0x12, 0x00];
+        assert_eq!(listing(&rom), "\
+label_0x200:
+        SE V0, 0x00                 ; 0x200  3000
+        LD I, LONG 0x0300           ; 0x202  F000 0300
+        JP 0x204                    ; 0x206  1204
+        JP label_0x200              ; 0x208  1200
+");
+
+        let mut memory = vec![0; 0x1000];
+        memory[0x202..0x206].copy_from_slice(&[0xF0, 0x00, 0x03, 0x00]);
+        assert_eq!(disassemble_at(&memory, 0x202, None), "LD I, LONG 0x0300");
+        let symbols = SymbolTable::parse("0x300 sprites").unwrap();
+        assert_eq!(disassemble_at(&memory, 0x202, Some(&symbols)), "LD I, LONG sprites");
+        //An F000 in the last word has no operand to show
+        memory[0xFFE..].copy_from_slice(&[0xF0, 0x00]);
+        assert_eq!(disassemble_at(&memory, 0xFFE, None), disassemble_with(0xF000, None));
+    }
+
+}
@@ -0,0 +1,249 @@
+use std::{
+    error, fs,
+    io::Read,
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration
+};
+
+use crate::{chip8::MAX_XO_CHIP_ROM_SIZE, config};
+
+//ROMs given as http:// or https:// URLs are downloaded and kept in the
+//data directory's cache/, named by the URL's SHA-1, so the next launch
+//doesn't need the network. --no-cache downloads again and leaves the
+//cache alone.
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+static USE_CACHE: AtomicBool = AtomicBool::new(true);
+
+//--no-cache, set before any ROM is read
+pub fn set_use_cache(use_cache: bool) {
+    USE_CACHE.store(use_cache, Ordering::Relaxed);
+}
+
+//Gets the bytes behind a URL, failing on anything over limit bytes
+pub trait Fetcher {
+    fn fetch(&self, url: &str, limit: usize) -> Result<Vec<u8>, String>;
+}
+
+pub struct HttpFetcher;
+
+impl Fetcher for HttpFetcher {
+
+    fn fetch(&self, url: &str, limit: usize) -> Result<Vec<u8>, String> {
+        let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).build();
+        let response = match agent.get(url).call() {
+            Ok(response) => response,
+            Err(ureq::Error::Status(code, response)) => return Err(format!("{} {}", code, response.status_text())),
+            Err(ureq::Error::Transport(e)) => return Err(transport_error(&e))
+        };
+
+        //A server's error or login page, not a ROM
+        if response.content_type() == "text/html" {
+            return Err("the server sent a web page, not a ROM".to_string());
+        }
+        let length = response.header("Content-Length").and_then(|length| length.parse::<usize>().ok());
+        if let Some(length) = length.filter(|length| *length > limit) {
+            return Err(format!("{} bytes, more than the {} that fit in memory", length, limit));
+        }
+
+        //The length can be missing or wrong, so no more than one byte
+        //past the limit is read either way
+        let mut rom = Vec::new();
+        response.into_reader()
+            .take(limit as u64 + 1)
+            .read_to_end(&mut rom)
+            .map_err(|e| e.to_string())?;
+        if rom.len() > limit {
+            return Err(format!("more than the {} bytes that fit in memory", limit));
+        }
+
+        Ok(rom)
+    }
+
+}
+
+//The ROM behind url, from the cache when it has been downloaded before
+pub fn fetch_rom(url: &str) -> Result<Vec<u8>, String> {
+    let cache = USE_CACHE.load(Ordering::Relaxed).then(default_cache_dir).flatten();
+    fetch_cached(url, &HttpFetcher, cache)
+}
+
+pub fn fetch_cached(url: &str, fetcher: &dyn Fetcher, cache: Option<PathBuf>) -> Result<Vec<u8>, String> {
+    let cached = cache.map(|dir| dir.join(format!("{}.ch8", sha1_smol::Sha1::from(url).digest())));
+    if let Some(rom) = cached.as_ref().and_then(|path| fs::read(path).ok()) {
+        log::debug!("{} is cached in {}", url, cached.as_ref().map(|path| path.display().to_string()).unwrap_or_default());
+        return Ok(rom);
+    }
+
+    log::info!("Downloading {}", url);
+    let rom = fetcher.fetch(url, MAX_XO_CHIP_ROM_SIZE).map_err(|e| format!("Couldn't download {}: {}", url, e))?;
+    if rom.is_empty() {
+        return Err(format!("Couldn't download {}: the server sent nothing", url));
+    }
+    if looks_like_html(&rom) {
+        return Err(format!("Couldn't download {}: the server sent a web page, not a ROM", url));
+    }
+
+    //A cache that can't be written costs the next launch a download
+    if let Some(path) = &cached {
+        let written = path.parent().map_or(Ok(()), fs::create_dir_all).and_then(|_| fs::write(path, &rom));
+        if let Err(e) = written {
+            log::warn!("Couldn't cache {} in {}: {}", url, path.display(), e);
+        }
+    }
+
+    Ok(rom)
+}
+
+//cache/ in the data directory, see config::data_dir
+pub fn default_cache_dir() -> Option<PathBuf> {
+    Some(config::data_dir()?.join("cache"))
+}
+
+//ureq's own message starts with the URL, which the caller already names
+fn transport_error(e: &ureq::Transport) -> String {
+    let mut message = e.kind().to_string();
+    for detail in [e.message().map(str::to_string), error::Error::source(e).map(|source| source.to_string())].into_iter().flatten() {
+        message.push_str(": ");
+        message.push_str(&detail);
+    }
+    message
+}
+
+//Servers that answer every path with a page often say it's binary
+fn looks_like_html(bytes: &[u8]) -> bool {
+    let start = String::from_utf8_lossy(&bytes[..bytes.len().min(64)]).trim_start().to_ascii_lowercase();
+    start.starts_with("<!doctype html") || start.starts_with("<html")
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::{
+        cell::{Cell, RefCell},
+        env,
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        thread
+    };
+
+    //Hands out the same ROM, counting the downloads
+    struct Served {
+        rom: Vec<u8>,
+        calls: Cell<usize>,
+        limit: RefCell<Option<usize>>
+    }
+
+    impl Served {
+
+        fn create(rom: &[u8]) -> Served {
+            Served { rom: rom.to_vec(), calls: Cell::new(0), limit: RefCell::new(None) }
+        }
+
+    }
+
+    impl Fetcher for Served {
+
+        fn fetch(&self, _url: &str, limit: usize) -> Result<Vec<u8>, String> {
+            self.calls.set(self.calls.get() + 1);
+            *self.limit.borrow_mut() = Some(limit);
+            Ok(self.rom.clone())
+        }
+
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("chip8-download-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    //Answers one request with headers and body, then hangs up
+    fn serve_once(headers: &str, body: &[u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/rom.ch8", listener.local_addr().unwrap());
+        let response = [format!("HTTP/1.1 200 OK\r\n{}Connection: close\r\n\r\n", headers).as_bytes(), body].concat();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 0 && line != "\r\n" {
+                line.clear();
+            }
+            let _ = reader.get_mut().write_all(&response);
+        });
+        url
+    }
+
+    #[test]
+    fn downloads_once_per_url() {
+        let dir = temp_dir("cache");
+        let served = Served::create(&[0x12, 0x00]);
+        let url = "https://example.com/pong.ch8";
+
+        assert_eq!(fetch_cached(url, &served, Some(dir.clone())).unwrap(), [0x12, 0x00]);
+        assert_eq!(fetch_cached(url, &served, Some(dir.clone())).unwrap(), [0x12, 0x00]);
+        assert_eq!(served.calls.get(), 1);
+        let cached = dir.join(format!("{}.ch8", sha1_smol::Sha1::from(url).digest()));
+        assert_eq!(fs::read(cached).unwrap(), [0x12, 0x00]);
+
+        fetch_cached("https://example.com/tetris.ch8", &served, Some(dir.clone())).unwrap();
+        assert_eq!(served.calls.get(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn without_a_cache_downloads_every_time() {
+        let served = Served::create(&[0x12, 0x00]);
+        fetch_cached("https://example.com/pong.ch8", &served, None).unwrap();
+        fetch_cached("https://example.com/pong.ch8", &served, None).unwrap();
+        assert_eq!(served.calls.get(), 2);
+    }
+
+    #[test]
+    fn asks_for_no_more_than_fits_in_memory() {
+        let served = Served::create(&[0x12, 0x00]);
+        fetch_cached("https://example.com/pong.ch8", &served, None).unwrap();
+        assert_eq!(*served.limit.borrow(), Some(MAX_XO_CHIP_ROM_SIZE));
+    }
+
+    #[test]
+    fn rejects_nothing_and_web_pages_without_caching_them() {
+        let dir = temp_dir("rejects");
+        let empty = fetch_cached("https://example.com/a.ch8", &Served::create(&[]), Some(dir.clone()));
+        assert_eq!(empty.unwrap_err(), "Couldn't download https://example.com/a.ch8: the server sent nothing");
+
+        let page = Served::create(b"  <!DOCTYPE html><html><body>Not found</body></html>");
+        let html = fetch_cached("https://example.com/b.ch8", &page, Some(dir.clone()));
+        assert_eq!(html.unwrap_err(), "Couldn't download https://example.com/b.ch8: the server sent a web page, not a ROM");
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn http_fetcher_reads_up_to_the_limit() {
+        let url = serve_once("Content-Length: 4\r\n", &[1, 2, 3, 4]);
+        assert_eq!(HttpFetcher.fetch(&url, 4).unwrap(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn http_fetcher_refuses_a_long_content_length() {
+        let url = serve_once("Content-Length: 5\r\n", &[1, 2, 3, 4, 5]);
+        assert_eq!(HttpFetcher.fetch(&url, 4).unwrap_err(), "5 bytes, more than the 4 that fit in memory");
+    }
+
+    #[test]
+    fn http_fetcher_stops_past_the_limit_without_a_length() {
+        let url = serve_once("", &[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(HttpFetcher.fetch(&url, 4).unwrap_err(), "more than the 4 bytes that fit in memory");
+    }
+
+    #[test]
+    fn http_fetcher_turns_away_web_pages() {
+        let url = serve_once("Content-Type: text/html\r\n", b"<html></html>");
+        assert_eq!(HttpFetcher.fetch(&url, 4).unwrap_err(), "the server sent a web page, not a ROM");
+    }
+
+}
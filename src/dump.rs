@@ -0,0 +1,239 @@
+use std::{
+    fs,
+    panic,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH}
+};
+
+use crate::{
+    chip8::{Chip8, Chip8Error},
+    diagnostic::ascii_framebuffer,
+    rom_dir::{rom_name, BLANK_NAME}
+};
+
+//--dump-state-on-exit=DIR: however the window goes away, a bundle for the
+//bug report is left in DIR/ROM-YYYYMMDD-HHMMSS/:
+//  machine.state    save state, loads with --load-state or diff-state
+//  diagnostic.txt   registers, stack and disassembly, as for a halt
+//  screen.txt       the display, # for lit pixels
+//  input.log        the --log-input recording, when there is one
+//A panic dumps the machine as of the last rendered frame.
+pub struct StateDump {
+    dir: PathBuf,
+    //Kept up to date for the panic hook
+    last_frame: Arc<Mutex<Option<Snapshot>>>
+}
+
+struct Snapshot {
+    rom_path: String,
+    state: Vec<u8>,
+    input_log: Option<String>
+}
+
+impl StateDump {
+
+    pub fn create(dir: &str) -> StateDump {
+        StateDump {
+            dir: PathBuf::from(dir),
+            last_frame: Arc::new(Mutex::new(None))
+        }
+    }
+
+    //Panics still print as before, the dump is written after
+    pub fn install_panic_hook(&self) {
+        let dir = self.dir.clone();
+        let last_frame = self.last_frame.clone();
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            previous(info);
+
+            let last_frame = last_frame.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let Some(snapshot) = last_frame.as_ref() else { return };
+            let Ok(chip8) = Chip8::from_state(&snapshot.state) else { return };
+            let report = chip8.exit_report(&format!("panic: {}", info));
+            match write_bundle(&dir, &snapshot.rom_path, &chip8, &report, snapshot.input_log.as_deref()) {
+                Ok(bundle) => eprintln!("Dumped the machine to {}", bundle.display()),
+                Err(e) => eprintln!("Couldn't dump the machine: {}", e)
+            }
+        }));
+    }
+
+    pub fn update(&self, chip8: &Chip8, rom_path: &str, input_log: Option<&str>) {
+        let snapshot = Snapshot {
+            rom_path: rom_path.to_string(),
+            state: chip8.save_state(),
+            input_log: input_log.map(str::to_string)
+        };
+        *self.last_frame.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(snapshot);
+    }
+
+    //On the way out: the halt that stopped the ROM, or reason. Failing only
+    //warns, whatever went wrong before has already been reported.
+    pub fn write(&self, chip8: &Chip8, rom_path: &str, halted: Option<&Chip8Error>, reason: &str, input_log: Option<&str>) {
+        let report = match halted {
+            Some(e) => chip8.diagnostic_report(e),
+            None => chip8.exit_report(reason)
+        };
+        match write_bundle(&self.dir, rom_path, chip8, &report, input_log) {
+            Ok(bundle) => log::info!("Dumped the machine to {}", bundle.display()),
+            Err(e) => log::warn!("Couldn't dump the machine: {}", e)
+        }
+    }
+
+}
+
+//Writes the bundle into a new directory in dir and returns it
+pub fn write_bundle(dir: &Path, rom_path: &str, chip8: &Chip8, report: &str, input_log: Option<&str>) -> Result<PathBuf, String> {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+    let rom = if rom_path.is_empty() { BLANK_NAME.to_string() } else { rom_name(Path::new(rom_path)) };
+    let name = format!("{}-{}", Path::new(&rom).with_extension("").display(), timestamp(seconds));
+
+    //Two dumps in the same second get -2, -3...
+    let mut bundle = dir.join(&name);
+    let mut count = 1;
+    while bundle.exists() {
+        count += 1;
+        bundle = dir.join(format!("{}-{}", name, count));
+    }
+    fs::create_dir_all(&bundle).map_err(|e| format!("Couldn't create {}: {}", bundle.display(), e))?;
+
+    let write = |file: &str, contents: &[u8]| {
+        let path = bundle.join(file);
+        fs::write(&path, contents).map_err(|e| format!("Couldn't write {}: {}", path.display(), e))
+    };
+    write("machine.state", &chip8.save_state())?;
+    write("diagnostic.txt", report.as_bytes())?;
+    write("screen.txt", ascii_framebuffer(&chip8.video, chip8.video_width()).as_bytes())?;
+    if let Some(input_log) = input_log {
+        write("input.log", input_log.as_bytes())?;
+    }
+
+    Ok(bundle)
+}
+
+//YYYYMMDD-HHMMSS in UTC
+fn timestamp(seconds: u64) -> String {
+    let days = (seconds / 86_400) as i64;
+    let time = seconds % 86_400;
+
+    //Civil date from days since 1970-01-01, Howard Hinnant's algorithm
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!("{:04}{:02}{:02}-{:02}{:02}{:02}", year, month, day, time / 3600, time % 3600 / 60, time % 60)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::env;
+
+    //V1 = 7, then a 4 pixel line at the top left, then a jump to itself
+    const DRAWING_ROM: [u8; 11] = [0x61, 0x07, 0x60, 0x00, 0xA2, 0x0A, 0xD0, 0x01, 0x12, 0x08, 0xF0];
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("chip8-dump-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn drawn() -> Chip8 {
+        let mut chip8 = Chip8::create();
+        chip8.load_bytes(&DRAWING_ROM).unwrap();
+        for _ in 0..4 {
+            chip8.step().unwrap();
+        }
+        chip8
+    }
+
+    fn bundles(dir: &Path) -> Vec<PathBuf> {
+        let mut bundles: Vec<PathBuf> = fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+        bundles.sort();
+        bundles
+    }
+
+    #[test]
+    fn clean_exit_writes_all_four_files() {
+        let dir = temp_dir("exit");
+        let chip8 = drawn();
+        let log = "seed 1\nframe 3  t=0.050s  keypad 5 pressed (W)\n";
+        StateDump::create(dir.to_str().unwrap()).write(&chip8, "roms/pong.ch8", None, "window closed", Some(log));
+
+        let bundles = bundles(&dir);
+        assert_eq!(bundles.len(), 1);
+        let bundle = &bundles[0];
+        let name = bundle.file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with("pong-"), "{}", name);
+
+        let restored = Chip8::from_state(&fs::read(bundle.join("machine.state")).unwrap()).unwrap();
+        assert_eq!(restored.program_counter, 0x208);
+        assert_eq!(restored.registers[1], 7);
+        assert_eq!(restored.video, chip8.video);
+
+        let report = fs::read_to_string(bundle.join("diagnostic.txt")).unwrap();
+        assert!(report.starts_with("CHIP-8 diagnostic report\nExit: window closed\n"), "{}", report);
+
+        let screen = fs::read_to_string(bundle.join("screen.txt")).unwrap();
+        let lines: Vec<&str> = screen.lines().collect();
+        assert_eq!(lines.len(), 32);
+        assert!(lines.iter().all(|line| line.len() == 64));
+        assert_eq!(&lines[0][..6], "####..");
+        assert!(lines[1].chars().all(|pixel| pixel == '.'));
+
+        assert_eq!(fs::read_to_string(bundle.join("input.log")).unwrap(), log);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_halt_reports_the_error() {
+        let dir = temp_dir("halt");
+        let error = Chip8Error::InvalidOpcode { address: 0x208, opcode: 0xFFFF };
+        StateDump::create(dir.to_str().unwrap()).write(&drawn(), "", Some(&error), "window closed", None);
+
+        let bundles = bundles(&dir);
+        assert_eq!(bundles.len(), 1);
+        let bundle = &bundles[0];
+        assert!(bundle.file_name().unwrap().to_str().unwrap().starts_with("blank-"));
+        let report = fs::read_to_string(bundle.join("diagnostic.txt")).unwrap();
+        assert!(report.contains("Error: Invalid opcode 0xffff at 0x208\n"), "{}", report);
+        assert!(!bundle.join("input.log").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dumps_in_the_same_second_get_a_count() {
+        let dir = temp_dir("count");
+        let chip8 = drawn();
+        let first = write_bundle(&dir, "pong.ch8", &chip8, "", None).unwrap();
+        let second = write_bundle(&dir, "pong.ch8", &chip8, "", None).unwrap();
+        let third = write_bundle(&dir, "pong.ch8", &chip8, "", None).unwrap();
+
+        //The clock may tick over between them, but never to the same name
+        assert_ne!(first, second);
+        assert_ne!(second, third);
+        let name = first.file_name().unwrap().to_str().unwrap();
+        if second.file_name().unwrap().to_str().unwrap().starts_with(name) {
+            assert_eq!(second, dir.join(format!("{}-2", name)));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn timestamps_are_utc_dates() {
+        assert_eq!(timestamp(0), "19700101-000000");
+        assert_eq!(timestamp(951_782_400 + 3_723), "20000229-010203");
+        assert_eq!(timestamp(1_709_251_199), "20240229-235959");
+        assert_eq!(timestamp(4_102_444_800), "21000101-000000");
+    }
+
+}
@@ -0,0 +1,215 @@
+use std::{cell::RefCell, fmt, fs, rc::Rc};
+
+use crate::{
+    chip8::{Chip8, Chip8Error},
+    profiler::{IdleDetector, IdleStatus}
+};
+
+//chip8 run --headless: runs a ROM for a fixed number of instructions with
+//no window and reports a hash of the final display. Instructions run in
+//frames of --ipf, like the window's --ipf, and the timers tick once at
+//the end of each frame, never by the clock. Keys come from an input
+//script scheduled by instruction count, so the same ROM, script, seed
+//and ipf always give the same hash.
+
+//A script line: @1200 press 5 or @1300 release 5. The keypad changes
+//before instruction 1200 (counting from 0) runs.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ScriptEvent {
+    pub cycle: u64,
+    pub key: u8,
+    pub pressed: bool
+}
+
+pub struct HeadlessReport {
+    pub cycles: u64,
+    //Whole frames, each ending in a timer tick
+    pub frames: u64,
+    //Why the run ended before the requested number of instructions
+    pub halted: Option<Chip8Error>,
+    //The ROM ran EXIT (00FD), which ends the run without an error
+    pub exited: bool,
+    //What the ROM was doing when the run ended, see profiler.rs
+    pub idle: IdleStatus,
+    pub hash: String
+}
+
+//One event per line, # starts a comment. Events come back sorted by
+//cycle, keeping the order of the file for events on the same cycle.
+pub fn parse_script(source: &str) -> Result<Vec<ScriptEvent>, String> {
+    let mut events = source.lines()
+        .enumerate()
+        .map(|(number, line)| (number, line.split('#').next().unwrap_or_default().trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(number, line)| parse_event(line).map_err(|e| format!("line {}: {}", number + 1, e)))
+        .collect::<Result<Vec<ScriptEvent>, String>>()?;
+    events.sort_by_key(|event| event.cycle);
+
+    Ok(events)
+}
+
+pub fn read_script(path: &str) -> Result<Vec<ScriptEvent>, String> {
+    let source = fs::read_to_string(path).map_err(|e| format!("Couldn't read {}: {}", path, e))?;
+    parse_script(&source).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn parse_event(line: &str) -> Result<ScriptEvent, String> {
+    let [cycle, action, key] = line.split_whitespace().collect::<Vec<&str>>()[..] else {
+        return Err(format!("{}: expected @CYCLE press|release KEY", line));
+    };
+
+    let cycle = cycle.strip_prefix('@')
+        .and_then(|cycle| cycle.replace('_', "").parse::<u64>().ok())
+        .ok_or_else(|| format!("{}: expected a cycle like @1200", cycle))?;
+    let pressed = match action {
+        "press" => true,
+        "release" => false,
+        _ => return Err(format!("{}: expected press or release", action))
+    };
+    let key = u8::from_str_radix(key, 16)
+        .ok()
+        .filter(|key| *key <= 0xF)
+        .ok_or_else(|| format!("{}: expected a key from 0 to F", key))?;
+
+    Ok(ScriptEvent { cycle, key, pressed })
+}
+
+//SHA-1 of the display, one byte per pixel. XO-CHIP's second plane
+//follows the first once anything was drawn into it, so hashes of
+//single-plane ROMs stay what they were.
+pub fn framebuffer_hash(chip8: &Chip8) -> String {
+    let mut hash = sha1_smol::Sha1::from(&chip8.video);
+    if chip8.uses_plane2() {
+        hash.update(&chip8.plane2);
+    }
+    hash.digest().to_string()
+}
+
+//Runs up to cycles instructions, ipf to a frame, applying each event
+//before the instruction it is scheduled for
+pub fn run(chip8: &mut Chip8, cycles: u64, ipf: u64, script: &[ScriptEvent]) -> HeadlessReport {
+    let idle_detector = Rc::new(RefCell::new(IdleDetector::create()));
+    chip8.add_exec_hook(idle_detector.clone());
+
+    let mut events = script.iter().peekable();
+    let mut executed = 0;
+    let mut halted = None;
+    while executed < cycles {
+        while let Some(event) = events.next_if(|event| event.cycle <= executed) {
+            chip8.keypad[event.key as usize] = event.pressed;
+        }
+
+        if let Err(e) = chip8.step() {
+            halted = Some(e);
+            break;
+        }
+        executed += 1;
+        if executed % ipf == 0 {
+            chip8.tick_timers();
+        }
+        if chip8.exited {
+            break;
+        }
+    }
+
+    let idle = idle_detector.borrow().status();
+    HeadlessReport {
+        cycles: executed,
+        frames: executed / ipf,
+        halted,
+        exited: chip8.exited,
+        idle,
+        hash: framebuffer_hash(chip8)
+    }
+}
+
+impl fmt::Display for HeadlessReport {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.halted {
+            Some(e) => writeln!(f, "Halted after {} instruction(s) and {} frame(s): {}", self.cycles, self.frames, e),
+            None if self.exited => writeln!(f, "Exited after {} instruction(s) and {} frame(s), display {}", self.cycles, self.frames, self.hash),
+            None => writeln!(f, "Ran {} instruction(s) in {} frame(s), {}", self.cycles, self.frames, self.idle)
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    //Draws the font's 0 at random places, forever
+    const RANDOM_DOTS: [u8; 10] = [0xC0, 0x3F, 0xC1, 0x1F, 0xA0, 0x50, 0xD0, 0x15, 0x12, 0x00];
+
+    fn hash_with_seed(seed: u64) -> String {
+        let mut chip8 = Chip8::create();
+        chip8.load_bytes(&RANDOM_DOTS).unwrap();
+        chip8.set_seed(seed);
+        run(&mut chip8, 5000, 10, &[]).hash
+    }
+
+    #[test]
+    fn same_seed_same_hash() {
+        assert_eq!(hash_with_seed(1234), hash_with_seed(1234));
+        assert_ne!(hash_with_seed(1234), hash_with_seed(1235));
+        assert_ne!(hash_with_seed(0), hash_with_seed(u64::MAX));
+    }
+
+
+    //Waits for a key and draws its digit at (0, 0), then halts on an
+    //invalid opcode once 7 has been drawn
+    const KEY_DIGITS: [u8; 14] = [0x00, 0xE0, 0xF0, 0x0A, 0xF0, 0x29, 0xD1, 0x15, 0x30, 0x07, 0x12, 0x00, 0xFF, 0xFF];
+
+    //The display with only digit drawn, at (0, 0)
+    fn digit_display(digit: u8) -> Chip8 {
+        let mut chip8 = Chip8::create();
+        chip8.load_bytes(&[0xA0, 0x50 + 5 * digit, 0xD1, 0x15]).unwrap();
+        run(&mut chip8, 2, 10, &[]);
+        chip8
+    }
+
+    #[test]
+    fn scripted_run() {
+        let script = parse_script("@50 press 3\n@60 release 3\n# then 7\n@200 press 7").unwrap();
+        let mut chip8 = Chip8::create();
+        chip8.load_bytes(&KEY_DIGITS).unwrap();
+        let report = run(&mut chip8, 1000, 10, &script);
+
+        assert_eq!(report.halted, Some(Chip8Error::InvalidOpcode { address: 0x20C, opcode: 0xFFFF }));
+        assert_eq!((report.cycles, report.frames), (204, 20));
+        assert_eq!(report.to_string(), "Halted after 204 instruction(s) and 20 frame(s): Invalid opcode 0xffff at 0x20c\n");
+        assert_eq!(report.hash, framebuffer_hash(&digit_display(7)));
+        assert_eq!(report.hash, "9118c53635fb301f3d4c8cca7137470430469eb7");
+    }
+
+    #[test]
+    fn hash_of_a_known_display() {
+        assert_eq!(framebuffer_hash(&Chip8::create()), "605db3fdbaff4ba13729371ad0c4fbab3889378e");
+        assert_ne!(framebuffer_hash(&digit_display(3)), framebuffer_hash(&digit_display(7)));
+    }
+
+    #[test]
+    fn script_errors() {
+        assert_eq!(parse_script("@1 press 5\n@2 hold 5"), Err("line 2: hold: expected press or release".to_string()));
+        assert_eq!(parse_script("1 press 5"), Err("line 1: 1: expected a cycle like @1200".to_string()));
+        assert_eq!(parse_script("@1 press 10"), Err("line 1: 10: expected a key from 0 to F".to_string()));
+        assert_eq!(parse_script("@1 press"), Err("line 1: @1 press: expected @CYCLE press|release KEY".to_string()));
+
+        let events = parse_script("@1_000 release A\n@5 press a").unwrap();
+        assert_eq!(events, [ScriptEvent { cycle: 5, key: 0xA, pressed: true }, ScriptEvent { cycle: 1000, key: 0xA, pressed: false }]);
+    }
+
+    #[test]
+    fn exit_ends_the_run_cleanly() {
+        let mut chip8 = Chip8::create();
+        chip8.load_bytes(&[0x00, 0xE0, 0x00, 0xFD, 0x12, 0x00]).unwrap();
+        let report = run(&mut chip8, 1000, 10, &[]);
+
+        assert!(report.exited);
+        assert_eq!((report.cycles, report.halted), (2, None));
+        assert_eq!(report.to_string(), format!("Exited after 2 instruction(s) and 0 frame(s), display {}\n", report.hash));
+    }
+
+}
@@ -0,0 +1,251 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH}
+};
+
+use serde_json::{json, Value};
+
+use crate::{config, rom_dir::rom_name};
+
+//The last ROMs that loaded, newest first, kept in recent.json in the data
+//directory. Shown at the top of the ROM browser and by chip8 recent.
+//--no-history neither reads nor writes it.
+pub const MAX_RECENT: usize = 10;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RecentRom {
+    //Canonical, with #ENTRY kept for a ROM inside a zip
+    pub path: String,
+    pub title: String,
+    //Seconds since the Unix epoch
+    pub played: u64
+}
+
+impl RecentRom {
+
+    //The size of the file, the archive for a zip entry. None once it's gone.
+    pub fn size(&self) -> Option<u64> {
+        fs::metadata(file_part(&self.path)).ok()
+            .filter(|meta| meta.is_file())
+            .map(|meta| meta.len())
+    }
+
+    //Downloaded ROMs come back from the cache or the network
+    pub fn is_url(&self) -> bool {
+        let path = self.path.to_ascii_lowercase();
+        path.starts_with("http://") || path.starts_with("https://")
+    }
+
+    pub fn exists(&self) -> bool {
+        self.is_url() || self.size().is_some()
+    }
+
+}
+
+//recent.json in the data directory, see config::data_dir
+pub fn default_path() -> Option<PathBuf> {
+    Some(config::data_dir()?.join("recent.json"))
+}
+
+pub fn parse(source: &str) -> Result<Vec<RecentRom>, String> {
+    let entries: Vec<Value> = serde_json::from_str(source).map_err(|e| e.to_string())?;
+    entries.iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            let path = entry["path"].as_str();
+            let title = entry["title"].as_str();
+            let played = entry["played"].as_u64();
+            match (path, title, played) {
+                (Some(path), Some(title), Some(played)) => Ok(RecentRom { path: path.to_string(), title: title.to_string(), played }),
+                _ => Err(format!("entry {}: expected path, title and played", index + 1))
+            }
+        })
+        .collect()
+}
+
+pub fn to_json(recent: &[RecentRom]) -> String {
+    let entries: Vec<Value> = recent.iter()
+        .map(|rom| json!({ "path": rom.path, "title": rom.title, "played": rom.played }))
+        .collect();
+    serde_json::to_string_pretty(&entries).unwrap_or_default()
+}
+
+//Puts rom first, dropping an older entry for the same file and anything
+//past MAX_RECENT
+pub fn record(recent: &mut Vec<RecentRom>, rom: RecentRom) {
+    recent.retain(|entry| entry.path != rom.path);
+    recent.insert(0, rom);
+    recent.truncate(MAX_RECENT);
+}
+
+//A missing file is an empty history. So is a broken one, with a warning,
+//since it gets rewritten on the next load anyway.
+pub fn load(file: &Path) -> Vec<RecentRom> {
+    let source = match fs::read_to_string(file) {
+        Ok(source) => source,
+        Err(_) => return Vec::new()
+    };
+    parse(&source).unwrap_or_else(|e| {
+        log::warn!("Ignoring the ROM history in {}: {}", file.display(), e);
+        Vec::new()
+    })
+}
+
+pub fn save(file: &Path, recent: &[RecentRom]) -> Result<(), String> {
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("Couldn't create {}: {}", dir.display(), e))?;
+    }
+    fs::write(file, to_json(recent)).map_err(|e| format!("Couldn't write {}: {}", file.display(), e))
+}
+
+//Called after rom_path loaded. Failing to save only warns, the ROM
+//still runs.
+pub fn remember(file: &Path, rom_path: &str) {
+    let played = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+    let mut recent = load(file);
+    record(&mut recent, RecentRom {
+        path: canonical(rom_path),
+        title: title(rom_path),
+        played
+    });
+    if let Err(e) = save(file, &recent) {
+        log::warn!("{}", e);
+    }
+}
+
+//The same ROM reached through different relative paths or links is one
+//entry. A zip entry keeps its #ENTRY after the canonical archive path.
+pub fn canonical(rom_path: &str) -> String {
+    let file = file_part(rom_path);
+    let entry = &rom_path[file.len()..];
+    match fs::canonicalize(file) {
+        Ok(path) => format!("{}{}", path.display(), entry),
+        Err(_) => rom_path.to_string()
+    }
+}
+
+//The file name, or the entry name for a ROM in a zip
+pub fn title(rom_path: &str) -> String {
+    let file = file_part(rom_path);
+    match rom_path[file.len()..].strip_prefix('#') {
+        Some(entry) => rom_name(Path::new(entry)),
+        None => rom_name(Path::new(file))
+    }
+}
+
+//How long ago, roughly: 5 min ago, 3 h ago, 2 days ago
+pub fn ago(now: u64, played: u64) -> String {
+    let seconds = now.saturating_sub(played);
+    match seconds {
+        0..=59 => "just now".to_string(),
+        60..=3599 => format!("{} min ago", seconds / 60),
+        3600..=86_399 => format!("{} h ago", seconds / 3600),
+        86_400..=172_799 => "1 day ago".to_string(),
+        _ => format!("{} days ago", seconds / 86_400)
+    }
+}
+
+//archive.zip#game.ch8 -> archive.zip, anything else is left alone
+fn file_part(rom_path: &str) -> &str {
+    match rom_path.split_once('#') {
+        Some((archive, _)) if archive.to_ascii_lowercase().ends_with(".zip") => archive,
+        _ => rom_path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::env;
+
+    use super::*;
+
+    fn rom(path: &str, played: u64) -> RecentRom {
+        RecentRom { path: path.to_string(), title: title(path), played }
+    }
+
+    //A fresh directory under the temp dir
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("chip8-history-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn newest_first_without_duplicates() {
+        let mut recent = Vec::new();
+        for i in 0..12 {
+            record(&mut recent, rom(&format!("/roms/{}.ch8", i), i));
+        }
+        assert_eq!(recent.len(), MAX_RECENT);
+        assert_eq!((recent[0].path.as_str(), recent[9].path.as_str()), ("/roms/11.ch8", "/roms/2.ch8"));
+
+        record(&mut recent, rom("/roms/5.ch8", 20));
+        assert_eq!(recent.len(), MAX_RECENT);
+        assert_eq!((recent[0].path.as_str(), recent[0].played), ("/roms/5.ch8", 20));
+        assert_eq!(recent.iter().filter(|entry| entry.path == "/roms/5.ch8").count(), 1);
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let recent = vec![rom("/roms/pong.ch8", 100), rom("/roms/games.zip#TETRIS.c8", 50)];
+        assert_eq!(parse(&to_json(&recent)).unwrap(), recent);
+        assert_eq!(recent[1].title, "TETRIS.c8");
+
+        assert_eq!(parse(r#"[{"path": "/a.ch8", "title": "a.ch8"}]"#).unwrap_err(), "entry 1: expected path, title and played");
+        assert!(parse("{").is_err());
+    }
+
+    #[test]
+    fn saves_and_loads() {
+        let dir = temp_dir("save");
+        let file = dir.join("nested").join("recent.json");
+        assert!(load(&file).is_empty());
+
+        let recent = vec![rom("/roms/pong.ch8", 100)];
+        save(&file, &recent).unwrap();
+        assert_eq!(load(&file), recent);
+
+        fs::write(&file, "not json").unwrap();
+        assert!(load(&file).is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn remembers_one_entry_per_file() {
+        let dir = temp_dir("remember");
+        let file = dir.join("recent.json");
+        fs::create_dir(dir.join("roms")).unwrap();
+        fs::write(dir.join("roms").join("pong.ch8"), [0x12, 0x00]).unwrap();
+
+        let direct = dir.join("roms").join("pong.ch8");
+        let roundabout = dir.join("roms").join("..").join("roms").join("pong.ch8");
+        remember(&file, &direct.to_string_lossy());
+        remember(&file, &roundabout.to_string_lossy());
+
+        let recent = load(&file);
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0].path, fs::canonicalize(&direct).unwrap().to_string_lossy());
+        assert_eq!(recent[0].title, "pong.ch8");
+        assert_eq!(recent[0].size(), Some(2));
+        assert!(recent[0].exists());
+
+        fs::remove_file(&direct).unwrap();
+        assert!(!recent[0].exists());
+        assert!(rom("https://example.com/pong.ch8", 0).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn says_how_long_ago() {
+        assert_eq!(ago(1000, 1000), "just now");
+        assert_eq!(ago(1000, 2000), "just now");
+        assert_eq!(ago(1000 + 5 * 60, 1000), "5 min ago");
+        assert_eq!(ago(3 * 3600, 0), "3 h ago");
+        assert_eq!(ago(86_400, 0), "1 day ago");
+        assert_eq!(ago(5 * 86_400, 0), "5 days ago");
+    }
+
+}
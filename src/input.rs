@@ -0,0 +1,317 @@
+use std::{collections::HashMap, io::Write, time::Instant};
+
+use log::info;
+use piston::{Button, Event, Key, PressEvent, ReleaseEvent};
+
+//Where the keypad state comes from. The main loop asks once per tick,
+//so anything that can report 16 key states (keyboard, terminal, replay)
+//can drive the emulator.
+pub trait InputSource {
+    fn key_state(&self) -> [bool; 16];
+}
+
+//Keyboard input from the piston window, tracked from press/release events
+//and translated to keypad indices through a keymap that can be swapped at
+//runtime
+pub struct PistonInput {
+    keys: [bool; 16],
+    keymap: HashMap<Key, u8>
+}
+
+impl PistonInput {
+
+    pub fn create() -> PistonInput {
+        PistonInput {
+            keys: [false; 16],
+            keymap: default_keymap()
+        }
+    }
+
+    //Keyboard key -> keypad index (0x0-0xF)
+    pub fn keymap(&self) -> &HashMap<Key, u8> {
+        &self.keymap
+    }
+
+    //Replaces the whole mapping. Fails on an index past 0xF, naming the
+    //key. Keys held down are released so nothing sticks.
+    pub fn set_keymap(&mut self, keymap: HashMap<Key, u8>) -> Result<(), String> {
+        if let Some((key, index)) = keymap.iter().find(|(_, index)| **index > 0xF) {
+            return Err(format!("{:?} is mapped to {:#x}, the keypad only goes up to 0xF", key, index));
+        }
+
+        self.keymap = keymap;
+        self.keys = [false; 16];
+        Ok(())
+    }
+
+    //Returns the keypad key the event pressed or released, if it changed one
+    pub fn handle_event(&mut self, e: &Event) -> Option<KeyChange> {
+        let (key, pressed) = match (e.press_args(), e.release_args()) {
+            (Some(Button::Keyboard(key)), _) => (key, true),
+            (_, Some(Button::Keyboard(key))) => (key, false),
+            _ => return None
+        };
+        let index = *self.keymap.get(&key)?;
+
+        let state = &mut self.keys[index as usize];
+        if *state == pressed {
+            return None;
+        }
+        *state = pressed;
+
+        Some(KeyChange { key, index, pressed })
+    }
+
+}
+
+impl InputSource for PistonInput {
+
+    fn key_state(&self) -> [bool; 16] {
+        self.keys
+    }
+
+}
+
+//A keypad key changing state and the keyboard key that did it
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct KeyChange {
+    pub key: Key,
+    pub index: u8,
+    pub pressed: bool
+}
+
+//--log-input: one human-readable line per keypad transition, e.g.
+//  frame 312  t=5.204s  keypad 0 pressed (X)
+pub struct InputLog {
+    output: Box<dyn Write>,
+    start: Instant,
+    //Everything written so far, for --dump-state-on-exit
+    recorded: String
+}
+
+impl InputLog {
+
+    //The seed goes first so the log and --seed replay the same run
+    pub fn create(output: Box<dyn Write>, seed: u64) -> InputLog {
+        let mut log = InputLog {
+            output,
+            start: Instant::now(),
+            recorded: String::new()
+        };
+        log.write(&format!("seed {}", seed));
+        log
+    }
+
+    pub fn log(&mut self, frame: u64, change: KeyChange) {
+        let state = if change.pressed { "pressed" } else { "released" };
+        let line = format!("frame {}  t={:.3}s  keypad {:X} {} ({:?})",
+            frame, self.start.elapsed().as_secs_f64(), change.index, state, change.key);
+        self.write(&line);
+    }
+
+    pub fn recorded(&self) -> &str {
+        &self.recorded
+    }
+
+    fn write(&mut self, line: &str) {
+        self.recorded.push_str(line);
+        self.recorded.push('\n');
+        if let Err(e) = writeln!(self.output, "{}", line).and_then(|_| self.output.flush()) {
+            eprintln!("Couldn't write the input log: {}", e);
+        }
+    }
+
+}
+
+//--log-chords: the whole set of keypad keys held, every time it changes,
+//e.g. "frame 312  keys held: 4 6 (2)". If pressing three keys only ever
+//shows two, the keyboard or OS dropped one before it reached the window.
+pub struct ChordLog {
+    last: [bool; 16],
+    //Most keys seen held at once
+    most: usize
+}
+
+impl ChordLog {
+
+    pub fn create() -> ChordLog {
+        ChordLog {
+            last: [false; 16],
+            most: 0
+        }
+    }
+
+    pub fn update(&mut self, frame: u64, keypad: &[bool; 16]) {
+        if *keypad == self.last {
+            return;
+        }
+        self.last = *keypad;
+
+        let held: Vec<String> = (0..16).filter(|key| keypad[*key]).map(|key| format!("{:X}", key)).collect();
+        self.most = self.most.max(held.len());
+        if held.is_empty() {
+            info!("frame {}  keys held: none", frame);
+        } else {
+            info!("frame {}  keys held: {} ({})", frame, held.join(" "), held.len());
+        }
+    }
+
+    pub fn most(&self) -> usize {
+        self.most
+    }
+
+}
+
+//Keyboard layout:   CHIP-8 keypad:
+//  1 2 3 4            1 2 3 C
+//  Q W E R            4 5 6 D
+//  A S D F            7 8 9 E
+//  Z X C V            A 0 B F
+pub fn default_keymap() -> HashMap<Key, u8> {
+    HashMap::from([
+        (Key::D1, 0x1),
+        (Key::D2, 0x2),
+        (Key::D3, 0x3),
+        (Key::D4, 0xC),
+        (Key::Q,  0x4),
+        (Key::W,  0x5),
+        (Key::E,  0x6),
+        (Key::R,  0xD),
+        (Key::A,  0x7),
+        (Key::S,  0x8),
+        (Key::D,  0x9),
+        (Key::F,  0xE),
+        (Key::Z,  0xA),
+        (Key::X,  0x0),
+        (Key::C,  0xB),
+        (Key::V,  0xF)
+    ])
+}
+
+pub const LAYOUT_NAMES: [&str; 5] = ["qwerty", "azerty", "qwertz", "dvorak", "colemak"];
+
+//--layout: the default keymap moved to the keys in the same places on
+//another keyboard layout, row by row as in the picture above
+pub fn layout_keymap(layout: &str) -> Option<HashMap<Key, u8>> {
+    use Key::*;
+
+    let rows = match layout {
+        "qwerty" => return Some(default_keymap()),
+        "azerty" => [[D1, D2, D3, D4], [A, Z, E, R], [Q, S, D, F], [W, X, C, V]],
+        "qwertz" => [[D1, D2, D3, D4], [Q, W, E, R], [A, S, D, F], [Y, X, C, V]],
+        "dvorak" => [[D1, D2, D3, D4], [Quote, Comma, Period, P], [A, O, E, U], [Semicolon, Q, J, K]],
+        "colemak" => [[D1, D2, D3, D4], [Q, W, F, P], [A, R, S, T], [Z, X, C, V]],
+        _ => return None
+    };
+    let qwerty = [[D1, D2, D3, D4], [Q, W, E, R], [A, S, D, F], [Z, X, C, V]];
+    let keymap = default_keymap();
+
+    Some(qwerty.iter().flatten().zip(rows.iter().flatten()).map(|(from, to)| (*to, keymap[from])).collect())
+}
+
+//A keyboard key by piston's name for it in any case ("x", "Up",
+//"NumPad1"), or by the character on it ("1", ";")
+pub fn parse_key(name: &str) -> Option<Key> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        let key = Key::from(c.to_ascii_lowercase() as u32);
+        if key != Key::Unknown {
+            return Some(key);
+        }
+    }
+
+    //Every code piston has a key for, see its From<u32>
+    (0x01..0x80).chain(0x4000_0039..=0x4000_011A)
+        .map(Key::from)
+        .find(|key| *key != Key::Unknown && format!("{:?}", key).eq_ignore_ascii_case(name))
+}
+
+//--keymap's KEY=PAD, e.g. Space=5, PAD a hex digit
+pub fn parse_keymap_entry(text: &str) -> Result<(Key, u8), String> {
+    let (name, pad) = text.split_once('=').ok_or_else(|| format!("expected KEY=PAD, like Space=5, not {}", text))?;
+    let key = parse_key(name).ok_or_else(|| format!("unknown key {}", name))?;
+    let index = u8::from_str_radix(pad, 16).ok()
+        .filter(|index| *index <= 0xF)
+        .ok_or_else(|| format!("{} isn't a keypad key, expected 0-F", pad))?;
+
+    Ok((key, index))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use piston::{ButtonArgs, ButtonState, Input};
+
+    use super::*;
+
+    fn event(key: Key, state: ButtonState) -> Event {
+        Event::Input(Input::Button(ButtonArgs { state, button: Button::Keyboard(key), scancode: None }), None)
+    }
+
+    #[test]
+    fn default_keymap_reaches_every_key_once() {
+        let mut indices: Vec<u8> = default_keymap().into_values().collect();
+        indices.sort();
+        assert_eq!(indices, (0..16).collect::<Vec<u8>>());
+        assert_eq!(default_keymap()[&Key::X], 0x0);
+    }
+
+    #[test]
+    fn set_keymap() {
+        let mut input = PistonInput::create();
+        input.handle_event(&event(Key::Q, ButtonState::Press));
+        assert!(input.key_state()[0x4]);
+
+        let mut keymap = input.keymap().clone();
+        keymap.insert(Key::Space, 0x10);
+        assert_eq!(input.set_keymap(keymap), Err("Space is mapped to 0x10, the keypad only goes up to 0xF".to_string()));
+        assert_eq!(input.keymap(), &default_keymap());
+        assert!(input.key_state()[0x4]);
+
+        let mut keymap = input.keymap().clone();
+        keymap.insert(Key::Space, 0x5);
+        input.set_keymap(keymap).unwrap();
+        assert_eq!(input.key_state(), [false; 16]);
+
+        let change = input.handle_event(&event(Key::Space, ButtonState::Press));
+        assert_eq!(change, Some(KeyChange { key: Key::Space, index: 0x5, pressed: true }));
+        assert!(input.key_state()[0x5]);
+        assert_eq!(input.handle_event(&event(Key::Space, ButtonState::Press)), None);
+        input.handle_event(&event(Key::Space, ButtonState::Release));
+        assert_eq!(input.key_state(), [false; 16]);
+
+        assert_eq!(input.handle_event(&event(Key::Up, ButtonState::Press)), None);
+    }
+
+    #[test]
+    fn layouts_keep_the_keypad_shape() {
+        for layout in LAYOUT_NAMES {
+            let keymap = layout_keymap(layout).unwrap();
+            let mut indices: Vec<u8> = keymap.values().copied().collect();
+            indices.sort();
+            assert_eq!(indices, (0..16).collect::<Vec<u8>>(), "{}", layout);
+            assert_eq!(keymap[&Key::D4], 0xC, "{}", layout);
+        }
+
+        let azerty = layout_keymap("azerty").unwrap();
+        assert_eq!((azerty[&Key::A], azerty[&Key::Z], azerty[&Key::Q], azerty[&Key::W]), (0x4, 0x5, 0x7, 0xA));
+        let dvorak = layout_keymap("dvorak").unwrap();
+        assert_eq!((dvorak[&Key::Quote], dvorak[&Key::U], dvorak[&Key::Q]), (0x4, 0xE, 0x0));
+        assert_eq!(layout_keymap("qwerty"), Some(default_keymap()));
+        assert_eq!(layout_keymap("bepo"), None);
+    }
+
+    #[test]
+    fn parses_keymap_entries() {
+        assert_eq!(parse_keymap_entry("X=0"), Ok((Key::X, 0x0)));
+        assert_eq!(parse_keymap_entry("space=a"), Ok((Key::Space, 0xA)));
+        assert_eq!(parse_keymap_entry("NumPad1=F"), Ok((Key::NumPad1, 0xF)));
+        assert_eq!(parse_keymap_entry("1=1"), Ok((Key::D1, 0x1)));
+        assert_eq!(parse_keymap_entry(";=E"), Ok((Key::Semicolon, 0xE)));
+
+        assert_eq!(parse_keymap_entry("Space"), Err("expected KEY=PAD, like Space=5, not Space".to_string()));
+        assert_eq!(parse_keymap_entry("Hyper=1"), Err("unknown key Hyper".to_string()));
+        assert_eq!(parse_keymap_entry("Space=10"), Err("10 isn't a keypad key, expected 0-F".to_string()));
+    }
+
+}
@@ -0,0 +1,399 @@
+use std::fmt;
+
+//A decoded CHIP-8 instruction. Register operands are indices into
+//the V registers, already extracted from the opcode nibbles.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Instruction {
+    Cls,                                        //00E0
+    Ret,                                        //00EE
+    Lores,                                      //00FE
+    Hires,                                      //00FF
+    ScrollDown { n: u8 },                       //00CN
+    ScrollUp { n: u8 },                         //00DN
+    ScrollRight,                                //00FB
+    ScrollLeft,                                 //00FC
+    Exit,                                       //00FD
+    Sys { address: u16 },                       //0NNN
+    Jump { address: u16 },                      //1NNN
+    Call { address: u16 },                      //2NNN
+    SkipEqByte { x: usize, byte: u8 },          //3XKK
+    SkipNeByte { x: usize, byte: u8 },          //4XKK
+    SkipEqReg { x: usize, y: usize },           //5XY0
+    SaveRange { x: usize, y: usize },           //5XY2
+    LoadRange { x: usize, y: usize },           //5XY3
+    LoadByte { x: usize, byte: u8 },            //6XKK
+    AddByte { x: usize, byte: u8 },             //7XKK
+    LoadReg { x: usize, y: usize },             //8XY0
+    Or { x: usize, y: usize },                  //8XY1
+    And { x: usize, y: usize },                 //8XY2
+    Xor { x: usize, y: usize },                 //8XY3
+    AddReg { x: usize, y: usize },              //8XY4
+    Sub { x: usize, y: usize },                 //8XY5
+    ShiftRight { x: usize, y: usize },          //8XY6
+    SubN { x: usize, y: usize },                //8XY7
+    ShiftLeft { x: usize, y: usize },           //8XYE
+    SkipNeReg { x: usize, y: usize },           //9XY0
+    LoadIndex { address: u16 },                 //ANNN
+    JumpV0 { address: u16 },                    //BNNN
+    Random { x: usize, byte: u8 },              //CXKK
+    Draw { x: usize, y: usize, height: u8 },    //DXYN
+    SkipKeyPressed { x: usize },                //EX9E
+    SkipKeyNotPressed { x: usize },             //EXA1
+    LoadDelay { x: usize },                     //FX07
+    WaitKey { x: usize },                       //FX0A
+    SetDelay { x: usize },                      //FX15
+    SetSound { x: usize },                      //FX18
+    AddIndex { x: usize },                      //FX1E
+    LoadFont { x: usize },                      //FX29
+    LoadBigFont { x: usize },                   //FX30
+    StoreBcd { x: usize },                      //FX33
+    StoreRegisters { x: usize },                //FX55
+    LoadRegisters { x: usize },                 //FX65
+    StoreFlags { x: usize },                    //FX75
+    LoadFlags { x: usize },                     //FX85
+    LoadLongIndex { address: u16 },             //F000 NNNN, see Chip8::step
+    Plane { n: u8 },                            //FN01
+    Invalid { opcode: u16 }
+}
+
+pub fn decode(opcode: u16) -> Instruction {
+    let x: usize = ((opcode & 0x0F00) >> 8) as usize;
+    let y: usize = ((opcode & 0x00F0) >> 4) as usize;
+    let n: u8 = (opcode & 0x000F) as u8;
+    let byte: u8 = (opcode & 0x00FF) as u8;
+    let address: u16 = opcode & 0x0FFF;
+
+    match (opcode & 0xF000) >> 12 {
+        0x0 => match address {
+            0x0E0 => Instruction::Cls,
+            0x0EE => Instruction::Ret,
+            0x0FE => Instruction::Lores,
+            0x0FF => Instruction::Hires,
+            0x0C0..=0x0CF => Instruction::ScrollDown { n },
+            0x0D0..=0x0DF => Instruction::ScrollUp { n },
+            0x0FB => Instruction::ScrollRight,
+            0x0FC => Instruction::ScrollLeft,
+            0x0FD => Instruction::Exit,
+            _ => Instruction::Sys { address }
+        },
+        0x1 => Instruction::Jump { address },
+        0x2 => Instruction::Call { address },
+        0x3 => Instruction::SkipEqByte { x, byte },
+        0x4 => Instruction::SkipNeByte { x, byte },
+        0x5 => match n {
+            0x0 => Instruction::SkipEqReg { x, y },
+            0x2 => Instruction::SaveRange { x, y },
+            0x3 => Instruction::LoadRange { x, y },
+            _ => Instruction::Invalid { opcode }
+        },
+        0x6 => Instruction::LoadByte { x, byte },
+        0x7 => Instruction::AddByte { x, byte },
+        0x8 => match n {
+            0x0 => Instruction::LoadReg { x, y },
+            0x1 => Instruction::Or { x, y },
+            0x2 => Instruction::And { x, y },
+            0x3 => Instruction::Xor { x, y },
+            0x4 => Instruction::AddReg { x, y },
+            0x5 => Instruction::Sub { x, y },
+            0x6 => Instruction::ShiftRight { x, y },
+            0x7 => Instruction::SubN { x, y },
+            0xE => Instruction::ShiftLeft { x, y },
+            _ => Instruction::Invalid { opcode }
+        },
+        0x9 => Instruction::SkipNeReg { x, y },
+        0xA => Instruction::LoadIndex { address },
+        0xB => Instruction::JumpV0 { address },
+        0xC => Instruction::Random { x, byte },
+        0xD => Instruction::Draw { x, y, height: n },
+        0xE => match byte {
+            0x9E => Instruction::SkipKeyPressed { x },
+            0xA1 => Instruction::SkipKeyNotPressed { x },
+            _ => Instruction::Invalid { opcode }
+        },
+        0xF => match byte {
+            0x07 => Instruction::LoadDelay { x },
+            0x0A => Instruction::WaitKey { x },
+            0x15 => Instruction::SetDelay { x },
+            0x18 => Instruction::SetSound { x },
+            0x1E => Instruction::AddIndex { x },
+            0x29 => Instruction::LoadFont { x },
+            0x30 => Instruction::LoadBigFont { x },
+            0x33 => Instruction::StoreBcd { x },
+            0x55 => Instruction::StoreRegisters { x },
+            0x65 => Instruction::LoadRegisters { x },
+            //There are only 8 flags, V8-VF don't fit
+            0x75 if x <= 7 => Instruction::StoreFlags { x },
+            0x85 if x <= 7 => Instruction::LoadFlags { x },
+            //Two planes, masks 0-3
+            0x01 if x <= 3 => Instruction::Plane { n: x as u8 },
+            _ => Instruction::Invalid { opcode }
+        },
+        _ => Instruction::Invalid { opcode }
+    }
+}
+
+impl Instruction {
+
+    //Pattern name of the instruction, e.g. ADD VA, V1 -> "8XY4"
+    pub fn family(&self) -> &'static str {
+        match self {
+            Instruction::Cls => "00E0",
+            Instruction::Ret => "00EE",
+            Instruction::Lores => "00FE",
+            Instruction::Hires => "00FF",
+            Instruction::ScrollDown { .. } => "00CN",
+            Instruction::ScrollUp { .. } => "00DN",
+            Instruction::ScrollRight => "00FB",
+            Instruction::ScrollLeft => "00FC",
+            Instruction::Exit => "00FD",
+            Instruction::Sys { .. } => "0NNN",
+            Instruction::Jump { .. } => "1NNN",
+            Instruction::Call { .. } => "2NNN",
+            Instruction::SkipEqByte { .. } => "3XKK",
+            Instruction::SkipNeByte { .. } => "4XKK",
+            Instruction::SkipEqReg { .. } => "5XY0",
+            Instruction::SaveRange { .. } => "5XY2",
+            Instruction::LoadRange { .. } => "5XY3",
+            Instruction::LoadByte { .. } => "6XKK",
+            Instruction::AddByte { .. } => "7XKK",
+            Instruction::LoadReg { .. } => "8XY0",
+            Instruction::Or { .. } => "8XY1",
+            Instruction::And { .. } => "8XY2",
+            Instruction::Xor { .. } => "8XY3",
+            Instruction::AddReg { .. } => "8XY4",
+            Instruction::Sub { .. } => "8XY5",
+            Instruction::ShiftRight { .. } => "8XY6",
+            Instruction::SubN { .. } => "8XY7",
+            Instruction::ShiftLeft { .. } => "8XYE",
+            Instruction::SkipNeReg { .. } => "9XY0",
+            Instruction::LoadIndex { .. } => "ANNN",
+            Instruction::JumpV0 { .. } => "BNNN",
+            Instruction::Random { .. } => "CXKK",
+            Instruction::Draw { .. } => "DXYN",
+            Instruction::SkipKeyPressed { .. } => "EX9E",
+            Instruction::SkipKeyNotPressed { .. } => "EXA1",
+            Instruction::LoadDelay { .. } => "FX07",
+            Instruction::WaitKey { .. } => "FX0A",
+            Instruction::SetDelay { .. } => "FX15",
+            Instruction::SetSound { .. } => "FX18",
+            Instruction::AddIndex { .. } => "FX1E",
+            Instruction::LoadFont { .. } => "FX29",
+            Instruction::LoadBigFont { .. } => "FX30",
+            Instruction::StoreBcd { .. } => "FX33",
+            Instruction::StoreRegisters { .. } => "FX55",
+            Instruction::LoadRegisters { .. } => "FX65",
+            Instruction::StoreFlags { .. } => "FX75",
+            Instruction::LoadFlags { .. } => "FX85",
+            Instruction::LoadLongIndex { .. } => "F000",
+            Instruction::Plane { .. } => "FN01",
+            Instruction::Invalid { .. } => "????"
+        }
+    }
+
+}
+
+//Mnemonics follow Cowgod's CHIP-8 technical reference.
+//Anything that doesn't decode is emitted as a raw DW word.
+impl fmt::Display for Instruction {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::Lores => write!(f, "LOW"),
+            Instruction::Hires => write!(f, "HIGH"),
+            Instruction::ScrollDown { n } => write!(f, "SCD {}", n),
+            Instruction::ScrollUp { n } => write!(f, "SCU {}", n),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::Sys { address } => write!(f, "SYS 0x{:03X}", address),
+            Instruction::Jump { address } => write!(f, "JP 0x{:03X}", address),
+            Instruction::Call { address } => write!(f, "CALL 0x{:03X}", address),
+            Instruction::SkipEqByte { x, byte } => write!(f, "SE V{:X}, 0x{:02X}", x, byte),
+            Instruction::SkipNeByte { x, byte } => write!(f, "SNE V{:X}, 0x{:02X}", x, byte),
+            Instruction::SkipEqReg { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::SaveRange { x, y } => write!(f, "SAVE V{:X}, V{:X}", x, y),
+            Instruction::LoadRange { x, y } => write!(f, "LOAD V{:X}, V{:X}", x, y),
+            Instruction::LoadByte { x, byte } => write!(f, "LD V{:X}, 0x{:02X}", x, byte),
+            Instruction::AddByte { x, byte } => write!(f, "ADD V{:X}, 0x{:02X}", x, byte),
+            Instruction::LoadReg { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddReg { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::ShiftRight { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::SubN { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::ShiftLeft { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SkipNeReg { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LoadIndex { address } => write!(f, "LD I, 0x{:03X}", address),
+            Instruction::JumpV0 { address } => write!(f, "JP V0, 0x{:03X}", address),
+            Instruction::Random { x, byte } => write!(f, "RND V{:X}, 0x{:02X}", x, byte),
+            Instruction::Draw { x, y, height } => write!(f, "DRW V{:X}, V{:X}, {}", x, y, height),
+            Instruction::SkipKeyPressed { x } => write!(f, "SKP V{:X}", x),
+            Instruction::SkipKeyNotPressed { x } => write!(f, "SKNP V{:X}", x),
+            Instruction::LoadDelay { x } => write!(f, "LD V{:X}, DT", x),
+            Instruction::WaitKey { x } => write!(f, "LD V{:X}, K", x),
+            Instruction::SetDelay { x } => write!(f, "LD DT, V{:X}", x),
+            Instruction::SetSound { x } => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddIndex { x } => write!(f, "ADD I, V{:X}", x),
+            Instruction::LoadFont { x } => write!(f, "LD F, V{:X}", x),
+            Instruction::LoadBigFont { x } => write!(f, "LD HF, V{:X}", x),
+            Instruction::StoreBcd { x } => write!(f, "LD B, V{:X}", x),
+            Instruction::StoreRegisters { x } => write!(f, "LD [I], V{:X}", x),
+            Instruction::LoadRegisters { x } => write!(f, "LD V{:X}, [I]", x),
+            Instruction::StoreFlags { x } => write!(f, "LD R, V{:X}", x),
+            Instruction::LoadFlags { x } => write!(f, "LD V{:X}, R", x),
+            Instruction::LoadLongIndex { address } => write!(f, "LD I, LONG 0x{:04X}", address),
+            Instruction::Plane { n } => write!(f, "PLANE {}", n),
+            Instruction::Invalid { opcode } => write!(f, "DW 0x{:04X}", opcode)
+        }
+    }
+
+}
+
+//Vx through Vy in the order 5XY2/5XY3 go through them, counting down
+//when x > y
+pub fn register_range(x: usize, y: usize) -> Vec<usize> {
+    if x <= y {
+        (x..=y).collect()
+    } else {
+        (y..=x).rev().collect()
+    }
+}
+
+//Reference entry for one opcode family, shown by chip8 decode and the
+//debugger's help <mnemonic>
+pub struct OpcodeInfo {
+    pub family: &'static str,
+    pub syntax: &'static str,
+    pub description: &'static str,
+    //Quirks (by QUIRK_NAMES name) that change what it does
+    pub quirks: &'static [&'static str]
+}
+
+const fn info(family: &'static str, syntax: &'static str, description: &'static str, quirks: &'static [&'static str]) -> OpcodeInfo {
+    OpcodeInfo { family, syntax, description, quirks }
+}
+
+pub const OPCODES: [OpcodeInfo; 49] = [
+    info("00E0", "CLS", "Clear the display", &[]),
+    info("00EE", "RET", "Return from a subroutine, PC = the address popped off the stack", &[]),
+    info("00FE", "LOW", "Switch to the 64x32 display (SUPER-CHIP), clearing it", &["scale-on-resize"]),
+    info("00FF", "HIGH", "Switch to the 128x64 display (SUPER-CHIP), clearing it", &["scale-on-resize"]),
+    info("00CN", "SCD nibble", "Scroll the display down n pixels, blank rows come in at the top (SUPER-CHIP)", &["half-scroll"]),
+    info("00DN", "SCU nibble", "Scroll the selected planes up n pixels, blank rows come in at the bottom (XO-CHIP)", &["half-scroll", "xo-chip-opcodes"]),
+    info("00FB", "SCR", "Scroll the display right 4 pixels, blank columns come in at the left (SUPER-CHIP)", &["half-scroll"]),
+    info("00FC", "SCL", "Scroll the display left 4 pixels, blank columns come in at the right (SUPER-CHIP)", &["half-scroll"]),
+    info("00FD", "EXIT", "Stop the program, nothing runs after it (SUPER-CHIP)", &[]),
+    info("0NNN", "SYS addr", "Call the machine code routine at nnn, this interpreter halts instead", &[]),
+    info("1NNN", "JP addr", "PC = nnn", &[]),
+    info("2NNN", "CALL addr", "Push PC and jump to the subroutine at nnn", &[]),
+    info("3XKK", "SE Vx, byte", "Skip the next instruction if Vx == kk", &[]),
+    info("4XKK", "SNE Vx, byte", "Skip the next instruction if Vx != kk", &[]),
+    info("5XY0", "SE Vx, Vy", "Skip the next instruction if Vx == Vy", &[]),
+    info("5XY2", "SAVE Vx, Vy", "Store Vx through Vy in memory starting at I, counting down when x > y. I is left alone (XO-CHIP)", &["xo-chip-opcodes"]),
+    info("5XY3", "LOAD Vx, Vy", "Load Vx through Vy from memory starting at I, counting down when x > y. I is left alone (XO-CHIP)", &["xo-chip-opcodes"]),
+    info("6XKK", "LD Vx, byte", "Vx = kk", &[]),
+    info("7XKK", "ADD Vx, byte", "Vx = Vx + kk, VF is left alone", &[]),
+    info("8XY0", "LD Vx, Vy", "Vx = Vy", &[]),
+    info("8XY1", "OR Vx, Vy", "Vx = Vx | Vy", &["vf-reset"]),
+    info("8XY2", "AND Vx, Vy", "Vx = Vx & Vy", &["vf-reset"]),
+    info("8XY3", "XOR Vx, Vy", "Vx = Vx ^ Vy", &["vf-reset"]),
+    info("8XY4", "ADD Vx, Vy", "Vx = Vx + Vy, VF = 1 on carry", &[]),
+    info("8XY5", "SUB Vx, Vy", "Vx = Vx - Vy, VF = 1 when there is no borrow", &[]),
+    info("8XY6", "SHR Vx {, Vy}", "Vx = Vx >> 1, VF = the bit shifted out", &["shift-vy"]),
+    info("8XY7", "SUBN Vx, Vy", "Vx = Vy - Vx, VF = 1 when there is no borrow", &[]),
+    info("8XYE", "SHL Vx {, Vy}", "Vx = Vx << 1, VF = the bit shifted out", &["shift-vy"]),
+    info("9XY0", "SNE Vx, Vy", "Skip the next instruction if Vx != Vy", &[]),
+    info("ANNN", "LD I, addr", "I = nnn", &[]),
+    info("BNNN", "JP V0, addr", "PC = nnn + V0", &["jump-vx"]),
+    info("CXKK", "RND Vx, byte", "Vx = a random byte & kk", &[]),
+    info("DXYN", "DRW Vx, Vy, nibble", "XOR the n-row sprite at I onto the screen at (Vx, Vy), VF = 1 if a lit pixel was erased. \
+        n = 0 is a 16x16 sprite of two bytes a row (SUPER-CHIP)", &["clip-x", "clip-y", "dxy0-8x16"]),
+    info("EX9E", "SKP Vx", "Skip the next instruction if the key in Vx is down", &["key-wrap"]),
+    info("EXA1", "SKNP Vx", "Skip the next instruction if the key in Vx is up", &["key-wrap"]),
+    info("FX07", "LD Vx, DT", "Vx = the delay timer", &[]),
+    info("FX0A", "LD Vx, K", "Wait for a key press and store the key in Vx", &[]),
+    info("FX15", "LD DT, Vx", "Delay timer = Vx", &[]),
+    info("FX18", "LD ST, Vx", "Sound timer = Vx", &[]),
+    info("FX1E", "ADD I, Vx", "I = I + Vx", &[]),
+    info("FX29", "LD F, Vx", "I = the address of the font glyph for the digit in the low nibble of Vx", &[]),
+    info("FX30", "LD HF, Vx", "I = the address of the 8x10 font glyph for the digit 0-9 in Vx (SUPER-CHIP)", &[]),
+    info("FX33", "LD B, Vx", "Store the hundreds, tens and ones of Vx at I, I+1 and I+2", &[]),
+    info("FX55", "LD [I], Vx", "Store V0 through Vx in memory starting at I", &["increment-i", "increment-i-x"]),
+    info("FX65", "LD Vx, [I]", "Load V0 through Vx from memory starting at I", &["increment-i", "increment-i-x"]),
+    info("FX75", "LD R, Vx", "Store V0 through Vx, x <= 7, in the RPL user flags (SUPER-CHIP)", &[]),
+    info("FX85", "LD Vx, R", "Load V0 through Vx, x <= 7, from the RPL user flags (SUPER-CHIP)", &[]),
+    info("F000", "LD I, LONG addr", "I = the 16-bit address in the next two bytes (XO-CHIP)", &["xo-chip-opcodes"]),
+    info("FN01", "PLANE nibble", "Select the drawing planes in the mask n, 0-3, for DRW, CLS and the scrolls (XO-CHIP)", &["xo-chip-opcodes"])
+];
+
+impl Instruction {
+
+    //None for opcodes that don't decode
+    pub fn info(&self) -> Option<&'static OpcodeInfo> {
+        OPCODES.iter().find(|info| info.family == self.family())
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn decodes_operands() {
+        let cases = [
+            (0x00E0, Instruction::Cls),
+            (0x00EE, Instruction::Ret),
+            (0x00C3, Instruction::ScrollDown { n: 3 }),
+            (0x0123, Instruction::Sys { address: 0x123 }),
+            (0x1ABC, Instruction::Jump { address: 0xABC }),
+            (0x2ABC, Instruction::Call { address: 0xABC }),
+            (0x3A42, Instruction::SkipEqByte { x: 0xA, byte: 0x42 }),
+            (0x4A42, Instruction::SkipNeByte { x: 0xA, byte: 0x42 }),
+            (0x5AB0, Instruction::SkipEqReg { x: 0xA, y: 0xB }),
+            (0x5AB2, Instruction::SaveRange { x: 0xA, y: 0xB }),
+            (0x6A42, Instruction::LoadByte { x: 0xA, byte: 0x42 }),
+            (0x7A42, Instruction::AddByte { x: 0xA, byte: 0x42 }),
+            (0x8AB4, Instruction::AddReg { x: 0xA, y: 0xB }),
+            (0x8ABE, Instruction::ShiftLeft { x: 0xA, y: 0xB }),
+            (0x9AB0, Instruction::SkipNeReg { x: 0xA, y: 0xB }),
+            (0xAABC, Instruction::LoadIndex { address: 0xABC }),
+            (0xBABC, Instruction::JumpV0 { address: 0xABC }),
+            (0xCA0F, Instruction::Random { x: 0xA, byte: 0x0F }),
+            (0xDAB5, Instruction::Draw { x: 0xA, y: 0xB, height: 5 }),
+            (0xEA9E, Instruction::SkipKeyPressed { x: 0xA }),
+            (0xEAA1, Instruction::SkipKeyNotPressed { x: 0xA }),
+            (0xFA0A, Instruction::WaitKey { x: 0xA }),
+            (0xFA29, Instruction::LoadFont { x: 0xA }),
+            (0xFA65, Instruction::LoadRegisters { x: 0xA }),
+            (0xF775, Instruction::StoreFlags { x: 7 }),
+            (0xF201, Instruction::Plane { n: 2 })
+        ];
+
+        for (opcode, instruction) in cases {
+            assert_eq!(decode(opcode), instruction, "{:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn invalid_opcodes() {
+        for opcode in [0x5AB1, 0x8AB8, 0xEA00, 0xFAFF, 0xF875, 0xF885, 0xF401] {
+            assert_eq!(decode(opcode), Instruction::Invalid { opcode }, "{:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn every_family_has_info() {
+        for opcode in 0..=0xFFFF {
+            let instruction = decode(opcode);
+            let has_info = instruction.info().is_some();
+            assert_eq!(has_info, !matches!(instruction, Instruction::Invalid { .. }), "{:04X}", opcode);
+        }
+    }
+
+}
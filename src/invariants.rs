@@ -0,0 +1,55 @@
+use crate::{
+    chip8::{Chip8, Chip8Error},
+    instruction::Instruction
+};
+
+//--verify-invariants checks the machine after every instruction and stops
+//with Chip8Error::InvariantViolated, naming the instruction, as soon as one
+//doesn't hold:
+//  - PC can still fetch a whole instruction
+//  - SP is within the 16-level stack
+//  - VF is 0 or 1 after the instructions that use it as a flag
+//  - after DXYN, VF is 1 exactly when the draw turned a lit pixel off
+impl Chip8 {
+
+    //address is where the instruction was fetched from, video_before both
+    //planes, one after the other, before a DXYN and None for anything else
+    pub fn check_invariants(&self, address: u16, instruction: Instruction, video_before: Option<&Vec<u8>>) -> Result<(), Chip8Error> {
+        let violated = |invariant: &'static str| Err(Chip8Error::InvariantViolated {
+            address,
+            opcode: self.opcode,
+            invariant
+        });
+
+        if self.program_counter as usize > self.memory.len() - 2 {
+            return violated("PC is outside memory");
+        }
+        if self.stack_pointer as usize > self.stack.len() {
+            return violated("SP is past the end of the stack");
+        }
+
+        let flag = self.registers[0xF];
+        let sets_flag = matches!(instruction,
+            Instruction::AddReg { .. } |
+            Instruction::Sub { .. } |
+            Instruction::ShiftRight { .. } |
+            Instruction::SubN { .. } |
+            Instruction::ShiftLeft { .. } |
+            Instruction::Draw { .. }
+        );
+        if sets_flag && flag > 1 {
+            return violated("VF holds something other than 0 or 1 after setting a flag");
+        }
+
+        if let Some(before) = video_before {
+            let after = self.video.iter().chain(&self.plane2);
+            let collided = before.iter().zip(after).any(|(before, after)| *before != 0 && *after == 0);
+            if collided != (flag == 1) {
+                return violated("VF doesn't match whether DXYN erased a pixel");
+            }
+        }
+
+        Ok(())
+    }
+
+}
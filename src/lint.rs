@@ -0,0 +1,262 @@
+use std::{collections::BTreeSet, fmt};
+
+use crate::{
+    disasm::{disassemble, opcode_at},
+    instruction::{decode, Instruction}
+};
+
+//Static checks for chip8 lint. The ROM is walked from 0x200 the way it
+//would execute: jumps and calls are followed and skips take both paths,
+//so sprite data behind the code isn't mistaken for instructions. Register
+//values set by LD Vx, byte (and carried through ADD Vx, byte and
+//LD Vx, Vy) are tracked along each path to check FX29.
+
+const LOAD_ADDRESS: usize = 0x200;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Severity {
+    Warning,
+    Error
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Finding {
+    pub address: u16,
+    pub severity: Severity,
+    pub message: String
+}
+
+//Constant register values known on the current path
+type Known = [Option<u8>; 16];
+
+pub fn lint(rom: &[u8]) -> Vec<Finding> {
+    let mut memory = vec![0; LOAD_ADDRESS];
+    memory.extend_from_slice(rom);
+    let rom_end = memory.len();
+
+    let mut findings = Vec::new();
+    //DXY0 is only suspicious if the ROM never switches to hires, lores
+    //interpreters don't agree on its size
+    let mut uses_hires = false;
+    let mut blank_draws = Vec::new();
+    let mut visited: BTreeSet<usize> = BTreeSet::new();
+    let mut pending: Vec<(usize, Known)> = vec![(LOAD_ADDRESS, [None; 16])];
+
+    while let Some((address, mut known)) = pending.pop() {
+        if !visited.insert(address) {
+            continue;
+        }
+        let Some(opcode) = opcode_at(&memory, address as u16) else {
+            findings.push(Finding::create(address, Severity::Error, "execution runs off the end of the ROM".to_string()));
+            continue;
+        };
+
+        let instruction = decode(opcode);
+        let mut finding = |severity, message: String| findings.push(Finding::create(address, severity, message));
+
+        let mut check_target = |name: &str, target: u16| -> bool {
+            let target = target as usize;
+            if !(LOAD_ADDRESS..rom_end).contains(&target) {
+                finding(Severity::Error, format!("{} target {:#05x} is outside the ROM ({:#05x}-{:#05x})", name, target, LOAD_ADDRESS, rom_end - 1));
+                return false;
+            }
+            if !target.is_multiple_of(2) {
+                finding(Severity::Warning, format!("{} target {:#05x} is at an odd address", name, target));
+            }
+            true
+        };
+
+        let next = address + 2;
+        match instruction {
+            Instruction::Invalid { opcode } => {
+                finding(Severity::Error, format!("{:#06x} isn't an instruction this interpreter implements", opcode));
+                continue;
+            },
+            Instruction::Sys { address: target } => {
+                finding(Severity::Warning, format!("SYS {:#05x} calls machine code, this interpreter halts on it", target));
+                continue;
+            },
+            Instruction::Ret | Instruction::Exit => continue,
+            Instruction::Hires => uses_hires = true,
+            Instruction::Jump { address: target } => {
+                if check_target("JP", target) {
+                    pending.push((target as usize, known));
+                }
+                continue;
+            },
+            //The target depends on V0, nothing to follow
+            Instruction::JumpV0 { .. } => continue,
+            Instruction::Call { address: target } => {
+                if check_target("CALL", target) {
+                    pending.push((target as usize, [None; 16]));
+                }
+                //Whatever the subroutine does to the registers is unknown
+                pending.push((next, [None; 16]));
+                continue;
+            },
+            Instruction::SkipEqByte { .. } |
+            Instruction::SkipNeByte { .. } |
+            Instruction::SkipEqReg { .. } |
+            Instruction::SkipNeReg { .. } |
+            Instruction::SkipKeyPressed { .. } |
+            Instruction::SkipKeyNotPressed { .. } => pending.push((next + 2, known)),
+            Instruction::Draw { height: 0, .. } => {
+                blank_draws.push(Finding::create(address, Severity::Warning, format!("{} is a SUPER-CHIP 16x16 sprite, in lores some interpreters draw it 8x16", disassemble(opcode))));
+            },
+            Instruction::LoadFont { x } => {
+                if let Some(value) = known[x].filter(|value| *value > 0xF) {
+                    finding(Severity::Warning, format!("LD F, V{:X} with V{:X} = {:#04x}, there is no font glyph past 0xF, only the low nibble counts", x, x, value));
+                }
+            },
+            Instruction::LoadBigFont { x } => {
+                if let Some(value) = known[x].filter(|value| *value & 0x0F > 9) {
+                    finding(Severity::Warning, format!("LD HF, V{:X} with V{:X} = {:#04x}, the big font only has the digits 0-9", x, x, value));
+                }
+            },
+            _ => ()
+        }
+
+        propagate(&mut known, instruction);
+        pending.push((next, known));
+    }
+
+    if !uses_hires {
+        findings.extend(blank_draws);
+    }
+    findings.sort_by_key(|finding| finding.address);
+    findings
+}
+
+//Updates the known register values for what instruction writes
+fn propagate(known: &mut Known, instruction: Instruction) {
+    match instruction {
+        Instruction::LoadByte { x, byte } => known[x] = Some(byte),
+        Instruction::AddByte { x, byte } => known[x] = known[x].map(|value| value.wrapping_add(byte)),
+        Instruction::LoadReg { x, y } => known[x] = known[y],
+        Instruction::Or { x, .. } |
+        Instruction::And { x, .. } |
+        Instruction::Xor { x, .. } |
+        Instruction::AddReg { x, .. } |
+        Instruction::Sub { x, .. } |
+        Instruction::ShiftRight { x, .. } |
+        Instruction::SubN { x, .. } |
+        Instruction::ShiftLeft { x, .. } => {
+            known[x] = None;
+            known[0xF] = None;
+        },
+        Instruction::Draw { .. } => known[0xF] = None,
+        Instruction::Random { x, .. } |
+        Instruction::LoadDelay { x } |
+        Instruction::WaitKey { x } => known[x] = None,
+        Instruction::LoadRegisters { x } |
+        Instruction::LoadFlags { x } => known[..=x].fill(None),
+        Instruction::LoadRange { x, y } => known[x.min(y)..=x.max(y)].fill(None),
+        _ => ()
+    }
+}
+
+impl Finding {
+
+    fn create(address: usize, severity: Severity, message: String) -> Finding {
+        Finding {
+            address: address as u16,
+            severity,
+            message
+        }
+    }
+
+}
+
+impl fmt::Display for Severity {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error")
+        }
+    }
+
+}
+
+impl fmt::Display for Finding {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#06x}  {:7}  {}", self.address, self.severity.to_string(), self.message)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    //Each finding as "address severity message" for short comparisons
+    fn check(rom: &[u8]) -> Vec<String> {
+        lint(rom).iter().map(|finding| format!("{:03x} {} {}", finding.address, finding.severity, finding.message)).collect()
+    }
+
+    #[test]
+    fn clean_rom_with_data_after_the_code() {
+        //LD I, 0x206; DRW V0, V0, 2; JP 0x204; then sprite bytes that
+        //aren't instructions
+        assert!(check(&[0xA2, 0x06, 0xD0, 0x02, 0x12, 0x04, 0xE0, 0x00]).is_empty());
+    }
+
+    #[test]
+    fn unimplemented_opcodes() {
+        assert_eq!(check(&[0x60, 0x01, 0xE0, 0x00]), ["202 error 0xe000 isn't an instruction this interpreter implements"]);
+    }
+
+    #[test]
+    fn running_off_the_end() {
+        assert_eq!(check(&[0x60, 0x01]), ["202 error execution runs off the end of the ROM"]);
+    }
+
+    #[test]
+    fn jump_and_call_targets() {
+        assert_eq!(check(&[0x13, 0x00]), ["200 error JP target 0x300 is outside the ROM (0x200-0x201)"]);
+        //An odd target is followed, here into the ROM's last byte
+        assert_eq!(check(&[0x22, 0x03, 0x12, 0x02]), [
+            "200 warning CALL target 0x203 is at an odd address",
+            "203 error execution runs off the end of the ROM"
+        ]);
+    }
+
+    #[test]
+    fn sys_calls() {
+        assert_eq!(check(&[0x03, 0x00]), ["200 warning SYS 0x300 calls machine code, this interpreter halts on it"]);
+    }
+
+    #[test]
+    fn big_sprites_without_hires() {
+        let rom = [0xD0, 0x10, 0x12, 0x00];
+        assert_eq!(check(&rom), ["200 warning DRW V0, V1, 0 is a SUPER-CHIP 16x16 sprite, in lores some interpreters draw it 8x16"]);
+        assert!(check(&[0x00, 0xFF, 0xD0, 0x10, 0x12, 0x02]).is_empty());
+    }
+
+    #[test]
+    fn font_digits_past_f() {
+        //LD V1, 0x0E; ADD V1, 2; LD V2, V1; LD F, V2
+        assert_eq!(check(&[0x61, 0x0E, 0x71, 0x02, 0x82, 0x10, 0xF2, 0x29, 0x12, 0x06]),
+            ["206 warning LD F, V2 with V2 = 0x10, there is no font glyph past 0xF, only the low nibble counts"]);
+        //Unknown after RND, and after a CALL
+        assert!(check(&[0x61, 0x10, 0xC1, 0xFF, 0xF1, 0x29, 0x12, 0x04]).is_empty());
+        assert!(check(&[0x61, 0x10, 0x22, 0x08, 0xF1, 0x29, 0x12, 0x04, 0x00, 0xEE]).is_empty());
+    }
+
+    #[test]
+    fn big_font_digits_past_9() {
+        //LD V3, 0x0A; LD HF, V3. 0x19 counts as the digit 9.
+        assert_eq!(check(&[0x63, 0x0A, 0xF3, 0x30, 0x12, 0x02]),
+            ["202 warning LD HF, V3 with V3 = 0x0a, the big font only has the digits 0-9"]);
+        assert!(check(&[0x63, 0x19, 0xF3, 0x30, 0x12, 0x02]).is_empty());
+    }
+
+    #[test]
+    fn skips_take_both_paths() {
+        //SE V0, 0 skips a bad opcode only sometimes
+        assert_eq!(check(&[0x30, 0x00, 0xE0, 0x00, 0x12, 0x04]), ["202 error 0xe000 isn't an instruction this interpreter implements"]);
+    }
+
+}
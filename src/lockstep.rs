@@ -0,0 +1,190 @@
+use std::fmt;
+
+use crate::{
+    chip8::{Chip8, Chip8Error},
+    disasm::disassemble,
+    headless::ScriptEvent,
+    instruction::Instruction,
+    state::StateDiff
+};
+
+//Runs two machines instruction by instruction on the same program and
+//input and stops at the first instruction after which their state
+//differs. Meant for checking that an interpreter change or a quirk
+//doesn't change behavior: set up a and b differently and compare.
+//b gets a's keypad before every instruction, and a's RND results, so
+//the random number generator isn't mistaken for a divergence.
+pub struct Divergence {
+    //1-based number of the instruction after which the states differ
+    pub cycle: u64,
+    //The instruction a executed last
+    pub address: u16,
+    pub opcode: u16,
+    pub diff: StateDiff,
+    //Set when only one side halted, or both with different errors
+    pub errors: (Option<Chip8Error>, Option<Chip8Error>)
+}
+
+//Runs up to cycles instructions, ticking the timers after each one, and
+//returns how many ran. Stops early without a divergence when both
+//machines halt with the same error.
+pub fn run_lockstep(a: &mut Chip8, b: &mut Chip8, cycles: u64) -> Result<u64, Divergence> {
+    for cycle in 1..=cycles {
+        let address = a.program_counter;
+        b.keypad = a.keypad;
+
+        let (result_a, result_b) = (a.step(), b.step());
+        if let (Ok(Instruction::Random { x, .. }), Ok(Instruction::Random { x: x_b, .. })) = (result_a, result_b) {
+            if x == x_b {
+                b.registers[x] = a.registers[x];
+            }
+        }
+        a.tick_timers();
+        b.tick_timers();
+
+        let errors = (result_a.err(), result_b.err());
+        let diff = StateDiff::compare(a, b);
+        if !diff.is_empty() || errors.0 != errors.1 {
+            return Err(Divergence { cycle, address, opcode: a.opcode, diff, errors });
+        }
+        if errors.0.is_some() {
+            return Ok(cycle);
+        }
+    }
+
+    Ok(cycles)
+}
+
+//Like run_lockstep for any number of machines, each compared with the
+//first. Machines keep running after they diverge so their final states
+//can be looked at too; a machine stops when it halts. Keys come from a
+//headless input script, applied to every machine alike. Returns where
+//each machine first diverged from the first one, None for the first
+//itself and for any that never did.
+pub fn run_compare(machines: &mut [Chip8], cycles: u64, script: &[ScriptEvent]) -> Vec<Option<Divergence>> {
+    let mut divergences: Vec<Option<Divergence>> = machines.iter().map(|_| None).collect();
+    let mut halted: Vec<Option<Chip8Error>> = vec![None; machines.len()];
+    let Some(reference) = machines.first() else {
+        return divergences;
+    };
+    let mut keypad = reference.keypad;
+    let mut events = script.iter().peekable();
+
+    for cycle in 1..=cycles {
+        if halted.iter().all(Option::is_some) {
+            break;
+        }
+        //Script cycles count instructions from 0, like headless.rs
+        while let Some(event) = events.next_if(|event| event.cycle < cycle) {
+            keypad[event.key as usize] = event.pressed;
+        }
+
+        let address = machines[0].program_counter;
+        let mut results = Vec::with_capacity(machines.len());
+        for (chip8, halt) in machines.iter_mut().zip(halted.iter_mut()) {
+            if halt.is_some() {
+                results.push(None);
+                continue;
+            }
+
+            chip8.keypad = keypad;
+            let result = chip8.step();
+            chip8.tick_timers();
+            *halt = result.err();
+            results.push(Some(result));
+        }
+
+        //Everyone gets the first machine's random numbers
+        if let Some(Ok(Instruction::Random { x, .. })) = results[0] {
+            let value = machines[0].registers[x];
+            for (chip8, result) in machines.iter_mut().zip(results.iter()).skip(1) {
+                if let Some(Ok(Instruction::Random { x: other, .. })) = result {
+                    if *other == x {
+                        chip8.registers[x] = value;
+                    }
+                }
+            }
+        }
+
+        let (reference, others) = machines.split_first().unwrap();
+        for (i, chip8) in others.iter().enumerate() {
+            let i = i + 1;
+            if divergences[i].is_some() {
+                continue;
+            }
+
+            let errors = (halted[0], halted[i]);
+            let diff = StateDiff::compare(reference, chip8);
+            if !diff.is_empty() || errors.0 != errors.1 {
+                divergences[i] = Some(Divergence { cycle, address, opcode: reference.opcode, diff, errors });
+            }
+        }
+    }
+
+    divergences
+}
+
+impl fmt::Display for Divergence {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Diverged after instruction {}, {:04X} {} at {:#05x}", self.cycle, self.opcode, disassemble(self.opcode), self.address)?;
+
+        let halted = |error: &Option<Chip8Error>| error.map_or("kept running".to_string(), |e| e.to_string());
+        if self.errors.0 != self.errors.1 {
+            writeln!(f, "a: {}", halted(&self.errors.0))?;
+            writeln!(f, "b: {}", halted(&self.errors.1))?;
+        }
+        if !self.diff.is_empty() {
+            write!(f, "{}", self.diff)?;
+        }
+
+        Ok(())
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{headless::parse_script, quirks::Quirks};
+
+    fn machines(rom: &[u8], profiles: &[&str]) -> Vec<Chip8> {
+        profiles.iter().map(|name| {
+            let mut chip8 = Chip8::create();
+            chip8.quirks = Quirks::profile(name).unwrap();
+            chip8.load_bytes(rom).unwrap();
+            chip8
+        }).collect()
+    }
+
+    #[test]
+    fn diverges_on_the_shift_quirk() {
+        //V0 = 6, V1 = 3, then SHR V0: 3 in place, 1 shifting V1
+        let rom = [0x60, 0x05, 0x61, 0x03, 0x70, 0x01, 0x80, 0x16, 0x12, 0x08];
+        let mut machines = machines(&rom, &["default", "schip", "chip8"]);
+        let divergences = run_compare(&mut machines, 100, &[]);
+
+        assert!(divergences[0].is_none());
+        assert!(divergences[1].is_none());
+        let divergence = divergences[2].as_ref().unwrap();
+        assert_eq!((divergence.cycle, divergence.address, divergence.opcode), (4, 0x206, 0x8016));
+        assert_eq!(divergence.diff.to_string(), "V0: 0x03 != 0x01\nVF: 0x00 != 0x01\n");
+    }
+
+    #[test]
+    fn scripted_keys_reach_every_machine() {
+        //Waits for a key into V0, V1 = 4, then SHR V0
+        let rom = [0xF0, 0x0A, 0x61, 0x04, 0x80, 0x16, 0x12, 0x06];
+
+        let mut waiting = machines(&rom, &["default", "chip8"]);
+        assert!(run_compare(&mut waiting, 100, &[]).iter().all(Option::is_none));
+
+        let script = parse_script("@10 press 6").unwrap();
+        let mut pressed = machines(&rom, &["default", "chip8"]);
+        let divergences = run_compare(&mut pressed, 100, &script);
+        assert_eq!(divergences[1].as_ref().map(|divergence| divergence.cycle), Some(13));
+        assert_eq!((pressed[0].registers[0], pressed[1].registers[0]), (3, 2));
+    }
+
+}
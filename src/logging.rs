@@ -0,0 +1,62 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+//Messages from the log macros go to stderr. The level comes from
+//--log-level, else RUST_LOG, else info.
+//Info messages print as they are, the rest get their level in front.
+struct StderrLogger;
+
+static LOGGER: StderrLogger = StderrLogger;
+
+//Installed first thing in main so subcommands log too, before the
+//command line is parsed
+pub fn init() {
+    if log::set_logger(&LOGGER).is_ok() {
+        let level = std::env::var("RUST_LOG").ok()
+            .and_then(|filter| env_level(&filter))
+            .unwrap_or(LevelFilter::Info);
+        log::set_max_level(level);
+    }
+}
+
+//RUST_LOG as env_logger writes it, e.g. debug or warn,CHIP8_emu=trace.
+//There's only the one crate to log, so the most verbose level named wins.
+fn env_level(filter: &str) -> Option<LevelFilter> {
+    filter.split(',')
+        .filter_map(|directive| parse_level(directive.rsplit('=').next().unwrap_or_default()).ok())
+        .max()
+}
+
+pub fn set_level(level: LevelFilter) {
+    log::set_max_level(level);
+}
+
+//off, error, warn, info, debug or trace
+pub fn parse_level(text: &str) -> Result<LevelFilter, String> {
+    text.trim().parse::<LevelFilter>().map_err(|_| "expected off, error, warn, info, debug or trace".to_string())
+}
+
+impl Log for StderrLogger {
+
+    //Dependencies like the http client log too, only their warnings
+    //and errors are worth showing
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+            && (metadata.level() <= Level::Warn || metadata.target().starts_with(env!("CARGO_CRATE_NAME")))
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        match record.level() {
+            Level::Info => eprintln!("{}", record.args()),
+            Level::Error => eprintln!("Error: {}", record.args()),
+            Level::Warn => eprintln!("Warning: {}", record.args()),
+            Level::Debug | Level::Trace => eprintln!("[{}] {}", record.level().as_str().to_lowercase(), record.args())
+        }
+    }
+
+    fn flush(&self) {}
+
+}
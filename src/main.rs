@@ -1,5 +1,51 @@
+mod asm;
+mod attract;
+mod bench;
 mod chip8;
+mod commands;
+mod config;
+mod coverage;
+mod debugger;
+mod detect;
 mod device;
+mod diagnostic;
+#[cfg(feature = "http")]
+mod download;
+mod dump;
+mod disasm;
+mod headless;
+mod history;
+mod input;
+mod instruction;
+mod invariants;
+mod lint;
+mod lockstep;
+mod logging;
+#[cfg(feature = "octo")]
+mod octo;
+mod octocart;
+mod poke;
+mod profiler;
+mod picker;
+mod quirks;
+mod recorder;
+mod remote;
+mod repl;
+mod rom_dir;
+mod rom_info;
+mod rng;
+mod rpl;
+mod settings;
+mod sprite;
+mod state;
+mod strict;
+mod symbols;
+mod text;
+mod timing;
+mod trace;
+mod uninit;
+#[cfg(feature = "zip")]
+mod zip;
 
 ///CHIP-8 Spec
 ///16x 8bit register -> index by V0 (0 is HEX)
@@ -18,110 +64,808 @@ mod device;
 ///16 Input keys
 ///64x32 Monochrome display memory
 ///
+use std::{env, fs, io, time::SystemTime, rc::Rc, cell::RefCell, path::{Path, PathBuf}, process};
+use device::{Device, DEFAULT_SCALE};
+use chip8::{Chip8, Chip8Error, LowMemoryProtection};
+use clap::CommandFactory;
+use attract::AttractMode;
+use config::Config;
+use commands::Command;
+use coverage::Coverage;
+use debugger::Debugger;
+use detect::Detection;
+use dump::StateDump;
+use input::{layout_keymap, ChordLog, InputLog, InputSource, PistonInput};
+use picker::RomPicker;
+use profiler::{IdleDetector, IdleStatus, Profiler};
+use recorder::GifRecorder;
+use remote::RemoteDebug;
+use rom_dir::{pick, rom_name, RomDir, BLANK_NAME};
+use rng::{RngLog, RngReplay};
+use rpl::FlagStore;
+use settings::Settings;
+use sprite::{SpritePreview, DEFAULT_SPRITE_HEIGHT};
+use strict::StrictChecker;
+use symbols::SymbolTable;
+use timing::{FramePacer, FrameStep, Pacing, Scheduler, DEFAULT_IPF};
+use trace::Tracer;
+use uninit::UninitTracker;
+use log::info;
+use piston::{Button, EventLoop, EventSettings, Events, Key, PressEvent, RenderEvent};
 
-use std::{env, time::SystemTime, collections::HashMap};
-use device::Device;
-use chip8::Chip8;
-use piston::{Button, EventSettings, Events, Key, PressEvent, ReleaseEvent, RenderEvent};
+//Passed to piston when --fps does the pacing, high enough that piston
+//never waits between renders
+const UNLIMITED_FPS: u64 = 1_000_000;
 
 fn main() {
+    logging::init();
     let args: Vec<String> = env::args().collect();
-    
-    let mut rom_path: String = String::new();
-    let mut config: HashMap<&str, &str> = HashMap::new();
-    
-    config.insert("--cycle-delay", "1");
-    config.insert("--scale", "16");
-
-    for (i, arg) in args.iter().skip(1).enumerate() {
-        if i == 0 {
-            rom_path.clone_from(arg);
-            continue;
+
+    //Subcommands run instead of the window, except chip8 attract which is
+    //a shorthand for window options, see attract.rs
+    let mut config = Config::load(args.clone());
+    match config.command.take() {
+        Some(Command::Attract { dir, seconds, options }) => config = Config::load(attract::command_line(&args[0], &dir, seconds, &options)),
+        Some(command) => process::exit(commands::run(command, &config.rom_sections)),
+        None => ()
+    }
+    if let Some(level) = config.log_level {
+        logging::set_level(level);
+    }
+    #[cfg(feature = "octo")]
+    if let Some(command) = &config.octo {
+        octo::set_command(command);
+    }
+    #[cfg(feature = "http")]
+    download::set_use_cache(!config.no_cache);
+
+    //--info=rom.ch8 describes the ROM and exits without opening a window
+    if let Some(path) = &config.info {
+        process::exit(commands::info(path, &config.rom_sections));
+    }
+
+    let cycle_delay = config.cycle_delay;
+
+    //The window comes back at the size it was left at, unless --scale says
+    let settings_file = settings::default_path();
+    let mut settings = settings_file.as_deref().map(Settings::load).unwrap_or_default();
+    let scale = config.scale.or(settings.scale).unwrap_or(DEFAULT_SCALE);
+
+    //--ipf and --cycle-accurate can't both be given, see timing.rs. --fps
+    //runs whole frames, so it implies --ipf unless one of them is given.
+    //So does --render-fps, since the per-tick speed depends on how many
+    //events arrive, renders included.
+    let pacing = match (config.ipf, config.cycle_accurate) {
+        (Some(ipf), _) => Pacing::InstructionsPerFrame(ipf.unwrap_or(DEFAULT_IPF)),
+        (None, true) => Pacing::CycleAccurate,
+        (None, false) if config.fps.is_some() || config.render_fps.is_some() => Pacing::InstructionsPerFrame(DEFAULT_IPF),
+        (None, false) => Pacing::PerTick
+    };
+    let mut scheduler = Scheduler::create(pacing);
+    if config.idle_throttle && pacing == Pacing::PerTick {
+        log::warn!("--idle-throttle needs --ipf, --cycle-accurate or --fps, ignoring it");
+    }
+
+    let profile_top = config.profile;
+
+    //--rom-dir=path browses every ROM in a directory with Page Up/Down
+    let mut rom_dir: Option<RomDir> = config.rom_dir.as_ref().map(|dir| {
+        RomDir::open(Path::new(dir)).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        })
+    });
+
+    //Every ROM that loads goes in the history, see history.rs
+    let history_file = if config.no_history { None } else { history::default_path() };
+    let recent = || history_file.as_deref().map(history::load).unwrap_or_default();
+
+    //FX75's flags are saved per ROM in --save-dir, see rpl.rs
+    let flag_dir = config.save_dir.as_ref().map(PathBuf::from).or_else(rpl::default_dir);
+    let mut flag_store: Option<FlagStore> = if config.no_flag_persistence { None } else { flag_dir.map(FlagStore::create) };
+
+    //Without a ROM the window opens on a ROM browser (see picker.rs), or
+    //without a display the user picks one in the terminal. --blank has
+    //no ROM at all and leaves rom_path empty.
+    let mut picker: Option<RomPicker> = (config.rom.is_none() && config.attract.is_none() && !config.no_picker && !config.blank && picker::graphical_session())
+        .then(|| RomPicker::open(&browse_dir(config.rom_dir.as_deref(), ""), &recent()));
+    let mut rom_path = match (&config.rom, rom_dir.as_mut()) {
+        (Some(rom), _) => rom.clone(),
+        (None, _) if picker.is_some() || config.blank => String::new(),
+        (None, Some(dir)) if config.no_picker || config.attract.is_some() => dir.current().to_string_lossy().into_owned(),
+        (None, dir) => pick_rom(dir, config.no_picker)
+    };
+
+    //Without --quirks the ROM picks its own profile, see detect.rs. The
+    //xo-chip one gets XO-CHIP's 64 KB of memory, see Quirks::memory_size.
+    let detected = detect_rom(&config, &rom_path);
+    let mut chip8 = Chip8::create_with_memory(quirks::Quirks::memory_size(config.profile(detected.as_ref())));
+    let rom_size = if config.blank {
+        info!("Starting without a ROM");
+        0
+    } else if picker.is_none() {
+        info!("Loading rom: {}", rom_path);
+        match chip8.load_rom(rom_path.as_str()) {
+            Ok(rom_size) => {
+                if let (Some(file), None) = (&history_file, config.attract) {
+                    history::remember(file, &rom_path);
+                }
+                rom_size
+            },
+            //Attract mode skips to the next ROM instead
+            Err(e) if config.attract.is_some() => {
+                log::error!("{}", e);
+                0
+            },
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(2);
+            }
         }
+    } else {
+        0
+    };
 
-        let arg_split: Vec<&str> = arg.split('=').collect();
-        let key = arg_split[0];
-        let val = arg_split[1];
-        
-        config.insert(key, val);
+    //--seed=N makes RND reproducible. Without it the seed is random and
+    //printed so a run can be repeated.
+    match config.seed {
+        Some(seed) => chip8.set_seed(seed),
+        None => info!("Seed: {}", chip8.seed)
     }
 
-    let cycle_delay: u128 = config.get("--cycle-delay").unwrap().parse::<u128>().unwrap();
-    let scale: u32 = config.get("--scale").unwrap().parse::<u32>().unwrap();
+    //--rng-log=file writes every number RND draws, --rng-replay=file feeds
+    //such a log back in place of the seeded numbers, see rng.rs
+    if let Some(path) = &config.rng_replay {
+        match RngReplay::load(path) {
+            Ok(replay) => chip8.set_random_source(Box::new(replay)),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(2);
+            }
+        }
+    }
+    if let Some(path) = &config.rng_log {
+        let file = fs::File::create(path).unwrap_or_else(|e| {
+            eprintln!("Can't create {}: {}", path, e);
+            process::exit(1);
+        });
+        chip8.set_rng_log(RngLog::create(Box::new(file)));
+    }
+
+    //--no-fontset leaves 0x50-0x103 zeroed for ROMs that bring their own font
+    if config.no_fontset {
+        chip8.set_fontset(false);
+    }
+
+    //--quirks=schip starts from an interpreter's quirks, --quirk=shift-vy=off
+    //changes single ones on top
+    chip8.quirks = config.quirks(detected.as_ref());
+
+    //--protect-low-mem stops on writes below 0x200, --protect-low-mem=warn only reports them
+    chip8.low_memory_protection = config.protect_low_mem;
+
+    //--load-state=file.state picks up a saved run of the same ROM. It
+    //replaces memory, the seed and the random numbers set up so far.
+    if let Some(path) = &config.load_state {
+        if let Err(e) = state::load_state_file(&mut chip8, path) {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    }
+
+    //--poke-file=patches.txt and then every --poke=addr=value patch the
+    //loaded ROM, later ones winning. They aren't the ROM writing, so low
+    //memory protection doesn't apply to them.
+    let file_pokes = config.poke_file.as_deref().map(poke::read_poke_file).transpose().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    let pokes: Vec<poke::Poke> = file_pokes.into_iter().flatten().chain(config.poke.iter().copied()).collect();
+    for patch in &pokes {
+        if patch.address < 0x200 && config.protect_low_mem != LowMemoryProtection::Off {
+            eprintln!("Note: poke at {:#05x} bypasses --protect-low-mem", patch.address);
+        }
+    }
+    if let Err(e) = poke::apply(&mut chip8, &pokes) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+
+    //--verify-invariants halts as soon as an instruction leaves the machine
+    //inconsistent, see invariants.rs
+    chip8.verify_invariants = config.verify_invariants;
+
+    //--lenient skips SUPER-CHIP/XO-CHIP instructions instead of halting on them
+    chip8.lenient = config.lenient;
+
+    //--warn-sprite-overrun flags DXYN drawing from past the end of the ROM
+    chip8.warn_sprite_overrun = config.warn_sprite_overrun;
 
-    println!("Loading rom: {}", rom_path);
-    let mut chip8 = Chip8::create();
-    chip8.load_rom(rom_path.as_str());
+    //--log-stack prints every CALL and RET, indented by call depth
+    chip8.log_stack = config.log_stack;
 
-    let mut device = Device::start(scale);   
-    let mut events = Events::new(EventSettings::new());
+    //--strict reports every place the ROM's behavior depends on a quirk,
+    //with a summary on exit, see strict.rs
+    if config.strict {
+        chip8.strict = Some(StrictChecker::create());
+    }
+
+    //--symbols=game.sym labels addresses in the debugger and --trace output,
+    //see symbols.rs for the file formats
+    let symbols: Option<Rc<SymbolTable>> = config.symbols.as_ref().map(|path| {
+        Rc::new(SymbolTable::load(path).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        }))
+    });
+
+    //--warn-uninit reports FX65/DXYN reads of memory nothing ever wrote,
+    //see uninit.rs
+    let uninit_tracker: Option<Rc<RefCell<UninitTracker>>> = config.warn_uninit.then(|| {
+        let tracker = Rc::new(RefCell::new(UninitTracker::create(rom_size, chip8.memory.len(), chip8.has_fontset())));
+        chip8.add_exec_hook(tracker.clone());
+        tracker
+    });
+
+    if config.trace {
+        chip8.add_exec_hook(Rc::new(RefCell::new(Tracer::create(symbols.clone()))));
+    }
+
+    let profiler = Rc::new(RefCell::new(Profiler::create()));
+    if profile_top.is_some() {
+        chip8.add_exec_hook(profiler.clone());
+    }
+
+    let idle_detector = Rc::new(RefCell::new(IdleDetector::create()));
+    chip8.add_exec_hook(idle_detector.clone());
+    let mut idle_status = IdleStatus::Running;
+
+    //--debug reads debugger commands from stdin while the window runs
+    let debug_commands = config.debug.then(debugger::spawn_stdin_reader);
+    //--debug-listen=addr:port serves the same debugger over TCP, see remote.rs
+    let mut remote: Option<RemoteDebug> = config.debug_listen.as_ref().map(|address| {
+        let remote = RemoteDebug::listen(address).unwrap_or_else(|e| {
+            eprintln!("Can't listen on {}: {}", address, e);
+            process::exit(1);
+        });
+        info!("Remote debugger listening on {}", remote.address);
+        remote
+    });
+    let mut debugger: Option<Debugger> = (debug_commands.is_some() || remote.is_some()).then(Debugger::create);
+    //Breakpoints and stepping have to see every pass of a loop
+    scheduler.idle_throttle = config.idle_throttle && debugger.is_none();
+    let mut halted: Option<Chip8Error> = None;
+
+    //--start-paused holds the machine at 0x200 until P is pressed. With a
+    //debugger the debugger does the pausing, so continue resumes too.
+    //--blank always starts paused, there is nothing to run yet.
+    let mut paused = false;
+    if config.start_paused || config.blank {
+        match debugger.as_mut() {
+            Some(debugger) => {
+                debugger.paused = true;
+                if debug_commands.is_some() {
+                    print!("Paused at {}", debugger.location(&chip8));
+                }
+            },
+            None => paused = true
+        }
+    }
+    let mut showing_paused = false;
+
+    //--coverage=out.png writes a map of executed/read/written memory on exit,
+    //the debugger's coverage command lists the same thing as text
+    let coverage_path = config.coverage.as_deref();
+    let coverage = Rc::new(RefCell::new(Coverage::create()));
+    if coverage_path.is_some() || debugger.is_some() {
+        chip8.add_exec_hook(coverage.clone());
+    }
+    if let Some(debugger) = debugger.as_mut() {
+        debugger.attach_coverage(coverage.clone());
+        if let Some(symbols) = &symbols {
+            debugger.attach_symbols(symbols.clone());
+        }
+    }
+
+    //--record-gif=path captures every rendered frame, F9 pauses and resumes
+    let mut gif_recorder: Option<GifRecorder> = config.record_gif.as_ref().map(|path| GifRecorder::create(path, scale as u16));
+
+    //F10 shows the sprite at I in the corner of the window
+    let mut sprite_overlay = false;
+
+    let mut device = Device::start(scale, config.integer_scale, config.display_mode, config.palette(detected.as_ref()));   
+    if config.blank {
+        device.set_rom_name(Some("no ROM"));
+    } else if picker.is_none() {
+        device.set_rom_name(Some(&rom_name(Path::new(&rom_path))));
+    }
+    //--fps=N runs one frame per render and sleeps until each one is due,
+    //piston's own frame limiter is turned off so it doesn't get in the way.
+    //--render-fps=N only lowers that limiter: the emulation runs on update
+    //events and is timed by the clock, and each render draws the latest
+    //video, so skipped renders cost nothing.
+    let mut pacer: Option<FramePacer> = config.fps.map(FramePacer::create);
+    let event_settings = match (pacer.as_ref(), config.render_fps) {
+        (Some(_), _) => EventSettings::new().ups(0).max_fps(UNLIMITED_FPS),
+        (None, Some(render_fps)) => EventSettings::new().max_fps(render_fps as u64),
+        (None, None) => EventSettings::new()
+    };
+    let mut events = Events::new(event_settings);
+    let mut input = PistonInput::create();
+    //--keymap adds to the --layout's keymap
+    let mut keymap = layout_keymap(&config.layout).unwrap_or_else(|| input.keymap().clone());
+    keymap.extend(config.keymap.iter().copied());
+    if let Err(e) = input.set_keymap(keymap) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+    let mut last_key_state = [false; 16];
+    //Emulated 60Hz frames run so far
+    let mut frame: u64 = 0;
+
+    //--log-input writes every keypad press and release to stderr,
+    //--log-input=path to a file
+    let mut input_log: Option<InputLog> = config.log_input.as_ref().map(|path| match path {
+        None => InputLog::create(Box::new(io::stderr()), chip8.seed),
+        Some(path) => InputLog::create(Box::new(fs::File::create(path).unwrap_or_else(|e| {
+            eprintln!("Can't create {}: {}", path, e);
+            process::exit(1);
+        })), chip8.seed)
+    });
+    let mut chord_log: Option<ChordLog> = config.log_chords.then(ChordLog::create);
+
+    //--dump-state-on-exit=DIR leaves a bundle for bug reports when the
+    //window closes or the emulator panics, see dump.rs
+    let state_dump: Option<StateDump> = config.dump_state_on_exit.as_deref().map(StateDump::create);
+    if let Some(dump) = &state_dump {
+        dump.install_panic_hook();
+    }
+
+    //--attract=N moves on to the next ROM every N seconds until a key is
+    //pressed. ROMs loaded by it don't go in the history, the one the
+    //player takes over does.
+    let mut attract: Option<AttractMode> = config.attract.map(|seconds| AttractMode::create(seconds, &rom_path));
+    if let Some(mode) = attract.as_mut().filter(|_| rom_size == 0) {
+        mode.fail();
+    }
 
     let mut last_time = SystemTime::now();
 
     while let Some(e) = events.next(&mut device.window) {
         let current_time = SystemTime::now();
         let dt = current_time.duration_since(last_time).unwrap().as_millis();
-        
+
+        //The ROM browser takes over the window until a ROM is chosen
+        if let Some(current) = picker.as_mut() {
+            let chosen = match e.press_args() {
+                Some(Button::Keyboard(key)) => current.handle_key(key),
+                _ => None
+            };
+            if let Some(args) = e.render_args() {
+                if let Some(pacer) = pacer.as_mut() {
+                    pacer.wait();
+                }
+                let screen = current.render();
+                device.render(&args, &screen.pixels, screen.width(), None);
+            }
+
+            if let Some(path) = chosen {
+                picker = None;
+                if let Some(dir) = rom_dir.as_mut() {
+                    if let Some(index) = dir.roms().iter().position(|rom| *rom == path) {
+                        dir.select(index);
+                    }
+                }
+                rom_path = path.to_string_lossy().into_owned();
+                switch_profile(&mut chip8, &mut device, &config, &rom_path);
+                switch_rom(&mut chip8, &rom_path, &coverage, uninit_tracker.as_deref(), &mut device, debugger.as_mut(), history_file.as_deref());
+                halted = None;
+                last_time = SystemTime::now();
+            }
+            continue;
+        }
+
+        if let (Some(Button::Keyboard(_)), Some(_)) = (e.press_args(), attract.as_ref()) {
+            attract = None;
+            chip8.keypad = [false; 16];
+            info!("Taking over {}", rom_path);
+            if let Some(file) = &history_file {
+                history::remember(file, &rom_path);
+            }
+        }
+        if let (Some(mode), Some(dir)) = (attract.as_mut(), rom_dir.as_mut()) {
+            if mode.all_failed(dir.roms().len()) {
+                log::error!("None of the ROMs will run, leaving attract mode");
+                attract = None;
+            } else if mode.is_over() {
+                rom_path = dir.next().to_string_lossy().into_owned();
+                switch_profile(&mut chip8, &mut device, &config, &rom_path);
+                let loaded = switch_rom(&mut chip8, &rom_path, &coverage, uninit_tracker.as_deref(), &mut device, debugger.as_mut(), None);
+                halted = None;
+                mode.start(&rom_path);
+                if !loaded {
+                    mode.fail();
+                }
+            }
+        }
+
+        if let (Some(change), Some(log)) = (input.handle_event(&e), input_log.as_mut()) {
+            log.log(frame, change);
+        }
+
+        //Only changes are copied in, so keys set by the debugger or a
+        //loaded state stick until the player presses something
+        let key_state = input.key_state();
+        if key_state != last_key_state {
+            chip8.keypad = key_state;
+            last_key_state = key_state;
+        }
+        //Checked against the keypad itself so keys set by the debugger or
+        //the remote protocol show up too
+        if let Some(log) = chord_log.as_mut() {
+            log.update(frame, &chip8.keypad);
+        }
+
         if let Some(Button::Keyboard(key)) = e.press_args() {
             match key {
-                Key::D1 => chip8.keypad[0x1] = true,
-                Key::D2 => chip8.keypad[0x2] = true,
-                Key::D3 => chip8.keypad[0x3] = true,
-                Key::D4 => chip8.keypad[0xC] = true,
-                Key::Q  => chip8.keypad[0x4] = true,
-                Key::W  => chip8.keypad[0x5] = true,
-                Key::E  => chip8.keypad[0x6] = true,
-                Key::R  => chip8.keypad[0xD] = true,
-                Key::A  => chip8.keypad[0x7] = true,
-                Key::S  => chip8.keypad[0x8] = true,
-                Key::D  => chip8.keypad[0x9] = true,
-                Key::F  => chip8.keypad[0xE] = true,
-                Key::Z  => chip8.keypad[0xA] = true,
-                Key::X  => chip8.keypad[0x9] = true,
-                Key::C  => chip8.keypad[0xB] = true,
-                Key::V  => chip8.keypad[0xF] = true,
                 Key::K => {
-                    for y in 0..32 {
-                        for x in 0..64 {
-                            print!("{} ", chip8.video[x + y * 64]);
+                    for row in chip8.video.chunks(chip8.video_width()) {
+                        for pixel in row {
+                            print!("{} ", pixel);
                         }
                         println!();
                     }
                 },
+                //F1-F8 flip the first eight quirks, in QUIRK_NAMES order
+                Key::F1 | Key::F2 | Key::F3 | Key::F4 | Key::F5 | Key::F6 | Key::F7 | Key::F8 => {
+                    let name = quirks::QUIRK_NAMES[key as usize - Key::F1 as usize];
+                    chip8.quirks.toggle(name);
+                    info!("Quirks: {}", chip8.quirks);
+                },
+                Key::F9 => {
+                    if let Some(recorder) = gif_recorder.as_mut() {
+                        recorder.toggle();
+                        info!("GIF recording {}", if recorder.is_recording() { "resumed" } else { "paused" });
+                    }
+                },
+                Key::F10 => sprite_overlay = !sprite_overlay,
+                Key::F11 => {
+                    device.display_mode = device.display_mode.next();
+                    info!("Display mode: {}", device.display_mode);
+                },
+                Key::P => match debugger.as_mut() {
+                    Some(debugger) if debugger.paused => debugger.resume(&chip8),
+                    Some(debugger) => {
+                        debugger.paused = true;
+                        print!("Paused at {}", debugger.location(&chip8));
+                    },
+                    None => paused = !paused
+                },
+                //Runs one frame and pauses, a single instruction without --ipf or --cycle-accurate
+                Key::Period => match debugger.as_mut() {
+                    Some(debugger) if halted.is_none() => {
+                        if let Err(e) = debugger.frame_step(&mut chip8, &mut scheduler) {
+                            report_halt(&chip8, &e, &rom_path, &mut device);
+                            halted = Some(e);
+                        }
+                        print!("{}", debugger.location(&chip8));
+                    },
+                    Some(_) => (),
+                    None => println!("Frame stepping needs --debug or --debug-listen")
+                },
+                Key::PageDown | Key::PageUp => {
+                    if let Some(dir) = rom_dir.as_mut() {
+                        let path = if key == Key::PageDown { dir.next() } else { dir.previous() };
+                        rom_path = path.to_string_lossy().into_owned();
+                        switch_profile(&mut chip8, &mut device, &config, &rom_path);
+                        switch_rom(&mut chip8, &rom_path, &coverage, uninit_tracker.as_deref(), &mut device, debugger.as_mut(), history_file.as_deref());
+                        halted = None;
+                    }
+                },
+                //Back to the ROM browser, the machine is reset
+                Key::Backspace => {
+                    picker = Some(RomPicker::open(&browse_dir(config.rom_dir.as_deref(), &rom_path), &recent()));
+                    chip8.reset();
+                    device.set_rom_name(None);
+                    device.set_status(None);
+                    halted = None;
+                },
                 _ => ()
             }
         }
 
-        if let Some(Button::Keyboard(key)) = e.release_args() {
-            match key {
-                Key::D1 => chip8.keypad[0x1] = false,
-                Key::D2 => chip8.keypad[0x2] = false,
-                Key::D3 => chip8.keypad[0x3] = false,
-                Key::D4 => chip8.keypad[0xC] = false,
-                Key::Q  => chip8.keypad[0x4] = false,
-                Key::W  => chip8.keypad[0x5] = false,
-                Key::E  => chip8.keypad[0x6] = false,
-                Key::R  => chip8.keypad[0xD] = false,
-                Key::A  => chip8.keypad[0x7] = false,
-                Key::S  => chip8.keypad[0x8] = false,
-                Key::D  => chip8.keypad[0x9] = false,
-                Key::F  => chip8.keypad[0xE] = false,
-                Key::Z  => chip8.keypad[0xA] = false,
-                Key::X  => chip8.keypad[0x9] = false,
-                Key::C  => chip8.keypad[0xB] = false,
-                Key::V  => chip8.keypad[0xF] = false,
-                _ => ()
+        if let (Some(debugger), Some(commands)) = (debugger.as_mut(), debug_commands.as_ref()) {
+            while let Ok(line) = commands.try_recv() {
+                print!("{}", debugger.execute(&mut chip8, &line));
             }
-        } 
+        }
+
+        let due = match pacer.as_mut() {
+            Some(pacer) if e.render_args().is_some() => {
+                pacer.wait();
+                if let Some(dropped) = pacer.report_drops() {
+                    eprintln!("Dropped {} frame(s), the host can't keep up with --fps={}", dropped, config.fps.unwrap_or_default());
+                }
+                true
+            },
+            Some(_) => false,
+            None => dt > cycle_delay
+        };
 
-        if dt > cycle_delay {
+        if due {
             last_time = current_time;
 
-            chip8.run();
-            if let Some(args) = e.render_args() {
-                device.render(&args, chip8.video);
+            //--exec-order=post draws before running the frame, see ExecOrder
+            for step in config.exec_order.steps() {
+                match step {
+                    FrameStep::Run => {
+                        let frames_due = if pacer.is_some() { 1 } else { scheduler.frames_due() };
+                        for _ in 0..frames_due {
+                            //EXIT stops the ROM like a halt, the window stays up to look at
+                            if halted.is_some() || chip8.exited {
+                                break;
+                            }
+
+                            if let Some(mode) = attract.as_mut() {
+                                mode.play_demo(&mut chip8.keypad);
+                            }
+                            if let Some(store) = flag_store.as_mut() {
+                                store.update(&mut chip8);
+                            }
+                            if let Err(e) = scheduler.run_frame(&mut chip8, |chip8| paused || debugger_paused(&mut debugger, chip8)) {
+                                match attract.as_mut() {
+                                    Some(mode) => {
+                                        log::warn!("{} halted, skipping it: {}", rom_path, e);
+                                        mode.fail();
+                                    },
+                                    None => {
+                                        report_halt(&chip8, &e, &rom_path, &mut device);
+                                        if let Some(debugger) = debugger.as_mut() {
+                                            debugger.paused = true;
+                                        }
+                                    }
+                                }
+                                halted = Some(e);
+                            } else if chip8.exited {
+                                match attract.as_mut() {
+                                    Some(mode) => {
+                                        info!("{} exited, skipping it", rom_path);
+                                        mode.fail();
+                                    },
+                                    None => {
+                                        info!("{} exited", rom_path);
+                                        device.set_status(Some("program exited"));
+                                    }
+                                }
+                            }
+                            frame += 1;
+                        }
+
+                        if let (Some(connection), Some(debugger)) = (remote.as_mut(), debugger.as_mut()) {
+                            //Once the client hangs up the ROM carries on by itself
+                            if !connection.poll(&mut chip8, debugger, halted.as_ref()) {
+                                remote = None;
+                                if halted.is_none() {
+                                    debugger.resume(&chip8);
+                                }
+                            }
+                        }
+                    },
+                    FrameStep::Render => {
+                        if let Some(args) = e.render_args() {
+                            let sprite = sprite_overlay.then(|| SpritePreview::create(&chip8.memory, chip8.index_register, DEFAULT_SPRITE_HEIGHT));
+                            device.render_video(&args, &chip8.video, &chip8.plane2, chip8.video_width(), sprite.as_ref());
+                            device.set_beeping(chip8.is_beeping());
+                            if let Some(recorder) = gif_recorder.as_mut() {
+                                recorder.capture(&chip8.video, chip8.video_width());
+                            }
+                            if let Some(dump) = &state_dump {
+                                dump.update(&chip8, &rom_path, input_log.as_ref().map(InputLog::recorded));
+                            }
+
+                            //[PAUSED] replaces the idle status until the machine runs again
+                            let is_paused = paused || debugger.as_ref().is_some_and(|debugger| debugger.paused);
+                            if halted.is_none() && !chip8.exited && is_paused != showing_paused {
+                                showing_paused = is_paused;
+                                idle_status = IdleStatus::Running;
+                                device.set_status(is_paused.then_some("[PAUSED]"));
+                            }
+
+                            let status = idle_detector.borrow().status();
+                            if halted.is_none() && !chip8.exited && !showing_paused && status != idle_status {
+                                idle_status = status;
+                                match status {
+                                    IdleStatus::Running => device.set_status(None),
+                                    _ => device.set_status(Some(&status.to_string()))
+                                }
+                            }
+                        }
+                    }
+                }
             }
-            
+        }
+    }
+
+    if let Some(log) = &chord_log {
+        info!("Most keypad keys held at once: {}", log.most());
+    }
+
+    //A --scale given for this run isn't remembered
+    if let (Some(file), None) = (&settings_file, config.scale) {
+        settings.scale = Some(device.scale());
+        if let Err(e) = settings.save(file) {
+            log::warn!("{}", e);
+        }
+    }
+
+    if let Some(store) = flag_store.as_mut() {
+        store.update(&mut chip8);
+        store.flush();
+    }
+
+    //Nothing to dump if the window closed on the ROM browser
+    if let (Some(dump), None) = (&state_dump, &picker) {
+        dump.write(&chip8, &rom_path, halted.as_ref(), "window closed", input_log.as_ref().map(InputLog::recorded));
+    }
+
+    if let Some(path) = &config.save_state_on_exit {
+        match state::save_state_file(&chip8, path) {
+            Ok(()) => println!("Saved the state to {}", path),
+            Err(e) => eprintln!("{}", e)
+        }
+    }
+
+    if let Some(recorder) = gif_recorder.as_mut() {
+        match recorder.finish() {
+            Ok(frames) => println!("Wrote {} frame(s) to {}", frames, recorder.path()),
+            Err(e) => eprintln!("Couldn't write {}: {}", recorder.path(), e)
+        }
+    }
+
+    if let Some(path) = coverage_path {
+        match coverage.borrow().write_png(path) {
+            Ok(()) => println!("Coverage map written to {}", path),
+            Err(e) => eprintln!("Couldn't write {}: {}", path, e)
+        }
+    }
+
+    if let Some(pacer) = &pacer {
+        println!("Frames: {}, dropped {}", pacer.frames, pacer.dropped);
+    }
+
+    if let Some(strict) = &chip8.strict {
+        print!("{}", strict.summary());
+    }
+
+    if let Some(top) = profile_top {
+        print!("{}", profiler.borrow().report().summary(&chip8.memory, top));
+    }
+}
+
+//No ROM was given: prints the usage and lets the user choose one of the
+//ROMs in the --rom-dir, or else the current directory, from a menu on
+//stdin. Exits when there is nothing to choose, nothing was chosen or
+//--no-picker is given.
+fn pick_rom(rom_dir: Option<&mut RomDir>, no_picker: bool) -> String {
+    eprintln!("{}\nRun with --help for every option\n", Config::command().render_usage());
+    if no_picker {
+        process::exit(2);
+    }
+
+    let mut current_dir = None;
+    let dir = match rom_dir {
+        Some(dir) => dir,
+        None => match RomDir::open(Path::new(".")) {
+            Ok(dir) => current_dir.insert(dir),
+            Err(_) => {
+                eprintln!("No ROM given and no ROMs in the current directory");
+                process::exit(2);
+            }
+        }
+    };
+
+    println!("No ROM given, found these:");
+    match pick(dir.roms(), &mut io::stdin().lock(), &mut io::stdout()) {
+        Some(index) => dir.select(index).to_string_lossy().into_owned(),
+        None => process::exit(2)
+    }
+}
+
+//Loads another ROM into the reset machine, forgetting what was
+//tracked about the last one, the debugger's rewind history included.
+//Returns whether the ROM loaded.
+fn switch_rom(chip8: &mut Chip8, path: &str, coverage: &RefCell<Coverage>, uninit_tracker: Option<&RefCell<UninitTracker>>,
+    device: &mut Device, debugger: Option<&mut Debugger>, history_file: Option<&Path>) -> bool {
+    info!("Loading rom: {}", path);
+    chip8.reset();
+    let loaded = chip8.load_rom(path);
+    let rom_size = match &loaded {
+        Ok(rom_size) => {
+            if let Some(file) = history_file {
+                history::remember(file, path);
+            }
+            *rom_size
+        },
+        Err(e) => {
+            log::error!("{}", e);
+            0
+        }
+    };
+    coverage.borrow_mut().clear();
+    if let Some(tracker) = uninit_tracker {
+        tracker.borrow_mut().reset(rom_size, chip8.memory.len());
+    }
+    if let Some(debugger) = debugger {
+        debugger.clear_history();
+    }
+    device.set_rom_name(Some(&rom_name(Path::new(path))));
+    device.set_status(None);
+    loaded.is_ok()
+}
+
+//What the ROM at path says about how it wants to run, see detect.rs.
+//Only read for it without --quirks and --no-autodetect.
+fn detect_rom(config: &Config, path: &str) -> Option<Detection> {
+    let detected = (config.autodetect() && !path.is_empty()).then(|| Detection::detect_path(path)).flatten();
+    if let Some(detected) = &detected {
+        info!("Detected: {}", detected);
+    }
+
+    detected
+}
+
+//Before switch_rom: a ROM picked while running gets its own detected
+//profile and colors. A given one stays, along with any quirks toggled
+//since.
+fn switch_profile(chip8: &mut Chip8, device: &mut Device, config: &Config, path: &str) {
+    if config.autodetect() {
+        let detected = detect_rom(config, path);
+        chip8.reset_with_memory(quirks::Quirks::memory_size(config.profile(detected.as_ref())));
+        chip8.quirks = config.quirks(detected.as_ref());
+        device.palette = config.palette(detected.as_ref());
+    }
+}
+
+//What the ROM browser lists: the --rom-dir, else the directory of the
+//current ROM, else the current directory
+fn browse_dir(rom_dir: Option<&str>, rom_path: &str) -> PathBuf {
+    match (rom_dir, Path::new(rom_path).parent()) {
+        (Some(dir), _) => PathBuf::from(dir),
+        (None, Some(parent)) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from(".")
+    }
+}
+
+//Checked before every instruction, stops on breakpoints and keeps
+//the history rstep rewinds through
+fn debugger_paused(debugger: &mut Option<Debugger>, chip8: &Chip8) -> bool {
+    match debugger.as_mut() {
+        Some(debugger) => {
+            if !debugger.paused && debugger.check_breakpoint(chip8) {
+                print!("Breakpoint hit at {}", debugger.location(chip8));
+            }
+            if !debugger.paused {
+                debugger.record(chip8);
+            }
+            debugger.paused
+        },
+        None => false
+    }
+}
+
+//Writes the post-mortem report next to the ROM and points the title bar at it
+fn report_halt(chip8: &Chip8, e: &Chip8Error, rom_path: &str, device: &mut Device) {
+    eprintln!("{}", e);
+
+    let report_path = format!("{}.diagnostic.txt", if rom_path.is_empty() { BLANK_NAME } else { rom_path });
+    match fs::write(&report_path, chip8.diagnostic_report(e)) {
+        Ok(()) => {
+            eprintln!("Diagnostic report written to {}", report_path);
+            device.set_status(Some(&format!("halted, see {}", report_path)));
+        },
+        Err(write_error) => {
+            eprintln!("Couldn't write {}: {}", report_path, write_error);
+            device.set_status(Some(&format!("halted: {}", e)));
         }
     }
 }
@@ -0,0 +1,76 @@
+use std::{
+    env, fs,
+    path::PathBuf,
+    process::Command,
+    sync::OnceLock,
+    time::{SystemTime, UNIX_EPOCH}
+};
+
+//Octo source (.8o) is assembled by running Octo's command line tool,
+//octo SOURCE DESTINATION, and reading back the ROM it writes. The tool is
+//--octo, else $CHIP8_OCTO, else octo on the PATH. Octo's own messages
+//carry the line numbers and are passed on with the file name in front.
+
+static COMMAND: OnceLock<String> = OnceLock::new();
+
+//--octo=PATH, set before any ROM is read
+pub fn set_command(command: &str) {
+    let _ = COMMAND.set(command.to_string());
+}
+
+fn command() -> String {
+    match COMMAND.get() {
+        Some(command) => command.clone(),
+        None => env::var("CHIP8_OCTO").ok().filter(|command| !command.is_empty()).unwrap_or_else(|| "octo".to_string())
+    }
+}
+
+pub fn assemble(path: &str) -> Result<Vec<u8>, String> {
+    run(path, path)
+}
+
+//Source that isn't in a file of its own, like an Octocart's. Octo's
+//messages name it as name.
+pub fn assemble_source(source: &str, name: &str) -> Result<Vec<u8>, String> {
+    let source_path = temp_path("8o");
+    fs::write(&source_path, source).map_err(|e| format!("Couldn't write {} for Octo: {}", source_path.display(), e))?;
+    let rom = run(&source_path.to_string_lossy(), name);
+    let _ = fs::remove_file(&source_path);
+
+    rom
+}
+
+//Messages are about path, named as name
+fn run(path: &str, name: &str) -> Result<Vec<u8>, String> {
+    let command = command();
+    let output_path = temp_path("ch8");
+
+    log::debug!("Assembling {} with {}", name, command);
+    let output = Command::new(&command)
+        .arg(path)
+        .arg(&output_path)
+        .output()
+        .map_err(|e| format!("Couldn't run {} to assemble {} (set --octo or CHIP8_OCTO to Octo's command line tool): {}", command, name, e))?;
+
+    let rom = fs::read(&output_path);
+    let _ = fs::remove_file(&output_path);
+
+    let messages: Vec<String> = String::from_utf8_lossy(&output.stderr).lines()
+        .chain(String::from_utf8_lossy(&output.stdout).lines())
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| format!("{}: {}", name, line.replace(path, name)))
+        .collect();
+    //Some versions exit with 0 on errors and just don't write the ROM
+    match rom {
+        Ok(rom) if output.status.success() => Ok(rom),
+        _ if !messages.is_empty() => Err(messages.join("\n")),
+        Ok(_) => Err(format!("{}: {} failed with {}", name, command, output.status)),
+        Err(e) => Err(format!("{}: {} wrote no ROM: {}", name, command, e))
+    }
+}
+
+fn temp_path(extension: &str) -> PathBuf {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.subsec_nanos()).unwrap_or(0);
+    env::temp_dir().join(format!("chip8-octo-{}-{}.{}", std::process::id(), nanos, extension))
+}
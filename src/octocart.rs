@@ -0,0 +1,222 @@
+use std::fs;
+
+use serde_json::Value;
+
+#[cfg(feature = "octo")]
+use crate::octo;
+use crate::{
+    device::{Palette, DEFAULT_PALETTE},
+    quirks::Quirks
+};
+
+//Octo shares programs as Octocarts, GIFs with a label on the front and
+//the program hidden in the pixels. Every pixel's color index carries
+//four bits in its low nibble, two pixels a byte, high nibble first,
+//frame after frame. The bytes are a 32-bit big-endian length and that
+//much UTF-8 JSON:
+//  {"program": "...Octo source...", "options": {"shiftQuirks": false, ...}}
+//The options become the cart's quirks, on top of the xo-chip profile
+//Octo itself runs with, and its colors. The rest (tickrate, the buzzer
+//colors, touch input...) is ignored. A program given as a list of bytes
+//instead of source is loaded as it is.
+pub struct Octocart {
+    pub program: Program,
+    pub options: Value
+}
+
+pub enum Program {
+    Source(String),
+    Bytes(Vec<u8>)
+}
+
+//Octo's option, the quirk it sets and the value the quirk gets when the
+//option is on. vBlankQuirks and vfOrderQuirks have no quirk here.
+const QUIRK_OPTIONS: [(&str, &str, bool); 6] = [
+    ("shiftQuirks", "shift-vy", false),
+    ("loadStoreQuirks", "increment-i", false),
+    ("clipQuirks", "clip-x", true),
+    ("clipQuirks", "clip-y", true),
+    ("jumpQuirks", "jump-vx", true),
+    ("logicQuirks", "vf-reset", true)
+];
+
+//In Palette order: no plane lit, the first, the second and both
+const COLOR_OPTIONS: [&str; 4] = ["backgroundColor", "fillColor", "fillColor2", "blendColor"];
+
+impl Octocart {
+
+    pub fn parse(gif: &[u8]) -> Result<Octocart, String> {
+        let payload = payload(gif)?;
+        let json: Value = serde_json::from_slice(&payload).map_err(|e| format!("not an Octocart, its data isn't JSON: {}", e))?;
+
+        let program = match &json["program"] {
+            Value::String(source) => Program::Source(source.clone()),
+            Value::Array(bytes) => Program::Bytes(bytes.iter()
+                .map(|byte| byte.as_u64().and_then(|byte| u8::try_from(byte).ok()))
+                .collect::<Option<Vec<u8>>>()
+                .ok_or("the Octocart's program isn't a list of bytes")?),
+            _ => return Err("not an Octocart, there is no program in it".to_string())
+        };
+
+        Ok(Octocart { program, options: json["options"].clone() })
+    }
+
+    pub fn load(path: &str) -> Result<Octocart, String> {
+        let gif = fs::read(path).map_err(|e| format!("Couldn't read {}: {}", path, e))?;
+        Octocart::parse(&gif).map_err(|e| format!("{} is {}", path, e))
+    }
+
+    //The program as a ROM. Source needs the octo feature, see octo.rs.
+    pub fn rom(&self, path: &str) -> Result<Vec<u8>, String> {
+        match &self.program {
+            Program::Source(source) => assemble(source, path),
+            Program::Bytes(bytes) => Ok(bytes.clone())
+        }
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        let mut quirks = Quirks::profile("xo-chip").unwrap_or_default();
+        for (option, quirk, on) in QUIRK_OPTIONS {
+            let value = self.options[option].as_bool();
+            if let (Some(value), Some(flag)) = (value, quirks.flag_mut(quirk)) {
+                *flag = value == on;
+            }
+        }
+
+        quirks
+    }
+
+    //None without any colors, the ones it leaves out are the defaults
+    pub fn palette(&self) -> Option<Palette> {
+        let colors: Vec<Option<&str>> = COLOR_OPTIONS.iter().map(|option| self.options[option].as_str()).collect();
+        if colors.iter().all(Option::is_none) {
+            return None;
+        }
+
+        let colors: Vec<&str> = colors.iter().zip(DEFAULT_PALETTE.split(','))
+            .map(|(color, default)| color.unwrap_or(default))
+            .collect();
+        Palette::parse(&colors.join(","))
+            .map_err(|e| log::warn!("Ignoring the Octocart's colors: {}", e))
+            .ok()
+    }
+
+}
+
+pub fn is_octocart(path: &str) -> bool {
+    path.to_ascii_lowercase().ends_with(".gif")
+}
+
+//The bytes behind the length at the start of the pixels
+fn payload(gif: &[u8]) -> Result<Vec<u8>, String> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options.read_info(gif).map_err(|e| format!("not an Octocart, it isn't a GIF: {}", e))?;
+
+    let mut nibbles = Vec::new();
+    while let Some(frame) = decoder.read_next_frame().map_err(|e| format!("not an Octocart, the GIF is broken: {}", e))? {
+        nibbles.extend(frame.buffer.iter().map(|index| index & 0xF));
+    }
+    let bytes: Vec<u8> = nibbles.chunks_exact(2).map(|pair| pair[0] << 4 | pair[1]).collect();
+
+    let length = bytes.get(..4)
+        .map(|length| u32::from_be_bytes([length[0], length[1], length[2], length[3]]) as usize)
+        .filter(|length| *length > 0 && *length <= bytes.len() - 4)
+        .ok_or("not an Octocart, there is no program in its pixels")?;
+
+    Ok(bytes[4..4 + length].to_vec())
+}
+
+#[cfg(feature = "octo")]
+fn assemble(source: &str, path: &str) -> Result<Vec<u8>, String> {
+    octo::assemble_source(source, path)
+}
+
+#[cfg(not(feature = "octo"))]
+fn assemble(_source: &str, path: &str) -> Result<Vec<u8>, String> {
+    Err(format!("{}: this build can't assemble the Octocart's Octo source, rebuild with --features octo", path))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::borrow::Cow;
+
+    use super::*;
+
+    //Carries {"key": "x", "program": [96, 66, 18, 2], "options": {...}},
+    //V0 = 0x42 and a jump to itself, with Octo's label in the high nibbles
+    const CART: &[u8] = include_bytes!("../tests/fixtures/octocart.gif");
+
+    //A one frame GIF with a pixel per nibble, in a grayscale palette
+    fn gif(nibbles: &[u8]) -> Vec<u8> {
+        let palette: Vec<u8> = (0..16).flat_map(|i| [i * 17; 3]).collect();
+        let mut gif = Vec::new();
+        let mut encoder = gif::Encoder::new(&mut gif, nibbles.len() as u16, 1, &palette).unwrap();
+        let frame = gif::Frame { width: nibbles.len() as u16, height: 1, buffer: Cow::Borrowed(nibbles), ..gif::Frame::default() };
+        encoder.write_frame(&frame).unwrap();
+        drop(encoder);
+
+        gif
+    }
+
+    //length followed by data, as nibbles
+    fn nibbles(length: u32, data: &[u8]) -> Vec<u8> {
+        length.to_be_bytes().iter().chain(data).flat_map(|byte| [byte >> 4, byte & 0xF]).collect()
+    }
+
+    #[test]
+    fn program() {
+        let cart = Octocart::parse(CART).unwrap();
+        assert!(matches!(&cart.program, Program::Bytes(bytes) if bytes == &[0x60, 0x42, 0x12, 0x02]));
+        assert_eq!(cart.rom("octocart.gif").unwrap(), [0x60, 0x42, 0x12, 0x02]);
+        assert_eq!(cart.options["tickrate"], 20);
+    }
+
+    #[test]
+    fn quirks_from_options() {
+        //shiftQuirks, loadStoreQuirks, clipQuirks and logicQuirks on,
+        //jumpQuirks off
+        let quirks = Octocart::parse(CART).unwrap().quirks();
+        assert!(!quirks.shift_vy && !quirks.increment_i);
+        assert!(quirks.clip_x && quirks.clip_y && quirks.vf_reset);
+        assert!(!quirks.jump_vx);
+        assert!(quirks.xo_chip_opcodes);
+
+        let cart = Octocart { program: Program::Bytes(Vec::new()), options: Value::Null };
+        assert_eq!(cart.quirks(), Quirks::profile("xo-chip").unwrap());
+    }
+
+    #[test]
+    fn palette_from_options() {
+        //backgroundColor and fillColor, the other two are the defaults
+        let palette = Octocart::parse(CART).unwrap().palette().unwrap();
+        assert_eq!(palette.0, [[0x00, 0x00, 0x80], [0xFF, 0x00, 0x00], [0xAA; 3], [0x55; 3]]);
+
+        let cart = Octocart { program: Program::Bytes(Vec::new()), options: serde_json::json!({"tickrate": 20}) };
+        assert_eq!(cart.palette(), None);
+    }
+
+    #[test]
+    fn length_must_fit_the_pixels() {
+        let json = br#"{"program":[224]}"#;
+        let length = json.len() as u32;
+
+        let cart = Octocart::parse(&gif(&nibbles(length, json))).unwrap();
+        assert_eq!(cart.rom("cart.gif").unwrap(), [0xE0]);
+
+        for length in [length + 1, u32::MAX, 0] {
+            assert_eq!(Octocart::parse(&gif(&nibbles(length, json))).err().unwrap(), "not an Octocart, there is no program in its pixels");
+        }
+        assert_eq!(payload(&gif(&[1, 2, 3, 4, 5])).unwrap_err(), "not an Octocart, there is no program in its pixels");
+    }
+
+    #[test]
+    fn not_an_octocart() {
+        assert!(Octocart::parse(b"GIF89a").err().unwrap().starts_with("not an Octocart, it isn't a GIF"));
+        assert!(Octocart::parse(&gif(&nibbles(5, b"hello"))).err().unwrap().starts_with("not an Octocart, its data isn't JSON"));
+        assert_eq!(Octocart::parse(&gif(&nibbles(2, b"{}"))).err().unwrap(), "not an Octocart, there is no program in it");
+        assert_eq!(Octocart::parse(&gif(&nibbles(17, br#"{"program":[256]}"#))).err().unwrap(), "the Octocart's program isn't a list of bytes");
+    }
+
+}
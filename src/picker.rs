@@ -0,0 +1,147 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf}
+};
+
+use piston::Key;
+
+use crate::{
+    history::RecentRom,
+    rom_dir::{rom_name, RomDir},
+    text::TextScreen
+};
+
+//The ROM browser shown in the window when no ROM is given, and again
+//whenever the player goes back to it from a running game
+const COLUMNS: usize = 64;
+const ROWS: usize = 24;
+//Below the title and a blank line, above a blank line and the key help
+const LIST_TOP: usize = 2;
+const LIST_ROWS: usize = ROWS - 4;
+
+pub struct RomPicker {
+    dir: PathBuf,
+    rows: Vec<Row>,
+    //Indices into rows of the ROMs the highlight can land on
+    choices: Vec<usize>,
+    selected: usize,
+    //First row on screen
+    scroll: usize
+}
+
+enum Row {
+    Heading(String),
+    //detail is the size, or why there is none. A recent ROM that is
+    //gone stays listed but can't be played.
+    Rom { path: PathBuf, name: String, detail: String, playable: bool }
+}
+
+impl RomPicker {
+
+    //Never fails, an unreadable or empty directory shows as a message.
+    //Recently played ROMs come first under their own heading when there
+    //are any, see history.rs.
+    pub fn open(dir: &Path, recent: &[RecentRom]) -> RomPicker {
+        let mut rows = Vec::new();
+        if !recent.is_empty() {
+            rows.push(Row::Heading("RECENT".to_string()));
+            rows.extend(recent.iter().map(|rom| Row::Rom {
+                path: PathBuf::from(&rom.path),
+                name: rom.title.clone(),
+                detail: match rom.size() {
+                    Some(size) => format!("{} B", size),
+                    None if rom.is_url() => "URL".to_string(),
+                    None => "MISSING".to_string()
+                },
+                playable: rom.exists()
+            }));
+            rows.push(Row::Heading(String::new()));
+            rows.push(Row::Heading("THIS DIRECTORY".to_string()));
+        }
+
+        match RomDir::open(dir) {
+            Ok(rom_dir) => rows.extend(rom_dir.roms().iter().map(|path| Row::Rom {
+                path: path.clone(),
+                name: rom_name(path),
+                detail: format!("{} B", fs::metadata(path).map(|meta| meta.len()).unwrap_or(0)),
+                playable: true
+            })),
+            Err(e) => rows.push(Row::Heading(e))
+        }
+        let choices = rows.iter()
+            .enumerate()
+            .filter(|(_, row)| matches!(row, Row::Rom { .. }))
+            .map(|(index, _)| index)
+            .collect();
+
+        RomPicker {
+            dir: dir.to_path_buf(),
+            rows,
+            choices,
+            selected: 0,
+            scroll: 0
+        }
+    }
+
+    //Moves the highlight, returns the ROM to load on Enter. A recent ROM
+    //that is gone can be highlighted but not played.
+    pub fn handle_key(&mut self, key: Key) -> Option<PathBuf> {
+        let last = self.choices.len().saturating_sub(1);
+        self.selected = match key {
+            Key::Up => self.selected.saturating_sub(1),
+            Key::Down => (self.selected + 1).min(last),
+            Key::PageUp => self.selected.saturating_sub(LIST_ROWS),
+            Key::PageDown => (self.selected + LIST_ROWS).min(last),
+            Key::Home => 0,
+            Key::End => last,
+            Key::Return | Key::NumPadEnter => return match self.choices.get(self.selected).map(|row| &self.rows[*row]) {
+                Some(Row::Rom { path, playable: true, .. }) => Some(path.clone()),
+                _ => None
+            },
+            _ => self.selected
+        };
+
+        //The first ROM scrolls back to the top so its heading shows
+        let row = if self.selected == 0 { 0 } else { self.choices.get(self.selected).copied().unwrap_or(0) };
+        if row < self.scroll {
+            self.scroll = row;
+        } else if row >= self.scroll + LIST_ROWS {
+            self.scroll = row + 1 - LIST_ROWS;
+        }
+        None
+    }
+
+    pub fn render(&self) -> TextScreen {
+        let mut screen = TextScreen::create(COLUMNS, ROWS);
+        screen.print(0, 0, &format!("ROMS IN {}", self.dir.display()));
+
+        let highlighted = self.choices.get(self.selected).copied();
+        for (index, row) in self.rows.iter().enumerate().skip(self.scroll).take(LIST_ROWS) {
+            let line = LIST_TOP + index - self.scroll;
+            match row {
+                Row::Heading(text) => screen.print(0, line, text),
+                Row::Rom { name, detail, .. } => {
+                    let name: String = name.chars().take(COLUMNS - 12).collect();
+                    screen.print(1, line, &format!("{:width$} {:>9}", name, detail, width = COLUMNS - 12));
+                }
+            }
+            if Some(index) == highlighted {
+                screen.invert_row(line);
+            }
+        }
+
+        screen.print(0, ROWS - 1, "UP/DOWN: CHOOSE  ENTER: PLAY  ESC: QUIT");
+        screen
+    }
+
+}
+
+//Whether a window can be opened. Only Linux and the BSDs can run
+//without a display.
+pub fn graphical_session() -> bool {
+    if cfg!(any(target_os = "windows", target_os = "macos")) {
+        return true;
+    }
+
+    ["DISPLAY", "WAYLAND_DISPLAY"].iter().any(|name| env::var_os(name).is_some_and(|value| !value.is_empty()))
+}
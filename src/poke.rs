@@ -0,0 +1,113 @@
+use std::fs;
+
+use crate::{chip8::Chip8, debugger::parse_address};
+
+//--poke ADDR=VALUE and --poke-file: memory patches applied after the ROM
+//is loaded. Numbers are hex like everywhere in the debugger, with or
+//without 0x. Addresses go up to 0xFFFF, whether they are in memory
+//depends on the machine, 4 KB or XO-CHIP's 64 KB, see apply.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Poke {
+    pub address: u16,
+    pub value: u8
+}
+
+pub fn parse_poke(text: &str) -> Result<Poke, String> {
+    let (address, value) = text.split_once('=').ok_or_else(|| format!("{}: expected ADDR=VALUE", text))?;
+    let address = parse_address(address.trim())
+        .ok_or_else(|| format!("{}: address {} isn't in 0x0000-0xFFFF", text, address.trim()))?;
+    let value = parse_address(value.trim())
+        .and_then(|value| u8::try_from(value).ok())
+        .ok_or_else(|| format!("{}: value {} doesn't fit in a byte", text, value.trim()))?;
+
+    Ok(Poke { address, value })
+}
+
+//One ADDR=VALUE per line, # starts a comment
+pub fn parse_poke_file(source: &str) -> Result<Vec<Poke>, String> {
+    source.lines()
+        .enumerate()
+        .map(|(number, line)| (number, line.split('#').next().unwrap_or_default().trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(number, line)| parse_poke(line).map_err(|e| format!("line {}: {}", number + 1, e)))
+        .collect()
+}
+
+pub fn read_poke_file(path: &str) -> Result<Vec<Poke>, String> {
+    let source = fs::read_to_string(path).map_err(|e| format!("Couldn't read {}: {}", path, e))?;
+    parse_poke_file(&source).map_err(|e| format!("{}: {}", path, e))
+}
+
+//Patches memory in order, so later pokes win. Stops at the first one
+//outside the machine's memory.
+pub fn apply(chip8: &mut Chip8, pokes: &[Poke]) -> Result<(), String> {
+    for poke in pokes {
+        chip8.poke(poke.address, &[poke.value])
+            .map_err(|_| format!("poke {:#05x}={:#04x}: the address is past the end of the {} byte memory", poke.address, poke.value, chip8.memory.len()))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{chip8::XO_CHIP_MEMORY_SIZE, debugger::Debugger};
+
+    #[test]
+    fn parses_pokes() {
+        assert_eq!(parse_poke("0x3A2=0xFF"), Ok(Poke { address: 0x3A2, value: 0xFF }));
+        assert_eq!(parse_poke("3a2 = 7"), Ok(Poke { address: 0x3A2, value: 7 }));
+        assert_eq!(parse_poke("0xFFFF=1"), Ok(Poke { address: 0xFFFF, value: 1 }));
+        assert_eq!(parse_poke("0x300"), Err("0x300: expected ADDR=VALUE".to_string()));
+        assert_eq!(parse_poke("0x10000=1"), Err("0x10000=1: address 0x10000 isn't in 0x0000-0xFFFF".to_string()));
+        assert_eq!(parse_poke("0x300=0x100"), Err("0x300=0x100: value 0x100 doesn't fit in a byte".to_string()));
+        assert_eq!(parse_poke("0x300=zz"), Err("0x300=zz: value zz doesn't fit in a byte".to_string()));
+    }
+
+    #[test]
+    fn parses_poke_files() {
+        let pokes = parse_poke_file("# lives\n0x3A2=9\n\n  0x3A3=1  # level\n").unwrap();
+        assert_eq!(pokes, [Poke { address: 0x3A2, value: 9 }, Poke { address: 0x3A3, value: 1 }]);
+
+        assert_eq!(parse_poke_file("0x3A2=9\n0x3A3\n"), Err("line 2: 0x3A3: expected ADDR=VALUE".to_string()));
+    }
+
+    #[test]
+    fn later_pokes_win() {
+        let mut chip8 = Chip8::create();
+        let pokes = parse_poke_file("0x300=1\n0x301=2\n0x300=3").unwrap();
+        apply(&mut chip8, &pokes).unwrap();
+
+        assert_eq!(chip8.memory[0x300..0x302], [3, 2]);
+    }
+
+    #[test]
+    fn pokes_stay_in_memory() {
+        let pokes = [Poke { address: 0xFFF, value: 1 }, Poke { address: 0x1000, value: 2 }];
+
+        let mut chip8 = Chip8::create();
+        assert_eq!(apply(&mut chip8, &pokes), Err("poke 0x1000=0x02: the address is past the end of the 4096 byte memory".to_string()));
+        assert_eq!(chip8.memory[0xFFF], 1);
+
+        let mut xo_chip = Chip8::create_with_memory(XO_CHIP_MEMORY_SIZE);
+        assert_eq!(apply(&mut xo_chip, &pokes), Ok(()));
+        assert_eq!(xo_chip.memory[0xFFF..0x1001], [1, 2]);
+    }
+
+    #[test]
+    fn debugger_poke() {
+        let mut chip8 = Chip8::create();
+        let mut debugger = Debugger::create();
+
+        assert_eq!(debugger.execute(&mut chip8, "poke 300 aa"), "Pause before editing the machine\n");
+        debugger.paused = true;
+        assert_eq!(debugger.execute(&mut chip8, "poke 300 aa bb"), "0x300: aa bb\n");
+        assert_eq!(chip8.memory[0x300..0x302], [0xAA, 0xBB]);
+        assert_eq!(debugger.execute(&mut chip8, "poke 0xFFF 1 2"), "2 byte(s) at 0xfff run past the end of memory\n");
+        assert_eq!(debugger.execute(&mut chip8, "poke 300 100"), "Bytes must be 00-FF\n");
+        assert_eq!(debugger.execute(&mut chip8, "poke 300"), "Usage: poke <addr> <byte>...\n");
+    }
+
+}
@@ -0,0 +1,300 @@
+use std::{collections::{HashMap, VecDeque}, fmt};
+
+use crate::{chip8::ExecHook, disasm, instruction::decode};
+
+//Counts executed instructions per address and per opcode family.
+//Counts are keyed by the address the opcode was fetched from, so
+//self-modifying code is attributed to where it ran, not to whatever
+//opcode happened to live there first.
+pub struct Profiler {
+    address_counts: HashMap<u16, u64>,
+    family_counts: HashMap<&'static str, u64>,
+    total: u64
+}
+
+pub struct ProfileReport {
+    //Sorted by count, hottest first (ties broken by address)
+    pub addresses: Vec<(u16, u64)>,
+    //Sorted by count, most frequent first (ties broken by name)
+    pub families: Vec<(&'static str, u64)>,
+    pub total: u64
+}
+
+impl Profiler {
+
+    pub fn create() -> Profiler {
+        Profiler {
+            address_counts: HashMap::new(),
+            family_counts: HashMap::new(),
+            total: 0
+        }
+    }
+
+    pub fn report(&self) -> ProfileReport {
+        let mut addresses: Vec<(u16, u64)> = self.address_counts.iter()
+            .map(|(address, count)| (*address, *count))
+            .collect();
+        addresses.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        let mut families: Vec<(&'static str, u64)> = self.family_counts.iter()
+            .map(|(family, count)| (*family, *count))
+            .collect();
+        families.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+        ProfileReport {
+            addresses,
+            families,
+            total: self.total
+        }
+    }
+
+}
+
+impl ExecHook for Profiler {
+
+    fn on_exec(&mut self, address: u16, opcode: u16) {
+        *self.address_counts.entry(address).or_insert(0) += 1;
+        *self.family_counts.entry(decode(opcode).family()).or_insert(0) += 1;
+        self.total += 1;
+    }
+
+}
+
+//Number of most recent instructions the idle detector looks at
+const IDLE_WINDOW: usize = 256;
+//A loop is idle when this many addresses account for...
+const IDLE_MAX_ADDRESSES: usize = 4;
+//...more than this percentage of the window
+const IDLE_THRESHOLD_PERCENT: usize = 95;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IdleStatus {
+    Running,
+    WaitingForDelayTimer,
+    WaitingForKey,
+    Spinning
+}
+
+//Spots ROMs sitting in a tight polling loop so a frontend can tell
+//"waiting for input" apart from a hang
+pub struct IdleDetector {
+    recent: VecDeque<(u16, u16)>
+}
+
+impl IdleDetector {
+
+    pub fn create() -> IdleDetector {
+        IdleDetector {
+            recent: VecDeque::with_capacity(IDLE_WINDOW)
+        }
+    }
+
+    pub fn status(&self) -> IdleStatus {
+        if self.recent.len() < IDLE_WINDOW {
+            return IdleStatus::Running;
+        }
+
+        let mut counts: HashMap<u16, usize> = HashMap::new();
+        for (address, _) in &self.recent {
+            *counts.entry(*address).or_insert(0) += 1;
+        }
+
+        let mut hottest: Vec<(u16, usize)> = counts.into_iter().collect();
+        hottest.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        hottest.truncate(IDLE_MAX_ADDRESSES);
+
+        let covered: usize = hottest.iter().map(|(_, count)| count).sum();
+        if covered * 100 <= self.recent.len() * IDLE_THRESHOLD_PERCENT {
+            return IdleStatus::Running;
+        }
+
+        //Classify by what the loop body executes, using the most recent
+        //opcode seen at each address in case the loop rewrote itself
+        let families: Vec<&str> = hottest.iter()
+            .filter_map(|(address, _)| self.recent.iter().rev().find(|(a, _)| a == address))
+            .map(|(_, opcode)| decode(*opcode).family())
+            .collect();
+
+        if families.iter().any(|f| matches!(*f, "EX9E" | "EXA1" | "FX0A")) {
+            IdleStatus::WaitingForKey
+        } else if families.contains(&"FX07") {
+            IdleStatus::WaitingForDelayTimer
+        } else {
+            IdleStatus::Spinning
+        }
+    }
+
+}
+
+impl ExecHook for IdleDetector {
+
+    fn on_exec(&mut self, address: u16, opcode: u16) {
+        if self.recent.len() == IDLE_WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent.push_back((address, opcode));
+    }
+
+}
+
+impl fmt::Display for IdleStatus {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IdleStatus::Running => write!(f, "running"),
+            IdleStatus::WaitingForDelayTimer => write!(f, "waiting for delay timer…"),
+            IdleStatus::WaitingForKey => write!(f, "waiting for key…"),
+            IdleStatus::Spinning => write!(f, "spinning in a tight loop…")
+        }
+    }
+
+}
+
+impl ProfileReport {
+
+    //Human readable summary, disassembling the hottest addresses from
+    //the memory as it is now
+    pub fn summary(&self, memory: &[u8], top: usize) -> String {
+        let mut out = String::new();
+        let percent = |count: u64| count as f64 * 100.0 / self.total.max(1) as f64;
+
+        out.push_str(&format!("Executed {} instructions\n", self.total));
+        out.push_str(&format!("Top {} addresses:\n", top.min(self.addresses.len())));
+        for (address, count) in self.addresses.iter().take(top) {
+            let opcode = disasm::opcode_at(memory, *address).unwrap_or(0);
+
+            out.push_str(&format!(
+                "  {:#05x}  {:>10}  {:>6.2}%  {:04X}  {}\n",
+                address, count, percent(*count), opcode, disasm::disassemble(opcode)
+            ));
+        }
+
+        out.push_str("Opcode histogram:\n");
+        for (family, count) in &self.families {
+            out.push_str(&format!("  {}  {:>10}  {:>6.2}%\n", family, count, percent(*count)));
+        }
+
+        out
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::chip8::Chip8;
+
+    //V0 = 0, then V0 += 1 until it reaches 100, then jumps to itself
+    const COUNTING_LOOP: [u8; 10] = [0x60, 0x00, 0x70, 0x01, 0x30, 0x64, 0x12, 0x02, 0x12, 0x08];
+
+    fn profile(rom: &[u8], steps: usize) -> (Chip8, ProfileReport) {
+        let mut chip8 = Chip8::create();
+        chip8.load_bytes(rom).unwrap();
+        let profiler = Rc::new(RefCell::new(Profiler::create()));
+        chip8.add_exec_hook(profiler.clone());
+        for _ in 0..steps {
+            chip8.step().unwrap();
+        }
+
+        let report = profiler.borrow().report();
+        (chip8, report)
+    }
+
+    #[test]
+    fn loop_body_dominates() {
+        let (_, report) = profile(&COUNTING_LOOP, 310);
+
+        assert_eq!(report.total, 310);
+        assert_eq!(&report.addresses[..3], &[(0x202, 100), (0x204, 100), (0x206, 99)]);
+        assert_eq!(report.addresses[3], (0x208, 10));
+        assert_eq!(report.addresses[4], (0x200, 1));
+        assert_eq!(report.families[0], ("1NNN", 109));
+        assert!(report.families.contains(&("7XKK", 100)));
+        assert!(report.families.contains(&("3XKK", 100)));
+    }
+
+    #[test]
+    fn rewritten_code_counts_by_address() {
+        let mut chip8 = Chip8::create();
+        //0x200: V1 += 1, 0x202: jump back
+        chip8.load_bytes(&[0x71, 0x01, 0x12, 0x00]).unwrap();
+        let profiler = Rc::new(RefCell::new(Profiler::create()));
+        chip8.add_exec_hook(profiler.clone());
+        for step in 0..20 {
+            //Halfway through 0x200 turns into V2 += 1
+            if step == 10 {
+                chip8.memory[0x200] = 0x72;
+            }
+            chip8.step().unwrap();
+        }
+
+        let report = profiler.borrow().report();
+        assert_eq!(report.addresses, vec![(0x200, 10), (0x202, 10)]);
+        assert_eq!((chip8.registers[1], chip8.registers[2]), (5, 5));
+    }
+
+    #[test]
+    fn summary_lists_the_hottest_addresses() {
+        let (chip8, report) = profile(&COUNTING_LOOP, 310);
+        let summary = report.summary(&chip8.memory, 2);
+
+        assert!(summary.starts_with("Executed 310 instructions\nTop 2 addresses:\n"));
+        assert!(summary.contains("0x202         100   32.26%  7001  ADD V0, 0x01"), "{}", summary);
+        assert!(!summary.contains("0x206  "));
+    }
+
+
+    fn idle_status(rom: &[u8], keys: &[usize], steps: usize) -> IdleStatus {
+        let mut chip8 = Chip8::create();
+        chip8.load_bytes(rom).unwrap();
+        for key in keys {
+            chip8.keypad[*key] = true;
+        }
+        let detector = Rc::new(RefCell::new(IdleDetector::create()));
+        chip8.add_exec_hook(detector.clone());
+        for _ in 0..steps {
+            chip8.run().unwrap();
+        }
+
+        let status = detector.borrow().status();
+        status
+    }
+
+    #[test]
+    fn idle_needs_a_full_window() {
+        //A jump to itself, but not for long enough
+        assert_eq!(idle_status(&[0x12, 0x00], &[], IDLE_WINDOW - 1), IdleStatus::Running);
+        assert_eq!(idle_status(&[0x12, 0x00], &[], IDLE_WINDOW), IdleStatus::Spinning);
+    }
+
+    #[test]
+    fn idle_waiting_for_delay_timer() {
+        //DT = 0xFF, then loop on V0 = DT until it is 0. The timers tick
+        //every instruction here, so DT runs out after 255.
+        let rom = [0x60, 0xFF, 0xF0, 0x15, 0xF0, 0x07, 0x30, 0x00, 0x12, 0x04, 0x12, 0x0A];
+        assert_eq!(idle_status(&rom, &[], 260), IdleStatus::WaitingForDelayTimer);
+        assert_eq!(idle_status(&rom, &[], 600), IdleStatus::Spinning);
+    }
+
+    #[test]
+    fn idle_waiting_for_key() {
+        //FX0A without a key down
+        assert_eq!(idle_status(&[0xF0, 0x0A], &[], 300), IdleStatus::WaitingForKey);
+        //Polling key 5 with SKP until it is down
+        let rom = [0x60, 0x05, 0xE0, 0x9E, 0x12, 0x02, 0x12, 0x06];
+        assert_eq!(idle_status(&rom, &[], 300), IdleStatus::WaitingForKey);
+        //The same once it is, which ends in a jump to itself
+        assert_eq!(idle_status(&rom, &[5], 300), IdleStatus::Spinning);
+    }
+
+    #[test]
+    fn busy_loop_is_running() {
+        //V0 += 1, V1 += 1, ..., V5 += 1 then jump back: 7 addresses
+        let rom = [0x70, 0x01, 0x71, 0x01, 0x72, 0x01, 0x73, 0x01, 0x74, 0x01, 0x75, 0x01, 0x12, 0x00];
+        assert_eq!(idle_status(&rom, &[], 1000), IdleStatus::Running);
+    }
+
+}
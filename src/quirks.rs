@@ -0,0 +1,168 @@
+use std::fmt;
+
+use crate::chip8::{MEMORY_SIZE, XO_CHIP_MEMORY_SIZE};
+
+//Behaviors that differ between CHIP-8 interpreters. All off is what
+//this emulator has always done, so existing ROMs keep working.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Quirks {
+    //8XY6/8XYE shift Vy into Vx (COSMAC VIP) instead of shifting Vx in place
+    pub shift_vy: bool,
+    //FX55/FX65 leave I pointing past the last register transferred
+    pub increment_i: bool,
+    //8XY1/8XY2/8XY3 clear VF
+    pub vf_reset: bool,
+    //BXNN jumps to XNN + VX (CHIP-48/SCHIP) instead of NNN + V0
+    pub jump_vx: bool,
+    //Sprites are cut off at the right edge instead of wrapping to the left
+    pub clip_x: bool,
+    //Sprites are cut off at the bottom edge instead of wrapping to the top
+    pub clip_y: bool,
+    //00FE/00FF scale the picture into the new resolution instead of clearing it
+    pub scale_on_resize: bool,
+    //EX9E/EXA1 with Vx over 0xF check the key in its low nibble (COSMAC VIP)
+    //instead of taking it as a key that is never down
+    pub key_wrap: bool,
+    //XO-CHIP's own instructions run instead of being invalid opcodes
+    pub xo_chip_opcodes: bool,
+    //00CN/00FB/00FC scroll half as far in lores, as SUPER-CHIP 1.1 did by
+    //scrolling its hires screen underneath
+    pub half_scroll: bool,
+    //DXY0 draws 8x16 in lores, as legacy SUPER-CHIP did, instead of 16x16
+    pub dxy0_8x16: bool,
+    //FX55/FX65 leave I on the last register transferred, I + X, as CHIP-48
+    //did. Takes precedence over increment_i.
+    pub increment_i_x: bool
+}
+
+//Command line / config names of every quirk, in toggle-key order (F1-F8)
+pub const QUIRK_NAMES: [&str; 12] = [
+    "shift-vy", "increment-i", "vf-reset", "jump-vx", "clip-x", "clip-y", "scale-on-resize", "key-wrap", "xo-chip-opcodes", "half-scroll",
+    "dxy0-8x16", "increment-i-x"
+];
+
+//Quirk sets of well-known interpreters, see Quirks::profile
+pub const PROFILE_NAMES: [&str; 5] = ["default", "chip8", "chip48", "schip", "xo-chip"];
+
+impl Quirks {
+
+    //default: what this emulator does out of the box
+    //chip8:   the original COSMAC VIP interpreter
+    //chip48:  CHIP-48 on the HP 48, which SUPER-CHIP grew out of
+    //schip:   SUPER-CHIP 1.1 on the HP 48
+    //xo-chip: Octo
+    //Sprites are clipped on both axes by the VIP, CHIP-48 and SUPER-CHIP and wrap
+    //on both in Octo and by default. Mixed clipping isn't any of these,
+    //it is there for the odd interpreter (and ROM) that does it.
+    pub fn profile(name: &str) -> Option<Quirks> {
+        let quirks = Quirks::default();
+
+        match name {
+            "default" => Some(quirks),
+            "chip8" => Some(Quirks { shift_vy: true, increment_i: true, vf_reset: true, clip_x: true, clip_y: true, key_wrap: true, ..quirks }),
+            "chip48" => Some(Quirks { jump_vx: true, clip_x: true, clip_y: true, increment_i_x: true, ..quirks }),
+            "schip" => Some(Quirks { jump_vx: true, clip_x: true, clip_y: true, half_scroll: true, ..quirks }),
+            "xo-chip" => Some(Quirks { shift_vy: true, increment_i: true, xo_chip_opcodes: true, ..quirks }),
+            _ => None
+        }
+    }
+
+    //Bytes of memory a machine for the profile is made with, XO-CHIP's
+    //64 KB under xo-chip. Unlike the quirks it can't change once the
+    //machine is running.
+    pub fn memory_size(name: &str) -> usize {
+        match name {
+            "xo-chip" => XO_CHIP_MEMORY_SIZE,
+            _ => MEMORY_SIZE
+        }
+    }
+
+    //One line on what a quirk does when it's on, for chip8 quirks --list
+    pub fn meaning(name: &str) -> Option<&'static str> {
+        match name {
+            "shift-vy" => Some("8XY6/8XYE shift VY into VX instead of shifting VX in place"),
+            "increment-i" => Some("FX55/FX65 leave I past the last register transferred"),
+            "vf-reset" => Some("8XY1/8XY2/8XY3 clear VF"),
+            "jump-vx" => Some("BXNN jumps to XNN + VX instead of NNN + V0"),
+            "clip-x" => Some("sprites are cut off at the right edge instead of wrapping to the left"),
+            "clip-y" => Some("sprites are cut off at the bottom edge instead of wrapping to the top"),
+            "scale-on-resize" => Some("00FE/00FF scale the picture into the new resolution instead of clearing it"),
+            "key-wrap" => Some("EX9E/EXA1 with VX over 0xF check key VX & 0xF instead of a key that is never down"),
+            "xo-chip-opcodes" => Some("XO-CHIP instructions (F000 NNNN) run instead of halting as invalid"),
+            "half-scroll" => Some("00CN/00FB/00FC scroll half as far in lores: N/2 pixels down, 2 sideways"),
+            "dxy0-8x16" => Some("DXY0 draws an 8x16 sprite in lores instead of 16x16"),
+            "increment-i-x" => Some("FX55/FX65 leave I on the last register transferred, I + X, over increment-i"),
+            _ => None
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<bool> {
+        match name {
+            "shift-vy" => Some(self.shift_vy),
+            "increment-i" => Some(self.increment_i),
+            "vf-reset" => Some(self.vf_reset),
+            "jump-vx" => Some(self.jump_vx),
+            "clip-x" => Some(self.clip_x),
+            "clip-y" => Some(self.clip_y),
+            "scale-on-resize" => Some(self.scale_on_resize),
+            "key-wrap" => Some(self.key_wrap),
+            "xo-chip-opcodes" => Some(self.xo_chip_opcodes),
+            "half-scroll" => Some(self.half_scroll),
+            "dxy0-8x16" => Some(self.dxy0_8x16),
+            "increment-i-x" => Some(self.increment_i_x),
+            _ => None
+        }
+    }
+
+    pub fn flag_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "shift-vy" => Some(&mut self.shift_vy),
+            "increment-i" => Some(&mut self.increment_i),
+            "vf-reset" => Some(&mut self.vf_reset),
+            "jump-vx" => Some(&mut self.jump_vx),
+            "clip-x" => Some(&mut self.clip_x),
+            "clip-y" => Some(&mut self.clip_y),
+            "scale-on-resize" => Some(&mut self.scale_on_resize),
+            "key-wrap" => Some(&mut self.key_wrap),
+            "xo-chip-opcodes" => Some(&mut self.xo_chip_opcodes),
+            "half-scroll" => Some(&mut self.half_scroll),
+            "dxy0-8x16" => Some(&mut self.dxy0_8x16),
+            "increment-i-x" => Some(&mut self.increment_i_x),
+            _ => None
+        }
+    }
+
+    //Flips a quirk, returning its new value
+    pub fn toggle(&mut self, name: &str) -> Option<bool> {
+        let flag = self.flag_mut(name)?;
+        *flag = !*flag;
+        Some(*flag)
+    }
+
+}
+
+//NAME=on or NAME=off, as given to --quirk
+pub fn parse_override(text: &str) -> Result<(String, bool), String> {
+    let (name, value) = text.split_once('=').ok_or_else(|| format!("expected NAME=on or NAME=off, not {}", text))?;
+    if !QUIRK_NAMES.contains(&name) {
+        return Err(format!("unknown quirk {}, expected one of {}", name, QUIRK_NAMES.join(", ")));
+    }
+
+    match value {
+        "on" | "true" => Ok((name.to_string(), true)),
+        "off" | "false" => Ok((name.to_string(), false)),
+        _ => Err(format!("{} should be on or off, not {}", name, value))
+    }
+}
+
+impl fmt::Display for Quirks {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flags: Vec<String> = QUIRK_NAMES.iter()
+            .map(|name| format!("{}={}", name, if self.get(name) == Some(true) { "on" } else { "off" }))
+            .collect();
+
+        write!(f, "{}", flags.join(" "))
+    }
+
+}
@@ -0,0 +1,141 @@
+use std::{
+    borrow::Cow,
+    fs::File,
+    io,
+    time::Instant
+};
+
+use gif::{Encoder, Frame, Repeat};
+
+//Same colors as the window: lit pixels white on black
+const PALETTE: [u8; 6] = [0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF];
+
+//GIF delays are in 1/100s and most viewers slow anything under 2 down to
+//10, so frames shorter than that are dropped in favour of the next one
+const MIN_DELAY: u64 = 2;
+
+struct Capture {
+    video: Vec<u8>,
+    width: usize,
+    //How long it was on screen while recording, in seconds
+    duration: f64
+}
+
+//Collects rendered frames for --record-gif and writes them out on exit.
+//Unchanged frames only lengthen the previous one.
+pub struct GifRecorder {
+    path: String,
+    scale: u16,
+    recording: bool,
+    captures: Vec<Capture>,
+    last_capture: Option<Instant>
+}
+
+impl GifRecorder {
+
+    pub fn create(path: &str, scale: u16) -> GifRecorder {
+        GifRecorder {
+            path: path.to_string(),
+            scale,
+            recording: true,
+            captures: Vec::new(),
+            last_capture: None
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    //Pauses or resumes capturing, the paused stretch is left out of the GIF
+    pub fn toggle(&mut self) {
+        self.close_last_capture();
+        self.recording = !self.recording;
+    }
+
+    //Called with every rendered frame, width being 64 or 128 in hires
+    pub fn capture(&mut self, video: &[u8], width: usize) {
+        if !self.recording {
+            return;
+        }
+
+        self.close_last_capture();
+        self.last_capture = Some(Instant::now());
+
+        match self.captures.last() {
+            Some(last) if last.video == video => (),
+            _ => self.captures.push(Capture { video: video.to_vec(), width, duration: 0.0 })
+        }
+    }
+
+    //Credits the time since the last capture to the frame shown during it
+    fn close_last_capture(&mut self) {
+        if let (Some(last), Some(time)) = (self.captures.last_mut(), self.last_capture.take()) {
+            last.duration += time.elapsed().as_secs_f64();
+        }
+    }
+
+    //Encodes everything captured so far, returns the number of GIF frames
+    pub fn finish(&mut self) -> io::Result<usize> {
+        self.close_last_capture();
+
+        let width = 64 * self.scale;
+        let height = 32 * self.scale;
+        let mut encoder = Encoder::new(File::create(&self.path)?, width, height, &PALETTE)
+            .map_err(io::Error::other)?;
+        encoder.set_repeat(Repeat::Infinite).map_err(io::Error::other)?;
+
+        //Delays are rounded against the running clock so they add up to
+        //the real duration instead of drifting
+        let mut frames = 0;
+        let mut clock: f64 = 0.0;
+        let mut pending: Option<(&Capture, u64)> = None;
+        for capture in &self.captures {
+            let start = (clock * 100.0).round() as u64;
+            clock += capture.duration;
+
+            match pending {
+                Some((shown, shown_start)) if start - shown_start >= MIN_DELAY => {
+                    encoder.write_frame(&self.frame(shown, start - shown_start)).map_err(io::Error::other)?;
+                    frames += 1;
+                    pending = Some((capture, start));
+                },
+                Some((_, shown_start)) => pending = Some((capture, shown_start)),
+                None => pending = Some((capture, start))
+            }
+        }
+
+        if let Some((shown, shown_start)) = pending {
+            let end = (clock * 100.0).round() as u64;
+            encoder.write_frame(&self.frame(shown, (end - shown_start).max(MIN_DELAY))).map_err(io::Error::other)?;
+            frames += 1;
+        }
+
+        Ok(frames)
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    //Always 64x32 times the scale, hires captures are sampled down to fit
+    fn frame(&self, capture: &Capture, delay: u64) -> Frame<'static> {
+        let (width, height) = (64 * self.scale as usize, 32 * self.scale as usize);
+        let video_height = capture.video.len() / capture.width;
+
+        let mut buffer = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let row = &capture.video[(y * video_height / height) * capture.width..];
+            buffer.extend((0..width).map(|x| (row[x * capture.width / width] != 0) as u8));
+        }
+
+        Frame {
+            width: 64 * self.scale,
+            height: 32 * self.scale,
+            delay: delay.min(u16::MAX as u64) as u16,
+            buffer: Cow::Owned(buffer),
+            ..Frame::default()
+        }
+    }
+
+}
@@ -0,0 +1,315 @@
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::mpsc::{self, Receiver},
+    thread
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    chip8::{Chip8, Chip8Error},
+    debugger::Debugger
+};
+
+//--debug-listen=addr:port drives the debugger over TCP. One client at a
+//time speaks line-delimited JSON:
+//  -> {"cmd": "read_mem", "addr": 512, "len": 4}
+//  <- {"ok": true, "data": [0, 224, 162, 42]}
+//  <- {"ok": false, "error": "..."}
+//and is sent {"event": "breakpoint", "pc": ...} or
+//{"event": "halted", "pc": ..., "error": "..."} whenever execution stops on its own.
+//
+//Commands: get_state, read_mem {addr, len}, write_mem/poke {addr, data},
+//set {target, value} (target being V0-VF, I, PC, DT or ST),
+//step {count}, pause, continue, set_breakpoint {addr},
+//delete_breakpoint {addr}, press_key {key}, release_key {key},
+//load_state {path}, save_state {path}.
+//Everything that changes the machine needs it to be paused first.
+
+enum Message {
+    Connected(TcpStream),
+    Line(String),
+    Disconnected
+}
+
+pub struct RemoteDebug {
+    //Where it listens, with the port picked for it when given port 0
+    pub address: SocketAddr,
+    messages: Receiver<Message>,
+    client: Option<TcpStream>,
+    //Debugger state as of the last poll, to spot stops made by the run loop
+    was_paused: bool
+}
+
+impl RemoteDebug {
+
+    //Binds right away so a bad address fails at startup, then waits for
+    //the client on a background thread
+    pub fn listen(address: &str) -> io::Result<RemoteDebug> {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            let Ok((stream, _)) = listener.accept() else { return };
+            let Ok(writer) = stream.try_clone() else { return };
+            if sender.send(Message::Connected(writer)).is_err() {
+                return;
+            }
+
+            for line in BufReader::new(stream).lines() {
+                let Ok(line) = line else { break };
+                if sender.send(Message::Line(line)).is_err() {
+                    return;
+                }
+            }
+
+            let _ = sender.send(Message::Disconnected);
+        });
+
+        Ok(RemoteDebug {
+            address,
+            messages: receiver,
+            client: None,
+            was_paused: false
+        })
+    }
+
+    //Answers pending requests and reports stops. Called once per frontend
+    //tick. Returns false once the client has gone away.
+    pub fn poll(&mut self, chip8: &mut Chip8, debugger: &mut Debugger, halted: Option<&Chip8Error>) -> bool {
+        if self.client.is_some() && !self.was_paused && debugger.paused {
+            let event = match halted {
+                Some(e) => json!({ "event": "halted", "pc": e.address(), "error": e.to_string() }),
+                None if debugger.is_breakpoint(chip8.program_counter) => {
+                    json!({ "event": "breakpoint", "pc": chip8.program_counter })
+                },
+                None => json!({ "event": "paused", "pc": chip8.program_counter })
+            };
+            self.send(&event);
+        }
+
+        while let Ok(message) = self.messages.try_recv() {
+            match message {
+                Message::Connected(stream) => {
+                    log::info!("Remote debugger connected");
+                    self.client = Some(stream);
+                },
+                Message::Line(line) => {
+                    let response = match serde_json::from_str::<Value>(&line) {
+                        Ok(request) => handle(&request, chip8, debugger, halted),
+                        Err(e) => Err(format!("invalid JSON: {}", e))
+                    };
+
+                    let response = match response {
+                        Ok(Value::Object(mut fields)) => {
+                            fields.insert("ok".to_string(), json!(true));
+                            Value::Object(fields)
+                        },
+                        Ok(_) => json!({ "ok": true }),
+                        Err(e) => json!({ "ok": false, "error": e })
+                    };
+                    self.send(&response);
+                },
+                Message::Disconnected => {
+                    log::info!("Remote debugger disconnected");
+                    self.client = None;
+                    return false;
+                }
+            }
+        }
+
+        self.was_paused = debugger.paused;
+        true
+    }
+
+    fn send(&mut self, message: &Value) {
+        let Some(client) = self.client.as_mut() else { return };
+        if writeln!(client, "{}", message).is_err() {
+            self.client = None;
+        }
+    }
+
+}
+
+fn handle(request: &Value, chip8: &mut Chip8, debugger: &mut Debugger, halted: Option<&Chip8Error>) -> Result<Value, String> {
+    let command = request["cmd"].as_str().ok_or("missing \"cmd\"")?;
+
+    let mutating = matches!(command, "write_mem" | "poke" | "set" | "step" | "press_key" | "release_key" | "load_state");
+    if mutating && !debugger.paused {
+        return Err(format!("{} needs the emulator to be paused", command));
+    }
+
+    match command {
+        "get_state" => Ok(json!({
+            "pc": chip8.program_counter,
+            "i": chip8.index_register,
+            "sp": chip8.stack_pointer,
+            "dt": chip8.delay_timer,
+            "st": chip8.sound_timer,
+            "beeping": chip8.is_beeping(),
+            "v": chip8.registers,
+            "stack": chip8.stack[..chip8.stack_pointer as usize],
+            "keys": chip8.keypad,
+            "paused": debugger.paused,
+            "halted": halted.map(|e| e.to_string())
+        })),
+        "read_mem" => {
+            let address = number(request, "addr")?;
+            let length = number(request, "len")?;
+            let data = chip8.memory.get(address..address + length).ok_or("read past the end of memory")?;
+
+            Ok(json!({ "data": data }))
+        },
+        "write_mem" | "poke" => {
+            let data = request["data"].as_array().ok_or("missing \"data\"")?;
+            let bytes = data.iter()
+                .map(|byte| byte.as_u64().filter(|byte| *byte <= 0xFF).map(|byte| byte as u8))
+                .collect::<Option<Vec<u8>>>()
+                .ok_or("\"data\" must be a list of bytes")?;
+            let echo = debugger.poke(chip8, address(request)?, &bytes)?;
+
+            Ok(json!({ "echo": echo.trim_end() }))
+        },
+        "set" => {
+            let target = request["target"].as_str().ok_or("missing \"target\"")?;
+            let value = u16::try_from(number(request, "value")?).map_err(|_| "\"value\" out of range".to_string())?;
+            let echo = debugger.set(chip8, target, value)?;
+
+            Ok(json!({ "echo": echo.trim_end() }))
+        },
+        "step" => {
+            let count = if request["count"].is_null() { 1 } else { number(request, "count")? };
+            debugger.step(chip8, count).map_err(|e| e.to_string())?;
+
+            Ok(json!({ "pc": chip8.program_counter }))
+        },
+        "pause" => {
+            debugger.paused = true;
+            Ok(json!({ "pc": chip8.program_counter }))
+        },
+        "continue" => {
+            debugger.resume(chip8);
+            Ok(json!({}))
+        },
+        "set_breakpoint" => {
+            debugger.add_breakpoint(address(request)?);
+            Ok(json!({}))
+        },
+        "delete_breakpoint" => match debugger.remove_breakpoint(address(request)?) {
+            true => Ok(json!({})),
+            false => Err("no breakpoint there".to_string())
+        },
+        "press_key" | "release_key" => {
+            let key = number(request, "key")?;
+            *chip8.keypad.get_mut(key).ok_or("key must be 0-15")? = command == "press_key";
+            Ok(json!({}))
+        },
+        "load_state" => {
+            let path = request["path"].as_str().ok_or("missing \"path\"")?;
+            let bytes = fs::read(path).map_err(|e| format!("couldn't read {}: {}", path, e))?;
+            chip8.load_state(&bytes).map_err(|e| e.to_string())?;
+            Ok(json!({ "pc": chip8.program_counter }))
+        },
+        "save_state" => {
+            let path = request["path"].as_str().ok_or("missing \"path\"")?;
+            fs::write(path, chip8.save_state()).map_err(|e| format!("couldn't write {}: {}", path, e))?;
+            Ok(json!({}))
+        },
+        _ => Err(format!("unknown command: {}", command))
+    }
+}
+
+fn number(request: &Value, field: &str) -> Result<usize, String> {
+    request[field].as_u64()
+        .map(|value| value as usize)
+        .ok_or(format!("missing or invalid \"{}\"", field))
+}
+
+fn address(request: &Value) -> Result<u16, String> {
+    u16::try_from(number(request, "addr")?).map_err(|_| "\"addr\" out of range".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    //Runs a ROM looping on V0 += 1 under a remote debugger on its own
+    //thread, the way the frontend does, until the client hangs up
+    fn serve() -> (SocketAddr, thread::JoinHandle<()>) {
+        let (sender, receiver) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            let mut chip8 = Chip8::create();
+            chip8.load_bytes(&[0x60, 0x00, 0x70, 0x01, 0x12, 0x02]).unwrap();
+            let mut debugger = Debugger::create();
+            debugger.paused = true;
+            let mut remote = RemoteDebug::listen("127.0.0.1:0").unwrap();
+            sender.send(remote.address).unwrap();
+
+            loop {
+                if !debugger.paused && !debugger.check_breakpoint(&chip8) {
+                    chip8.run().unwrap();
+                }
+                if !remote.poll(&mut chip8, &mut debugger, None) {
+                    break;
+                }
+            }
+        });
+
+        (receiver.recv().unwrap(), handle)
+    }
+
+    fn request(client: &mut TcpStream, reader: &mut impl BufRead, request: Value) -> Value {
+        writeln!(client, "{}", request).unwrap();
+        receive(reader)
+    }
+
+    fn receive(reader: &mut impl BufRead) -> Value {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[test]
+    fn breakpoint_over_tcp() {
+        let (address, handle) = serve();
+        let mut client = TcpStream::connect(address).unwrap();
+        let mut reader = BufReader::new(client.try_clone().unwrap());
+
+        assert_eq!(request(&mut client, &mut reader, json!({ "cmd": "set_breakpoint", "addr": 0x204 })), json!({ "ok": true }));
+        assert_eq!(request(&mut client, &mut reader, json!({ "cmd": "continue" })), json!({ "ok": true }));
+        assert_eq!(receive(&mut reader), json!({ "event": "breakpoint", "pc": 0x204 }));
+        assert_eq!(request(&mut client, &mut reader, json!({ "cmd": "get_state" }))["v"][0], 1);
+
+        //Once more round the loop
+        request(&mut client, &mut reader, json!({ "cmd": "continue" }));
+        assert_eq!(receive(&mut reader), json!({ "event": "breakpoint", "pc": 0x204 }));
+        assert_eq!(request(&mut client, &mut reader, json!({ "cmd": "get_state" }))["v"][0], 2);
+
+        drop((client, reader));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn rejects_bad_requests() {
+        let (address, handle) = serve();
+        let mut client = TcpStream::connect(address).unwrap();
+        let mut reader = BufReader::new(client.try_clone().unwrap());
+
+        writeln!(client, "not json").unwrap();
+        assert_eq!(receive(&mut reader)["ok"], false);
+        assert_eq!(request(&mut client, &mut reader, json!({ "cmd": "frob" })), json!({ "ok": false, "error": "unknown command: frob" }));
+        assert_eq!(request(&mut client, &mut reader, json!({ "cmd": "read_mem", "addr": 0xFFF, "len": 2 })),
+            json!({ "ok": false, "error": "read past the end of memory" }));
+        request(&mut client, &mut reader, json!({ "cmd": "continue" }));
+        assert_eq!(request(&mut client, &mut reader, json!({ "cmd": "step" })),
+            json!({ "ok": false, "error": "step needs the emulator to be paused" }));
+
+        drop((client, reader));
+        handle.join().unwrap();
+    }
+
+}
@@ -0,0 +1,174 @@
+use std::io::{BufRead, Write};
+
+use crate::{
+    asm,
+    chip8::Chip8,
+    debugger::{parse_address, Debugger},
+    diagnostic::ascii_framebuffer,
+    disasm::disassemble,
+    instruction::decode
+};
+
+const HELP: &str = "\
+Commands:
+  exec <opcode>        execute an opcode, e.g. exec 6005
+  exec <instruction>   assemble and execute one instruction, e.g. exec LD V0, 5
+  mem <addr> [n]       show n bytes of memory (default 16)
+  tick [n]             count the timers down n times (default 1)
+  screen               show the display
+  quit                 leave the REPL
+
+  regs, set, poke, step, rstep, bt, sprite, save, load, diffstate and
+  help <mnemonic> work as in the debugger. step runs what is in memory at PC.
+";
+
+//chip8 repl: a CHIP-8 machine with no ROM, driven one typed instruction
+//at a time. Debugger commands cover inspecting and editing it; exec runs
+//an instruction as if it had just been fetched from PC, so PC moves past
+//it and skips and jumps work relative to it.
+pub fn run(input: impl BufRead, output: &mut impl Write) -> i32 {
+    let mut chip8 = Chip8::create();
+    let mut debugger = Debugger::create();
+    debugger.paused = true;
+
+    let _ = write!(output, "CHIP-8 REPL, type help for commands\n> ");
+    let _ = output.flush();
+    for line in input.lines() {
+        let Ok(line) = line else { break };
+        let line = line.trim();
+        if line == "quit" || line == "exit" {
+            break;
+        }
+
+        let _ = write!(output, "{}> ", execute(&mut chip8, &mut debugger, line));
+        let _ = output.flush();
+    }
+
+    0
+}
+
+fn execute(chip8: &mut Chip8, debugger: &mut Debugger, line: &str) -> String {
+    let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let rest = rest.trim();
+    let args: Vec<&str> = rest.split_whitespace().collect();
+
+    match command {
+        "" => String::new(),
+        "help" | "h" if args.is_empty() => HELP.to_string(),
+        "exec" | "x" => match parse_instruction(rest) {
+            Ok(opcode) => exec(chip8, debugger, opcode),
+            Err(e) => format!("{}\n", e)
+        },
+        "mem" | "m" => {
+            let address = args.first().and_then(|address| parse_address(address));
+            let length = args.get(1).map_or(Ok(16), |length| length.parse::<usize>());
+            match (address, length) {
+                (Some(address), Ok(length)) => memory(chip8, address as usize, length),
+                _ => "Usage: mem <addr> [n]\n".to_string()
+            }
+        },
+        "tick" => match args.first().map_or(Ok(1), |count| count.parse::<usize>()) {
+            Ok(count) => {
+                (0..count).for_each(|_| chip8.tick_timers());
+                format!("DT {}  ST {}\n", chip8.delay_timer, chip8.sound_timer)
+            },
+            Err(_) => "Usage: tick [n]\n".to_string()
+        },
+        "screen" => ascii_framebuffer(&chip8.video, chip8.video_width()),
+        "help" | "h" | "regs" | "r" | "set" | "poke" | "step" | "s" | "rstep" | "bt" | "sprite" | "save" | "load" | "diffstate" => {
+            let output = debugger.execute(chip8, line);
+            debugger.paused = true;
+            output
+        },
+        _ => format!("Unknown command: {} (try help)\n", command)
+    }
+}
+
+//Four hex digits are an opcode, anything else is assembled
+fn parse_instruction(text: &str) -> Result<u16, String> {
+    if text.is_empty() {
+        return Err("Usage: exec <opcode> | exec <instruction>".to_string());
+    }
+    if text.len() == 4 && text.chars().all(|c| c.is_ascii_hexdigit()) {
+        return u16::from_str_radix(text, 16).map_err(|e| e.to_string());
+    }
+
+    match asm::assemble(text) {
+        Ok(bytes) if bytes.len() == 2 => Ok(u16::from_be_bytes([bytes[0], bytes[1]])),
+        Ok(_) => Err("exec takes a single instruction".to_string()),
+        Err(e) => Err(e.message + ": " + &e.token)
+    }
+}
+
+fn exec(chip8: &mut Chip8, debugger: &mut Debugger, opcode: u16) -> String {
+    //Lets rstep undo it like a stepped instruction
+    debugger.record(chip8);
+
+    chip8.opcode = opcode;
+    chip8.program_counter = chip8.program_counter.wrapping_add(2);
+    match chip8.execute(decode(opcode)) {
+        Ok(()) => format!("{:04X}  {}\n", opcode, disassemble(opcode)),
+        Err(e) => format!("{}\n", e)
+    }
+}
+
+fn memory(chip8: &Chip8, start: usize, length: usize) -> String {
+    let end = (start + length).min(chip8.memory.len());
+    if start >= end {
+        return format!("{:#05x} is outside memory\n", start);
+    }
+
+    let mut out = String::new();
+    for (line, bytes) in chip8.memory[start..end].chunks(16).enumerate() {
+        let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        out.push_str(&format!("{:#05x}  {}\n", start + line * 16, hex.join(" ")));
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn session(lines: &[&str]) -> String {
+        let mut output = Vec::new();
+        run(lines.join("\n").as_bytes(), &mut output);
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn parses_opcodes_and_instructions() {
+        assert_eq!(parse_instruction("6005"), Ok(0x6005));
+        assert_eq!(parse_instruction("LD V0, 5"), Ok(0x6005));
+        assert_eq!(parse_instruction("add v1, v2"), Ok(0x8124));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(parse_instruction(""), Err("Usage: exec <opcode> | exec <instruction>".to_string()));
+        assert!(parse_instruction("LD V0").is_err());
+        assert!(parse_instruction("FROB V0, 1").is_err());
+        assert_eq!(parse_instruction("db 1, 2, 3"), Err("exec takes a single instruction".to_string()));
+    }
+
+    #[test]
+    fn bad_commands() {
+        let output = session(&["frob", "mem", "mem 0x200 many", "tick x", "diffstate"]);
+
+        assert!(output.contains("Unknown command: frob (try help)\n"));
+        assert!(output.contains("Usage: mem <addr> [n]\n"));
+        assert!(output.contains("Usage: tick [n]\n"));
+        assert!(output.contains("Usage: diffstate <file>\n"));
+    }
+
+    #[test]
+    fn exec_and_inspect() {
+        let output = session(&["exec LD V3, 0x2A", "exec A300", "exec LD B, V3", "mem 0x300 3"]);
+
+        assert!(output.contains("632A  LD V3, 0x2A\n"));
+        assert!(output.contains("0x300  00 04 02\n"), "{}", output);
+    }
+
+}
@@ -0,0 +1,82 @@
+use std::{fs, io::Write};
+
+//--rng-log=FILE writes every number CXKK's random number generator hands
+//out, before the KK mask, one per line as two hex digits. --rng-replay=FILE
+//feeds such a file back to CXKK in place of the seeded numbers, so a run
+//a user captured gets the same random numbers whatever the seed. Blank
+//lines and # comments are skipped.
+
+//Where CXKK's numbers come from instead of the seeded generator. None
+//hands back to it.
+pub trait RandomSource {
+    fn next_byte(&mut self) -> Option<u8>;
+}
+
+//--rng-replay's numbers, in order. Once they run out CXKK goes on with
+//the seeded ones, with a warning.
+pub struct RngReplay {
+    numbers: Vec<u8>,
+    position: usize
+}
+
+impl RngReplay {
+
+    pub fn create(numbers: Vec<u8>) -> RngReplay {
+        RngReplay { numbers, position: 0 }
+    }
+
+    pub fn parse(source: &str) -> Result<RngReplay, String> {
+        let numbers = source.lines()
+            .enumerate()
+            .map(|(index, line)| (index, line.split('#').next().unwrap_or_default().trim()))
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(index, line)| u8::from_str_radix(line, 16).map_err(|_| format!("line {}: expected a byte in hex, got \"{}\"", index + 1, line)))
+            .collect::<Result<Vec<u8>, String>>()?;
+
+        Ok(RngReplay::create(numbers))
+    }
+
+    pub fn load(path: &str) -> Result<RngReplay, String> {
+        let source = fs::read_to_string(path).map_err(|e| format!("Couldn't read {}: {}", path, e))?;
+        RngReplay::parse(&source).map_err(|e| format!("{}: {}", path, e))
+    }
+
+}
+
+impl RandomSource for RngReplay {
+
+    fn next_byte(&mut self) -> Option<u8> {
+        let byte = self.numbers.get(self.position).copied();
+        if byte.is_some() {
+            self.position += 1;
+        } else if self.position == self.numbers.len() {
+            log::warn!("--rng-replay ran out after {} numbers, going on with the seeded ones", self.numbers.len());
+            self.position += 1;
+        }
+
+        byte
+    }
+
+}
+
+//--rng-log's output. A write that fails is reported once and the log
+//stops, the run carries on.
+pub struct RngLog {
+    output: Option<Box<dyn Write>>
+}
+
+impl RngLog {
+
+    pub fn create(output: Box<dyn Write>) -> RngLog {
+        RngLog { output: Some(output) }
+    }
+
+    pub fn record(&mut self, byte: u8) {
+        let Some(output) = self.output.as_mut() else { return };
+        if let Err(e) = writeln!(output, "{:02x}", byte).and_then(|_| output.flush()) {
+            log::warn!("Couldn't write the RNG log, no longer logging: {}", e);
+            self.output = None;
+        }
+    }
+
+}
@@ -0,0 +1,116 @@
+use std::{
+    fs,
+    io::{BufRead, Write},
+    path::{Path, PathBuf}
+};
+
+use crate::chip8::MAX_XO_CHIP_ROM_SIZE;
+
+const ROM_EXTENSIONS: [&str; 3] = ["ch8", "c8", "rom"];
+
+//A sorted list of the loadable ROMs in a directory with a cursor
+//that wraps around at either end
+pub struct RomDir {
+    roms: Vec<PathBuf>,
+    current: usize
+}
+
+impl RomDir {
+
+    pub fn open(dir: &Path) -> Result<RomDir, String> {
+        let entries = fs::read_dir(dir)
+            .map_err(|e| format!("Can't read ROM directory {}: {}", dir.display(), e))?;
+
+        let mut roms: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| is_rom(path))
+            .collect();
+        roms.sort();
+
+        if roms.is_empty() {
+            return Err(format!("No ROMs found in {}", dir.display()));
+        }
+
+        Ok(RomDir {
+            roms,
+            current: 0
+        })
+    }
+
+    pub fn roms(&self) -> &[PathBuf] {
+        &self.roms
+    }
+
+    //Moves the cursor, index is clamped to the last ROM
+    pub fn select(&mut self, index: usize) -> &Path {
+        self.current = index.min(self.roms.len() - 1);
+        self.current()
+    }
+
+    pub fn current(&self) -> &Path {
+        &self.roms[self.current]
+    }
+
+    pub fn next(&mut self) -> &Path {
+        self.current = (self.current + 1) % self.roms.len();
+        self.current()
+    }
+
+    pub fn previous(&mut self) -> &Path {
+        self.current = (self.current + self.roms.len() - 1) % self.roms.len();
+        self.current()
+    }
+
+}
+
+//Lists roms as a numbered menu and reads a choice from input, asking
+//again until it names one of them. None when input ends or the user
+//types q.
+pub fn pick(roms: &[PathBuf], input: &mut impl BufRead, output: &mut impl Write) -> Option<usize> {
+    for (number, path) in roms.iter().enumerate() {
+        let _ = writeln!(output, "{:3}) {}", number + 1, rom_name(path));
+    }
+
+    loop {
+        let _ = write!(output, "Pick a ROM (1-{}, q to quit): ", roms.len());
+        let _ = output.flush();
+
+        let mut line = String::new();
+        if input.read_line(&mut line).unwrap_or(0) == 0 {
+            return None;
+        }
+        let line = line.trim();
+        if line == "q" {
+            return None;
+        }
+
+        match line.parse::<usize>() {
+            Ok(number) if (1..=roms.len()).contains(&number) => return Some(number - 1),
+            _ => { let _ = writeln!(output, "No ROM numbered {}", line); }
+        }
+    }
+}
+
+//A ROM is a non-empty file with a known extension that fits in memory
+pub fn is_rom(path: &Path) -> bool {
+    let extension_ok = path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| ROM_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+        .unwrap_or(false);
+
+    let size_ok = fs::metadata(path)
+        .map(|meta| meta.is_file() && meta.len() > 0 && meta.len() <= MAX_XO_CHIP_ROM_SIZE as u64)
+        .unwrap_or(false);
+
+    extension_ok && size_ok
+}
+
+//Stands in for the ROM's name in files written about a --blank machine
+pub const BLANK_NAME: &str = "blank";
+
+pub fn rom_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
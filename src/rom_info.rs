@@ -0,0 +1,123 @@
+use std::fmt;
+
+use crate::chip8::MAX_ROM_SIZE;
+
+//What --info prints about a ROM. Extension opcodes are found by looking
+//at every even offset, so sprite data can produce the odd false match;
+//the platform is only a guess from what turns up.
+pub struct RomInfo {
+    pub size: usize,
+    pub sha1: String,
+    //Opcode pattern -> addresses it was seen at
+    pub schip: Vec<(&'static str, Vec<u16>)>,
+    pub xo_chip: Vec<(&'static str, Vec<u16>)>
+}
+
+const LOAD_ADDRESS: u16 = 0x200;
+//Addresses listed per opcode before the rest are just counted
+const SHOWN_ADDRESSES: usize = 4;
+
+//SUPER-CHIP opcodes, all unused by CHIP-8 (DXY0 draws nothing there)
+pub fn schip_pattern(opcode: u16) -> Option<&'static str> {
+    match opcode {
+        0x00C0..=0x00CF => Some("00CN"),
+        0x00FB => Some("00FB"),
+        0x00FC => Some("00FC"),
+        0x00FD => Some("00FD"),
+        0x00FE => Some("00FE"),
+        0x00FF => Some("00FF"),
+        _ if opcode & 0xF00F == 0xD000 => Some("DXY0"),
+        _ if opcode & 0xF0FF == 0xF030 => Some("FX30"),
+        _ if opcode & 0xF0FF == 0xF075 => Some("FX75"),
+        _ if opcode & 0xF0FF == 0xF085 => Some("FX85"),
+        _ => None
+    }
+}
+
+pub fn xo_chip_pattern(opcode: u16) -> Option<&'static str> {
+    match opcode {
+        0x00D0..=0x00DF => Some("00DN"),
+        0xF000 => Some("F000"),
+        0xF002 => Some("F002"),
+        _ if opcode & 0xF00F == 0x5002 => Some("5XY2"),
+        _ if opcode & 0xF00F == 0x5003 => Some("5XY3"),
+        _ if opcode & 0xF0FF == 0xF001 => Some("FN01"),
+        _ if opcode & 0xF0FF == 0xF03A => Some("FX3A"),
+        _ => None
+    }
+}
+
+impl RomInfo {
+
+    pub fn scan(rom: &[u8]) -> RomInfo {
+        let mut info = RomInfo {
+            size: rom.len(),
+            sha1: sha1_smol::Sha1::from(rom).digest().to_string(),
+            schip: Vec::new(),
+            xo_chip: Vec::new()
+        };
+
+        for (i, pair) in rom.chunks_exact(2).enumerate() {
+            let opcode = u16::from_be_bytes([pair[0], pair[1]]);
+            let address = LOAD_ADDRESS + 2 * i as u16;
+
+            if let Some(pattern) = schip_pattern(opcode) {
+                note(&mut info.schip, pattern, address);
+            }
+            if let Some(pattern) = xo_chip_pattern(opcode) {
+                note(&mut info.xo_chip, pattern, address);
+            }
+        }
+
+        info
+    }
+
+    //Only XO-CHIP has room for ROMs that don't fit the classic 4K
+    pub fn platform_guess(&self) -> &'static str {
+        if !self.xo_chip.is_empty() || self.size > MAX_ROM_SIZE {
+            "xo-chip"
+        } else if !self.schip.is_empty() {
+            "schip"
+        } else {
+            "chip-8"
+        }
+    }
+
+}
+
+fn note(found: &mut Vec<(&'static str, Vec<u16>)>, pattern: &'static str, address: u16) {
+    match found.iter_mut().find(|(seen, _)| *seen == pattern) {
+        Some((_, addresses)) => addresses.push(address),
+        None => found.push((pattern, vec![address]))
+    }
+}
+
+fn write_opcodes(f: &mut fmt::Formatter, name: &str, found: &[(&'static str, Vec<u16>)]) -> fmt::Result {
+    if found.is_empty() {
+        return writeln!(f, "{:10} none", name);
+    }
+
+    writeln!(f, "{}", name)?;
+    for (pattern, addresses) in found {
+        let shown: Vec<String> = addresses.iter().take(SHOWN_ADDRESSES).map(|address| format!("{:#05x}", address)).collect();
+        let more = match addresses.len().saturating_sub(SHOWN_ADDRESSES) {
+            0 => String::new(),
+            more => format!(" and {} more", more)
+        };
+        writeln!(f, "  {}  {}x at {}{}", pattern, addresses.len(), shown.join(", "), more)?;
+    }
+
+    Ok(())
+}
+
+impl fmt::Display for RomInfo {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Size:      {} bytes", self.size)?;
+        writeln!(f, "SHA-1:     {}", self.sha1)?;
+        write_opcodes(f, "SUPER-CHIP", &self.schip)?;
+        write_opcodes(f, "XO-CHIP", &self.xo_chip)?;
+        writeln!(f, "Platform:  {} (guessed)", self.platform_guess())
+    }
+
+}
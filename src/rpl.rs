@@ -0,0 +1,186 @@
+use std::{
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant}
+};
+
+use crate::{chip8::Chip8, config};
+
+//FX75/FX85's RPL user flags are where SUPER-CHIP games keep high scores
+//and settings, so they are saved per ROM: rpl/SHA1.flags in the data
+//directory, the 8 flag bytes as they are. Changes are written once
+//they have settled for WRITE_DELAY, and whatever is left when the
+//window closes. --no-flag-persistence leaves the flags in memory only.
+pub const WRITE_DELAY: Duration = Duration::from_secs(1);
+
+pub struct FlagStore {
+    dir: PathBuf,
+    //The ROM the flags below belong to
+    rom_hash: Option<[u8; 20]>,
+    saved: [u8; 8],
+    current: [u8; 8],
+    //When current last changed, while it isn't saved
+    changed: Option<Instant>
+}
+
+impl FlagStore {
+
+    pub fn create(dir: PathBuf) -> FlagStore {
+        FlagStore {
+            dir,
+            rom_hash: None,
+            saved: [0; 8],
+            current: [0; 8],
+            changed: None
+        }
+    }
+
+    //Called before every frame. A different ROM than last time gets its
+    //own flags, after the last ROM's are saved.
+    pub fn update(&mut self, chip8: &mut Chip8) {
+        if chip8.rom_hash != self.rom_hash {
+            self.flush();
+            self.rom_hash = chip8.rom_hash;
+            self.saved = self.rom_hash.map(|hash| self.load(&hash)).unwrap_or_default();
+            self.current = self.saved;
+            self.changed = None;
+            chip8.rpl_flags = self.saved;
+            return;
+        }
+
+        if chip8.rpl_flags != self.current {
+            self.current = chip8.rpl_flags;
+            self.changed = Some(Instant::now());
+        }
+        if self.changed.is_some_and(|changed| changed.elapsed() >= WRITE_DELAY) {
+            self.flush();
+        }
+    }
+
+    //Writes the flags if they changed since they were last saved. Failing
+    //only warns, the game carries on with them in memory.
+    pub fn flush(&mut self) {
+        self.changed = None;
+        let Some(hash) = self.rom_hash.filter(|_| self.current != self.saved) else { return };
+
+        let file = self.file(&hash);
+        let written = fs::create_dir_all(&self.dir).and_then(|_| fs::write(&file, self.current));
+        match written {
+            Ok(()) => self.saved = self.current,
+            Err(e) => log::warn!("Couldn't save the RPL flags to {}: {}", file.display(), e)
+        }
+    }
+
+    //A missing file is all zeros, like a fresh calculator
+    fn load(&self, hash: &[u8; 20]) -> [u8; 8] {
+        let file = self.file(hash);
+        match fs::read(&file) {
+            Ok(bytes) => <[u8; 8]>::try_from(bytes.as_slice()).unwrap_or_else(|_| {
+                log::warn!("Ignoring {}, it isn't 8 bytes", file.display());
+                [0; 8]
+            }),
+            Err(_) => [0; 8]
+        }
+    }
+
+    fn file(&self, hash: &[u8; 20]) -> PathBuf {
+        let name: String = hash.iter().map(|byte| format!("{:02x}", byte)).collect();
+        self.dir.join(format!("{}.flags", name))
+    }
+
+}
+
+//rpl/ in the data directory, see config::data_dir
+pub fn default_dir() -> Option<PathBuf> {
+    Some(config::data_dir()?.join("rpl"))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::env;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("chip8-rpl-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn machine(rom: &[u8]) -> Chip8 {
+        let mut chip8 = Chip8::create();
+        chip8.load_bytes(rom).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn flags_come_back_in_a_fresh_emulator() {
+        let dir = temp_dir("restart");
+        let mut store = FlagStore::create(dir.clone());
+        let mut chip8 = machine(&[0x12, 0x00]);
+        store.update(&mut chip8);
+        assert_eq!(chip8.rpl_flags, [0; 8]);
+
+        chip8.rpl_flags = [1, 2, 3, 4, 5, 6, 7, 8];
+        store.update(&mut chip8);
+        store.flush();
+
+        let mut store = FlagStore::create(dir.clone());
+        let mut chip8 = machine(&[0x12, 0x00]);
+        store.update(&mut chip8);
+        assert_eq!(chip8.rpl_flags, [1, 2, 3, 4, 5, 6, 7, 8]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn writes_once_the_flags_settle() {
+        let dir = temp_dir("settle");
+        let mut store = FlagStore::create(dir.clone());
+        let mut chip8 = machine(&[0x12, 0x00]);
+        store.update(&mut chip8);
+        let file = store.file(&chip8.rom_hash.unwrap());
+
+        chip8.rpl_flags[0] = 42;
+        store.update(&mut chip8);
+        assert!(!file.exists());
+
+        //Unchanged for WRITE_DELAY
+        store.changed = store.changed.map(|changed| changed - WRITE_DELAY);
+        store.update(&mut chip8);
+        assert_eq!(fs::read(&file).unwrap(), [42, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(store.changed, None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn each_rom_has_its_own_flags() {
+        let dir = temp_dir("roms");
+        let mut store = FlagStore::create(dir.clone());
+        let mut first = machine(&[0x12, 0x00]);
+        store.update(&mut first);
+        first.rpl_flags[7] = 9;
+        store.update(&mut first);
+
+        //Changing ROM saves the last one's flags and starts from zeros
+        let mut second = machine(&[0x60, 0x01, 0x12, 0x00]);
+        store.update(&mut second);
+        assert_eq!(second.rpl_flags, [0; 8]);
+        assert_eq!(fs::read(store.file(&first.rom_hash.unwrap())).unwrap(), [0, 0, 0, 0, 0, 0, 0, 9]);
+        assert!(!store.file(&second.rom_hash.unwrap()).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn ignores_a_file_of_the_wrong_size() {
+        let dir = temp_dir("size");
+        let mut chip8 = machine(&[0x12, 0x00]);
+        let mut store = FlagStore::create(dir.clone());
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(store.file(&chip8.rom_hash.unwrap()), [1, 2, 3]).unwrap();
+
+        store.update(&mut chip8);
+        assert_eq!(chip8.rpl_flags, [0; 8]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+}
@@ -0,0 +1,69 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use toml_edit::{table, value, Document};
+
+use crate::config;
+
+//The window as it was last left, kept in settings.toml next to the config
+//file. Read at startup and rewritten when the window closes. Unlike the
+//config file it isn't meant to be edited: --scale on the command line or
+//in the config file wins for that run and isn't saved.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Settings {
+    //Window pixels per CHIP-8 pixel
+    pub scale: Option<u32>
+}
+
+impl Settings {
+
+    //Keys it doesn't know are skipped, a bad value is an error
+    pub fn parse(source: &str) -> Result<Settings, String> {
+        let document: Document = source.parse().map_err(|e: toml_edit::TomlError| e.to_string())?;
+
+        let scale = match document.get("window").and_then(|window| window.get("scale")) {
+            None => None,
+            Some(item) => match item.as_integer().and_then(|scale| u32::try_from(scale).ok()).filter(|scale| *scale >= 1) {
+                Some(scale) => Some(scale),
+                None => return Err("window.scale: expected a whole number of 1 or more".to_string())
+            }
+        };
+
+        Ok(Settings { scale })
+    }
+
+    pub fn to_toml(&self) -> String {
+        let mut document = Document::new();
+        document["window"] = table();
+        if let Some(scale) = self.scale {
+            document["window"]["scale"] = value(scale as i64);
+        }
+
+        format!("# Written by chip8 when the window closes, options given to it win\n{}", document)
+    }
+
+    //A missing file is the defaults. So is a broken one, with a warning,
+    //since it gets rewritten on exit anyway.
+    pub fn load(file: &Path) -> Settings {
+        let source = match fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(_) => return Settings::default()
+        };
+        Settings::parse(&source).unwrap_or_else(|e| {
+            log::warn!("Ignoring the saved settings in {}: {}", file.display(), e);
+            Settings::default()
+        })
+    }
+
+    pub fn save(&self, file: &Path) -> Result<(), String> {
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("Couldn't create {}: {}", dir.display(), e))?;
+        }
+        fs::write(file, self.to_toml()).map_err(|e| format!("Couldn't write {}: {}", file.display(), e))
+    }
+
+}
+
+//settings.toml next to the config file, see config::config_dir
+pub fn default_path() -> Option<PathBuf> {
+    Some(config::config_dir()?.join("settings.toml"))
+}
@@ -0,0 +1,132 @@
+use std::fmt;
+
+use crate::chip8::{BIG_FONTSET_SIZE, BIG_FONTSET_START_ADDRESS, FONTSET_SIZE, FONTSET_START_ADDRESS};
+
+pub const DEFAULT_SPRITE_HEIGHT: usize = 15;
+
+//The bytes DXYN would draw from a given address
+pub struct SpritePreview {
+    pub address: u16,
+    pub rows: Vec<u8>,
+    //Fewer rows than asked for because memory ran out
+    pub clamped: bool
+}
+
+impl SpritePreview {
+
+    pub fn create(memory: &[u8], address: u16, height: usize) -> SpritePreview {
+        let start = (address as usize).min(memory.len());
+        let end = (start + height).min(memory.len());
+
+        SpritePreview {
+            address,
+            rows: memory[start..end].to_vec(),
+            clamped: end - start < height
+        }
+    }
+
+    //Glyph number and row within it when address is inside the fontset
+    pub fn font_glyph(&self) -> Option<(u16, u16)> {
+        let offset = self.address.checked_sub(FONTSET_START_ADDRESS)?;
+        (offset < FONTSET_SIZE).then_some((offset / 5, offset % 5))
+    }
+
+    //The same for the 8x10 digits FX30 points at
+    pub fn big_font_glyph(&self) -> Option<(u16, u16)> {
+        let offset = self.address.checked_sub(BIG_FONTSET_START_ADDRESS)?;
+        (offset < BIG_FONTSET_SIZE).then_some((offset / 10, offset % 10))
+    }
+
+}
+
+//One row of lit/unlit pixels per sprite byte, most significant bit first
+pub fn rasterize(rows: &[u8]) -> Vec<[bool; 8]> {
+    rows.iter()
+        .map(|byte| std::array::from_fn(|col| byte & (0x80 >> col) != 0))
+        .collect()
+}
+
+impl fmt::Display for SpritePreview {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Sprite at {:#05x}, {} row(s)", self.address, self.rows.len())?;
+        match self.font_glyph() {
+            Some((glyph, 0)) => write!(f, " (font glyph {:X})", glyph)?,
+            Some((glyph, row)) => write!(f, " (inside font glyph {:X}, row {})", glyph, row)?,
+            None => ()
+        }
+        match self.big_font_glyph() {
+            Some((glyph, 0)) => write!(f, " (big font glyph {})", glyph)?,
+            Some((glyph, row)) => write!(f, " (inside big font glyph {}, row {})", glyph, row)?,
+            None => ()
+        }
+        if self.clamped {
+            write!(f, " (clamped at end of memory)")?;
+        }
+        writeln!(f)?;
+
+        for (byte, pixels) in self.rows.iter().zip(rasterize(&self.rows)) {
+            let line: String = pixels.iter().map(|lit| if *lit { "##" } else { ".." }).collect();
+            writeln!(f, "  {:02X}  {}", byte, line)?;
+        }
+
+        Ok(())
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::chip8::Chip8;
+
+    #[test]
+    fn rasterizes_the_zero_glyph() {
+        let chip8 = Chip8::create();
+        let preview = SpritePreview::create(&chip8.memory, FONTSET_START_ADDRESS, 5);
+
+        let pixels: Vec<String> = rasterize(&preview.rows).iter()
+            .map(|row| row.iter().map(|lit| if *lit { '#' } else { '.' }).collect())
+            .collect();
+        assert_eq!(pixels, ["####....", "#..#....", "#..#....", "#..#....", "####...."]);
+        assert_eq!(preview.to_string(), "\
+Sprite at 0x050, 5 row(s) (font glyph 0)
+  F0  ########........
+  90  ##....##........
+  90  ##....##........
+  90  ##....##........
+  F0  ########........
+");
+    }
+
+    #[test]
+    fn notes_the_font_and_the_end_of_memory() {
+        let chip8 = Chip8::create();
+
+        let inside = SpritePreview::create(&chip8.memory, FONTSET_START_ADDRESS + 7, 1);
+        assert_eq!(inside.font_glyph(), Some((1, 2)));
+        assert!(inside.to_string().starts_with("Sprite at 0x057, 1 row(s) (inside font glyph 1, row 2)\n"));
+        assert_eq!(SpritePreview::create(&chip8.memory, FONTSET_START_ADDRESS + FONTSET_SIZE, 1).font_glyph(), None);
+
+        let end = SpritePreview::create(&chip8.memory, 0xFFD, DEFAULT_SPRITE_HEIGHT);
+        assert_eq!((end.rows.len(), end.clamped), (3, true));
+        assert!(end.to_string().starts_with("Sprite at 0xffd, 3 row(s) (clamped at end of memory)\n"));
+        assert!(SpritePreview::create(&chip8.memory, 0xFFFF, 1).rows.is_empty());
+    }
+
+    #[test]
+    fn names_big_font_glyphs() {
+        let chip8 = Chip8::create();
+
+        let eight = SpritePreview::create(&chip8.memory, BIG_FONTSET_START_ADDRESS + 80, 10);
+        assert_eq!(eight.big_font_glyph(), Some((8, 0)));
+        assert_eq!(eight.font_glyph(), None);
+        assert!(eight.to_string().starts_with("Sprite at 0x0f0, 10 row(s) (big font glyph 8)\n  3C  ....########....\n"));
+
+        let inside = SpritePreview::create(&chip8.memory, BIG_FONTSET_START_ADDRESS + 13, 1);
+        assert!(inside.to_string().starts_with("Sprite at 0x0ad, 1 row(s) (inside big font glyph 1, row 3)\n"));
+        assert_eq!(SpritePreview::create(&chip8.memory, BIG_FONTSET_START_ADDRESS + BIG_FONTSET_SIZE, 1).big_font_glyph(), None);
+    }
+
+}
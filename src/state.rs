@@ -0,0 +1,483 @@
+use std::{error::Error, fmt, fs};
+
+use crate::chip8::{Chip8, HIRES_HEIGHT, HIRES_WIDTH, LORES_HEIGHT, LORES_WIDTH, MEMORY_SIZE, XO_CHIP_MEMORY_SIZE};
+
+const STATE_MAGIC: &[u8; 4] = b"C8ST";
+const STATE_VERSION: u8 = 4;
+//Stands for no ROM hash, e.g. a machine built by hand in the REPL
+const NO_ROM_HASH: [u8; 20] = [0; 20];
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum StateError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    SizeMismatch { field: &'static str, expected: usize, found: usize }
+}
+
+//Snapshot layout (all integers big-endian):
+//  "C8ST" version:u8
+//  V0-VF:16  I:u16  PC:u16  SP:u16  stack:16*u16  DT:u8  ST:u8  keypad:16
+//  memory_len:u32 memory  video_len:u32 video
+//  seed:u64  rng_draws:u64                           (version 2 on)
+//  rom_sha1:20, zeros without a ROM                   (version 3 on)
+//  plane_mask:u8  plane2_len:u32 plane2               (version 4 on)
+//The memory length gives the memory size, 4 KB or XO-CHIP's 64 KB, and
+//the video length the resolution: 64*32 lores or 128*64 hires.
+//Older snapshots still load, keeping the machine's random numbers and
+//ROM hash where they don't have one and with XO-CHIP's second plane
+//blank and unselected.
+impl Chip8 {
+
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.memory.len() + self.video.len() + 128);
+
+        out.extend_from_slice(STATE_MAGIC);
+        out.push(STATE_VERSION);
+        out.extend_from_slice(&self.registers);
+        out.extend_from_slice(&self.index_register.to_be_bytes());
+        out.extend_from_slice(&self.program_counter.to_be_bytes());
+        out.extend_from_slice(&self.stack_pointer.to_be_bytes());
+        for address in &self.stack {
+            out.extend_from_slice(&address.to_be_bytes());
+        }
+        out.push(self.delay_timer);
+        out.push(self.sound_timer);
+        out.extend(self.keypad.iter().map(|pressed| *pressed as u8));
+        out.extend_from_slice(&(self.memory.len() as u32).to_be_bytes());
+        out.extend_from_slice(&self.memory);
+        out.extend_from_slice(&(self.video.len() as u32).to_be_bytes());
+        out.extend_from_slice(&self.video);
+        out.extend_from_slice(&self.seed.to_be_bytes());
+        out.extend_from_slice(&self.rng_draws.to_be_bytes());
+        out.extend_from_slice(&self.rom_hash.unwrap_or(NO_ROM_HASH));
+        out.push(self.plane_mask);
+        out.extend_from_slice(&(self.plane2.len() as u32).to_be_bytes());
+        out.extend_from_slice(&self.plane2);
+
+        out
+    }
+
+    //Replaces the machine state, keeping hooks and quirks. The memory
+    //takes the snapshot's size. Nothing is changed when the snapshot is
+    //rejected.
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), StateError> {
+        let mut reader = Reader { bytes };
+
+        if reader.take(4)? != STATE_MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = reader.u8()?;
+        if version == 0 || version > STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        let mut registers = [0u8; 16];
+        registers.copy_from_slice(reader.take(16)?);
+        let index_register = reader.u16()?;
+        let program_counter = reader.u16()?;
+        let stack_pointer = reader.u16()?;
+        let mut stack = [0u16; 16];
+        for address in stack.iter_mut() {
+            *address = reader.u16()?;
+        }
+        let delay_timer = reader.u8()?;
+        let sound_timer = reader.u8()?;
+        let mut keypad = [false; 16];
+        for (key, pressed) in keypad.iter_mut().zip(reader.take(16)?) {
+            *key = *pressed != 0;
+        }
+        let memory = reader.sized_any("memory", &[MEMORY_SIZE, XO_CHIP_MEMORY_SIZE])?;
+        let video = reader.sized_any("video", &[LORES_WIDTH * LORES_HEIGHT, HIRES_WIDTH * HIRES_HEIGHT])?;
+        let rng = if version >= 2 { Some((reader.u64()?, reader.u64()?)) } else { None };
+        let rom_hash = if version >= 3 { Some(reader.take(20)?) } else { None };
+        let planes = if version >= 4 { Some((reader.u8()?, reader.sized("plane2", video.len())?)) } else { None };
+
+        if stack_pointer as usize > stack.len() {
+            return Err(StateError::SizeMismatch { field: "stack pointer", expected: stack.len(), found: stack_pointer as usize });
+        }
+
+        self.registers = registers;
+        self.index_register = index_register;
+        self.program_counter = program_counter;
+        self.stack_pointer = stack_pointer;
+        self.stack = stack;
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+        self.keypad = keypad;
+        self.memory = memory.into();
+        self.video = video.to_vec();
+        self.video_dirty = true;
+        self.hires = video.len() == HIRES_WIDTH * HIRES_HEIGHT;
+        let (plane_mask, plane2) = planes.unwrap_or((1, &[]));
+        self.plane_mask = plane_mask & 0x3;
+        self.plane2 = if plane2.is_empty() { vec![0; video.len()] } else { plane2.to_vec() };
+        self.rebuild_call_frames();
+        if let Some((seed, draws)) = rng {
+            self.restore_rng(seed, draws);
+        }
+        if let Some(hash) = rom_hash {
+            let hash: [u8; 20] = hash.try_into().unwrap_or(NO_ROM_HASH);
+            self.rom_hash = (hash != NO_ROM_HASH).then_some(hash);
+        }
+
+        Ok(())
+    }
+
+    pub fn from_state(bytes: &[u8]) -> Result<Chip8, StateError> {
+        let mut chip8 = Chip8::create();
+        chip8.load_state(bytes)?;
+        Ok(chip8)
+    }
+
+}
+
+//--load-state: restores a snapshot file over a machine with its ROM
+//loaded, refusing one saved from a different ROM
+pub fn load_state_file(chip8: &mut Chip8, path: &str) -> Result<(), String> {
+    let bytes = fs::read(path).map_err(|e| format!("Couldn't read {}: {}", path, e))?;
+    let saved = Chip8::from_state(&bytes).map_err(|e| format!("Couldn't load {}: {}", path, e))?;
+
+    match (saved.rom_hash, chip8.rom_hash) {
+        (Some(saved), Some(loaded)) if saved != loaded => {
+            return Err(format!("{} was saved from a different ROM (SHA-1 {}, the loaded one is {})", path, hex(&saved), hex(&loaded)));
+        },
+        (None, _) => log::warn!("{} doesn't record its ROM, so it can't be checked against the loaded one", path),
+        _ => ()
+    }
+
+    chip8.load_state(&bytes).map_err(|e| format!("Couldn't load {}: {}", path, e))
+}
+
+//--save-state-on-exit
+pub fn save_state_file(chip8: &Chip8, path: &str) -> Result<(), String> {
+    fs::write(path, chip8.save_state()).map_err(|e| format!("Couldn't write {}: {}", path, e))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8]
+}
+
+impl<'a> Reader<'a> {
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], StateError> {
+        if self.bytes.len() < count {
+            return Err(StateError::Truncated);
+        }
+
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, StateError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, StateError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, StateError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    //A length-prefixed block that has to match the machine's size
+    fn sized(&mut self, field: &'static str, expected: usize) -> Result<&'a [u8], StateError> {
+        self.sized_any(field, &[expected])
+    }
+
+    //Same, for blocks that may come in a few sizes
+    fn sized_any(&mut self, field: &'static str, allowed: &[usize]) -> Result<&'a [u8], StateError> {
+        let found = self.u32()? as usize;
+        if !allowed.contains(&found) {
+            return Err(StateError::SizeMismatch { field, expected: allowed[0], found });
+        }
+
+        self.take(found)
+    }
+
+}
+
+impl fmt::Display for StateError {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a CHIP-8 save state"),
+            StateError::UnsupportedVersion(version) => write!(f, "unsupported save state version {}", version),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::SizeMismatch { field, expected, found } => {
+                write!(f, "save state {} size is {}, expected {}", field, found, expected)
+            }
+        }
+    }
+
+}
+
+impl Error for StateError {}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Difference {
+    Register { index: usize, a: u8, b: u8 },
+    IndexRegister { a: u16, b: u16 },
+    ProgramCounter { a: u16, b: u16 },
+    StackPointer { a: u16, b: u16 },
+    Stack { index: usize, a: u16, b: u16 },
+    DelayTimer { a: u8, b: u8 },
+    SoundTimer { a: u8, b: u8 },
+    Key { index: usize, a: bool, b: bool },
+    //Half-open range of differing addresses
+    Memory { start: usize, end: usize },
+    //Bytes of memory on each side, only the ones both have are compared
+    MemorySize { a: usize, b: usize },
+    //(width, height) of each side, pixels aren't compared when they differ
+    Resolution { a: (usize, usize), b: (usize, usize) },
+    Video { differing: usize, lit_a: usize, lit_b: usize }
+}
+
+//Field-by-field comparison of two machines, in a stable order
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct StateDiff {
+    pub differences: Vec<Difference>
+}
+
+impl StateDiff {
+
+    pub fn compare(a: &Chip8, b: &Chip8) -> StateDiff {
+        let mut differences = Vec::new();
+
+        for (index, (ra, rb)) in a.registers.iter().zip(b.registers.iter()).enumerate() {
+            if ra != rb {
+                differences.push(Difference::Register { index, a: *ra, b: *rb });
+            }
+        }
+        if a.index_register != b.index_register {
+            differences.push(Difference::IndexRegister { a: a.index_register, b: b.index_register });
+        }
+        if a.program_counter != b.program_counter {
+            differences.push(Difference::ProgramCounter { a: a.program_counter, b: b.program_counter });
+        }
+        if a.stack_pointer != b.stack_pointer {
+            differences.push(Difference::StackPointer { a: a.stack_pointer, b: b.stack_pointer });
+        }
+        for (index, (sa, sb)) in a.stack.iter().zip(b.stack.iter()).enumerate() {
+            if sa != sb {
+                differences.push(Difference::Stack { index, a: *sa, b: *sb });
+            }
+        }
+        if a.delay_timer != b.delay_timer {
+            differences.push(Difference::DelayTimer { a: a.delay_timer, b: b.delay_timer });
+        }
+        if a.sound_timer != b.sound_timer {
+            differences.push(Difference::SoundTimer { a: a.sound_timer, b: b.sound_timer });
+        }
+        for (index, (ka, kb)) in a.keypad.iter().zip(b.keypad.iter()).enumerate() {
+            if ka != kb {
+                differences.push(Difference::Key { index, a: *ka, b: *kb });
+            }
+        }
+
+        if a.memory.len() != b.memory.len() {
+            differences.push(Difference::MemorySize { a: a.memory.len(), b: b.memory.len() });
+        }
+        //One past the end closes a range that runs to the last byte
+        let shared = a.memory.len().min(b.memory.len());
+        let mut range_start: Option<usize> = None;
+        for address in 0..=shared {
+            let differs = address < shared && a.memory[address] != b.memory[address];
+            match (differs, range_start) {
+                (true, None) => range_start = Some(address),
+                (false, Some(start)) => {
+                    differences.push(Difference::Memory { start, end: address });
+                    range_start = None;
+                },
+                _ => ()
+            }
+        }
+
+        //Both planes, as one picture
+        let pixels = |chip8: &Chip8| chip8.video.iter().chain(&chip8.plane2).copied().collect::<Vec<u8>>();
+        let (video_a, video_b) = (pixels(a), pixels(b));
+        let differing = video_a.iter().zip(video_b.iter()).filter(|(pa, pb)| pa != pb).count();
+        if a.hires != b.hires {
+            differences.push(Difference::Resolution {
+                a: (a.video_width(), a.video_height()),
+                b: (b.video_width(), b.video_height())
+            });
+        } else if differing > 0 {
+            differences.push(Difference::Video {
+                differing,
+                lit_a: video_a.iter().filter(|pixel| **pixel != 0).count(),
+                lit_b: video_b.iter().filter(|pixel| **pixel != 0).count()
+            });
+        }
+
+        StateDiff { differences }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.differences.is_empty()
+    }
+
+}
+
+impl fmt::Display for Difference {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Difference::Register { index, a, b } => write!(f, "V{:X}: {:#04x} != {:#04x}", index, a, b),
+            Difference::IndexRegister { a, b } => write!(f, "I: {:#06x} != {:#06x}", a, b),
+            Difference::ProgramCounter { a, b } => write!(f, "PC: {:#06x} != {:#06x}", a, b),
+            Difference::StackPointer { a, b } => write!(f, "SP: {} != {}", a, b),
+            Difference::Stack { index, a, b } => write!(f, "stack[{}]: {:#06x} != {:#06x}", index, a, b),
+            Difference::DelayTimer { a, b } => write!(f, "DT: {} != {}", a, b),
+            Difference::SoundTimer { a, b } => write!(f, "ST: {} != {}", a, b),
+            Difference::Key { index, a, b } => {
+                let state = |pressed: &bool| if *pressed { "down" } else { "up" };
+                write!(f, "key {:X}: {} != {}", index, state(a), state(b))
+            },
+            Difference::Memory { start, end } if end - start == 1 => write!(f, "memory {:#06x}: 1 byte differs", start),
+            Difference::Memory { start, end } => {
+                write!(f, "memory {:#06x}-{:#06x}: {} bytes differ", start, end - 1, end - start)
+            },
+            Difference::MemorySize { a, b } => write!(f, "memory size: {} != {} bytes", a, b),
+            Difference::Resolution { a, b } => write!(f, "resolution: {}x{} != {}x{}", a.0, a.1, b.0, b.1),
+            Difference::Video { differing, lit_a, lit_b } => {
+                write!(f, "video: {} pixel(s) differ ({} lit != {} lit)", differing, lit_a, lit_b)
+            }
+        }
+    }
+
+}
+
+impl fmt::Display for StateDiff {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.differences.is_empty() {
+            return writeln!(f, "States are identical");
+        }
+
+        for difference in &self.differences {
+            writeln!(f, "{}", difference)?;
+        }
+
+        Ok(())
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::env;
+
+    use super::*;
+    use crate::headless;
+
+    //Random dots, 3 frames apart by DT, cleared while key 5 is down
+    const BUSY_ROM: [u8; 26] = [
+        0xC0, 0x3F, 0xC1, 0x1F, 0xA0, 0x50, 0xD0, 0x15, 0x62, 0x03, 0xF2, 0x15, 0xF2, 0x07, 0x32, 0x00,
+        0x12, 0x0C, 0x63, 0x05, 0xE3, 0xA1, 0x00, 0xE0, 0x12, 0x00
+    ];
+
+    fn temp_file(name: &str) -> String {
+        env::temp_dir().join(format!("chip8-{}-{}.state", name, std::process::id())).to_string_lossy().into_owned()
+    }
+
+    fn busy_machine() -> Chip8 {
+        let mut chip8 = Chip8::create();
+        chip8.load_bytes(&BUSY_ROM).unwrap();
+        chip8.set_seed(99);
+        chip8
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut chip8 = Chip8::create();
+        chip8.load_bytes(&[0x60, 0x2A, 0x22, 0x06, 0x12, 0x04, 0xA3, 0x00, 0xF0, 0x33, 0x00, 0xEE]).unwrap();
+        for _ in 0..5 {
+            chip8.run().unwrap();
+        }
+
+        let loaded = Chip8::from_state(&chip8.save_state()).unwrap();
+        assert!(StateDiff::compare(&chip8, &loaded).is_empty());
+        assert_eq!(loaded.save_state(), chip8.save_state());
+    }
+
+    #[test]
+    fn truncated_state() {
+        let state = Chip8::create().save_state();
+
+        assert_eq!(Chip8::from_state(&state[..state.len() - 1]).err(), Some(StateError::Truncated));
+        assert_eq!(Chip8::from_state(b"C8SX").err(), Some(StateError::BadMagic));
+    }
+
+    #[test]
+    fn diff_of_three_changes() {
+        let a = Chip8::create();
+        let mut b = Chip8::from_state(&a.save_state()).unwrap();
+        b.registers[3] = 0x42;
+        b.memory[0x300..0x304].copy_from_slice(&[1, 2, 3, 4]);
+        b.video[0] = 0xFF;
+
+        let diff = StateDiff::compare(&a, &Chip8::from_state(&b.save_state()).unwrap());
+        assert_eq!(diff.to_string(), "\
+V3: 0x00 != 0x42
+memory 0x0300-0x0303: 4 bytes differ
+video: 1 pixel(s) differ (0 lit != 1 lit)
+");
+        assert_eq!(StateDiff::compare(&a, &a).to_string(), "States are identical\n");
+    }
+
+
+    #[test]
+    fn resumed_run_matches_a_continuous_one() {
+        //Key 5 goes down before the save and comes up after it
+        let script = headless::parse_script("@500 press 5\n@600 release 5\n@1900 press 5\n@2100 release 5").unwrap();
+        let mut continuous = busy_machine();
+        headless::run(&mut continuous, 4000, 10, &script);
+
+        let path = temp_file("resume");
+        let mut first = busy_machine();
+        headless::run(&mut first, 2000, 10, &script);
+        save_state_file(&first, &path).unwrap();
+
+        let mut resumed = busy_machine();
+        load_state_file(&mut resumed, &path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert!(resumed.keypad[5]);
+        //The script's cycles count from the restored state
+        let rest = headless::parse_script("@100 release 5").unwrap();
+        headless::run(&mut resumed, 2000, 10, &rest);
+
+        assert!(StateDiff::compare(&continuous, &resumed).is_empty(), "{}", StateDiff::compare(&continuous, &resumed));
+        assert_eq!(resumed.save_state(), continuous.save_state());
+    }
+
+    #[test]
+    fn refuses_another_roms_state() {
+        let path = temp_file("other-rom");
+        save_state_file(&busy_machine(), &path).unwrap();
+
+        let mut other = Chip8::create();
+        other.load_bytes(&[0x12, 0x00]).unwrap();
+        let error = load_state_file(&mut other, &path).unwrap_err();
+        let _ = fs::remove_file(&path);
+        assert!(error.starts_with(&format!("{} was saved from a different ROM (SHA-1 ", path)), "{}", error);
+
+        assert!(load_state_file(&mut other, &path).unwrap_err().starts_with(&format!("Couldn't read {}: ", path)));
+    }
+
+}
@@ -0,0 +1,380 @@
+use std::{collections::BTreeSet, fmt};
+
+use crate::{
+    chip8::Chip8,
+    instruction::{register_range, Instruction},
+    quirks::Quirks
+};
+
+//--strict watches for the moments where the configured quirks actually
+//change what a ROM does, so authors can see which ones their program
+//relies on. Execution still follows the configured quirks. Detected:
+//  - shift-vy: 8XY6/8XYE while Vx and Vy hold different values
+//  - increment-i, increment-i-x: FX55/FX65 followed by an instruction that reads I
+//  - vf-reset: 8XY1/2/3 with a nonzero VF, followed by a VF read
+//  - clip-x, clip-y: DXYN drawing pixels past the right or bottom edge
+//  - dxy0-8x16: DXY0 in lores
+//  - jump-vx: BNNN while V0 and VX hold different values
+//  - key-wrap: EX9E/EXA1 while VX is over 0xF
+//  - half-scroll: 00CN with N over 0, 00FB or 00FC in lores
+//Each quirk is reported once per instruction address.
+pub struct StrictChecker {
+    findings: Vec<QuirkFinding>,
+    reported: BTreeSet<(&'static str, u16)>,
+    //An FX55/FX65 whose effect on I hasn't been read yet
+    pending_index: Option<QuirkFinding>,
+    //An 8XY1/2/3 whose effect on VF hasn't been read yet
+    pending_flag: Option<QuirkFinding>
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct QuirkFinding {
+    pub address: u16,
+    pub opcode: u16,
+    pub quirk: &'static str,
+    //What made it matter, e.g. "V1 != V2"
+    pub situation: String,
+    //What happens with the quirk on and off
+    pub on: String,
+    pub off: String
+}
+
+impl StrictChecker {
+
+    pub fn create() -> StrictChecker {
+        StrictChecker {
+            findings: Vec::new(),
+            reported: BTreeSet::new(),
+            pending_index: None,
+            pending_flag: None
+        }
+    }
+
+    //Called before the instruction at address executes
+    pub fn check(&mut self, chip8: &Chip8, address: u16, instruction: Instruction) {
+        let registers = &chip8.registers;
+        let finding = |quirk, situation: String, on: String, off: String| QuirkFinding {
+            address,
+            opcode: chip8.opcode,
+            quirk,
+            situation,
+            on,
+            off
+        };
+
+        //Earlier FX55/FX65 and 8XY1/2/3 only matter once their result is read
+        if let Some(mut pending) = self.pending_index.take() {
+            if reads_index(instruction) {
+                pending.situation = format!("I is read by {:04X} at {:#05x}", chip8.opcode, address);
+                self.report(pending);
+            } else if !sets_index(instruction) {
+                self.pending_index = Some(pending);
+            }
+        }
+        if let Some(mut pending) = self.pending_flag.take() {
+            if reads_register(instruction, 0xF, &chip8.quirks) {
+                pending.situation = format!("VF is read by {:04X} at {:#05x}", chip8.opcode, address);
+                self.report(pending);
+            } else if !writes_register(instruction, 0xF) {
+                self.pending_flag = Some(pending);
+            }
+        }
+
+        match instruction {
+            Instruction::ShiftRight { x, y } | Instruction::ShiftLeft { x, y } if registers[x] != registers[y] => {
+                let shift = |value: u8| if matches!(instruction, Instruction::ShiftRight { .. }) { value >> 1 } else { value << 1 };
+                let symbol = if matches!(instruction, Instruction::ShiftRight { .. }) { ">>" } else { "<<" };
+                self.report(finding("shift-vy", format!("V{:X} != V{:X}", x, y),
+                    format!("V{:X} = V{:X} {} 1 = {:#04x}", x, y, symbol, shift(registers[y])),
+                    format!("V{:X} = V{:X} {} 1 = {:#04x}", x, x, symbol, shift(registers[x]))));
+            },
+            Instruction::StoreRegisters { x } | Instruction::LoadRegisters { x } => {
+                let index = chip8.index_register;
+                let (quirk, step) = if chip8.quirks.increment_i_x { ("increment-i-x", x as u16) } else { ("increment-i", x as u16 + 1) };
+                self.pending_index = Some(finding(quirk, String::new(),
+                    format!("I = {:#05x} afterwards", index.wrapping_add(step)),
+                    format!("I stays {:#05x}", index)));
+            },
+            Instruction::Or { x, y } | Instruction::And { x, y } | Instruction::Xor { x, y } => {
+                let result = match instruction {
+                    Instruction::Or { .. } => registers[x] | registers[y],
+                    Instruction::And { .. } => registers[x] & registers[y],
+                    _ => registers[x] ^ registers[y]
+                };
+                let flag = if x == 0xF { result } else { registers[0xF] };
+                if flag != 0 {
+                    self.pending_flag = Some(finding("vf-reset", String::new(), "VF = 0".to_string(), format!("VF = {:#04x}", flag)));
+                }
+            },
+            Instruction::Draw { x, y, height } => {
+                let (past_right, past_bottom) = crosses_edge(chip8, registers[x], registers[y], height);
+                if past_right {
+                    self.report(finding("clip-x", "the sprite crosses the right edge".to_string(),
+                        "the part past the edge is cut off".to_string(),
+                        "it wraps around to the left edge".to_string()));
+                }
+                if past_bottom {
+                    self.report(finding("clip-y", "the sprite crosses the bottom edge".to_string(),
+                        "the part past the edge is cut off".to_string(),
+                        "it wraps around to the top edge".to_string()));
+                }
+                if height == 0 && !chip8.hires {
+                    self.report(finding("dxy0-8x16", "a 16-row sprite in lores".to_string(),
+                        "it is 8 pixels wide".to_string(),
+                        "it is 16 pixels wide".to_string()));
+                }
+            },
+            Instruction::JumpV0 { address: target } => {
+                let x = (target >> 8) as usize;
+                if registers[x] != registers[0] {
+                    self.report(finding("jump-vx", format!("V0 != V{:X}", x),
+                        format!("jumps to {:#05x} + V{:X} = {:#05x}", target, x, target + registers[x] as u16),
+                        format!("jumps to {:#05x} + V0 = {:#05x}", target, target + registers[0] as u16)));
+                }
+            },
+            Instruction::ScrollDown { n } if n > 0 && !chip8.hires => {
+                self.report(finding("half-scroll", "scrolling in lores".to_string(),
+                    format!("down {} pixel(s)", n / 2),
+                    format!("down {} pixel(s)", n)));
+            },
+            Instruction::ScrollRight | Instruction::ScrollLeft if !chip8.hires => {
+                let direction = if instruction == Instruction::ScrollRight { "right" } else { "left" };
+                self.report(finding("half-scroll", "scrolling in lores".to_string(),
+                    format!("{} 2 pixels", direction),
+                    format!("{} 4 pixels", direction)));
+            },
+            Instruction::SkipKeyPressed { x } | Instruction::SkipKeyNotPressed { x } if registers[x] > 0xF => {
+                self.report(finding("key-wrap", format!("V{:X} = {:#04x}", x, registers[x]),
+                    format!("key {:X} is checked", registers[x] & 0xF),
+                    "no key is down".to_string()));
+            },
+            _ => ()
+        }
+    }
+
+    fn report(&mut self, finding: QuirkFinding) {
+        if self.reported.insert((finding.quirk, finding.address)) {
+            eprintln!("Strict: {}", finding);
+            self.findings.push(finding);
+        }
+    }
+
+    //Printed on exit
+    pub fn summary(&self) -> String {
+        if self.findings.is_empty() {
+            return "Strict: nothing this run depended on a quirk\n".to_string();
+        }
+
+        let mut summary = format!("Strict: {} quirk-dependent instruction(s) this run\n", self.findings.len());
+        for finding in &self.findings {
+            summary.push_str(&format!("  {}\n", finding));
+        }
+        summary
+    }
+
+}
+
+fn reads_index(instruction: Instruction) -> bool {
+    matches!(instruction,
+        Instruction::Draw { .. } |
+        Instruction::AddIndex { .. } |
+        Instruction::StoreBcd { .. } |
+        Instruction::StoreRegisters { .. } |
+        Instruction::LoadRegisters { .. } |
+        Instruction::SaveRange { .. } |
+        Instruction::LoadRange { .. }
+    )
+}
+
+fn sets_index(instruction: Instruction) -> bool {
+    matches!(instruction, Instruction::LoadIndex { .. } | Instruction::LoadFont { .. } | Instruction::LoadBigFont { .. })
+}
+
+fn reads_register(instruction: Instruction, register: usize, quirks: &Quirks) -> bool {
+    match instruction {
+        Instruction::SkipEqByte { x, .. } |
+        Instruction::SkipNeByte { x, .. } |
+        Instruction::AddByte { x, .. } |
+        Instruction::SkipKeyPressed { x } |
+        Instruction::SkipKeyNotPressed { x } |
+        Instruction::SetDelay { x } |
+        Instruction::SetSound { x } |
+        Instruction::AddIndex { x } |
+        Instruction::LoadFont { x } |
+        Instruction::LoadBigFont { x } |
+        Instruction::StoreBcd { x } => x == register,
+        Instruction::SkipEqReg { x, y } |
+        Instruction::SkipNeReg { x, y } |
+        Instruction::Or { x, y } |
+        Instruction::And { x, y } |
+        Instruction::Xor { x, y } |
+        Instruction::AddReg { x, y } |
+        Instruction::Sub { x, y } |
+        Instruction::SubN { x, y } |
+        Instruction::Draw { x, y, .. } => x == register || y == register,
+        Instruction::LoadReg { y, .. } => y == register,
+        Instruction::ShiftRight { x, y } | Instruction::ShiftLeft { x, y } => register == if quirks.shift_vy { y } else { x },
+        Instruction::JumpV0 { address } => register == if quirks.jump_vx { (address >> 8) as usize } else { 0 },
+        Instruction::StoreRegisters { x } |
+        Instruction::StoreFlags { x } => register <= x,
+        Instruction::SaveRange { x, y } => register_range(x, y).contains(&register),
+        _ => false
+    }
+}
+
+//Only for instructions that didn't read the register first
+fn writes_register(instruction: Instruction, register: usize) -> bool {
+    match instruction {
+        Instruction::LoadByte { x, .. } |
+        Instruction::LoadReg { x, .. } |
+        Instruction::Random { x, .. } |
+        Instruction::LoadDelay { x } |
+        Instruction::WaitKey { x } => x == register,
+        Instruction::LoadRegisters { x } |
+        Instruction::LoadFlags { x } => register <= x,
+        Instruction::LoadRange { x, y } => register_range(x, y).contains(&register),
+        Instruction::AddReg { .. } |
+        Instruction::Sub { .. } |
+        Instruction::ShiftRight { .. } |
+        Instruction::SubN { .. } |
+        Instruction::ShiftLeft { .. } |
+        Instruction::Draw { .. } => register == 0xF,
+        _ => false
+    }
+}
+
+//Whether a DXYN at (vx, vy) has lit pixels that land past the right
+//and past the bottom edge, i.e. whether clipping and wrapping draw
+//different things on each axis
+fn crosses_edge(chip8: &Chip8, vx: u8, vy: u8, height: u8) -> (bool, bool) {
+    let width = chip8.video_width();
+    let screen_height = chip8.video_height();
+    let x = vx as usize % width;
+    let y = vy as usize % screen_height;
+
+    //Each row left-aligned in 16 bits, the second byte only for DXY0
+    let (rows, bytes_per_row) = chip8.sprite_size(height);
+    let byte = |address: usize| chip8.memory[address % chip8.memory.len()] as u16;
+    let rows: Vec<u16> = (0..rows)
+        .map(|row| chip8.index_register as usize + row * bytes_per_row)
+        .map(|address| (byte(address) << 8) | if bytes_per_row == 2 { byte(address + 1) } else { 0 })
+        .collect();
+    let past_right = rows.iter().any(|sprite| (0..16).any(|col| sprite & (0x8000 >> col) != 0 && x + col >= width));
+    let past_bottom = rows.iter().enumerate().any(|(row, sprite)| *sprite != 0 && y + row >= screen_height);
+
+    (past_right, past_bottom)
+}
+
+impl fmt::Display for QuirkFinding {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04X} at {:#05x} depends on {} ({}): on, {}; off, {}",
+            self.opcode, self.address, self.quirk, self.situation, self.on, self.off)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    //Runs steps instructions of rom under --strict and returns the
+    //quirks reported with the address of the instruction they're about
+    fn run(rom: &[u8], steps: usize, quirks: Quirks) -> Vec<(&'static str, u16)> {
+        let mut chip8 = Chip8::create();
+        chip8.quirks = quirks;
+        chip8.load_bytes(rom).unwrap();
+        chip8.strict = Some(StrictChecker::create());
+        for _ in 0..steps {
+            chip8.step().unwrap();
+        }
+
+        chip8.strict.unwrap().findings.iter().map(|finding| (finding.quirk, finding.address)).collect()
+    }
+
+    fn detect(rom: &[u8], steps: usize) -> Vec<(&'static str, u16)> {
+        run(rom, steps, Quirks::default())
+    }
+
+    #[test]
+    fn shift_with_different_registers() {
+        //LD V1, 3; SHR V0, V1 then SHR V0, V0
+        assert_eq!(detect(&[0x61, 0x03, 0x80, 0x16, 0x80, 0x06], 3), [("shift-vy", 0x202)]);
+        assert!(detect(&[0x80, 0x16], 1).is_empty());
+    }
+
+    #[test]
+    fn index_read_after_store() {
+        //LD [I], V1 then DRW reads I
+        assert_eq!(detect(&[0xF1, 0x55, 0xD0, 0x01], 2), [("increment-i", 0x200)]);
+        //LD I first makes it moot
+        assert!(detect(&[0xF1, 0x55, 0xA3, 0x00, 0xD0, 0x01], 3).is_empty());
+        let quirks = Quirks { increment_i_x: true, ..Quirks::default() };
+        assert_eq!(run(&[0xF1, 0x65, 0xF0, 0x1E], 2, quirks), [("increment-i-x", 0x200)]);
+    }
+
+    #[test]
+    fn flag_read_after_logic() {
+        //LD VF, 1; OR V0, V1; SE VF, 1
+        assert_eq!(detect(&[0x6F, 0x01, 0x80, 0x11, 0x3F, 0x01], 3), [("vf-reset", 0x202)]);
+        //VF overwritten before it's read
+        assert!(detect(&[0x6F, 0x01, 0x80, 0x11, 0x6F, 0x00, 0x3F, 0x01], 4).is_empty());
+        //VF already 0, both settings agree
+        assert!(detect(&[0x80, 0x11, 0x3F, 0x01], 2).is_empty());
+    }
+
+    #[test]
+    fn sprites_across_each_edge() {
+        //LD V0, 60; LD V1, 30; LD I, 0x20C; DRW V0, V1, 1 at (60, 30), then
+        //DRW V1, V0, 5 at (30, 28)
+        let rom = [0x60, 0x3C, 0x61, 0x1E, 0xA2, 0x0C, 0xD0, 0x11, 0xD1, 0x05, 0x12, 0x0A, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+        assert_eq!(detect(&rom, 4), [("clip-x", 0x206)]);
+        assert_eq!(detect(&rom, 5), [("clip-x", 0x206), ("clip-y", 0x208)]);
+    }
+
+    #[test]
+    fn big_sprite_in_lores() {
+        assert_eq!(detect(&[0xD0, 0x00], 1), [("dxy0-8x16", 0x200)]);
+        assert!(detect(&[0x00, 0xFF, 0xD0, 0x00], 2).is_empty());
+    }
+
+    #[test]
+    fn jump_with_v0_and_vx_different() {
+        //LD V3, 2; JP V0, 0x300
+        assert_eq!(detect(&[0x63, 0x02, 0xB3, 0x00], 2), [("jump-vx", 0x202)]);
+        assert!(detect(&[0xB3, 0x00], 1).is_empty());
+    }
+
+    #[test]
+    fn keys_past_f() {
+        //LD V0, 0x12; SKP V0
+        assert_eq!(detect(&[0x60, 0x12, 0xE0, 0x9E], 2), [("key-wrap", 0x202)]);
+        assert!(detect(&[0x60, 0x02, 0xE0, 0xA1], 2).is_empty());
+    }
+
+    #[test]
+    fn scrolls_in_lores() {
+        assert_eq!(detect(&[0x00, 0xC1, 0x00, 0xFB, 0x00, 0xFC, 0x00, 0xC0], 4),
+            [("half-scroll", 0x200), ("half-scroll", 0x202), ("half-scroll", 0x204)]);
+        assert!(detect(&[0x00, 0xFF, 0x00, 0xC1, 0x00, 0xFB], 3).is_empty());
+    }
+
+    #[test]
+    fn reports_each_address_once() {
+        //SHR V0, V1 round a loop, with V1 = 3
+        let mut chip8 = Chip8::create();
+        chip8.load_bytes(&[0x61, 0x03, 0x80, 0x16, 0x12, 0x02]).unwrap();
+        chip8.strict = Some(StrictChecker::create());
+        for _ in 0..9 {
+            chip8.step().unwrap();
+        }
+
+        assert_eq!(chip8.strict.unwrap().summary(), "\
+Strict: 1 quirk-dependent instruction(s) this run
+  8016 at 0x202 depends on shift-vy (V0 != V1): on, V0 = V1 >> 1 = 0x01; off, V0 = V0 >> 1 = 0x00
+");
+        assert_eq!(StrictChecker::create().summary(), "Strict: nothing this run depended on a quirk\n");
+    }
+
+}
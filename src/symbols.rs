@@ -0,0 +1,218 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fmt, fs
+};
+
+use serde_json::Value;
+
+use crate::debugger::parse_address;
+
+//Labels for addresses, loaded with --symbols. Two formats:
+//
+//  Text, one label per line, # starts a comment:
+//      0x0200 main
+//      0x02A4 draw_score
+//
+//  JSON, a list of objects (address as a number or a hex string):
+//      [{"address": "0x200", "name": "main"}, {"address": 676, "name": "draw_score"}]
+//
+//Each name and each address may only appear once.
+pub struct SymbolTable {
+    by_address: BTreeMap<u16, String>,
+    by_name: HashMap<String, u16>
+}
+
+//line is 1-based; for JSON files it is the entry number instead
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SymbolError {
+    pub line: usize,
+    pub message: String
+}
+
+impl SymbolTable {
+
+    pub fn create() -> SymbolTable {
+        SymbolTable {
+            by_address: BTreeMap::new(),
+            by_name: HashMap::new()
+        }
+    }
+
+    pub fn load(path: &str) -> Result<SymbolTable, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Can't read {}: {}", path, e))?;
+        let parsed = if text.trim_start().starts_with('[') { SymbolTable::parse_json(&text) } else { SymbolTable::parse(&text) };
+
+        parsed.map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn parse(text: &str) -> Result<SymbolTable, SymbolError> {
+        let mut table = SymbolTable::create();
+        let mut lines: HashMap<u16, usize> = HashMap::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let line_number = i + 1;
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [address, name] = fields[..] else {
+                return Err(SymbolError { line: line_number, message: format!("expected \"<address> <name>\", got \"{}\"", line) });
+            };
+            let address = parse_address(address).ok_or(SymbolError {
+                line: line_number,
+                message: format!("invalid address \"{}\"", address)
+            })?;
+
+            table.insert(address, name, line_number, &lines)?;
+            lines.insert(address, line_number);
+        }
+
+        Ok(table)
+    }
+
+    pub fn parse_json(text: &str) -> Result<SymbolTable, SymbolError> {
+        let entries: Vec<Value> = serde_json::from_str(text).map_err(|e| SymbolError {
+            line: e.line(),
+            message: e.to_string()
+        })?;
+
+        let mut table = SymbolTable::create();
+        let mut lines: HashMap<u16, usize> = HashMap::new();
+        for (i, entry) in entries.iter().enumerate() {
+            let entry_number = i + 1;
+            let invalid = |message: &str| SymbolError { line: entry_number, message: message.to_string() };
+
+            let name = entry["name"].as_str().ok_or_else(|| invalid("missing \"name\""))?;
+            let address = match &entry["address"] {
+                Value::Number(number) => number.as_u64().and_then(|address| u16::try_from(address).ok()),
+                Value::String(text) => parse_address(text),
+                _ => None
+            }.ok_or_else(|| invalid("missing or invalid \"address\""))?;
+
+            table.insert(address, name, entry_number, &lines)?;
+            lines.insert(address, entry_number);
+        }
+
+        Ok(table)
+    }
+
+    fn insert(&mut self, address: u16, name: &str, line: usize, lines: &HashMap<u16, usize>) -> Result<(), SymbolError> {
+        if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.') || name.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err(SymbolError { line, message: format!("invalid label name \"{}\"", name) });
+        }
+        if address as usize >= 4096 {
+            return Err(SymbolError { line, message: format!("address {:#06x} is outside memory", address) });
+        }
+        if let Some(existing) = self.by_name.get(name) {
+            return Err(SymbolError { line, message: format!("label {} already defined on line {}", name, lines[existing]) });
+        }
+        if let Some(existing) = self.by_address.get(&address) {
+            return Err(SymbolError {
+                line,
+                message: format!("{:#06x} already labelled {} on line {}", address, existing, lines[&address])
+            });
+        }
+
+        self.by_address.insert(address, name.to_string());
+        self.by_name.insert(name.to_string(), address);
+        Ok(())
+    }
+
+    //The label exactly at address
+    pub fn name_at(&self, address: u16) -> Option<&str> {
+        self.by_address.get(&address).map(String::as_str)
+    }
+
+    pub fn address_of(&self, name: &str) -> Option<u16> {
+        self.by_name.get(name).copied()
+    }
+
+    //Nearest label at or before address, e.g. "main" or "main+0x4"
+    pub fn describe(&self, address: u16) -> Option<String> {
+        let (label_address, name) = self.by_address.range(..=address).next_back()?;
+
+        Some(match address - label_address {
+            0 => name.clone(),
+            offset => format!("{}+{:#x}", name, offset)
+        })
+    }
+
+}
+
+impl fmt::Display for SymbolError {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+
+}
+
+impl Error for SymbolError {}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::disasm;
+
+    const SYMBOLS: &str = "\
+# game.sym
+0x0200 main
+0x02A4 draw_score   # called every frame
+2B0h   score.digits
+";
+
+    #[test]
+    fn describes_addresses_by_the_label_before_them() {
+        let table = SymbolTable::parse(SYMBOLS).unwrap();
+
+        assert_eq!(table.describe(0x200).as_deref(), Some("main"));
+        assert_eq!(table.describe(0x204).as_deref(), Some("main+0x4"));
+        assert_eq!(table.describe(0x2A3).as_deref(), Some("main+0xa3"));
+        assert_eq!(table.describe(0x2A4).as_deref(), Some("draw_score"));
+        assert_eq!(table.describe(0x2B2).as_deref(), Some("score.digits+0x2"));
+        assert_eq!(table.describe(0x1FF), None);
+        assert_eq!((table.name_at(0x2A4), table.name_at(0x2A6)), (Some("draw_score"), None));
+        assert_eq!((table.address_of("score.digits"), table.address_of("nope")), (Some(0x2B0), None));
+    }
+
+    #[test]
+    fn labels_show_in_disassembly() {
+        let table = SymbolTable::parse(SYMBOLS).unwrap();
+        let mut memory = vec![0; 4096];
+        memory[0x200..0x206].copy_from_slice(&[0x22, 0xA4, 0x12, 0xB0, 0x12, 0x08]);
+
+        //Targets are labelled only when a label is right there
+        assert_eq!(disasm::disassemble_at(&memory, 0x200, Some(&table)), "CALL draw_score");
+        assert_eq!(disasm::disassemble_at(&memory, 0x202, Some(&table)), "JP score.digits");
+        assert_eq!(disasm::disassemble_at(&memory, 0x204, Some(&table)), "JP 0x208");
+        assert_eq!(disasm::describe_address(0x202, Some(&table)), "0x0202 <main+0x2>");
+    }
+
+    #[test]
+    fn parses_json() {
+        let table = SymbolTable::parse_json(r#"[{"address": "0x200", "name": "main"}, {"address": 676, "name": "draw_score"}]"#).unwrap();
+        assert_eq!((table.address_of("main"), table.address_of("draw_score")), (Some(0x200), Some(0x2A4)));
+
+        let error = |text| SymbolTable::parse_json(text).err().map(|e| e.to_string());
+        assert_eq!(error(r#"[{"address": 512}]"#).as_deref(), Some("line 1: missing \"name\""));
+        assert_eq!(error(r#"[{"name": "a", "address": 1}, {"name": "b", "address": -1}]"#).as_deref(),
+            Some("line 2: missing or invalid \"address\""));
+    }
+
+    #[test]
+    fn rejects_bad_lines_with_their_number() {
+        let error = |text| SymbolTable::parse(text).err().map(|e| e.to_string());
+
+        assert_eq!(error("0x200 main\n\n0x202 main").as_deref(), Some("line 3: label main already defined on line 1"));
+        assert_eq!(error("0x200 main\n0x200 start").as_deref(), Some("line 2: 0x0200 already labelled main on line 1"));
+        assert_eq!(error("0x200").as_deref(), Some("line 1: expected \"<address> <name>\", got \"0x200\""));
+        assert_eq!(error("0xZZ main").as_deref(), Some("line 1: invalid address \"0xZZ\""));
+        assert_eq!(error("0x200 2fast").as_deref(), Some("line 1: invalid label name \"2fast\""));
+        assert_eq!(error("0x1000 past").as_deref(), Some("line 1: address 0x1000 is outside memory"));
+    }
+
+}
@@ -0,0 +1,187 @@
+//A framebuffer with a 3x5 pixel font for the frontend's own screens,
+//drawn through the same path as the CHIP-8 display. Every character
+//takes a 4x6 cell, the extra column and row being the spacing.
+pub const CELL_WIDTH: usize = 4;
+pub const CELL_HEIGHT: usize = 6;
+
+pub struct TextScreen {
+    pub columns: usize,
+    pub rows: usize,
+    //columns * CELL_WIDTH pixels wide, 0x00 or 0xFF like Chip8::video
+    pub pixels: Vec<u8>
+}
+
+impl TextScreen {
+
+    pub fn create(columns: usize, rows: usize) -> TextScreen {
+        TextScreen {
+            columns,
+            rows,
+            pixels: vec![0; columns * CELL_WIDTH * rows * CELL_HEIGHT]
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.columns * CELL_WIDTH
+    }
+
+    //Writes text from (column, row), cutting it off at the right edge.
+    //Lowercase is shown as uppercase.
+    pub fn print(&mut self, column: usize, row: usize, text: &str) {
+        if row >= self.rows {
+            return;
+        }
+
+        let width = self.width();
+        for (offset, c) in text.chars().enumerate().take(self.columns.saturating_sub(column)) {
+            let left = (column + offset) * CELL_WIDTH;
+            for (y, bits) in glyph(c).iter().enumerate() {
+                for x in 0..3 {
+                    if bits & (0b100 >> x) != 0 {
+                        self.pixels[left + x + (row * CELL_HEIGHT + y) * width] = 0xFF;
+                    }
+                }
+            }
+        }
+    }
+
+    //Flips a whole row of cells, for highlighting
+    pub fn invert_row(&mut self, row: usize) {
+        let width = self.width();
+        let start = row * CELL_HEIGHT * width;
+        for pixel in &mut self.pixels[start..start + CELL_HEIGHT * width] {
+            *pixel ^= 0xFF;
+        }
+    }
+
+}
+
+//Rows top to bottom, the low three bits of each lit left to right.
+//Anything without a glyph shows as ?.
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        ' ' => [0b000, 0b000, 0b000, 0b000, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '\\' => [0b100, 0b100, 0b010, 0b001, 0b001],
+        '(' => [0b001, 0b010, 0b010, 0b010, 0b001],
+        ')' => [0b100, 0b010, 0b010, 0b010, 0b100],
+        '[' => [0b011, 0b010, 0b010, 0b010, 0b011],
+        ']' => [0b110, 0b010, 0b010, 0b010, 0b110],
+        '<' => [0b001, 0b010, 0b100, 0b010, 0b001],
+        '>' => [0b100, 0b010, 0b001, 0b010, 0b100],
+        '!' => [0b010, 0b010, 0b010, 0b000, 0b010],
+        '\'' => [0b010, 0b010, 0b000, 0b000, 0b000],
+        '"' => [0b101, 0b101, 0b000, 0b000, 0b000],
+        '#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+        '&' => [0b010, 0b101, 0b010, 0b101, 0b011],
+        '*' => [0b101, 0b010, 0b101, 0b000, 0b000],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        _ => [0b110, 0b001, 0b010, 0b000, 0b010]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn lines(screen: &TextScreen) -> Vec<String> {
+        screen.pixels.chunks(screen.width())
+            .map(|row| row.iter().map(|pixel| if *pixel != 0 { '#' } else { '.' }).collect())
+            .collect()
+    }
+
+    #[test]
+    fn prints_glyphs_in_their_cells() {
+        let mut screen = TextScreen::create(3, 1);
+        screen.print(1, 0, "A1");
+        assert_eq!(lines(&screen), [
+            ".....#...#..",
+            "....#.#.##..",
+            "....###..#..",
+            "....#.#..#..",
+            "....#.#.###.",
+            "............"
+        ]);
+    }
+
+    #[test]
+    fn cuts_text_off_at_the_edges() {
+        let mut screen = TextScreen::create(2, 2);
+        screen.print(1, 1, "---");
+        screen.print(0, 2, "-");
+        screen.print(5, 0, "-");
+        let lit: Vec<usize> = screen.pixels.iter().enumerate().filter(|(_, pixel)| **pixel != 0).map(|(i, _)| i).collect();
+        //Only the first - fits, in the second cell of the second row
+        let row = (CELL_HEIGHT + 2) * screen.width();
+        assert_eq!(lit, [row + 4, row + 5, row + 6]);
+    }
+
+    #[test]
+    fn lowercase_and_unknown_characters() {
+        let print = |text: &str| {
+            let mut screen = TextScreen::create(1, 1);
+            screen.print(0, 0, text);
+            screen.pixels
+        };
+        assert_eq!(print("q"), print("Q"));
+        assert_eq!(print("~"), print("é"));
+        assert_ne!(print("~"), print(" "));
+    }
+
+    #[test]
+    fn inverts_a_whole_row() {
+        let mut screen = TextScreen::create(2, 2);
+        screen.print(0, 1, ".");
+        screen.invert_row(1);
+
+        let lines = lines(&screen);
+        assert!(lines[..CELL_HEIGHT].iter().all(|line| line == "........"));
+        assert!(lines[CELL_HEIGHT..CELL_HEIGHT + 4].iter().all(|line| line == "########"));
+        assert_eq!(lines[CELL_HEIGHT + 4], "#.######");
+    }
+
+}
@@ -0,0 +1,504 @@
+use std::{
+    hint,
+    thread,
+    time::{Duration, Instant}
+};
+
+use crate::{
+    chip8::{Chip8, Chip8Error},
+    instruction::Instruction
+};
+
+//--cycle-accurate budgets time per 60Hz frame instead of running one
+//instruction per tick. Every instruction costs roughly what it took the
+//original COSMAC VIP interpreter (1.76MHz CDP1802) to execute it.
+//
+//Costs are in microseconds, from the COSMAC VIP column of Jackson S,
+//"Chip-8 Instruction Scheduling and Frequency" (2019), which derives them
+//from the machine-cycle counts of the VIP interpreter routines. They are
+//averages: the real cost of DXYN depends on sprite height, position and
+//where the display interrupt falls, and skips cost a little more when taken.
+pub const FRAME_MICROS: i64 = 1_000_000 / 60;
+
+pub fn vip_micros(instruction: &Instruction) -> i64 {
+    match instruction {
+        Instruction::Cls => 109,
+        Instruction::Ret => 105,
+        //SUPER-CHIP only, the VIP never ran these
+        Instruction::Lores |
+        Instruction::Hires |
+        Instruction::ScrollDown { .. } |
+        Instruction::ScrollRight |
+        Instruction::ScrollLeft |
+        Instruction::Exit => 109,
+        //XO-CHIP only, priced like ANNN plus the extra fetch
+        Instruction::LoadLongIndex { .. } => 110,
+        //XO-CHIP only, priced like the SUPER-CHIP scrolls
+        Instruction::ScrollUp { .. } => 109,
+        Instruction::Plane { .. } => 45,
+        //XO-CHIP only, priced like FX55/FX65
+        Instruction::SaveRange { .. } |
+        Instruction::LoadRange { .. } => 605,
+        Instruction::Sys { .. } => 105,
+        Instruction::Jump { .. } => 105,
+        Instruction::Call { .. } => 105,
+        Instruction::SkipEqByte { .. } => 55,
+        Instruction::SkipNeByte { .. } => 55,
+        Instruction::SkipEqReg { .. } => 73,
+        Instruction::LoadByte { .. } => 27,
+        Instruction::AddByte { .. } => 45,
+        Instruction::LoadReg { .. } |
+        Instruction::Or { .. } |
+        Instruction::And { .. } |
+        Instruction::Xor { .. } |
+        Instruction::AddReg { .. } |
+        Instruction::Sub { .. } |
+        Instruction::ShiftRight { .. } |
+        Instruction::SubN { .. } |
+        Instruction::ShiftLeft { .. } => 200,
+        Instruction::SkipNeReg { .. } => 73,
+        Instruction::LoadIndex { .. } => 55,
+        Instruction::JumpV0 { .. } => 105,
+        Instruction::Random { .. } => 164,
+        Instruction::Draw { .. } => 22734,
+        Instruction::SkipKeyPressed { .. } => 73,
+        Instruction::SkipKeyNotPressed { .. } => 73,
+        Instruction::LoadDelay { .. } => 45,
+        //Polls once per pass while no key is down
+        Instruction::WaitKey { .. } => 45,
+        Instruction::SetDelay { .. } => 45,
+        Instruction::SetSound { .. } => 45,
+        Instruction::AddIndex { .. } => 86,
+        Instruction::LoadFont { .. } => 91,
+        //SUPER-CHIP only, priced like FX29
+        Instruction::LoadBigFont { .. } => 91,
+        Instruction::StoreBcd { .. } => 927,
+        Instruction::StoreRegisters { .. } => 605,
+        Instruction::LoadRegisters { .. } => 605,
+        //SUPER-CHIP only, priced like FX55/FX65
+        Instruction::StoreFlags { .. } |
+        Instruction::LoadFlags { .. } => 605,
+        Instruction::Invalid { .. } => 0
+    }
+}
+
+//Hands out one frame of VIP time at a time
+pub struct CycleBudget {
+    available: i64,
+    last_frame: Instant
+}
+
+//Frames caught up in one go after the window stalls, so a long hiccup
+//doesn't turn into a burst of emulation
+const MAX_CATCH_UP_FRAMES: u32 = 4;
+
+impl CycleBudget {
+
+    pub fn create() -> CycleBudget {
+        CycleBudget {
+            available: 0,
+            last_frame: Instant::now()
+        }
+    }
+
+    //Number of 60Hz frames that have started since the last call
+    pub fn frames_due(&mut self) -> u32 {
+        let frame = Duration::from_micros(FRAME_MICROS as u64);
+        let mut frames = 0;
+
+        while self.last_frame.elapsed() >= frame {
+            self.last_frame += frame;
+            frames += 1;
+        }
+
+        frames.min(MAX_CATCH_UP_FRAMES)
+    }
+
+    //Adds a frame's worth of time. Overspending (a DXYN longer than a
+    //frame) carries over, unused time does not.
+    pub fn start_frame(&mut self) {
+        self.available = self.available.min(0) + FRAME_MICROS;
+    }
+
+    pub fn has_time(&self) -> bool {
+        self.available > 0
+    }
+
+    pub fn spend(&mut self, instruction: &Instruction) {
+        self.available -= vip_micros(instruction);
+    }
+
+}
+
+//Instructions per frame for a bare --ipf, 600 a second
+pub const DEFAULT_IPF: u32 = 10;
+
+//How instructions and timer ticks are spread over time
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Pacing {
+    //One instruction and one timer tick per frontend tick (the default)
+    PerTick,
+    //--ipf=N: N instructions and one timer tick per 60Hz frame
+    InstructionsPerFrame(u32),
+    //--cycle-accurate: as much as fits in a frame of VIP time, one timer tick
+    CycleAccurate
+}
+
+//--exec-order: whether the window runs a frame's instructions before or
+//after the render that falls in the same iteration of the main loop.
+//Pre (the default) shows each frame as soon as it has run, as the 60Hz
+//model has it. Post draws what the last frame left and then runs the
+//next, so the screen is a frame behind: a key press takes one more
+//render to show, which some demos timed against a real interpreter
+//expect.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ExecOrder {
+    #[default]
+    Pre,
+    Post
+}
+
+pub const EXEC_ORDER_NAMES: [&str; 2] = ["pre", "post"];
+
+//The two halves of a main loop iteration, in ExecOrder::steps
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FrameStep {
+    Run,
+    Render
+}
+
+impl ExecOrder {
+
+    pub fn parse(name: &str) -> Result<ExecOrder, String> {
+        match name {
+            "pre" => Ok(ExecOrder::Pre),
+            "post" => Ok(ExecOrder::Post),
+            _ => Err(format!("expected one of {}", EXEC_ORDER_NAMES.join(", ")))
+        }
+    }
+
+    pub fn steps(self) -> [FrameStep; 2] {
+        match self {
+            ExecOrder::Pre => [FrameStep::Run, FrameStep::Render],
+            ExecOrder::Post => [FrameStep::Render, FrameStep::Run]
+        }
+    }
+
+}
+
+//Runs the machine a frame at a time under one of the pacing models. A
+//frame is the unit the debugger's frame step advances by.
+pub struct Scheduler {
+    pacing: Pacing,
+    budget: CycleBudget,
+    //--idle-throttle, see IdleProbe
+    pub idle_throttle: bool
+}
+
+impl Scheduler {
+
+    pub fn create(pacing: Pacing) -> Scheduler {
+        Scheduler {
+            pacing,
+            budget: CycleBudget::create(),
+            idle_throttle: false
+        }
+    }
+
+    //Frames to run this frontend tick: always one per tick, or for the
+    //60Hz models however many have elapsed
+    pub fn frames_due(&mut self) -> u32 {
+        match self.pacing {
+            Pacing::PerTick => 1,
+            _ => self.budget.frames_due()
+        }
+    }
+
+    //Runs one frame. stop is asked before every instruction and ends the
+    //frame early when it returns true, as does EXIT; the timers still tick
+    //if anything ran. Returns the number of instructions executed.
+    pub fn run_frame(&mut self, chip8: &mut Chip8, mut stop: impl FnMut(&Chip8) -> bool) -> Result<u32, Chip8Error> {
+        if self.pacing == Pacing::CycleAccurate {
+            self.budget.start_frame();
+        }
+
+        //Per tick there is only one instruction in a frame to skip
+        let mut probe = (self.idle_throttle && self.pacing != Pacing::PerTick && !chip8.keypad.contains(&true))
+            .then(|| IdleProbe::start(chip8));
+
+        let mut executed = 0;
+        while !self.frame_done(executed) && !chip8.exited && !stop(chip8) {
+            let instruction = match self.pacing {
+                Pacing::PerTick => {
+                    chip8.run()?;
+                    None
+                },
+                Pacing::InstructionsPerFrame(_) => Some(chip8.step()?),
+                Pacing::CycleAccurate => {
+                    let instruction = chip8.step()?;
+                    self.budget.spend(&instruction);
+                    Some(instruction)
+                }
+            };
+            executed += 1;
+
+            if let (Some(current), Some(instruction)) = (probe.as_mut(), instruction) {
+                match current.record(chip8, instruction) {
+                    Probe::Running => (),
+                    Probe::Looped => {
+                        executed += self.skip_passes(&current.pass, executed);
+                        probe = None;
+                    },
+                    Probe::NotIdle => probe = None
+                }
+            }
+        }
+
+        if self.pacing != Pacing::PerTick && executed > 0 {
+            chip8.tick_timers();
+        }
+
+        Ok(executed)
+    }
+
+    //At least one instruction runs per frame, even when the cycle budget
+    //is still paying off a long draw
+    fn frame_done(&self, executed: u32) -> bool {
+        match self.pacing {
+            Pacing::PerTick => executed >= 1,
+            Pacing::InstructionsPerFrame(count) => executed >= count,
+            Pacing::CycleAccurate => executed > 0 && !self.budget.has_time()
+        }
+    }
+
+    //Instructions the rest of the frame would spend going round pass,
+    //in whole passes, taken out of the frame without running them
+    fn skip_passes(&mut self, pass: &[Instruction], executed: u32) -> u32 {
+        let length = pass.len() as u32;
+        match self.pacing {
+            Pacing::PerTick => 0,
+            Pacing::InstructionsPerFrame(count) => count.saturating_sub(executed) / length * length,
+            Pacing::CycleAccurate => {
+                //A pass is only skipped if the frame can't end partway through it
+                let all_but_last: i64 = pass[..pass.len() - 1].iter().map(vip_micros).sum();
+                let mut skipped = 0;
+                while self.budget.available - all_but_last > 0 {
+                    for instruction in pass {
+                        self.budget.spend(instruction);
+                    }
+                    skipped += length;
+                }
+                skipped
+            }
+        }
+    }
+
+}
+
+//--idle-throttle: a ROM polling the keypad with no key down, or parked on
+//a jump to itself, spends most of a frame going round the same few
+//instructions to no effect. The first pass of the frame runs; if it ends
+//with the machine exactly as it started, every further whole pass would
+//too, so they are counted but not run and the frame finishes with what is
+//left over. The machine ends the frame as it would have anyway, only the
+//host does less work. Exec hooks (--profile, --trace, coverage) only see
+//the pass that ran.
+const MAX_IDLE_PASS: usize = 16;
+
+struct IdleProbe {
+    start: IdleState,
+    pass: Vec<Instruction>
+}
+
+//Everything the instructions allowed in a pass can change. The timers
+//only tick between frames under the 60Hz models.
+#[derive(PartialEq, Eq)]
+struct IdleState {
+    registers: [u8; 16],
+    index_register: u16,
+    program_counter: u16,
+    opcode: u16,
+    delay_timer: u8,
+    sound_timer: u8
+}
+
+enum Probe {
+    Running,
+    Looped,
+    NotIdle
+}
+
+impl IdleProbe {
+
+    fn start(chip8: &Chip8) -> IdleProbe {
+        IdleProbe {
+            start: IdleState::of(chip8),
+            pass: Vec::new()
+        }
+    }
+
+    fn record(&mut self, chip8: &Chip8, instruction: Instruction) -> Probe {
+        if !only_touches_registers(&instruction) || self.pass.len() == MAX_IDLE_PASS {
+            return Probe::NotIdle;
+        }
+        self.pass.push(instruction);
+        match IdleState::of(chip8) == self.start {
+            true => Probe::Looped,
+            false => Probe::Running
+        }
+    }
+
+}
+
+impl IdleState {
+
+    fn of(chip8: &Chip8) -> IdleState {
+        IdleState {
+            registers: chip8.registers,
+            index_register: chip8.index_register,
+            program_counter: chip8.program_counter,
+            opcode: chip8.opcode,
+            delay_timer: chip8.delay_timer,
+            sound_timer: chip8.sound_timer
+        }
+    }
+
+}
+
+//Touches nothing but V, I, PC and the timers: no memory, display, stack
+//or random numbers
+fn only_touches_registers(instruction: &Instruction) -> bool {
+    matches!(instruction,
+        Instruction::Jump { .. } |
+        Instruction::JumpV0 { .. } |
+        Instruction::SkipEqByte { .. } |
+        Instruction::SkipNeByte { .. } |
+        Instruction::SkipEqReg { .. } |
+        Instruction::SkipNeReg { .. } |
+        Instruction::SkipKeyPressed { .. } |
+        Instruction::SkipKeyNotPressed { .. } |
+        Instruction::WaitKey { .. } |
+        Instruction::LoadDelay { .. } |
+        Instruction::LoadByte { .. } |
+        Instruction::AddByte { .. } |
+        Instruction::LoadReg { .. } |
+        Instruction::LoadIndex { .. })
+}
+
+//Slept time overshoots by up to a scheduler tick, so the last stretch
+//before a deadline is spun instead
+const SPIN_MARGIN: Duration = Duration::from_micros(1500);
+
+//--fps=N: one frame every 1/N seconds on the monotonic clock. Deadlines
+//are fixed from the start, so one slow frame doesn't push every later
+//frame back. Deadlines the host has already missed entirely are dropped
+//rather than run back to back.
+pub struct FramePacer {
+    interval: Duration,
+    next: Instant,
+    pub frames: u64,
+    pub dropped: u64,
+    //Drops since report_drops last returned some
+    unreported: u64,
+    last_report: Instant
+}
+
+impl FramePacer {
+
+    pub fn create(fps: u32) -> FramePacer {
+        let now = Instant::now();
+        FramePacer {
+            interval: Duration::from_secs(1) / fps,
+            next: now,
+            frames: 0,
+            dropped: 0,
+            unreported: 0,
+            last_report: now
+        }
+    }
+
+    //Blocks until the next frame is due. Returns the number of frames
+    //dropped because their deadline passed before this call.
+    pub fn wait(&mut self) -> u32 {
+        let now = Instant::now();
+        let mut dropped = 0;
+
+        if now < self.next {
+            let remaining = self.next - now;
+            if remaining > SPIN_MARGIN {
+                thread::sleep(remaining - SPIN_MARGIN);
+            }
+            while Instant::now() < self.next {
+                hint::spin_loop();
+            }
+        } else {
+            dropped = ((now - self.next).as_nanos() / self.interval.as_nanos()) as u32;
+            self.next += self.interval * dropped;
+        }
+
+        self.next += self.interval;
+        self.frames += 1;
+        self.dropped += dropped as u64;
+        self.unreported += dropped as u64;
+        dropped
+    }
+
+    //Frames dropped since the last report, at most once a second so a
+    //struggling host isn't also flooded with warnings
+    pub fn report_drops(&mut self) -> Option<u64> {
+        if self.unreported == 0 || self.last_report.elapsed() < Duration::from_secs(1) {
+            return None;
+        }
+
+        self.last_report = Instant::now();
+        Some(std::mem::take(&mut self.unreported))
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    //LD DT, V0 with V0 = 30, then ADD V1, 1 round a loop
+    const COUNTING_ROM: [u8; 8] = [0x60, 0x1E, 0xF0, 0x15, 0x71, 0x01, 0x12, 0x04];
+
+    fn machine() -> Chip8 {
+        let mut chip8 = Chip8::create();
+        chip8.load_bytes(&COUNTING_ROM).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn paused_frames_run_nothing() {
+        for pacing in [Pacing::PerTick, Pacing::InstructionsPerFrame(DEFAULT_IPF), Pacing::CycleAccurate] {
+            let mut chip8 = machine();
+            let mut scheduler = Scheduler::create(pacing);
+            let mut paused = true;
+
+            for _ in 0..5 {
+                assert_eq!(scheduler.run_frame(&mut chip8, |_| paused).unwrap(), 0, "{:?}", pacing);
+            }
+            assert_eq!((chip8.program_counter, chip8.registers[0], chip8.delay_timer), (0x200, 0, 0), "{:?}", pacing);
+
+            paused = false;
+            assert!(scheduler.run_frame(&mut chip8, |_| paused).unwrap() > 0, "{:?}", pacing);
+            assert_ne!(chip8.program_counter, 0x200, "{:?}", pacing);
+        }
+    }
+
+    #[test]
+    fn exit_ends_the_frame() {
+        for pacing in [Pacing::PerTick, Pacing::InstructionsPerFrame(DEFAULT_IPF), Pacing::CycleAccurate] {
+            let mut chip8 = Chip8::create();
+            chip8.load_bytes(&[0x60, 0x01, 0x00, 0xFD, 0x12, 0x00]).unwrap();
+            let mut scheduler = Scheduler::create(pacing);
+
+            //PerTick takes two frames to get there, the others one
+            let executed: u32 = (0..5).map(|_| scheduler.run_frame(&mut chip8, |_| false).unwrap()).sum();
+            assert_eq!((executed, chip8.program_counter), (2, 0x204), "{:?}", pacing);
+        }
+    }
+
+}
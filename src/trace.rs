@@ -0,0 +1,70 @@
+use std::rc::Rc;
+
+use crate::{chip8::ExecHook, disasm, symbols::SymbolTable};
+
+//--trace prints every instruction as it is fetched, with the label it
+//falls under when symbols are loaded
+pub struct Tracer {
+    symbols: Option<Rc<SymbolTable>>
+}
+
+impl Tracer {
+
+    pub fn create(symbols: Option<Rc<SymbolTable>>) -> Tracer {
+        Tracer { symbols }
+    }
+
+    //The line printed for the instruction at address
+    pub fn line(&self, address: u16, opcode: u16) -> String {
+        let symbols = self.symbols.as_deref();
+        format!("{}  {:04X}  {}", disasm::describe_address(address, symbols), opcode, disasm::disassemble_with(opcode, symbols))
+    }
+
+    //One line for both words, like the disassembler
+    pub fn long_line(&self, address: u16, opcode: u16, operand: u16) -> String {
+        let symbols = self.symbols.as_deref();
+        format!(
+            "{}  {:04X} {:04X}  {}",
+            disasm::describe_address(address, symbols), opcode, operand, disasm::disassemble_long(operand, symbols)
+        )
+    }
+
+}
+
+impl ExecHook for Tracer {
+
+    fn on_exec(&mut self, address: u16, opcode: u16) {
+        println!("{}", self.line(address, opcode));
+    }
+
+    fn on_exec_long(&mut self, address: u16, opcode: u16, operand: u16) {
+        println!("{}", self.long_line(address, opcode, operand));
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn annotates_addresses_with_labels() {
+        let symbols = SymbolTable::parse("0x200 main\n0x2A4 draw_score").unwrap();
+        let tracer = Tracer::create(Some(Rc::new(symbols)));
+
+        assert_eq!(tracer.line(0x200, 0x22A4), "0x0200 <main>  22A4  CALL draw_score");
+        assert_eq!(tracer.line(0x2A8, 0x00EE), "0x02a8 <draw_score+0x4>  00EE  RET");
+        assert_eq!(tracer.line(0x1FE, 0x1200), "0x01fe  1200  JP main");
+        assert_eq!(Tracer::create(None).line(0x200, 0x22A4), "0x0200  22A4  CALL 0x2A4");
+    }
+
+    #[test]
+    fn long_loads_trace_on_one_line() {
+        let symbols = SymbolTable::parse("0x200 main\n0xA34 sprites").unwrap();
+        let tracer = Tracer::create(Some(Rc::new(symbols)));
+        assert_eq!(tracer.long_line(0x202, 0xF000, 0x0A34), "0x0202 <main+0x2>  F000 0A34  LD I, LONG sprites");
+        assert_eq!(Tracer::create(None).long_line(0x202, 0xF000, 0x1234), "0x0202  F000 1234  LD I, LONG 0x1234");
+    }
+
+}
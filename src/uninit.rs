@@ -0,0 +1,149 @@
+use std::collections::BTreeSet;
+
+use crate::chip8::{ExecHook, BIG_FONTSET_SIZE, BIG_FONTSET_START_ADDRESS, FONTSET_START_ADDRESS};
+
+const LOAD_ADDRESS: usize = 0x200;
+
+//--warn-uninit: warns once per address when FX65 or DXYN reads a byte
+//that neither the ROM, the fontset nor an earlier write put there. Other
+//interpreters don't all zero memory, so such reads are where ROMs start
+//behaving differently. That includes sprites drawn from past the end
+//of the ROM, a likely ROM bug. Only installed as a hook when asked for.
+pub struct UninitTracker {
+    initialized: Vec<bool>,
+    warned: BTreeSet<usize>,
+    //The instruction being executed, from on_exec
+    current: (u16, u16),
+    //Whether the font area counts as initialized
+    fontset: bool
+}
+
+impl UninitTracker {
+
+    //rom_size bytes from 0x200 count as initialized, and the font area
+    //when the fontset is loaded. memory_size is the machine's.
+    pub fn create(rom_size: usize, memory_size: usize, fontset: bool) -> UninitTracker {
+        let mut tracker = UninitTracker {
+            initialized: Vec::new(),
+            warned: BTreeSet::new(),
+            current: (0, 0),
+            fontset
+        };
+        tracker.reset(rom_size, memory_size);
+
+        tracker
+    }
+
+    //For a freshly loaded ROM, on a machine that may have another size
+    pub fn reset(&mut self, rom_size: usize, memory_size: usize) {
+        self.initialized.clear();
+        self.initialized.resize(memory_size, false);
+        self.warned.clear();
+
+        let font_end = if self.fontset { BIG_FONTSET_START_ADDRESS + BIG_FONTSET_SIZE } else { FONTSET_START_ADDRESS };
+        let font = FONTSET_START_ADDRESS as usize..font_end as usize;
+        let rom = LOAD_ADDRESS..(LOAD_ADDRESS + rom_size).min(memory_size);
+        for address in font.chain(rom) {
+            self.initialized[address] = true;
+        }
+    }
+
+}
+
+impl ExecHook for UninitTracker {
+
+    fn on_exec(&mut self, address: u16, opcode: u16) {
+        self.current = (address, opcode);
+    }
+
+    fn on_read(&mut self, target: usize) {
+        let (address, opcode) = self.current;
+        let checked = opcode & 0xF000 == 0xD000 || opcode & 0xF0FF == 0xF065;
+
+        if checked && !self.initialized.get(target).copied().unwrap_or(true) && self.warned.insert(target) {
+            log::warn!("{:04X} at {:#05x} read {:#05x}, which was never initialized", opcode, address, target);
+        }
+    }
+
+    fn on_write(&mut self, target: usize) {
+        if let Some(initialized) = self.initialized.get_mut(target) {
+            *initialized = true;
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{chip8::Chip8, quirks::Quirks};
+
+    //A machine on the profile's memory running rom, with a tracker on it
+    fn tracked(rom: &[u8], profile: &str) -> (Chip8, Rc<RefCell<UninitTracker>>) {
+        let mut chip8 = Chip8::create_with_memory(Quirks::memory_size(profile));
+        chip8.quirks = Quirks::profile(profile).unwrap();
+        chip8.load_bytes(rom).unwrap();
+
+        let tracker = Rc::new(RefCell::new(UninitTracker::create(rom.len(), chip8.memory.len(), chip8.has_fontset())));
+        chip8.add_exec_hook(tracker.clone());
+
+        (chip8, tracker)
+    }
+
+    fn warned(tracker: &RefCell<UninitTracker>) -> Vec<usize> {
+        tracker.borrow().warned.iter().copied().collect()
+    }
+
+    #[test]
+    fn big_sprite_past_the_rom_end() {
+        //I = 0x20A, hires, a 16x16 sprite drawn over and over from the
+        //last two bytes of the ROM and 30 past it
+        let rom = [0xA2, 0x0A, 0x00, 0xFF, 0xD0, 0x10, 0x12, 0x04, 0x00, 0x00, 0xFF, 0xFF];
+        let (mut chip8, tracker) = tracked(&rom, "schip");
+
+        for _ in 0..3 {
+            chip8.step().unwrap();
+        }
+        assert_eq!(warned(&tracker), (0x20C..0x22A).collect::<Vec<usize>>());
+
+        for _ in 0..10 {
+            chip8.step().unwrap();
+        }
+        assert_eq!(warned(&tracker).len(), 30);
+    }
+
+    #[test]
+    fn reads_past_a_4k_rom() {
+        //I = 0x11FF, V0-V1 = [I], the last byte of the ROM and the one after
+        let mut rom = vec![0xF0, 0x00, 0x11, 0xFF, 0xF1, 0x65, 0x12, 0x06];
+        rom.resize(4096, 0);
+        rom[4095] = 0xAA;
+        let (mut chip8, tracker) = tracked(&rom, "xo-chip");
+
+        for _ in 0..3 {
+            chip8.step().unwrap();
+        }
+        assert_eq!(chip8.registers[..2], [0xAA, 0x00]);
+        assert_eq!(warned(&tracker), [0x1200]);
+    }
+
+    #[test]
+    fn written_memory_is_initialized() {
+        //I = 0x300, [I] = V0-V1, V0-V1 = [I]
+        let rom = [0xA3, 0x00, 0xF1, 0x55, 0xA3, 0x00, 0xF1, 0x65, 0xA3, 0x02, 0xF0, 0x65];
+        let (mut chip8, tracker) = tracked(&rom, "chip8");
+
+        for _ in 0..4 {
+            chip8.step().unwrap();
+        }
+        assert!(warned(&tracker).is_empty());
+
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        assert_eq!(warned(&tracker), [0x302]);
+    }
+
+}
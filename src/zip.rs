@@ -0,0 +1,161 @@
+use miniz_oxide::inflate::decompress_to_vec_with_limit;
+
+use crate::chip8::MAX_XO_CHIP_ROM_SIZE;
+
+//Just enough of the zip format to pull a ROM out of an archive: the
+//central directory at the end lists the files, each stored as is or
+//deflated. Multi-disk archives, zip64 and encryption aren't supported.
+
+const END_OF_DIRECTORY: u32 = 0x0605_4B50;
+const DIRECTORY_ENTRY: u32 = 0x0201_4B50;
+const LOCAL_HEADER: u32 = 0x0403_4B50;
+//The end of directory record is 22 bytes plus a comment of up to 64KB
+const END_OF_DIRECTORY_SIZE: usize = 22;
+const MAX_COMMENT: usize = 0xFFFF;
+
+struct Entry {
+    name: String,
+    method: u16,
+    compressed_size: usize,
+    local_offset: usize
+}
+
+//The ROM in archive: the one named, or the only .ch8/.c8 file in it
+pub fn extract_rom(archive: &[u8], name: Option<&str>) -> Result<Vec<u8>, String> {
+    let entries = entries(archive)?;
+    let entry = match name {
+        Some(name) => entries.iter()
+            .find(|entry| entry.name == name)
+            .ok_or_else(|| format!("no {} in the archive", name))?,
+        None => {
+            let roms: Vec<&Entry> = entries.iter().filter(|entry| is_rom(&entry.name)).collect();
+            match roms[..] {
+                [rom] => rom,
+                [] => return Err("no .ch8 or .c8 file in the archive".to_string()),
+                _ => {
+                    let names: Vec<&str> = roms.iter().map(|rom| rom.name.as_str()).collect();
+                    return Err(format!("several ROMs in the archive, pick one with #NAME: {}", names.join(", ")));
+                }
+            }
+        }
+    };
+
+    //The sizes in the directory can't be trusted, so the limit is checked
+    //on what comes out
+    let rom = read_entry(archive, entry)?;
+    if rom.len() > MAX_XO_CHIP_ROM_SIZE {
+        return Err(format!("{} is {} bytes, more than the {} that fit in memory", entry.name, rom.len(), MAX_XO_CHIP_ROM_SIZE));
+    }
+
+    Ok(rom)
+}
+
+fn is_rom(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    !name.starts_with("__macosx/") && (name.ends_with(".ch8") || name.ends_with(".c8"))
+}
+
+fn entries(archive: &[u8]) -> Result<Vec<Entry>, String> {
+    //Scan back from the end for the end of directory record
+    let earliest = archive.len().saturating_sub(END_OF_DIRECTORY_SIZE + MAX_COMMENT);
+    let end = (earliest..=archive.len().saturating_sub(END_OF_DIRECTORY_SIZE))
+        .rev()
+        .find(|offset| u32_at(archive, *offset) == Some(END_OF_DIRECTORY))
+        .ok_or("not a zip archive")?;
+
+    let count = u16_at(archive, end + 10).ok_or("truncated archive")? as usize;
+    let mut offset = u32_at(archive, end + 16).ok_or("truncated archive")? as usize;
+    let mut entries = Vec::with_capacity(count);
+    for _ in 0..count {
+        if u32_at(archive, offset) != Some(DIRECTORY_ENTRY) {
+            return Err("corrupt central directory".to_string());
+        }
+        let field = |at: usize| u16_at(archive, offset + at).map(usize::from).ok_or("truncated archive");
+        let long = |at: usize| u32_at(archive, offset + at).map(|value| value as usize).ok_or("truncated archive");
+
+        let name_length = field(28)?;
+        let extra_length = field(30)?;
+        let comment_length = field(32)?;
+        let name = archive.get(offset + 46..offset + 46 + name_length).ok_or("truncated archive")?;
+        entries.push(Entry {
+            name: String::from_utf8_lossy(name).into_owned(),
+            method: field(10)? as u16,
+            compressed_size: long(20)?,
+            local_offset: long(42)?
+        });
+
+        offset += 46 + name_length + extra_length + comment_length;
+    }
+
+    Ok(entries)
+}
+
+fn read_entry(archive: &[u8], entry: &Entry) -> Result<Vec<u8>, String> {
+    let offset = entry.local_offset;
+    if u32_at(archive, offset) != Some(LOCAL_HEADER) {
+        return Err(format!("{}: corrupt local header", entry.name));
+    }
+    //The local name and extra field can differ in length from the central ones
+    let name_length = u16_at(archive, offset + 26).ok_or("truncated archive")? as usize;
+    let extra_length = u16_at(archive, offset + 28).ok_or("truncated archive")? as usize;
+    let start = offset + 30 + name_length + extra_length;
+    let data = archive.get(start..start + entry.compressed_size).ok_or_else(|| format!("{}: truncated", entry.name))?;
+
+    match entry.method {
+        0 => Ok(data.to_vec()),
+        8 => decompress_to_vec_with_limit(data, MAX_XO_CHIP_ROM_SIZE)
+            .map_err(|_| format!("{}: corrupt, or more than the {} bytes that fit in memory", entry.name, MAX_XO_CHIP_ROM_SIZE)),
+        method => Err(format!("{}: unsupported compression method {}", entry.name, method))
+    }
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> Option<u16> {
+    bytes.get(offset..offset + 2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Option<u32> {
+    bytes.get(offset..offset + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const ONE_ROM: &[u8] = include_bytes!("../tests/fixtures/one_rom.zip");
+    const SEVERAL_ROMS: &[u8] = include_bytes!("../tests/fixtures/several_roms.zip");
+    const NO_ROM: &[u8] = include_bytes!("../tests/fixtures/no_rom.zip");
+    const TOO_BIG: &[u8] = include_bytes!("../tests/fixtures/too_big.zip");
+
+    #[test]
+    fn picks_the_only_rom() {
+        //Deflated, next to a README and macOS metadata
+        assert_eq!(extract_rom(ONE_ROM, None).unwrap(), [0x60, 0x05, 0x12, 0x00].repeat(8));
+        assert_eq!(extract_rom(ONE_ROM, Some("README.txt")).unwrap(), b"Pong, public domain\n");
+    }
+
+    #[test]
+    fn several_roms_need_a_name() {
+        assert_eq!(extract_rom(SEVERAL_ROMS, None).unwrap_err(), "several ROMs in the archive, pick one with #NAME: PONG.ch8, games/TETRIS.c8");
+        assert_eq!(extract_rom(SEVERAL_ROMS, Some("PONG.ch8")).unwrap(), [0x60, 0x05, 0x12, 0x00]);
+        assert_eq!(extract_rom(SEVERAL_ROMS, Some("games/TETRIS.c8")).unwrap(), [0x61, 0x07, 0x12, 0x00]);
+        assert_eq!(extract_rom(SEVERAL_ROMS, Some("BRIX.ch8")).unwrap_err(), "no BRIX.ch8 in the archive");
+    }
+
+    #[test]
+    fn archive_without_a_rom() {
+        assert_eq!(extract_rom(NO_ROM, None).unwrap_err(), "no .ch8 or .c8 file in the archive");
+    }
+
+    #[test]
+    fn limits_the_decompressed_size() {
+        assert_eq!(extract_rom(TOO_BIG, None).unwrap_err(), format!("HUGE.ch8: corrupt, or more than the {} bytes that fit in memory", MAX_XO_CHIP_ROM_SIZE));
+    }
+
+    #[test]
+    fn rejects_what_isnt_a_zip() {
+        assert_eq!(extract_rom(&[0x12, 0x00], None).unwrap_err(), "not a zip archive");
+        assert_eq!(extract_rom(&ONE_ROM[ONE_ROM.len() - 22..], None).unwrap_err(), "corrupt central directory");
+    }
+
+}